@@ -0,0 +1,78 @@
+//! Times the bulk sample conversion per block for every SIMD level this CPU supports.
+//!
+//!     cargo run --release --example bulk_convert
+
+use lobster::asio_core::bulk_convert::{BulkConverter, SimdLevel};
+use lobster::asio_core::sample_convert::Scaling;
+use lobster::asio_core::ASIOSampleType;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const BLOCK_SIZES: [usize; 7] = [32, 64, 128, 256, 512, 1024, 2048];
+
+const SAMPLE_TYPES: [ASIOSampleType; 5] = [
+	ASIOSampleType::Int16LSB,
+	ASIOSampleType::Int24LSB,
+	ASIOSampleType::Int32LSB,
+	ASIOSampleType::Int32MSB,
+	ASIOSampleType::Float32LSB
+];
+
+/// Time spent measuring each combination
+const MEASURE_TIME: Duration = Duration::from_millis(50);
+
+/// Average time of one call of `convert`, in nanoseconds
+fn time_per_call(mut convert: impl FnMut()) -> f64 {
+	for _ in 0..100 {
+		convert();
+	}
+
+	let start = Instant::now();
+	let mut calls = 0u64;
+	while start.elapsed() < MEASURE_TIME {
+		for _ in 0..100 {
+			convert();
+		}
+		calls += 100;
+	}
+	start.elapsed().as_nanos() as f64 / calls as f64
+}
+
+fn main() {
+	let levels: Vec<SimdLevel> = [SimdLevel::Scalar, SimdLevel::Sse2, SimdLevel::Avx2, SimdLevel::Neon]
+		.into_iter()
+		.filter(|level| level.is_supported())
+		.collect();
+
+	println!("Nanoseconds per block of one channel, f32 samples");
+	for sample_type in SAMPLE_TYPES {
+		println!();
+		println!("{:?}", sample_type);
+		print!("{:>8}", "frames");
+		for level in &levels {
+			print!("{:>12}{:>12}", format!("{:?} enc", level), format!("{:?} dec", level));
+		}
+		println!();
+
+		for frames in BLOCK_SIZES {
+			let samples: Vec<f32> = (0..frames).map(|index| (index as f32 * 0.01).sin() * 0.8).collect();
+			let mut bytes = vec![0u8; frames * sample_type.size_in_bytes()];
+			let mut decoded = vec![0.0f32; frames];
+
+			print!("{:>8}", frames);
+			for level in &levels {
+				let converter = BulkConverter::with_level(*level);
+				let encode = time_per_call(|| {
+					converter.encode_f32(sample_type, Scaling::Symmetric, black_box(&samples), &mut bytes);
+					black_box(&bytes);
+				});
+				let decode = time_per_call(|| {
+					converter.decode_f32(sample_type, Scaling::Symmetric, black_box(&bytes), &mut decoded);
+					black_box(&decoded);
+				});
+				print!("{:>12.0}{:>12.0}", encode, decode);
+			}
+			println!();
+		}
+	}
+}
@@ -1,7 +1,16 @@
+use crate::asio_core::conversion_policy::{ClipCounter, ConversionPolicy};
 use crate::asio_core::input_channel::InputChannel;
+use crate::asio_core::native_sample::NativeSample;
 use crate::asio_core::output_channel::OutputChannel;
-use crate::asio_core::sample_convert::SampleConvert;
+use crate::asio_core::quantizer::Quantization;
+use crate::asio_core::sample_convert::{SampleConvert, Scaling};
+use crate::asio_core::time::from_asio_samples;
 use crate::asio_core::{ASIOBool, ASIOError, BufferInfo, Callbacks, ChannelInfo, Time, IASIO};
+use crate::dsp::audio_block::{AudioBuffer, ChannelLabel};
+use crate::dsp::block_adapter::BlockAdapter;
+use crate::dsp::processor::Processor;
+use crate::dsp::sample::Sample;
+use crate::dsp::transport::Transport;
 
 pub trait ASIODeviceType {
 	fn buffer_switch(
@@ -13,36 +22,73 @@ pub trait ASIODeviceType {
 	fn get_sample_rate(&self) -> f64;
 	fn get_driver_name(&self) -> &str;
 	fn set_sample_rate(&mut self, sample_rate: f64) -> bool;
+	fn set_output_quantization(&mut self, channel: usize, mode: Quantization);
+	fn set_output_policy(&mut self, channel: usize, policy: ConversionPolicy);
+	fn set_input_scaling(&mut self, channel: usize, scaling: Scaling);
+	fn get_clip_counter(&self, channel: usize) -> ClipCounter;
+	fn set_block_size(&mut self, block_size: usize);
+	fn get_latencies(&self) -> Latencies;
+	fn set_transport(&mut self, transport: Transport);
 	fn start(&mut self);
 	fn stop(&mut self);
 }
 
-pub struct ASIODevice<T> {
+/// Latencies in frames as reported by the driver, plus the latency added by processing
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Latencies {
+	pub input: usize,
+	pub output: usize,
+	pub processing: usize
+}
+
+impl Latencies {
+	/// Round trip from the input connector to the output connector
+	pub fn total(&self) -> usize {
+		self.input + self.processing + self.output
+	}
+}
+
+/// Channels and buffers the factory set up with the driver
+pub struct DeviceSettings {
+	pub driver_name: String,
+	pub num_input_channels: i32,
+	pub num_output_channels: i32,
+	pub pref_buffer_size: i32,
+	pub buffer_infos: Vec<BufferInfo>,
+}
+
+pub struct ASIODevice<T, S: Sample> {
 	iasio: IASIO,
 	#[allow(dead_code)]
 	callbacks: Box<Callbacks>,
-	process: fn(input: Vec<Vec<f64>>, outputs: &mut [Vec<f64>]),
-	processed_samples: Box<[Vec<f64>]>,
+	processor: BlockAdapter<S>,
+	input_buffer: AudioBuffer<S>,
+	output_buffer: AudioBuffer<S>,
+	transport: Transport,
 	pub driver_name: String,
 	pub input_channels: Box<[InputChannel<T>]>,
-	pub output_channels: Box<[OutputChannel<T>]>,
+	pub output_channels: Box<[OutputChannel<T, S>]>,
 }
 
-impl<T: 'static + Copy> ASIODevice<T> {
+impl<T: 'static + NativeSample, S: Sample> ASIODevice<T, S> {
 	pub fn new(
 		iasio: IASIO,
-		driver_name: String,
-		num_input_channels: i32,
-		num_output_channels: i32,
-		pref_buffer_size: i32,
-		buffer_infos: Vec<BufferInfo>,
+		settings: DeviceSettings,
 		callbacks: Box<Callbacks>,
-		process: fn(input: Vec<Vec<f64>>, outputs: &mut [Vec<f64>]),
-	) -> ASIODevice<T> {
+		processor: Box<dyn Processor<S>>,
+	) -> ASIODevice<T, S> {
+		let DeviceSettings {
+			driver_name,
+			num_input_channels,
+			num_output_channels,
+			pref_buffer_size,
+			buffer_infos,
+		} = settings;
+
 		let mut input_channels = Vec::<InputChannel<T>>::new();
 		for index in 0..num_input_channels {
 			let buffer_info = &buffer_infos[index as usize];
-			input_channels.push(ASIODevice::<T>::get_input_channel(
+			input_channels.push(ASIODevice::<T, S>::get_input_channel(
 				&iasio,
 				index,
 				buffer_info,
@@ -51,10 +97,10 @@ impl<T: 'static + Copy> ASIODevice<T> {
 		}
 		let input_channels = input_channels.into_boxed_slice();
 
-		let mut output_channels = Vec::<OutputChannel<T>>::new();
+		let mut output_channels = Vec::<OutputChannel<T, S>>::new();
 		for index in 0..num_output_channels {
 			let buffer_info = &buffer_infos[(num_input_channels + index) as usize];
-			output_channels.push(ASIODevice::<T>::get_output_channel(
+			output_channels.push(ASIODevice::<T, S>::get_output_channel(
 				&iasio,
 				index,
 				buffer_info,
@@ -63,13 +109,19 @@ impl<T: 'static + Copy> ASIODevice<T> {
 		}
 		let output_channels = output_channels.into_boxed_slice();
 
-		let mut processed_buffers: Vec<Vec<f64>> = Vec::with_capacity(num_output_channels as usize);
+		let input_labels = input_channels.iter().enumerate().map(|(index, channel)| ChannelLabel::new(index, &channel.name)).collect();
+		let output_labels = output_channels.iter().enumerate().map(|(index, channel)| ChannelLabel::new(index, &channel.name)).collect();
+		let input_buffer = AudioBuffer::<S>::new(input_labels, pref_buffer_size as usize);
+		let output_buffer = AudioBuffer::<S>::new(output_labels, pref_buffer_size as usize);
 
-		for _ in 0..num_output_channels as usize {
-			processed_buffers.push(vec![0.0; pref_buffer_size as usize]);
-		}
-
-		let processed_samples = processed_buffers.into_boxed_slice();
+		// Until a block size is configured, the processor sees the driver's buffers unchanged
+		let processor = BlockAdapter::new(
+			processor,
+			pref_buffer_size as usize,
+			input_buffer.labels().to_vec(),
+			output_buffer.labels().to_vec(),
+			pref_buffer_size as usize,
+		);
 
 		ASIODevice {
 			iasio,
@@ -77,8 +129,10 @@ impl<T: 'static + Copy> ASIODevice<T> {
 			driver_name,
 			input_channels,
 			output_channels,
-			process,
-			processed_samples,
+			processor,
+			input_buffer,
+			output_buffer,
+			transport: Transport::default(),
 		}
 	}
 
@@ -131,7 +185,8 @@ impl<T: 'static + Copy> ASIODevice<T> {
 			}
 		}
 
-		String::from_utf8(channel_info.name.to_vec()).expect("Channel name is utf-8")
+		let trimmed: Vec<u8> = channel_info.name.iter().take_while(|c| **c != 0u8).cloned().collect();
+		String::from_utf8(trimmed).expect("Channel name is utf-8")
 	}
 
 	fn get_input_channel(
@@ -144,9 +199,9 @@ impl<T: 'static + Copy> ASIODevice<T> {
 		let buffer_b: *const () = buffer_info.buffers[1];
 
 		InputChannel::<T>::new(
-			&ASIODevice::<T>::get_channel_name(iasio, true, id),
-			buffer_a as *const T,
-			buffer_b as *const T,
+			&ASIODevice::<T, S>::get_channel_name(iasio, true, id),
+			buffer_a as *const u8,
+			buffer_b as *const u8,
 			buffer_size as usize,
 		)
 	}
@@ -156,20 +211,38 @@ impl<T: 'static + Copy> ASIODevice<T> {
 		id: i32,
 		buffer_info: &BufferInfo,
 		buffer_size: i32,
-	) -> OutputChannel<T> {
+	) -> OutputChannel<T, S> {
 		let buffer_a: *mut () = buffer_info.buffers[0];
 		let buffer_b: *mut () = buffer_info.buffers[1];
 
-		OutputChannel::<T>::new(
-			&ASIODevice::<T>::get_channel_name(iasio, false, id),
-			buffer_a as *mut T,
-			buffer_b as *mut T,
+		OutputChannel::<T, S>::new(
+			&ASIODevice::<T, S>::get_channel_name(iasio, false, id),
+			buffer_a as *mut u8,
+			buffer_b as *mut u8,
 			buffer_size as usize,
+			// Each channel gets its own dither sequence, so that the noise is uncorrelated
+			id as u64 + 1,
 		)
 	}
+
+	/// Sample position of the current buffer, from the time info if the driver passes one
+	fn get_sample_position(&self, params: *const Time) -> i64 {
+		if !params.is_null() {
+			return from_asio_samples(unsafe { (*params).time_info.sample_position });
+		}
+
+		let mut position = 0i64;
+		let mut time_stamp = 0i64;
+		unsafe {
+			if self.iasio.get_sample_position(&mut position, &mut time_stamp) != ASIOError::Ok {
+				return 0;
+			}
+		}
+		from_asio_samples(position)
+	}
 }
 
-impl<T: 'static + Copy + SampleConvert<Sample = T>> ASIODeviceType for ASIODevice<T> {
+impl<T: 'static + NativeSample + SampleConvert<Sample = T>, S: Sample> ASIODeviceType for ASIODevice<T, S> {
 	fn buffer_switch(
 		&mut self,
 		params: *const Time,
@@ -179,25 +252,19 @@ impl<T: 'static + Copy + SampleConvert<Sample = T>> ASIODeviceType for ASIODevic
 		// The double_buffer_index indicates,
 		// - which output buffer the host should now start to fill
 		// - which input buffer is filled with incoming data by the driver
-		let input_count = self.input_channels.len();
-		let mut vec_input: Vec<Vec<f64>> = Vec::with_capacity(input_count);
-
-		for channel in 0..input_count {
-			let source = &mut self.input_channels[channel];
-			let samples: Vec<f64> = source
-				.iter(double_buffer_index)
-				.map(|n| n.from_native())
-				.collect();
-			vec_input.push(samples);
+		self.transport.set_position(self.get_sample_position(params));
+
+		let mut input = self.input_buffer.as_block_mut();
+
+		for (channel, samples) in input.channels_mut().enumerate() {
+			self.input_channels[channel].read_samples(double_buffer_index, samples);
 		}
 
-		let output_count = self.output_channels.len();
-		(self.process)(vec_input, &mut self.processed_samples);
+		let mut output = self.output_buffer.as_block_mut();
+		self.processor.process(&input.as_block(), &mut output);
 
-		for channel in 0..output_count {
-			let samples = &self.processed_samples[channel];
-			let native = &mut samples.iter().map(|s| T::to_native(*s));
-			self.output_channels[channel].write(double_buffer_index, native);
+		for (channel, samples) in output.as_block().channels().enumerate() {
+			self.output_channels[channel].write_samples(double_buffer_index, samples);
 		}
 		params
 	}
@@ -210,13 +277,54 @@ impl<T: 'static + Copy + SampleConvert<Sample = T>> ASIODeviceType for ASIODevic
 				if iasio_ref.set_sample_rate(sample_rate) != ASIOError::Ok {
 					panic!("Cannot set desired sample rate '{}'", sample_rate)
 				}
-				return self.get_sample_rate() == sample_rate;
+				let effective = self.get_sample_rate();
+				self.processor.prepare(effective, self.input_buffer.capacity());
+				return effective == sample_rate;
 			} else {
 				return false;
 			}
 		}
 	}
 
+	fn set_output_quantization(&mut self, channel: usize, mode: Quantization) {
+		self.output_channels[channel].quantizer.set_mode(mode);
+	}
+
+	fn set_output_policy(&mut self, channel: usize, policy: ConversionPolicy) {
+		self.output_channels[channel].policy = policy;
+	}
+
+	fn set_input_scaling(&mut self, channel: usize, scaling: Scaling) {
+		self.input_channels[channel].scaling = scaling;
+	}
+
+	/// The returned counter can be read from any thread while the device is running
+	fn get_clip_counter(&self, channel: usize) -> ClipCounter {
+		self.output_channels[channel].clip_counter.clone()
+	}
+
+	/// Re-blocks the processor's input and output to `block_size` frames. Call before `start`.
+	fn set_block_size(&mut self, block_size: usize) {
+		self.processor.set_block_size(block_size);
+	}
+
+	fn get_latencies(&self) -> Latencies {
+		let mut input_latency = 0i32;
+		let mut output_latency = 0i32;
+
+		unsafe {
+			if self.iasio.get_latencies(&mut input_latency, &mut output_latency) != ASIOError::Ok {
+				panic!("Failed to get latency information");
+			}
+		}
+
+		Latencies {
+			input: input_latency as usize,
+			output: output_latency as usize,
+			processing: self.processor.latency(),
+		}
+	}
+
 	fn get_sample_rate(&self) -> f64 {
 		let iasio_ref = &self.iasio;
 
@@ -231,7 +339,16 @@ impl<T: 'static + Copy + SampleConvert<Sample = T>> ASIODeviceType for ASIODevic
 		&self.driver_name
 	}
 
+	/// Tempo synced processors should share `transport`, the device publishes the
+	/// sample position to it on every buffer switch
+	fn set_transport(&mut self, transport: Transport) {
+		self.transport = transport;
+	}
+
 	fn start(&mut self) {
+		let sample_rate = self.get_sample_rate();
+		self.processor.prepare(sample_rate, self.input_buffer.capacity());
+
 		let iasio_ref = &self.iasio;
 		let error;
 
@@ -0,0 +1,694 @@
+use crate::asio_core::native_sample::{
+	Float32MSB, Float64MSB, Int16MSB, Int24LSB, Int24MSB, Int32LSB16, Int32LSB18, Int32LSB20,
+	Int32LSB24, Int32MSB, Int32MSB16, Int32MSB18, Int32MSB20, Int32MSB24, NativeSample,
+};
+use crate::asio_core::sample_convert::{full_scale, max_int_value, Scaling};
+use crate::asio_core::ASIOSampleType;
+use std::sync::OnceLock;
+
+/// Number of integer samples unpacked on the stack before a vector kernel runs over them
+const CHUNK_SIZE: usize = 64;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SimdLevel {
+	Scalar,
+	Sse2,
+	Avx2,
+	Neon
+}
+
+impl SimdLevel {
+	/// Best instruction set available on this machine, determined once
+	pub fn detect() -> SimdLevel {
+		static LEVEL: OnceLock<SimdLevel> = OnceLock::new();
+
+		*LEVEL.get_or_init(|| {
+			[SimdLevel::Avx2, SimdLevel::Sse2, SimdLevel::Neon]
+				.into_iter()
+				.find(|level| level.is_supported())
+				.unwrap_or(SimdLevel::Scalar)
+		})
+	}
+
+	pub fn is_supported(&self) -> bool {
+		match self {
+			SimdLevel::Scalar => true,
+			#[cfg(target_arch = "x86_64")]
+			SimdLevel::Sse2 => is_x86_feature_detected!("sse2"),
+			#[cfg(target_arch = "x86_64")]
+			SimdLevel::Avx2 => is_x86_feature_detected!("avx2"),
+			#[cfg(target_arch = "aarch64")]
+			SimdLevel::Neon => std::arch::is_aarch64_feature_detected!("neon"),
+			_ => false
+		}
+	}
+}
+
+/// Converts whole buffers between ASIO sample types and f32 or f64, using the vector
+/// instructions the CPU offers. Integer samples are rounded to the nearest value (ties to even)
+/// and saturated, so use `Quantizer` sample by sample where truncation or dither is wanted.
+#[derive(Copy, Clone, Debug)]
+pub struct BulkConverter {
+	level: SimdLevel
+}
+
+impl Default for BulkConverter {
+	fn default() -> BulkConverter {
+		BulkConverter::new()
+	}
+}
+
+impl BulkConverter {
+	pub fn new() -> BulkConverter {
+		BulkConverter {
+			level: SimdLevel::detect()
+		}
+	}
+
+	/// Forces a specific code path, e.g. to compare it against the scalar fallback
+	pub fn with_level(level: SimdLevel) -> BulkConverter {
+		if !level.is_supported() {
+			panic!("SIMD level '{:?}' is not supported on this CPU", level);
+		}
+		BulkConverter { level }
+	}
+
+	pub fn level(&self) -> SimdLevel {
+		self.level
+	}
+
+	/// Converts `dst.len()` native samples from `src`
+	pub fn decode_f32(&self, sample_type: ASIOSampleType, scaling: Scaling, src: &[u8], dst: &mut [f32]) {
+		match sample_type {
+			ASIOSampleType::Float32LSB => read_each(src, dst, |s: f32| s),
+			ASIOSampleType::Float32MSB => read_each(src, dst, |s: Float32MSB| s.0),
+			ASIOSampleType::Float64LSB => read_each(src, dst, |s: f64| s as f32),
+			ASIOSampleType::Float64MSB => read_each(src, dst, |s: Float64MSB| s.0 as f32),
+			_ => {
+				let scale = (1.0 / full_scale(significant_bits(sample_type), scaling)) as f32;
+				let stride = sample_type.size_in_bytes();
+				let mut ints = [0i32; CHUNK_SIZE];
+
+				for (target, bytes) in dst.chunks_mut(CHUNK_SIZE).zip(src.chunks(CHUNK_SIZE * stride)) {
+					let ints = &mut ints[..target.len()];
+					unpack(sample_type, bytes, ints);
+					self.i32_to_f32(ints, target, scale);
+				}
+			}
+		}
+	}
+
+	/// Converts `dst.len()` native samples from `src`
+	pub fn decode_f64(&self, sample_type: ASIOSampleType, scaling: Scaling, src: &[u8], dst: &mut [f64]) {
+		match sample_type {
+			ASIOSampleType::Float32LSB => read_each(src, dst, |s: f32| s as f64),
+			ASIOSampleType::Float32MSB => read_each(src, dst, |s: Float32MSB| s.0 as f64),
+			ASIOSampleType::Float64LSB => read_each(src, dst, |s: f64| s),
+			ASIOSampleType::Float64MSB => read_each(src, dst, |s: Float64MSB| s.0),
+			_ => {
+				let scale = 1.0 / full_scale(significant_bits(sample_type), scaling);
+				let stride = sample_type.size_in_bytes();
+				let mut ints = [0i32; CHUNK_SIZE];
+
+				for (target, bytes) in dst.chunks_mut(CHUNK_SIZE).zip(src.chunks(CHUNK_SIZE * stride)) {
+					let ints = &mut ints[..target.len()];
+					unpack(sample_type, bytes, ints);
+					self.i32_to_f64(ints, target, scale);
+				}
+			}
+		}
+	}
+
+	/// Converts all samples of `src` into native samples in `dst`
+	pub fn encode_f32(&self, sample_type: ASIOSampleType, scaling: Scaling, src: &[f32], dst: &mut [u8]) {
+		match sample_type {
+			ASIOSampleType::Float32LSB => write_each(src, dst, |s| s),
+			ASIOSampleType::Float32MSB => write_each(src, dst, Float32MSB),
+			ASIOSampleType::Float64LSB => write_each(src, dst, |s| s as f64),
+			ASIOSampleType::Float64MSB => write_each(src, dst, |s| Float64MSB(s as f64)),
+			_ => {
+				let limits = IntLimits::new(significant_bits(sample_type), scaling);
+				let stride = sample_type.size_in_bytes();
+				let mut ints = [0i32; CHUNK_SIZE];
+
+				for (source, bytes) in src.chunks(CHUNK_SIZE).zip(dst.chunks_mut(CHUNK_SIZE * stride)) {
+					let ints = &mut ints[..source.len()];
+					self.f32_to_i32(source, ints, &limits);
+					pack(sample_type, ints, bytes);
+				}
+			}
+		}
+	}
+
+	/// Converts all samples of `src` into native samples in `dst`
+	pub fn encode_f64(&self, sample_type: ASIOSampleType, scaling: Scaling, src: &[f64], dst: &mut [u8]) {
+		match sample_type {
+			ASIOSampleType::Float32LSB => write_each(src, dst, |s| s as f32),
+			ASIOSampleType::Float32MSB => write_each(src, dst, |s| Float32MSB(s as f32)),
+			ASIOSampleType::Float64LSB => write_each(src, dst, |s| s),
+			ASIOSampleType::Float64MSB => write_each(src, dst, Float64MSB),
+			_ => {
+				let limits = IntLimits::new(significant_bits(sample_type), scaling);
+				let stride = sample_type.size_in_bytes();
+				let mut ints = [0i32; CHUNK_SIZE];
+
+				for (source, bytes) in src.chunks(CHUNK_SIZE).zip(dst.chunks_mut(CHUNK_SIZE * stride)) {
+					let ints = &mut ints[..source.len()];
+					self.f64_to_i32(source, ints, &limits);
+					pack(sample_type, ints, bytes);
+				}
+			}
+		}
+	}
+
+	fn i32_to_f32(&self, src: &[i32], dst: &mut [f32], scale: f32) {
+		unsafe {
+			match self.level {
+				#[cfg(target_arch = "x86_64")]
+				SimdLevel::Avx2 => x86::i32_to_f32_avx2(src, dst, scale),
+				#[cfg(target_arch = "x86_64")]
+				SimdLevel::Sse2 => x86::i32_to_f32_sse2(src, dst, scale),
+				#[cfg(target_arch = "aarch64")]
+				SimdLevel::Neon => arm::i32_to_f32_neon(src, dst, scale),
+				_ => scalar::i32_to_f32(src, dst, scale)
+			}
+		}
+	}
+
+	fn i32_to_f64(&self, src: &[i32], dst: &mut [f64], scale: f64) {
+		unsafe {
+			match self.level {
+				#[cfg(target_arch = "x86_64")]
+				SimdLevel::Avx2 => x86::i32_to_f64_avx2(src, dst, scale),
+				#[cfg(target_arch = "x86_64")]
+				SimdLevel::Sse2 => x86::i32_to_f64_sse2(src, dst, scale),
+				#[cfg(target_arch = "aarch64")]
+				SimdLevel::Neon => arm::i32_to_f64_neon(src, dst, scale),
+				_ => scalar::i32_to_f64(src, dst, scale)
+			}
+		}
+	}
+
+	fn f32_to_i32(&self, src: &[f32], dst: &mut [i32], limits: &IntLimits) {
+		let (scale, min, max) = (limits.scale as f32, limits.min_f32, limits.max_f32);
+
+		unsafe {
+			match self.level {
+				#[cfg(target_arch = "x86_64")]
+				SimdLevel::Avx2 => x86::f32_to_i32_avx2(src, dst, scale, min, max),
+				#[cfg(target_arch = "x86_64")]
+				SimdLevel::Sse2 => x86::f32_to_i32_sse2(src, dst, scale, min, max),
+				#[cfg(target_arch = "aarch64")]
+				SimdLevel::Neon => arm::f32_to_i32_neon(src, dst, scale, min, max),
+				_ => scalar::f32_to_i32(src, dst, scale, min, max)
+			}
+		}
+	}
+
+	fn f64_to_i32(&self, src: &[f64], dst: &mut [i32], limits: &IntLimits) {
+		let (scale, min, max) = (limits.scale, limits.min, limits.max);
+
+		unsafe {
+			match self.level {
+				#[cfg(target_arch = "x86_64")]
+				SimdLevel::Avx2 => x86::f64_to_i32_avx2(src, dst, scale, min, max),
+				#[cfg(target_arch = "x86_64")]
+				SimdLevel::Sse2 => x86::f64_to_i32_sse2(src, dst, scale, min, max),
+				#[cfg(target_arch = "aarch64")]
+				SimdLevel::Neon => arm::f64_to_i32_neon(src, dst, scale, min, max),
+				_ => scalar::f64_to_i32(src, dst, scale, min, max)
+			}
+		}
+	}
+}
+
+/// Multiplier and clipping range for converting floats to an integer format
+struct IntLimits {
+	scale: f64,
+	min: f64,
+	max: f64,
+	// f32 cannot represent all 32 bit limits, so the nearest values inside the range are used
+	min_f32: f32,
+	max_f32: f32
+}
+
+impl IntLimits {
+	fn new(bits: u32, scaling: Scaling) -> IntLimits {
+		let max = max_int_value(bits);
+		let min = match scaling {
+			Scaling::Symmetric => -max,
+			Scaling::PowerOfTwo => -max - 1.0
+		};
+
+		IntLimits {
+			scale: full_scale(bits, scaling),
+			min,
+			max,
+			min_f32: f32_inside(min),
+			max_f32: f32_inside(max)
+		}
+	}
+}
+
+/// Nearest f32 that does not exceed the magnitude of `value`
+fn f32_inside(value: f64) -> f32 {
+	let narrowed = value as f32;
+
+	match (narrowed as f64).abs() > value.abs() {
+		true => f32::from_bits(narrowed.to_bits() - 1),
+		false => narrowed
+	}
+}
+
+fn significant_bits(sample_type: ASIOSampleType) -> u32 {
+	match sample_type {
+		ASIOSampleType::Int16LSB | ASIOSampleType::Int16MSB => 16,
+		ASIOSampleType::Int24LSB | ASIOSampleType::Int24MSB => 24,
+		ASIOSampleType::Int32LSB | ASIOSampleType::Int32MSB => 32,
+		ASIOSampleType::Int32LSB16 | ASIOSampleType::Int32MSB16 => 16,
+		ASIOSampleType::Int32LSB18 | ASIOSampleType::Int32MSB18 => 18,
+		ASIOSampleType::Int32LSB20 | ASIOSampleType::Int32MSB20 => 20,
+		ASIOSampleType::Int32LSB24 | ASIOSampleType::Int32MSB24 => 24,
+		_ => panic!("Unsupported sample type '{:?}'.", sample_type)
+	}
+}
+
+fn sign_extend(value: i32, bits: u32) -> i32 {
+	let shift = 32 - bits;
+	(value << shift) >> shift
+}
+
+fn read_each<T: NativeSample, S>(src: &[u8], dst: &mut [S], convert: impl Fn(T) -> S) {
+	let stride = T::SAMPLE_TYPE.size_in_bytes();
+
+	for (target, bytes) in dst.iter_mut().zip(src.chunks_exact(stride)) {
+		*target = convert(T::read(bytes));
+	}
+}
+
+fn write_each<T: NativeSample, S: Copy>(src: &[S], dst: &mut [u8], convert: impl Fn(S) -> T) {
+	let stride = T::SAMPLE_TYPE.size_in_bytes();
+
+	for (source, bytes) in src.iter().zip(dst.chunks_exact_mut(stride)) {
+		convert(*source).write(bytes);
+	}
+}
+
+/// Reads integer samples as sign extended i32 values
+fn unpack(sample_type: ASIOSampleType, src: &[u8], dst: &mut [i32]) {
+	match sample_type {
+		ASIOSampleType::Int16LSB => read_each(src, dst, |s: i16| s as i32),
+		ASIOSampleType::Int16MSB => read_each(src, dst, |s: Int16MSB| s.0 as i32),
+		ASIOSampleType::Int24LSB => read_each(src, dst, |s: Int24LSB| s.0),
+		ASIOSampleType::Int24MSB => read_each(src, dst, |s: Int24MSB| s.0),
+		ASIOSampleType::Int32LSB => read_each(src, dst, |s: i32| s),
+		ASIOSampleType::Int32MSB => read_each(src, dst, |s: Int32MSB| s.0),
+		ASIOSampleType::Int32LSB16 => read_each(src, dst, |s: Int32LSB16| sign_extend(s.0, 16)),
+		ASIOSampleType::Int32LSB18 => read_each(src, dst, |s: Int32LSB18| sign_extend(s.0, 18)),
+		ASIOSampleType::Int32LSB20 => read_each(src, dst, |s: Int32LSB20| sign_extend(s.0, 20)),
+		ASIOSampleType::Int32LSB24 => read_each(src, dst, |s: Int32LSB24| sign_extend(s.0, 24)),
+		ASIOSampleType::Int32MSB16 => read_each(src, dst, |s: Int32MSB16| sign_extend(s.0, 16)),
+		ASIOSampleType::Int32MSB18 => read_each(src, dst, |s: Int32MSB18| sign_extend(s.0, 18)),
+		ASIOSampleType::Int32MSB20 => read_each(src, dst, |s: Int32MSB20| sign_extend(s.0, 20)),
+		ASIOSampleType::Int32MSB24 => read_each(src, dst, |s: Int32MSB24| sign_extend(s.0, 24)),
+		_ => panic!("Unsupported sample type '{:?}'.", sample_type)
+	}
+}
+
+/// Writes i32 values, which are already limited to the format's range, as integer samples
+fn pack(sample_type: ASIOSampleType, src: &[i32], dst: &mut [u8]) {
+	match sample_type {
+		ASIOSampleType::Int16LSB => write_each(src, dst, |v| v as i16),
+		ASIOSampleType::Int16MSB => write_each(src, dst, |v| Int16MSB(v as i16)),
+		ASIOSampleType::Int24LSB => write_each(src, dst, Int24LSB),
+		ASIOSampleType::Int24MSB => write_each(src, dst, Int24MSB),
+		ASIOSampleType::Int32LSB => write_each(src, dst, |v| v),
+		ASIOSampleType::Int32MSB => write_each(src, dst, Int32MSB),
+		ASIOSampleType::Int32LSB16 => write_each(src, dst, Int32LSB16),
+		ASIOSampleType::Int32LSB18 => write_each(src, dst, Int32LSB18),
+		ASIOSampleType::Int32LSB20 => write_each(src, dst, Int32LSB20),
+		ASIOSampleType::Int32LSB24 => write_each(src, dst, Int32LSB24),
+		ASIOSampleType::Int32MSB16 => write_each(src, dst, Int32MSB16),
+		ASIOSampleType::Int32MSB18 => write_each(src, dst, Int32MSB18),
+		ASIOSampleType::Int32MSB20 => write_each(src, dst, Int32MSB20),
+		ASIOSampleType::Int32MSB24 => write_each(src, dst, Int32MSB24),
+		_ => panic!("Unsupported sample type '{:?}'.", sample_type)
+	}
+}
+
+/// Reference implementations, also used for the remainder that does not fill a vector register.
+/// The vector kernels perform the same operations in the same order, so results are identical.
+mod scalar {
+	pub fn i32_to_f32(src: &[i32], dst: &mut [f32], scale: f32) {
+		for (target, source) in dst.iter_mut().zip(src) {
+			*target = (*source as f32 * scale).max(-1.0);
+		}
+	}
+
+	pub fn i32_to_f64(src: &[i32], dst: &mut [f64], scale: f64) {
+		for (target, source) in dst.iter_mut().zip(src) {
+			*target = (*source as f64 * scale).max(-1.0);
+		}
+	}
+
+	pub fn f32_to_i32(src: &[f32], dst: &mut [i32], scale: f32, min: f32, max: f32) {
+		for (target, source) in dst.iter_mut().zip(src) {
+			*target = (*source * scale).max(min).min(max).round_ties_even() as i32;
+		}
+	}
+
+	pub fn f64_to_i32(src: &[f64], dst: &mut [i32], scale: f64, min: f64, max: f64) {
+		for (target, source) in dst.iter_mut().zip(src) {
+			*target = (*source * scale).max(min).min(max).round_ties_even() as i32;
+		}
+	}
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+	use super::scalar;
+	use std::arch::x86_64::*;
+
+	// The float to integer conversions rely on the default MXCSR rounding mode (nearest, ties to even)
+
+	#[target_feature(enable = "sse2")]
+	pub unsafe fn i32_to_f32_sse2(src: &[i32], dst: &mut [f32], scale: f32) {
+		let len = src.len().min(dst.len());
+		let vectorized = len - len % 4;
+		let scale_v = _mm_set1_ps(scale);
+		let lower = _mm_set1_ps(-1.0);
+
+		for i in (0..vectorized).step_by(4) {
+			let ints = _mm_loadu_si128(src.as_ptr().add(i) as *const __m128i);
+			let floats = _mm_max_ps(_mm_mul_ps(_mm_cvtepi32_ps(ints), scale_v), lower);
+			_mm_storeu_ps(dst.as_mut_ptr().add(i), floats);
+		}
+		scalar::i32_to_f32(&src[vectorized..len], &mut dst[vectorized..len], scale);
+	}
+
+	#[target_feature(enable = "sse2")]
+	pub unsafe fn i32_to_f64_sse2(src: &[i32], dst: &mut [f64], scale: f64) {
+		let len = src.len().min(dst.len());
+		let vectorized = len - len % 2;
+		let scale_v = _mm_set1_pd(scale);
+		let lower = _mm_set1_pd(-1.0);
+
+		for i in (0..vectorized).step_by(2) {
+			let ints = _mm_loadl_epi64(src.as_ptr().add(i) as *const __m128i);
+			let floats = _mm_max_pd(_mm_mul_pd(_mm_cvtepi32_pd(ints), scale_v), lower);
+			_mm_storeu_pd(dst.as_mut_ptr().add(i), floats);
+		}
+		scalar::i32_to_f64(&src[vectorized..len], &mut dst[vectorized..len], scale);
+	}
+
+	#[target_feature(enable = "sse2")]
+	pub unsafe fn f32_to_i32_sse2(src: &[f32], dst: &mut [i32], scale: f32, min: f32, max: f32) {
+		let len = src.len().min(dst.len());
+		let vectorized = len - len % 4;
+		let (scale_v, min_v, max_v) = (_mm_set1_ps(scale), _mm_set1_ps(min), _mm_set1_ps(max));
+
+		for i in (0..vectorized).step_by(4) {
+			let floats = _mm_mul_ps(_mm_loadu_ps(src.as_ptr().add(i)), scale_v);
+			let ints = _mm_cvtps_epi32(_mm_min_ps(_mm_max_ps(floats, min_v), max_v));
+			_mm_storeu_si128(dst.as_mut_ptr().add(i) as *mut __m128i, ints);
+		}
+		scalar::f32_to_i32(&src[vectorized..len], &mut dst[vectorized..len], scale, min, max);
+	}
+
+	#[target_feature(enable = "sse2")]
+	pub unsafe fn f64_to_i32_sse2(src: &[f64], dst: &mut [i32], scale: f64, min: f64, max: f64) {
+		let len = src.len().min(dst.len());
+		let vectorized = len - len % 2;
+		let (scale_v, min_v, max_v) = (_mm_set1_pd(scale), _mm_set1_pd(min), _mm_set1_pd(max));
+
+		for i in (0..vectorized).step_by(2) {
+			let floats = _mm_mul_pd(_mm_loadu_pd(src.as_ptr().add(i)), scale_v);
+			let ints = _mm_cvtpd_epi32(_mm_min_pd(_mm_max_pd(floats, min_v), max_v));
+			_mm_storel_epi64(dst.as_mut_ptr().add(i) as *mut __m128i, ints);
+		}
+		scalar::f64_to_i32(&src[vectorized..len], &mut dst[vectorized..len], scale, min, max);
+	}
+
+	#[target_feature(enable = "avx2")]
+	pub unsafe fn i32_to_f32_avx2(src: &[i32], dst: &mut [f32], scale: f32) {
+		let len = src.len().min(dst.len());
+		let vectorized = len - len % 8;
+		let scale_v = _mm256_set1_ps(scale);
+		let lower = _mm256_set1_ps(-1.0);
+
+		for i in (0..vectorized).step_by(8) {
+			let ints = _mm256_loadu_si256(src.as_ptr().add(i) as *const __m256i);
+			let floats = _mm256_max_ps(_mm256_mul_ps(_mm256_cvtepi32_ps(ints), scale_v), lower);
+			_mm256_storeu_ps(dst.as_mut_ptr().add(i), floats);
+		}
+		scalar::i32_to_f32(&src[vectorized..len], &mut dst[vectorized..len], scale);
+	}
+
+	#[target_feature(enable = "avx2")]
+	pub unsafe fn i32_to_f64_avx2(src: &[i32], dst: &mut [f64], scale: f64) {
+		let len = src.len().min(dst.len());
+		let vectorized = len - len % 4;
+		let scale_v = _mm256_set1_pd(scale);
+		let lower = _mm256_set1_pd(-1.0);
+
+		for i in (0..vectorized).step_by(4) {
+			let ints = _mm_loadu_si128(src.as_ptr().add(i) as *const __m128i);
+			let floats = _mm256_max_pd(_mm256_mul_pd(_mm256_cvtepi32_pd(ints), scale_v), lower);
+			_mm256_storeu_pd(dst.as_mut_ptr().add(i), floats);
+		}
+		scalar::i32_to_f64(&src[vectorized..len], &mut dst[vectorized..len], scale);
+	}
+
+	#[target_feature(enable = "avx2")]
+	pub unsafe fn f32_to_i32_avx2(src: &[f32], dst: &mut [i32], scale: f32, min: f32, max: f32) {
+		let len = src.len().min(dst.len());
+		let vectorized = len - len % 8;
+		let (scale_v, min_v, max_v) = (_mm256_set1_ps(scale), _mm256_set1_ps(min), _mm256_set1_ps(max));
+
+		for i in (0..vectorized).step_by(8) {
+			let floats = _mm256_mul_ps(_mm256_loadu_ps(src.as_ptr().add(i)), scale_v);
+			let ints = _mm256_cvtps_epi32(_mm256_min_ps(_mm256_max_ps(floats, min_v), max_v));
+			_mm256_storeu_si256(dst.as_mut_ptr().add(i) as *mut __m256i, ints);
+		}
+		scalar::f32_to_i32(&src[vectorized..len], &mut dst[vectorized..len], scale, min, max);
+	}
+
+	#[target_feature(enable = "avx2")]
+	pub unsafe fn f64_to_i32_avx2(src: &[f64], dst: &mut [i32], scale: f64, min: f64, max: f64) {
+		let len = src.len().min(dst.len());
+		let vectorized = len - len % 4;
+		let (scale_v, min_v, max_v) = (_mm256_set1_pd(scale), _mm256_set1_pd(min), _mm256_set1_pd(max));
+
+		for i in (0..vectorized).step_by(4) {
+			let floats = _mm256_mul_pd(_mm256_loadu_pd(src.as_ptr().add(i)), scale_v);
+			let ints = _mm256_cvtpd_epi32(_mm256_min_pd(_mm256_max_pd(floats, min_v), max_v));
+			_mm_storeu_si128(dst.as_mut_ptr().add(i) as *mut __m128i, ints);
+		}
+		scalar::f64_to_i32(&src[vectorized..len], &mut dst[vectorized..len], scale, min, max);
+	}
+}
+
+#[cfg(target_arch = "aarch64")]
+mod arm {
+	use super::scalar;
+	use std::arch::aarch64::*;
+
+	#[target_feature(enable = "neon")]
+	pub unsafe fn i32_to_f32_neon(src: &[i32], dst: &mut [f32], scale: f32) {
+		let len = src.len().min(dst.len());
+		let vectorized = len - len % 4;
+		let scale_v = vdupq_n_f32(scale);
+		let lower = vdupq_n_f32(-1.0);
+
+		for i in (0..vectorized).step_by(4) {
+			let floats = vcvtq_f32_s32(vld1q_s32(src.as_ptr().add(i)));
+			vst1q_f32(dst.as_mut_ptr().add(i), vmaxq_f32(vmulq_f32(floats, scale_v), lower));
+		}
+		scalar::i32_to_f32(&src[vectorized..len], &mut dst[vectorized..len], scale);
+	}
+
+	#[target_feature(enable = "neon")]
+	pub unsafe fn i32_to_f64_neon(src: &[i32], dst: &mut [f64], scale: f64) {
+		let len = src.len().min(dst.len());
+		let vectorized = len - len % 2;
+		let scale_v = vdupq_n_f64(scale);
+		let lower = vdupq_n_f64(-1.0);
+
+		for i in (0..vectorized).step_by(2) {
+			let floats = vcvtq_f64_s64(vmovl_s32(vld1_s32(src.as_ptr().add(i))));
+			vst1q_f64(dst.as_mut_ptr().add(i), vmaxq_f64(vmulq_f64(floats, scale_v), lower));
+		}
+		scalar::i32_to_f64(&src[vectorized..len], &mut dst[vectorized..len], scale);
+	}
+
+	#[target_feature(enable = "neon")]
+	pub unsafe fn f32_to_i32_neon(src: &[f32], dst: &mut [i32], scale: f32, min: f32, max: f32) {
+		let len = src.len().min(dst.len());
+		let vectorized = len - len % 4;
+		let (scale_v, min_v, max_v) = (vdupq_n_f32(scale), vdupq_n_f32(min), vdupq_n_f32(max));
+
+		for i in (0..vectorized).step_by(4) {
+			let floats = vmulq_f32(vld1q_f32(src.as_ptr().add(i)), scale_v);
+			let ints = vcvtnq_s32_f32(vminq_f32(vmaxq_f32(floats, min_v), max_v));
+			vst1q_s32(dst.as_mut_ptr().add(i), ints);
+		}
+		scalar::f32_to_i32(&src[vectorized..len], &mut dst[vectorized..len], scale, min, max);
+	}
+
+	#[target_feature(enable = "neon")]
+	pub unsafe fn f64_to_i32_neon(src: &[f64], dst: &mut [i32], scale: f64, min: f64, max: f64) {
+		let len = src.len().min(dst.len());
+		let vectorized = len - len % 2;
+		let (scale_v, min_v, max_v) = (vdupq_n_f64(scale), vdupq_n_f64(min), vdupq_n_f64(max));
+
+		for i in (0..vectorized).step_by(2) {
+			let floats = vmulq_f64(vld1q_f64(src.as_ptr().add(i)), scale_v);
+			let ints = vcvtnq_s64_f64(vminq_f64(vmaxq_f64(floats, min_v), max_v));
+			vst1_s32(dst.as_mut_ptr().add(i), vmovn_s64(ints));
+		}
+		scalar::f64_to_i32(&src[vectorized..len], &mut dst[vectorized..len], scale, min, max);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::asio_core::random::Random;
+
+	const NATIVE_TYPES: [ASIOSampleType; 18] = [
+		ASIOSampleType::Int16LSB,
+		ASIOSampleType::Int24LSB,
+		ASIOSampleType::Int32LSB,
+		ASIOSampleType::Float32LSB,
+		ASIOSampleType::Float64LSB,
+		ASIOSampleType::Int32LSB16,
+		ASIOSampleType::Int32LSB18,
+		ASIOSampleType::Int32LSB20,
+		ASIOSampleType::Int32LSB24,
+		ASIOSampleType::Int16MSB,
+		ASIOSampleType::Int24MSB,
+		ASIOSampleType::Int32MSB,
+		ASIOSampleType::Float32MSB,
+		ASIOSampleType::Float64MSB,
+		ASIOSampleType::Int32MSB16,
+		ASIOSampleType::Int32MSB18,
+		ASIOSampleType::Int32MSB20,
+		ASIOSampleType::Int32MSB24
+	];
+
+	const SCALINGS: [Scaling; 2] = [Scaling::Symmetric, Scaling::PowerOfTwo];
+
+	/// Lengths below, at and above the vector widths and the chunk size, with odd tails
+	const LENGTHS: [usize; 14] = [0, 1, 2, 3, 5, 7, 8, 9, 15, 31, 63, 64, 65, 131];
+
+	fn is_float(sample_type: ASIOSampleType) -> bool {
+		matches!(sample_type, ASIOSampleType::Float32LSB | ASIOSampleType::Float32MSB | ASIOSampleType::Float64LSB | ASIOSampleType::Float64MSB)
+	}
+
+	fn vector_levels() -> Vec<SimdLevel> {
+		[SimdLevel::Sse2, SimdLevel::Avx2, SimdLevel::Neon].into_iter().filter(|level| level.is_supported()).collect()
+	}
+
+	/// Mostly in range, with exact full scale, values beyond it and ties between two steps
+	fn test_signal(random: &mut Random, len: usize, bits: u32, scaling: Scaling) -> Vec<f64> {
+		let scale = full_scale(bits, scaling);
+		let specials = [0.0, 1.0, -1.0, 1.5, -1.5, 0.5 / scale, -2.5 / scale, 1.0 - 0.5 / scale];
+
+		(0..len).map(|index| match index % 5 {
+			0 => specials[(index / 5) % specials.len()],
+			_ => 2.2 * random.next_f64() - 1.1
+		}).collect()
+	}
+
+	#[test]
+	fn vector_encoding_matches_scalar() {
+		let scalar = BulkConverter::with_level(SimdLevel::Scalar);
+		let mut random = Random::new(5);
+
+		for level in vector_levels() {
+			let vector = BulkConverter::with_level(level);
+
+			for sample_type in NATIVE_TYPES {
+				let stride = sample_type.size_in_bytes();
+				let bits = if is_float(sample_type) { 24 } else { significant_bits(sample_type) };
+
+				for scaling in SCALINGS {
+					for len in LENGTHS {
+						let src = test_signal(&mut random, len, bits, scaling);
+						let src_f32: Vec<f32> = src.iter().map(|s| *s as f32).collect();
+
+						let mut expected = vec![0u8; len * stride];
+						let mut actual = vec![0xAAu8; len * stride];
+						scalar.encode_f64(sample_type, scaling, &src, &mut expected);
+						vector.encode_f64(sample_type, scaling, &src, &mut actual);
+						assert_eq!(actual, expected, "{:?} f64 {:?} {:?} {}", level, sample_type, scaling, len);
+
+						scalar.encode_f32(sample_type, scaling, &src_f32, &mut expected);
+						vector.encode_f32(sample_type, scaling, &src_f32, &mut actual);
+						assert_eq!(actual, expected, "{:?} f32 {:?} {:?} {}", level, sample_type, scaling, len);
+					}
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn vector_decoding_matches_scalar() {
+		let scalar = BulkConverter::with_level(SimdLevel::Scalar);
+		let mut random = Random::new(6);
+
+		for level in vector_levels() {
+			let vector = BulkConverter::with_level(level);
+
+			for sample_type in NATIVE_TYPES {
+				for scaling in SCALINGS {
+					for len in LENGTHS {
+						// Any bit pattern, including the unused bits of aligned containers, except
+						// NaNs in float formats, whose payload is not part of the comparison
+						let mut src: Vec<u8> = (0..len * sample_type.size_in_bytes()).map(|_| random.next_u64() as u8).collect();
+						if is_float(sample_type) {
+							for byte in src.iter_mut() {
+								*byte &= 0x3F;
+							}
+						}
+
+						let mut expected = vec![0.0f64; len];
+						let mut actual = vec![f64::NAN; len];
+						scalar.decode_f64(sample_type, scaling, &src, &mut expected);
+						vector.decode_f64(sample_type, scaling, &src, &mut actual);
+						let bits = |values: &[f64]| values.iter().map(|v| v.to_bits()).collect::<Vec<u64>>();
+						assert_eq!(bits(&actual), bits(&expected), "{:?} f64 {:?} {:?} {}", level, sample_type, scaling, len);
+
+						let mut expected = vec![0.0f32; len];
+						let mut actual = vec![f32::NAN; len];
+						scalar.decode_f32(sample_type, scaling, &src, &mut expected);
+						vector.decode_f32(sample_type, scaling, &src, &mut actual);
+						let bits = |values: &[f32]| values.iter().map(|v| v.to_bits()).collect::<Vec<u32>>();
+						assert_eq!(bits(&actual), bits(&expected), "{:?} f32 {:?} {:?} {}", level, sample_type, scaling, len);
+					}
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn scalar_path_matches_sample_conversion() {
+		use crate::asio_core::sample_convert::SampleConvert;
+
+		let scalar = BulkConverter::with_level(SimdLevel::Scalar);
+		let mut random = Random::new(7);
+		let src = test_signal(&mut random, 131, 24, Scaling::Symmetric);
+		let mut bytes = vec![0u8; src.len() * 3];
+
+		scalar.encode_f64(ASIOSampleType::Int24MSB, Scaling::Symmetric, &src, &mut bytes);
+		for (sample, encoded) in src.iter().zip(bytes.chunks(3)) {
+			assert_eq!(Int24MSB::read(encoded), Int24MSB::to_native(*sample, Scaling::Symmetric));
+		}
+
+		let mut decoded = vec![0.0f64; src.len()];
+		scalar.decode_f64(ASIOSampleType::Int24MSB, Scaling::Symmetric, &bytes, &mut decoded);
+		for (value, encoded) in decoded.iter().zip(bytes.chunks(3)) {
+			assert_eq!(*value, Int24MSB::read(encoded).from_native(Scaling::Symmetric));
+		}
+	}
+}
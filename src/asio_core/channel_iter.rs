@@ -1,24 +1,28 @@
+use crate::asio_core::native_sample::NativeSample;
 use std::marker::PhantomData;
+use std::slice;
 
 pub struct ChannelIter<'a, T> {
 	phantom: PhantomData<&'a T>,
-	buffer: *const T,
+	buffer: *const u8,
+	stride: usize,
 	len: usize,
 	pos: usize
 }
 
-impl<'a, T: Copy> ChannelIter<'a, T> {
-	pub fn new(buffer: *const T, len: usize) -> ChannelIter<'a, T> {
+impl<'a, T: NativeSample> ChannelIter<'a, T> {
+	pub fn new(buffer: *const u8, len: usize) -> ChannelIter<'a, T> {
 		ChannelIter { 
 			phantom: PhantomData,
-			buffer: buffer, 
-			len: len, 
+			buffer, 
+			stride: T::SAMPLE_TYPE.size_in_bytes(),
+			len, 
 			pos: 0 
 		}
 	}
 }
 
-impl<'a, T: Copy> Iterator for ChannelIter<'a, T> {
+impl<'a, T: NativeSample> Iterator for ChannelIter<'a, T> {
 	type Item = T;
 
 	fn next(&mut self) -> Option<T> {
@@ -26,7 +30,8 @@ impl<'a, T: Copy> Iterator for ChannelIter<'a, T> {
 			true => {
 				let result;
 				unsafe {
-					result = Some(*self.buffer.offset(self.pos as isize));
+					let bytes = slice::from_raw_parts(self.buffer.add(self.pos * self.stride), self.stride);
+					result = Some(T::read(bytes));
 				}
 				self.pos += 1;
 				result
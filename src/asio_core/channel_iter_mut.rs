@@ -1,32 +1,37 @@
+use crate::asio_core::native_sample::NativeSample;
 use std::marker::PhantomData;
+use std::slice;
 
 pub struct ChannelIterMut<'a, T> {
 	phantom: PhantomData<&'a T>,
-	buffer: *mut T,
+	buffer: *mut u8,
+	stride: usize,
 	len: usize,
 	pos: usize
 }
 
-impl<'a, T: Copy> ChannelIterMut<'a, T> {
-	pub fn new(buffer: *mut T, len: usize) -> ChannelIterMut<'a, T> {
+impl<'a, T: NativeSample> ChannelIterMut<'a, T> {
+	pub fn new(buffer: *mut u8, len: usize) -> ChannelIterMut<'a, T> {
 		ChannelIterMut { 
 			phantom: PhantomData, 
-			buffer: buffer, 
-			len: len, 
+			buffer, 
+			stride: T::SAMPLE_TYPE.size_in_bytes(),
+			len, 
 			pos: 0 
 		}
 	}
 }
 
-impl<'a, T: Copy> Iterator for ChannelIterMut<'a, T> {
-	type Item = *mut T;
+impl<'a, T: NativeSample> Iterator for ChannelIterMut<'a, T> {
+	/// The bytes of a single sample slot, to be filled by `NativeSample::write`
+	type Item = &'a mut [u8];
 
 	fn next(&mut self) -> Option<Self::Item> {
 		match self.pos < self.len {
 			true => {
 				let result;
 				unsafe {
-					result = Some(self.buffer.offset(self.pos as isize));
+					result = Some(slice::from_raw_parts_mut(self.buffer.add(self.pos * self.stride), self.stride));
 				}
 				self.pos += 1;
 				result
@@ -34,4 +39,4 @@ impl<'a, T: Copy> Iterator for ChannelIterMut<'a, T> {
 			false => None
 		}
 	}
-}
\ No newline at end of file
+}
@@ -0,0 +1,110 @@
+use crate::asio_core::sample_convert::Scaling;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Level above which the soft clipping curves start to bend
+const SOFT_CLIP_THRESHOLD: f64 = 0.9;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Clipping {
+	Hard,			// limit to [-1.0, 1.0]
+	SoftTanh,		// bend into a tanh curve above the threshold
+	SoftCubic,		// bend into a cubic curve above the threshold, reaching full scale at 1.05
+	CountOnly		// leave samples unchanged, integer formats still saturate on conversion
+}
+
+impl Clipping {
+	pub fn apply(&self, sample: f64) -> f64 {
+		let magnitude = sample.abs();
+
+		match self {
+			Clipping::Hard => sample.clamp(-1.0, 1.0),
+			Clipping::CountOnly => sample,
+			_ if magnitude <= SOFT_CLIP_THRESHOLD => sample,
+			Clipping::SoftTanh => {
+				let headroom = 1.0 - SOFT_CLIP_THRESHOLD;
+				let excess = (magnitude - SOFT_CLIP_THRESHOLD) / headroom;
+				(SOFT_CLIP_THRESHOLD + headroom * excess.tanh()).copysign(sample)
+			},
+			Clipping::SoftCubic => {
+				// u - 4/27 u^3 has unity slope at 0 and reaches 1.0 with zero slope at u = 1.5
+				let headroom = 1.0 - SOFT_CLIP_THRESHOLD;
+				let excess = ((magnitude - SOFT_CLIP_THRESHOLD) / headroom).min(1.5);
+				let bent = excess - 4.0 / 27.0 * excess * excess * excess;
+				(SOFT_CLIP_THRESHOLD + headroom * bent).copysign(sample)
+			}
+		}
+	}
+}
+
+/// Determines how processed samples are mapped onto the native sample format
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ConversionPolicy {
+	pub scaling: Scaling,
+	pub clipping: Clipping
+}
+
+impl ConversionPolicy {
+	pub const fn new(scaling: Scaling, clipping: Clipping) -> ConversionPolicy {
+		ConversionPolicy { scaling, clipping }
+	}
+}
+
+impl Default for ConversionPolicy {
+	fn default() -> ConversionPolicy {
+		ConversionPolicy::new(Scaling::Symmetric, Clipping::Hard)
+	}
+}
+
+/// Number of samples outside [-1.0, 1.0], shared between the callback and other threads
+#[derive(Clone, Default)]
+pub struct ClipCounter {
+	count: Arc<AtomicU64>
+}
+
+impl ClipCounter {
+	pub fn new() -> ClipCounter {
+		ClipCounter::default()
+	}
+
+	pub fn get(&self) -> u64 {
+		self.count.load(Ordering::Relaxed)
+	}
+
+	/// Returns the current count and starts over from zero
+	pub fn reset(&self) -> u64 {
+		self.count.swap(0, Ordering::Relaxed)
+	}
+
+	pub fn add(&self, clipped: u64) {
+		self.count.fetch_add(clipped, Ordering::Relaxed);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn soft_clipping_curves() {
+		for clipping in [Clipping::SoftTanh, Clipping::SoftCubic] {
+			assert_eq!(clipping.apply(0.5), 0.5);
+			assert_eq!(clipping.apply(-SOFT_CLIP_THRESHOLD), -SOFT_CLIP_THRESHOLD);
+
+			// Monotonic, odd and never beyond full scale
+			let mut previous = 0.0;
+			for index in 1..=400 {
+				let sample = index as f64 * 0.005;
+				let bent = clipping.apply(sample);
+				assert!(bent >= previous && bent <= 1.0, "{:?} {} {}", clipping, sample, bent);
+				assert_eq!(clipping.apply(-sample), -bent);
+				previous = bent;
+			}
+		}
+
+		assert_eq!(Clipping::SoftCubic.apply(1.05), 1.0);
+		assert!(Clipping::SoftCubic.apply(1.04) < 1.0);
+		assert_eq!(Clipping::Hard.apply(-1.5), -1.0);
+		assert_eq!(Clipping::CountOnly.apply(1.5), 1.5);
+	}
+}
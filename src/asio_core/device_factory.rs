@@ -1,18 +1,25 @@
-use crate::asio_core::asio_device::{ASIODevice, ASIODeviceType};
+use crate::asio_core::asio_device::{ASIODevice, ASIODeviceType, DeviceSettings};
 use crate::asio_core::device_singleton::DeviceSingleton;
+use crate::asio_core::native_sample::{
+	Float32MSB, Float64MSB, Int16MSB, Int24LSB, Int24MSB, Int32LSB16, Int32LSB18, Int32LSB20,
+	Int32LSB24, Int32MSB, Int32MSB16, Int32MSB18, Int32MSB20, Int32MSB24, NativeSample,
+};
+use crate::asio_core::sample_convert::SampleConvert;
 use crate::asio_core::{
 	create_device, ASIOBool, ASIOError, ASIOSampleType, BufferInfo, Callbacks, ChannelInfo,
 	DriverInfo, IASIO,
 };
+use crate::dsp::processor::Processor;
+use crate::dsp::sample::Sample;
 
 pub struct DeviceFactory {}
 
 impl DeviceFactory {
-	pub fn create_device(
+	pub fn create_device<S: Sample, P: 'static + Processor<S>>(
 		clsid: com::CLSID,
-		process: fn(input: Vec<Vec<f64>>, outputs: &mut [Vec<f64>]),
+		processor: P,
 	) -> &'static mut dyn ASIODeviceType {
-		DeviceSingleton::new(Box::new(DeviceFactory::open(clsid, process)));
+		DeviceSingleton::new(DeviceFactory::open(clsid, Box::new(processor)));
 		DeviceSingleton::get_device()
 	}
 
@@ -20,10 +27,10 @@ impl DeviceFactory {
 		DeviceSingleton::drop()
 	}
 
-	fn open(
+	fn open<S: Sample>(
 		clsid: com::CLSID,
-		process: fn(input: Vec<Vec<f64>>, outputs: &mut [Vec<f64>]),
-	) -> impl ASIODeviceType {
+		processor: Box<dyn Processor<S>>,
+	) -> Box<dyn ASIODeviceType> {
 		let iasio = match create_device(&clsid) {
 			Ok(value) => value,
 			Err(hr) => panic!("Failed to create ASIO device: 0x{:x}", hr),
@@ -51,19 +58,46 @@ impl DeviceFactory {
 			iasio.get_channel_info(&mut channel_info);
 		}
 
-		match channel_info.sample_type {
-			ASIOSampleType::Int32LSB => ASIODevice::<i32>::new(
-				iasio,
-				driver_name,
-				num_input_channels,
-				num_output_channels,
-				pref_buffer_size,
-				buffer_infos,
-				callbacks,
-				process,
-			),
+		let new_device = match channel_info.sample_type {
+			ASIOSampleType::Int16LSB => DeviceFactory::new_device::<i16, S>,
+			ASIOSampleType::Int24LSB => DeviceFactory::new_device::<Int24LSB, S>,
+			ASIOSampleType::Int32LSB => DeviceFactory::new_device::<i32, S>,
+			ASIOSampleType::Float32LSB => DeviceFactory::new_device::<f32, S>,
+			ASIOSampleType::Float64LSB => DeviceFactory::new_device::<f64, S>,
+			ASIOSampleType::Int16MSB => DeviceFactory::new_device::<Int16MSB, S>,
+			ASIOSampleType::Int24MSB => DeviceFactory::new_device::<Int24MSB, S>,
+			ASIOSampleType::Int32MSB => DeviceFactory::new_device::<Int32MSB, S>,
+			ASIOSampleType::Float32MSB => DeviceFactory::new_device::<Float32MSB, S>,
+			ASIOSampleType::Float64MSB => DeviceFactory::new_device::<Float64MSB, S>,
+			ASIOSampleType::Int32LSB16 => DeviceFactory::new_device::<Int32LSB16, S>,
+			ASIOSampleType::Int32LSB18 => DeviceFactory::new_device::<Int32LSB18, S>,
+			ASIOSampleType::Int32LSB20 => DeviceFactory::new_device::<Int32LSB20, S>,
+			ASIOSampleType::Int32LSB24 => DeviceFactory::new_device::<Int32LSB24, S>,
+			ASIOSampleType::Int32MSB16 => DeviceFactory::new_device::<Int32MSB16, S>,
+			ASIOSampleType::Int32MSB18 => DeviceFactory::new_device::<Int32MSB18, S>,
+			ASIOSampleType::Int32MSB20 => DeviceFactory::new_device::<Int32MSB20, S>,
+			ASIOSampleType::Int32MSB24 => DeviceFactory::new_device::<Int32MSB24, S>,
 			_ => panic!("Unsupported sample type '{:?}'.", channel_info.sample_type),
-		}
+		};
+
+		let settings = DeviceSettings {
+			driver_name,
+			num_input_channels,
+			num_output_channels,
+			pref_buffer_size,
+			buffer_infos,
+		};
+
+		new_device(iasio, settings, callbacks, processor)
+	}
+
+	fn new_device<T: 'static + NativeSample + SampleConvert<Sample = T>, S: Sample>(
+		iasio: IASIO,
+		settings: DeviceSettings,
+		callbacks: Box<Callbacks>,
+		processor: Box<dyn Processor<S>>,
+	) -> Box<dyn ASIODeviceType> {
+		Box::new(ASIODevice::<T, S>::new(iasio, settings, callbacks, processor))
 	}
 
 	fn get_driver_name(iasio: &IASIO) -> String {
@@ -1,29 +1,51 @@
+use crate::asio_core::bulk_convert::BulkConverter;
 use crate::asio_core::channel_iter::ChannelIter;
+use crate::asio_core::native_sample::NativeSample;
+use crate::asio_core::sample_convert::Scaling;
+use crate::dsp::sample::Sample;
+use std::marker::PhantomData;
+use std::slice;
 
 pub struct InputChannel<T> {
 	pub name: String,
-	ptr_a: *const T,
-	ptr_b: *const T,
+	phantom: PhantomData<T>,
+	ptr_a: *const u8,
+	ptr_b: *const u8,
+	pub scaling: Scaling,
+	converter: BulkConverter,
 	len: usize
 }
 
-impl<T: Copy> InputChannel<T> {
-	pub fn new(name: &str, ptr_a: *const T, ptr_b: *const T, len: usize) -> InputChannel<T> {
+impl<T: NativeSample> InputChannel<T> {
+	pub fn new(name: &str, ptr_a: *const u8, ptr_b: *const u8, len: usize) -> InputChannel<T> {
 		InputChannel {
 			name: String::from(name),
+			phantom: PhantomData,
 			ptr_a: ptr_a,
 			ptr_b: ptr_b,
+			scaling: Scaling::Symmetric,
+			converter: BulkConverter::new(),
 			len: len
 		}
 	}
 
 	pub fn iter(&mut self, double_buffer_index: i32) -> ChannelIter<T> {
+		ChannelIter::<T>::new(self.current_buffer(double_buffer_index), self.len)
+	}
+
+	/// Converts the current input buffer into `samples` in one go
+	pub fn read_samples<S: Sample>(&self, double_buffer_index: i32, samples: &mut [S]) {
+		let size = self.len * T::SAMPLE_TYPE.size_in_bytes();
+		let source = unsafe { slice::from_raw_parts(self.current_buffer(double_buffer_index), size) };
+
+		S::decode(&self.converter, T::SAMPLE_TYPE, self.scaling, source, &mut samples[..self.len]);
+	}
+
+	fn current_buffer(&self, double_buffer_index: i32) -> *const u8 {
 		let read_second_half = double_buffer_index == 0;
-		let ptr_current = match read_second_half {
+		match read_second_half {
 			true => self.ptr_b,
 			false => self.ptr_a
-		};
-		ChannelIter::<T>::new(ptr_current, self.len)
+		}
 	}
 }
-
@@ -1,10 +1,16 @@
 //pub mod sample_buffer;
 pub mod asio_device;
+pub mod bulk_convert;
+pub mod conversion_policy;
 pub mod device_factory;
 pub mod device_singleton;
 pub mod input_channel;
+pub mod native_sample;
 pub mod output_channel;
+pub mod quantizer;
+pub mod random;
 pub mod sample_convert;
+pub mod time;
 pub mod channel_iter;
 pub mod channel_iter_mut;
 
@@ -63,7 +69,7 @@ pub enum ASIOSampleType {
 
 	// these are used for 32 bit data buffer, with different alignment of the data inside
 	// 32 bit PCI bus systems can more easily used with these
-	Int32LSB16 = 24,		// 32 bit data with 18 bit alignment
+	Int32LSB16 = 24,		// 32 bit data with 16 bit alignment
 	Int32LSB18 = 25,		// 32 bit data with 18 bit alignment
 	Int32LSB20 = 26,		// 32 bit data with 20 bit alignment
 	Int32LSB24 = 27,		// 32 bit data with 24 bit alignment
@@ -96,7 +102,7 @@ impl ASIOSampleType {
 			ASIOSampleType::Float32LSB => 4,		// IEEE 754 32 bit float, as found on Intel x86 architecture
 			ASIOSampleType::Float64LSB => 8, 		// IEEE 754 64 bit double float, as found on Intel x86 architecture
 		
-			ASIOSampleType::Int32LSB16 => 2,		// 32 bit data with 18 bit alignment
+			ASIOSampleType::Int32LSB16 => 4,		// 32 bit data with 16 bit alignment
 			ASIOSampleType::Int32LSB18 => 4,		// 32 bit data with 18 bit alignment
 			ASIOSampleType::Int32LSB20 => 4,		// 32 bit data with 20 bit alignment
 			ASIOSampleType::Int32LSB24 => 4,		// 32 bit data with 24 bit alignment
@@ -110,18 +116,6 @@ impl ASIOSampleType {
 	}
 }
 
-#[allow(dead_code)]
-pub fn sample_count(sample_rate: f64, ms: f64) -> f64 {
-	(sample_rate * ms + 1.0) / 1000.0
-}
-
-#[allow(dead_code)]
-pub fn buffer_size(sample_rate: f64, ms: f64, sample_type: ASIOSampleType) -> i64 {
-	let sample_size = sample_type.size_in_bytes() as f64;
-
-	(sample_count(sample_rate, ms) * sample_size) as i64
-}
-
 #[repr(C)]
 #[derive(Copy, Clone)]
 pub struct DriverInfo {
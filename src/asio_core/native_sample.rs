@@ -0,0 +1,258 @@
+use crate::asio_core::ASIOSampleType;
+
+/// Describes how a single sample is stored in the driver's buffers
+pub trait NativeSample: Copy {
+	/// The ASIO sample type, which also determines the byte stride within a buffer
+	const SAMPLE_TYPE: ASIOSampleType;
+
+	/// Decodes a sample from exactly `SAMPLE_TYPE.size_in_bytes()` bytes
+	fn read(bytes: &[u8]) -> Self;
+
+	/// Encodes a sample into exactly `SAMPLE_TYPE.size_in_bytes()` bytes
+	fn write(self, bytes: &mut [u8]);
+}
+
+/// 16 bit sample, most significant byte first
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Int16MSB(pub i16);
+
+/// 32 bit sample, most significant byte first
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Int32MSB(pub i32);
+
+/// IEEE 754 32 bit float, most significant byte first
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Float32MSB(pub f32);
+
+/// IEEE 754 64 bit float, most significant byte first
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Float64MSB(pub f64);
+
+/// 32 bit container with 16 significant bits, most significant byte first
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Int32MSB16(pub i32);
+
+/// 32 bit container with 18 significant bits, most significant byte first
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Int32MSB18(pub i32);
+
+/// 32 bit container with 20 significant bits, most significant byte first
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Int32MSB20(pub i32);
+
+/// 32 bit container with 24 significant bits, most significant byte first
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Int32MSB24(pub i32);
+
+/// 32 bit container with 16 significant bits, least significant byte first
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Int32LSB16(pub i32);
+
+/// 32 bit container with 18 significant bits, least significant byte first
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Int32LSB18(pub i32);
+
+/// 32 bit container with 20 significant bits, least significant byte first
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Int32LSB20(pub i32);
+
+/// 32 bit container with 24 significant bits, least significant byte first
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Int32LSB24(pub i32);
+
+/// Packed 3 byte sample, least significant byte first. Holds the sign extended value.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Int24LSB(pub i32);
+
+/// Packed 3 byte sample, most significant byte first. Holds the sign extended value.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Int24MSB(pub i32);
+
+/// Implements `NativeSample` for a primitive or a newtype stored least significant byte first
+macro_rules! little_endian_sample {
+	($name:ident, $inner:ty, $sample_type:ident) => {
+		impl NativeSample for $name {
+			const SAMPLE_TYPE: ASIOSampleType = ASIOSampleType::$sample_type;
+
+			fn read(bytes: &[u8]) -> Self {
+				let mut raw = [0u8; std::mem::size_of::<$inner>()];
+				raw.copy_from_slice(bytes);
+				$name(<$inner>::from_le_bytes(raw))
+			}
+
+			fn write(self, bytes: &mut [u8]) {
+				bytes.copy_from_slice(&self.0.to_le_bytes());
+			}
+		}
+	};
+	($type:ty, $sample_type:ident) => {
+		impl NativeSample for $type {
+			const SAMPLE_TYPE: ASIOSampleType = ASIOSampleType::$sample_type;
+
+			fn read(bytes: &[u8]) -> Self {
+				let mut raw = [0u8; std::mem::size_of::<$type>()];
+				raw.copy_from_slice(bytes);
+				<$type>::from_le_bytes(raw)
+			}
+
+			fn write(self, bytes: &mut [u8]) {
+				bytes.copy_from_slice(&self.to_le_bytes());
+			}
+		}
+	};
+}
+
+/// Implements `NativeSample` for a newtype whose value is stored most significant byte first
+macro_rules! big_endian_sample {
+	($name:ident, $inner:ty, $sample_type:ident) => {
+		impl NativeSample for $name {
+			const SAMPLE_TYPE: ASIOSampleType = ASIOSampleType::$sample_type;
+
+			fn read(bytes: &[u8]) -> Self {
+				let mut raw = [0u8; std::mem::size_of::<$inner>()];
+				raw.copy_from_slice(bytes);
+				$name(<$inner>::from_be_bytes(raw))
+			}
+
+			fn write(self, bytes: &mut [u8]) {
+				bytes.copy_from_slice(&self.0.to_be_bytes());
+			}
+		}
+	};
+}
+
+little_endian_sample!(i16, Int16LSB);
+little_endian_sample!(i32, Int32LSB);
+little_endian_sample!(f32, Float32LSB);
+little_endian_sample!(f64, Float64LSB);
+little_endian_sample!(Int32LSB16, i32, Int32LSB16);
+little_endian_sample!(Int32LSB18, i32, Int32LSB18);
+little_endian_sample!(Int32LSB20, i32, Int32LSB20);
+little_endian_sample!(Int32LSB24, i32, Int32LSB24);
+
+big_endian_sample!(Int16MSB, i16, Int16MSB);
+big_endian_sample!(Int32MSB, i32, Int32MSB);
+big_endian_sample!(Float32MSB, f32, Float32MSB);
+big_endian_sample!(Float64MSB, f64, Float64MSB);
+big_endian_sample!(Int32MSB16, i32, Int32MSB16);
+big_endian_sample!(Int32MSB18, i32, Int32MSB18);
+big_endian_sample!(Int32MSB20, i32, Int32MSB20);
+big_endian_sample!(Int32MSB24, i32, Int32MSB24);
+
+impl NativeSample for Int24LSB {
+	const SAMPLE_TYPE: ASIOSampleType = ASIOSampleType::Int24LSB;
+
+	fn read(bytes: &[u8]) -> Self {
+		// Place the 24 bits in the upper part of an i32 and shift back to sign extend
+		Int24LSB(i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8)
+	}
+
+	fn write(self, bytes: &mut [u8]) {
+		let raw = (self.0 << 8).to_le_bytes();
+		bytes.copy_from_slice(&raw[1..4]);
+	}
+}
+
+impl NativeSample for Int24MSB {
+	const SAMPLE_TYPE: ASIOSampleType = ASIOSampleType::Int24MSB;
+
+	fn read(bytes: &[u8]) -> Self {
+		Int24MSB(i32::from_be_bytes([bytes[0], bytes[1], bytes[2], 0]) >> 8)
+	}
+
+	fn write(self, bytes: &mut [u8]) {
+		let raw = (self.0 << 8).to_be_bytes();
+		bytes.copy_from_slice(&raw[0..3]);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::fmt::Debug;
+
+	/// Checks that `value` encodes to exactly `bytes` and decodes back from them
+	fn assert_round_trip<T: NativeSample + PartialEq + Debug>(value: T, bytes: &[u8]) {
+		assert_eq!(bytes.len(), T::SAMPLE_TYPE.size_in_bytes());
+
+		let mut written = vec![0xAAu8; bytes.len()];
+		value.write(&mut written);
+		assert_eq!(written, bytes, "{:?} encoded", value);
+		assert_eq!(T::read(bytes), value, "{:02X?} decoded", bytes);
+	}
+
+	#[test]
+	fn packed_24_bit() {
+		assert_round_trip(Int24LSB(0), &[0x00, 0x00, 0x00]);
+		assert_round_trip(Int24LSB(0x7FFFFF), &[0xFF, 0xFF, 0x7F]);
+		assert_round_trip(Int24LSB(-0x800000), &[0x00, 0x00, 0x80]);
+		assert_round_trip(Int24LSB(-1), &[0xFF, 0xFF, 0xFF]);
+		assert_round_trip(Int24LSB(0x123456), &[0x56, 0x34, 0x12]);
+
+		assert_round_trip(Int24MSB(0), &[0x00, 0x00, 0x00]);
+		assert_round_trip(Int24MSB(0x7FFFFF), &[0x7F, 0xFF, 0xFF]);
+		assert_round_trip(Int24MSB(-0x800000), &[0x80, 0x00, 0x00]);
+		assert_round_trip(Int24MSB(-1), &[0xFF, 0xFF, 0xFF]);
+		assert_round_trip(Int24MSB(0x123456), &[0x12, 0x34, 0x56]);
+	}
+
+	#[test]
+	fn integers() {
+		assert_round_trip(0i16, &[0x00, 0x00]);
+		assert_round_trip(i16::MAX, &[0xFF, 0x7F]);
+		assert_round_trip(i16::MIN, &[0x00, 0x80]);
+		assert_round_trip(Int16MSB(0), &[0x00, 0x00]);
+		assert_round_trip(Int16MSB(i16::MAX), &[0x7F, 0xFF]);
+		assert_round_trip(Int16MSB(i16::MIN), &[0x80, 0x00]);
+
+		assert_round_trip(0i32, &[0x00, 0x00, 0x00, 0x00]);
+		assert_round_trip(i32::MAX, &[0xFF, 0xFF, 0xFF, 0x7F]);
+		assert_round_trip(i32::MIN, &[0x00, 0x00, 0x00, 0x80]);
+		assert_round_trip(0x12345678i32, &[0x78, 0x56, 0x34, 0x12]);
+		assert_round_trip(Int32MSB(0), &[0x00, 0x00, 0x00, 0x00]);
+		assert_round_trip(Int32MSB(i32::MAX), &[0x7F, 0xFF, 0xFF, 0xFF]);
+		assert_round_trip(Int32MSB(i32::MIN), &[0x80, 0x00, 0x00, 0x00]);
+		assert_round_trip(Int32MSB(0x12345678), &[0x12, 0x34, 0x56, 0x78]);
+	}
+
+	#[test]
+	fn aligned_containers() {
+		// The significant bits sit in the low part of the container, sign extended
+		assert_round_trip(Int32LSB16(0x7FFF), &[0xFF, 0x7F, 0x00, 0x00]);
+		assert_round_trip(Int32LSB16(-0x8000), &[0x00, 0x80, 0xFF, 0xFF]);
+		assert_round_trip(Int32LSB18(0x1FFFF), &[0xFF, 0xFF, 0x01, 0x00]);
+		assert_round_trip(Int32LSB18(-0x20000), &[0x00, 0x00, 0xFE, 0xFF]);
+		assert_round_trip(Int32LSB20(0x7FFFF), &[0xFF, 0xFF, 0x07, 0x00]);
+		assert_round_trip(Int32LSB20(-0x80000), &[0x00, 0x00, 0xF8, 0xFF]);
+		assert_round_trip(Int32LSB24(0x7FFFFF), &[0xFF, 0xFF, 0x7F, 0x00]);
+		assert_round_trip(Int32LSB24(-0x800000), &[0x00, 0x00, 0x80, 0xFF]);
+		assert_round_trip(Int32LSB24(0), &[0x00, 0x00, 0x00, 0x00]);
+
+		assert_round_trip(Int32MSB16(0x7FFF), &[0x00, 0x00, 0x7F, 0xFF]);
+		assert_round_trip(Int32MSB16(-0x8000), &[0xFF, 0xFF, 0x80, 0x00]);
+		assert_round_trip(Int32MSB18(0x1FFFF), &[0x00, 0x01, 0xFF, 0xFF]);
+		assert_round_trip(Int32MSB18(-0x20000), &[0xFF, 0xFE, 0x00, 0x00]);
+		assert_round_trip(Int32MSB20(0x7FFFF), &[0x00, 0x07, 0xFF, 0xFF]);
+		assert_round_trip(Int32MSB20(-0x80000), &[0xFF, 0xF8, 0x00, 0x00]);
+		assert_round_trip(Int32MSB24(0x7FFFFF), &[0x00, 0x7F, 0xFF, 0xFF]);
+		assert_round_trip(Int32MSB24(-0x800000), &[0xFF, 0x80, 0x00, 0x00]);
+		assert_round_trip(Int32MSB24(0), &[0x00, 0x00, 0x00, 0x00]);
+	}
+
+	#[test]
+	fn floats() {
+		assert_round_trip(0.0f32, &[0x00, 0x00, 0x00, 0x00]);
+		assert_round_trip(1.0f32, &[0x00, 0x00, 0x80, 0x3F]);
+		assert_round_trip(-1.0f32, &[0x00, 0x00, 0x80, 0xBF]);
+		assert_round_trip(Float32MSB(0.0), &[0x00, 0x00, 0x00, 0x00]);
+		assert_round_trip(Float32MSB(1.0), &[0x3F, 0x80, 0x00, 0x00]);
+		assert_round_trip(Float32MSB(-1.0), &[0xBF, 0x80, 0x00, 0x00]);
+
+		assert_round_trip(0.0f64, &[0x00; 8]);
+		assert_round_trip(1.0f64, &[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xF0, 0x3F]);
+		assert_round_trip(-0.5f64, &[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xE0, 0xBF]);
+		assert_round_trip(Float64MSB(0.0), &[0x00; 8]);
+		assert_round_trip(Float64MSB(1.0), &[0x3F, 0xF0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+		assert_round_trip(Float64MSB(-0.5), &[0xBF, 0xE0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+	}
+}
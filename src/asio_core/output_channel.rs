@@ -1,29 +1,44 @@
+use crate::asio_core::bulk_convert::BulkConverter;
 use crate::asio_core::channel_iter_mut::ChannelIterMut;
+use crate::asio_core::conversion_policy::{ClipCounter, ConversionPolicy};
+use crate::asio_core::native_sample::NativeSample;
+use crate::asio_core::quantizer::{Quantization, Quantizer};
+use crate::asio_core::sample_convert::SampleConvert;
+use crate::dsp::sample::Sample;
+use std::marker::PhantomData;
+use std::slice;
 
-pub struct OutputChannel<T> {
+pub struct OutputChannel<T, S> {
 	pub name: String,
-	pub ptr_a: *mut T,
-	pub ptr_b: *mut T,
+	phantom: PhantomData<T>,
+	pub ptr_a: *mut u8,
+	pub ptr_b: *mut u8,
+	pub quantizer: Quantizer,
+	pub policy: ConversionPolicy,
+	pub clip_counter: ClipCounter,
+	converter: BulkConverter,
+	limited: Vec<S>,
 	len: usize
 }
 
-impl<T: Copy> OutputChannel<T> {
-	pub fn new(name: &str, ptr_a: *mut T, ptr_b: *mut T, len: usize) -> OutputChannel<T> {
+impl<T: NativeSample, S: Sample> OutputChannel<T, S> {
+	pub fn new(name: &str, ptr_a: *mut u8, ptr_b: *mut u8, len: usize, seed: u64) -> OutputChannel<T, S> {
 		OutputChannel {
 			name: String::from(name),
+			phantom: PhantomData,
 			ptr_a: ptr_a,
 			ptr_b: ptr_b,
+			quantizer: Quantizer::new(Quantization::Round, seed),
+			policy: ConversionPolicy::default(),
+			clip_counter: ClipCounter::new(),
+			converter: BulkConverter::new(),
+			limited: vec![S::ZERO; len],
 			len: len,
 		}
 	}
 
 	pub fn iter_mut(&mut self, double_buffer_index: i32) -> ChannelIterMut<T> {
-		let write_second_half = double_buffer_index != 0;
-		let ptr_current = match write_second_half {
-			true => self.ptr_b,
-			false => self.ptr_a
-		};
-		ChannelIterMut::new(ptr_current, self.len)
+		ChannelIterMut::new(self.current_buffer(double_buffer_index), self.len)
 	}
 
 	pub fn write(&mut self, double_buffer_index: i32, native_samples : &mut impl Iterator<Item = T>) {
@@ -32,14 +47,90 @@ impl<T: Copy> OutputChannel<T> {
 
 		for native_sample in native_samples.take(avail) {
 			match target.next() {
-				Some(t) => {
-					unsafe {
-						*t = native_sample;
-					}
-				},
+				Some(t) => native_sample.write(t),
 				None => break
 			}
 		}
 	}
+
+	fn current_buffer(&self, double_buffer_index: i32) -> *mut u8 {
+		let write_second_half = double_buffer_index != 0;
+		match write_second_half {
+			true => self.ptr_b,
+			false => self.ptr_a
+		}
+	}
 }
 
+impl<T: NativeSample + SampleConvert<Sample = T>, S: Sample> OutputChannel<T, S> {
+	/// Clips, quantizes and converts the processed samples into the current output buffer
+	pub fn write_samples(&mut self, double_buffer_index: i32, samples: &[S]) {
+		let count = samples.len().min(self.len);
+		let samples = &samples[..count];
+		let scaling = self.policy.scaling;
+		let clipping = self.policy.clipping;
+		let target = self.current_buffer(double_buffer_index);
+
+		let clipped = samples.iter().filter(|s| s.abs() > S::ONE).count() as u64;
+
+		// Touch the shared counter once per block only
+		if clipped > 0 {
+			self.clip_counter.add(clipped);
+		}
+
+		match self.quantizer.mode() {
+			Quantization::Round => {
+				// Rounding needs no state between samples, so the whole block is converted at once
+				let limited = &mut self.limited[..count];
+				for (limit, sample) in limited.iter_mut().zip(samples) {
+					*limit = S::from_f64(clipping.apply(sample.to_f64()));
+				}
+
+				let size = count * T::SAMPLE_TYPE.size_in_bytes();
+				let bytes = unsafe { slice::from_raw_parts_mut(target, size) };
+				S::encode(&self.converter, T::SAMPLE_TYPE, scaling, limited, bytes);
+			},
+			_ => {
+				for (slot, sample) in ChannelIterMut::<T>::new(target, count).zip(samples) {
+					let quantized = self.quantizer.quantize(clipping.apply(sample.to_f64()), T::BITS, scaling);
+					T::to_native(quantized, scaling).write(slot);
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::asio_core::conversion_policy::Clipping;
+	use crate::asio_core::sample_convert::Scaling;
+
+	fn decode(buffer: &[u8]) -> Vec<i16> {
+		buffer.chunks(2).map(i16::read).collect()
+	}
+
+	#[test]
+	fn counts_clipped_samples() {
+		let mut buffer = vec![0u8; 2 * 6];
+		let mut channel = OutputChannel::<i16, f32>::new("Out", buffer.as_mut_ptr(), buffer.as_mut_ptr(), 6, 1);
+		let counter = channel.clip_counter.clone();
+
+		channel.write_samples(0, &[0.5, 1.5, -2.0, 1.0, -1.0, 0.0]);
+		assert_eq!(counter.get(), 2);
+		assert_eq!(decode(&buffer), [16384, i16::MAX, -i16::MAX, i16::MAX, -i16::MAX, 0]);
+
+		// Counts accumulate over blocks until they are reset
+		channel.write_samples(0, &[1.01, 0.0, 0.0, 0.0, 0.0, -1.01]);
+		assert_eq!(counter.get(), 4);
+		assert_eq!(counter.reset(), 4);
+		assert_eq!(counter.get(), 0);
+
+		// Only counting leaves the saturation to the conversion, which clips either way
+		channel.policy = ConversionPolicy::new(Scaling::Symmetric, Clipping::CountOnly);
+		channel.quantizer.set_mode(Quantization::Dither);
+		channel.write_samples(0, &[3.0, -3.0, 0.0, 0.0, 0.0, 0.0]);
+		assert_eq!(counter.get(), 2);
+		assert_eq!(&decode(&buffer)[..2], [i16::MAX, -i16::MAX]);
+	}
+}
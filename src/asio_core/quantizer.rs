@@ -0,0 +1,165 @@
+use crate::asio_core::random::Random;
+use crate::asio_core::sample_convert::{full_scale, Scaling};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Quantization {
+	Truncate,				// drop the fractional part (rounds towards zero)
+	Round,					// round to the nearest step, ties to even
+	Dither,					// add triangular (TPDF) dither of +/- 1 LSB before rounding
+	NoiseShapedFirstOrder,	// TPDF dither with first order error feedback, 1 - z^-1
+	NoiseShapedSecondOrder	// TPDF dither with second order error feedback, (1 - z^-1)^2
+}
+
+/// Reduces the resolution of output samples to the integer grid of the target format
+pub struct Quantizer {
+	mode: Quantization,
+	random: Random,
+	error: [f64; 2]
+}
+
+impl Quantizer {
+	pub fn new(mode: Quantization, seed: u64) -> Quantizer {
+		Quantizer {
+			mode,
+			random: Random::new(seed),
+			error: [0.0; 2]
+		}
+	}
+
+	pub fn mode(&self) -> Quantization {
+		self.mode
+	}
+
+	pub fn set_mode(&mut self, mode: Quantization) {
+		self.mode = mode;
+		self.error = [0.0; 2];
+	}
+
+	/// Restarts the dither sequence, so that the output can be reproduced
+	pub fn seed(&mut self, seed: u64) {
+		self.random = Random::new(seed);
+		self.error = [0.0; 2];
+	}
+
+	/// Moves `sample` onto the grid of a signed integer with `bits` significant bits.
+	/// Floating point formats (`bits == 0`) are passed through unchanged.
+	pub fn quantize(&mut self, sample: f64, bits: u32, scaling: Scaling) -> f64 {
+		if bits == 0 {
+			return sample;
+		}
+		let scale = full_scale(bits, scaling);
+		let value = sample * scale;
+
+		let quantized = match self.mode {
+			Quantization::Truncate => value.trunc(),
+			Quantization::Round => value.round_ties_even(),
+			Quantization::Dither => (value + self.random.next_triangular()).round(),
+			Quantization::NoiseShapedFirstOrder => {
+				let shaped = value - self.error[0];
+				let quantized = (shaped + self.random.next_triangular()).round();
+				self.error[0] = quantized - shaped;
+				quantized
+			},
+			Quantization::NoiseShapedSecondOrder => {
+				let shaped = value - 2.0 * self.error[0] + self.error[1];
+				let quantized = (shaped + self.random.next_triangular()).round();
+				self.error[1] = self.error[0];
+				self.error[0] = quantized - shaped;
+				quantized
+			}
+		};
+		quantized / scale
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::asio_core::sample_convert::full_scale;
+
+	const MODES: [Quantization; 5] = [
+		Quantization::Truncate,
+		Quantization::Round,
+		Quantization::Dither,
+		Quantization::NoiseShapedFirstOrder,
+		Quantization::NoiseShapedSecondOrder
+	];
+
+	/// Slowly varying test signal well inside full scale
+	fn signal(index: usize) -> f64 {
+		0.7 * (index as f64 * 0.01).sin() + 0.001 * (index as f64 * 0.37).cos()
+	}
+
+	#[test]
+	fn output_is_on_the_integer_grid() {
+		for mode in MODES {
+			let mut quantizer = Quantizer::new(mode, 1);
+			let scale = full_scale(16, Scaling::Symmetric);
+
+			for index in 0..10_000 {
+				let steps = quantizer.quantize(signal(index), 16, Scaling::Symmetric) * scale;
+				assert!((steps - steps.round()).abs() < 1e-9, "{:?} {}", mode, steps);
+			}
+		}
+	}
+
+	#[test]
+	fn truncate_and_round() {
+		let mut quantizer = Quantizer::new(Quantization::Truncate, 1);
+		let step = 1.0 / full_scale(16, Scaling::Symmetric);
+		assert_eq!(quantizer.quantize(2.7 * step, 16, Scaling::Symmetric), 2.0 * step);
+		assert_eq!(quantizer.quantize(-2.7 * step, 16, Scaling::Symmetric), -2.0 * step);
+
+		quantizer.set_mode(Quantization::Round);
+		assert_eq!(quantizer.quantize(2.7 * step, 16, Scaling::Symmetric), 3.0 * step);
+		assert_eq!(quantizer.quantize(2.5 * step, 16, Scaling::Symmetric), 2.0 * step);
+		assert_eq!(quantizer.quantize(3.5 * step, 16, Scaling::Symmetric), 4.0 * step);
+
+		// Floating point formats pass through
+		assert_eq!(quantizer.quantize(0.123456789, 0, Scaling::Symmetric), 0.123456789);
+	}
+
+	#[test]
+	fn tpdf_dither_stays_within_one_lsb() {
+		let mut quantizer = Quantizer::new(Quantization::Dither, 3);
+		let scale = full_scale(16, Scaling::Symmetric);
+		let mut error_sum = 0.0;
+		let count = 100_000;
+
+		for index in 0..count {
+			let value = signal(index) * scale;
+			let steps = quantizer.quantize(signal(index), 16, Scaling::Symmetric) * scale;
+
+			// The dither moves the value by less than 1 LSB, rounding by at most another half
+			let error = steps - value;
+			assert!(error.abs() < 1.5, "{} -> {}", value, steps);
+			error_sum += error;
+		}
+
+		// The error is independent of the signal, without a bias
+		assert!((error_sum / count as f64).abs() < 0.01);
+
+		// A level between two steps is reproduced on average instead of being rounded away
+		let level = 0.25 / scale;
+		let average = (0..count).map(|_| quantizer.quantize(level, 16, Scaling::Symmetric)).sum::<f64>() / count as f64;
+		assert!((average * scale - 0.25).abs() < 0.01, "{}", average * scale);
+	}
+
+	#[test]
+	fn dither_is_deterministic_per_seed() {
+		for mode in [Quantization::Dither, Quantization::NoiseShapedFirstOrder, Quantization::NoiseShapedSecondOrder] {
+			let run = |quantizer: &mut Quantizer| -> Vec<f64> {
+				(0..1000).map(|index| quantizer.quantize(signal(index), 16, Scaling::Symmetric)).collect()
+			};
+
+			let mut quantizer = Quantizer::new(mode, 11);
+			let first = run(&mut quantizer);
+			assert_eq!(run(&mut Quantizer::new(mode, 11)), first);
+			assert_ne!(run(&mut Quantizer::new(mode, 12)), first);
+
+			// Reseeding restarts the sequence and clears the error feedback
+			quantizer.seed(11);
+			assert_eq!(run(&mut quantizer), first);
+		}
+	}
+}
@@ -0,0 +1,82 @@
+/// Small deterministic pseudo random number generator (xorshift64*)
+pub struct Random {
+	state: u64
+}
+
+impl Random {
+	pub fn new(seed: u64) -> Random {
+		// The all-zero state is a fixed point of xorshift, so it is replaced by an arbitrary constant
+		let state = match seed {
+			0 => 0x9E3779B97F4A7C15,
+			_ => seed
+		};
+		Random { state }
+	}
+
+	pub fn next_u64(&mut self) -> u64 {
+		let mut x = self.state;
+		x ^= x >> 12;
+		x ^= x << 25;
+		x ^= x >> 27;
+		self.state = x;
+		x.wrapping_mul(0x2545F4914F6CDD1D)
+	}
+
+	/// Uniformly distributed value in [0.0, 1.0)
+	pub fn next_f64(&mut self) -> f64 {
+		(self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+	}
+
+	/// Triangular distributed value in (-1.0, 1.0), the sum of two uniform values
+	pub fn next_triangular(&mut self) -> f64 {
+		self.next_f64() - self.next_f64()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn sequence_depends_on_seed_only() {
+		let mut a = Random::new(42);
+		let mut b = Random::new(42);
+		let mut c = Random::new(43);
+		let first: Vec<u64> = (0..100).map(|_| a.next_u64()).collect();
+
+		assert!(first.iter().all(|value| *value == b.next_u64()));
+		assert!(first.iter().any(|value| *value != c.next_u64()));
+	}
+
+	#[test]
+	fn zero_seed_is_usable() {
+		let mut random = Random::new(0);
+		let values: Vec<u64> = (0..4).map(|_| random.next_u64()).collect();
+
+		assert!(values.iter().all(|value| *value != 0));
+		assert_ne!(values[0], values[1]);
+	}
+
+	#[test]
+	fn distributions() {
+		let mut random = Random::new(7);
+		let count = 100_000;
+		let (mut sum, mut sum_squares) = (0.0, 0.0);
+
+		for _ in 0..count {
+			let uniform = random.next_f64();
+			assert!((0.0..1.0).contains(&uniform));
+
+			let triangular = random.next_triangular();
+			assert!(triangular > -1.0 && triangular < 1.0);
+			sum += triangular;
+			sum_squares += triangular * triangular;
+		}
+
+		// Triangular on (-1, 1) has mean 0 and variance 1/6
+		let mean = sum / count as f64;
+		let variance = sum_squares / count as f64 - mean * mean;
+		assert!(mean.abs() < 0.01, "mean {}", mean);
+		assert!((variance - 1.0 / 6.0).abs() < 0.005, "variance {}", variance);
+	}
+}
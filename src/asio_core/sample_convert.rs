@@ -1,34 +1,213 @@
-const MAX_I32_VALUE: f64 = 2147483647.0f64;
-//const PAN_LEFT: f64 = -1.0f64;
-//const PAN_RIGHT: f64 = 1.0f64;
+use crate::asio_core::native_sample::{
+	Float32MSB, Float64MSB, Int16MSB, Int24LSB, Int24MSB, Int32LSB16, Int32LSB18, Int32LSB20,
+	Int32LSB24, Int32MSB, Int32MSB16, Int32MSB18, Int32MSB20, Int32MSB24,
+};
+
+/// Maps full scale [-1.0, 1.0] onto signed integers, floating point formats are not scaled
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Scaling {
+	Symmetric,		// full scale is 2^(n-1) - 1 either way, the most negative code reads as -1.0
+	PowerOfTwo		// full scale is 2^(n-1), -1.0 reaches the most negative code and 1.0 clips
+}
 
 pub trait SampleConvert {
 	type Sample;
 
-	fn from_native(self) -> f64;
-	fn to_native(sample: f64) -> Self::Sample;
+	/// Number of significant bits of integer formats, 0 for floating point formats
+	const BITS: u32;
+
+	fn from_native(self, scaling: Scaling) -> f64;
+	fn to_native(sample: f64, scaling: Scaling) -> Self::Sample;
+}
+
+impl SampleConvert for i16 {
+	type Sample = i16;
+	const BITS: u32 = 16;
+
+	fn from_native(self, scaling: Scaling) -> f64 {
+		int_from_native(self as i32, 16, scaling)
+	}
+
+	fn to_native(sample: f64, scaling: Scaling) -> Self::Sample {
+		int_to_native(sample, 16, scaling) as Self::Sample
+	}
 }
 
 impl SampleConvert for i32 {
 	type Sample = i32;
+	const BITS: u32 = 32;
 
-	fn from_native(self) -> f64 {
-		(self as f64) / MAX_I32_VALUE
+	fn from_native(self, scaling: Scaling) -> f64 {
+		int_from_native(self, 32, scaling)
 	}
 
-	fn to_native(sample: f64) -> Self::Sample {
-		(sample * MAX_I32_VALUE) as Self::Sample
+	fn to_native(sample: f64, scaling: Scaling) -> Self::Sample {
+		int_to_native(sample, 32, scaling)
+	}
+}
+
+impl SampleConvert for f32 {
+	type Sample = f32;
+	const BITS: u32 = 0;
+
+	fn from_native(self, _scaling: Scaling) -> f64 {
+		self as f64
+	}
+
+	fn to_native(sample: f64, _scaling: Scaling) -> Self::Sample {
+		sample as f32
 	}
 }
 
 impl SampleConvert for f64 {
 	type Sample = f64;
+	const BITS: u32 = 0;
 
-	fn from_native(self) -> f64 {
+	fn from_native(self, _scaling: Scaling) -> f64 {
 		self
 	}
 
-	fn to_native(sample: f64) -> Self::Sample {
+	fn to_native(sample: f64, _scaling: Scaling) -> Self::Sample {
 		sample
 	}
 }
+
+impl SampleConvert for Int24LSB {
+	type Sample = Int24LSB;
+	const BITS: u32 = 24;
+
+	fn from_native(self, scaling: Scaling) -> f64 {
+		int_from_native(self.0, 24, scaling)
+	}
+
+	fn to_native(sample: f64, scaling: Scaling) -> Self::Sample {
+		Int24LSB(int_to_native(sample, 24, scaling))
+	}
+}
+
+impl SampleConvert for Int24MSB {
+	type Sample = Int24MSB;
+	const BITS: u32 = 24;
+
+	fn from_native(self, scaling: Scaling) -> f64 {
+		int_from_native(self.0, 24, scaling)
+	}
+
+	fn to_native(sample: f64, scaling: Scaling) -> Self::Sample {
+		Int24MSB(int_to_native(sample, 24, scaling))
+	}
+}
+
+impl SampleConvert for Int16MSB {
+	type Sample = Int16MSB;
+	const BITS: u32 = 16;
+
+	fn from_native(self, scaling: Scaling) -> f64 {
+		self.0.from_native(scaling)
+	}
+
+	fn to_native(sample: f64, scaling: Scaling) -> Self::Sample {
+		Int16MSB(i16::to_native(sample, scaling))
+	}
+}
+
+impl SampleConvert for Int32MSB {
+	type Sample = Int32MSB;
+	const BITS: u32 = 32;
+
+	fn from_native(self, scaling: Scaling) -> f64 {
+		self.0.from_native(scaling)
+	}
+
+	fn to_native(sample: f64, scaling: Scaling) -> Self::Sample {
+		Int32MSB(i32::to_native(sample, scaling))
+	}
+}
+
+impl SampleConvert for Float32MSB {
+	type Sample = Float32MSB;
+	const BITS: u32 = 0;
+
+	fn from_native(self, scaling: Scaling) -> f64 {
+		self.0.from_native(scaling)
+	}
+
+	fn to_native(sample: f64, scaling: Scaling) -> Self::Sample {
+		Float32MSB(f32::to_native(sample, scaling))
+	}
+}
+
+impl SampleConvert for Float64MSB {
+	type Sample = Float64MSB;
+	const BITS: u32 = 0;
+
+	fn from_native(self, _scaling: Scaling) -> f64 {
+		self.0
+	}
+
+	fn to_native(sample: f64, _scaling: Scaling) -> Self::Sample {
+		Float64MSB(sample)
+	}
+}
+
+/// Implements `SampleConvert` for a 32 bit container holding `bits` significant bits
+/// in its lower part
+macro_rules! aligned_sample_convert {
+	($name:ident, $bits:expr) => {
+		impl SampleConvert for $name {
+			type Sample = $name;
+			const BITS: u32 = $bits;
+
+			fn from_native(self, scaling: Scaling) -> f64 {
+				int_from_native(self.0, $bits, scaling)
+			}
+
+			fn to_native(sample: f64, scaling: Scaling) -> Self::Sample {
+				$name(int_to_native(sample, $bits, scaling))
+			}
+		}
+	};
+}
+
+aligned_sample_convert!(Int32LSB16, 16);
+aligned_sample_convert!(Int32LSB18, 18);
+aligned_sample_convert!(Int32LSB20, 20);
+aligned_sample_convert!(Int32LSB24, 24);
+aligned_sample_convert!(Int32MSB16, 16);
+aligned_sample_convert!(Int32MSB18, 18);
+aligned_sample_convert!(Int32MSB20, 20);
+aligned_sample_convert!(Int32MSB24, 24);
+
+/// Largest positive value of a signed integer with the given number of bits
+pub fn max_int_value(bits: u32) -> f64 {
+	((1i64 << (bits - 1)) - 1) as f64
+}
+
+/// Integer value that corresponds to full scale with the given number of bits
+pub fn full_scale(bits: u32, scaling: Scaling) -> f64 {
+	match scaling {
+		Scaling::Symmetric => max_int_value(bits),
+		Scaling::PowerOfTwo => max_int_value(bits) + 1.0
+	}
+}
+
+/// Sign extends the lower `bits` of `value` and scales the result to [-1.0, 1.0]
+fn int_from_native(value: i32, bits: u32, scaling: Scaling) -> f64 {
+	let shift = 32 - bits;
+	let value = ((value << shift) >> shift) as f64;
+
+	// With symmetric scaling the most negative code would fall slightly below -1.0
+	(value * (1.0 / full_scale(bits, scaling))).max(-1.0)
+}
+
+/// Scales `sample` to the nearest signed integer (ties to even), clipped to the range of the
+/// given number of bits. Use a `Quantizer` beforehand to select truncation or dithering instead.
+fn int_to_native(sample: f64, bits: u32, scaling: Scaling) -> i32 {
+	let max_value = max_int_value(bits);
+	let min_value = match scaling {
+		Scaling::Symmetric => -max_value,
+		Scaling::PowerOfTwo => -max_value - 1.0
+	};
+
+	(sample * full_scale(bits, scaling)).clamp(min_value, max_value).round_ties_even() as i32
+}
@@ -0,0 +1,368 @@
+use crate::asio_core::ASIOSampleType;
+use std::fmt;
+
+// Conversions between sample positions and time. Rounding rules:
+// - samples to seconds or milliseconds is exact up to f64 precision
+// - seconds or milliseconds to samples rounds to the nearest sample, halves away from zero
+// - samples to SMPTE truncates to the frame that contains the sample
+// - SMPTE to samples yields the first sample of the frame
+// Round trips samples -> seconds -> samples and SMPTE -> samples -> SMPTE are lossless.
+
+pub fn samples_to_seconds(samples: i64, sample_rate: f64) -> f64 {
+	samples as f64 / sample_rate
+}
+
+pub fn seconds_to_samples(seconds: f64, sample_rate: f64) -> i64 {
+	(seconds * sample_rate).round() as i64
+}
+
+pub fn samples_to_ms(samples: i64, sample_rate: f64) -> f64 {
+	samples as f64 * 1000.0 / sample_rate
+}
+
+pub fn ms_to_samples(ms: f64, sample_rate: f64) -> i64 {
+	(ms * sample_rate / 1000.0).round() as i64
+}
+
+/// Number of bytes needed for `frames` samples of one channel. DSD formats with 1 bit
+/// samples pack 8 samples into a byte, a partly used byte counts as a whole byte.
+pub fn frames_to_bytes(frames: usize, sample_type: ASIOSampleType) -> usize {
+	match sample_type {
+		ASIOSampleType::DSDInt8LSB1 | ASIOSampleType::DSDInt8MSB1 => frames.div_ceil(8),
+		_ => frames * sample_type.size_in_bytes()
+	}
+}
+
+/// Number of complete samples of one channel within `bytes`
+pub fn bytes_to_frames(bytes: usize, sample_type: ASIOSampleType) -> usize {
+	match sample_type {
+		ASIOSampleType::DSDInt8LSB1 | ASIOSampleType::DSDInt8MSB1 => bytes * 8,
+		_ => bytes / sample_type.size_in_bytes()
+	}
+}
+
+/// Bytes of one channel covering `ms` milliseconds
+pub fn ms_to_bytes(ms: f64, sample_rate: f64, sample_type: ASIOSampleType) -> usize {
+	frames_to_bytes(ms_to_samples(ms, sample_rate).max(0) as usize, sample_type)
+}
+
+/// Sample position from an `ASIOSamples` value read as i64. The driver stores the high
+/// 32 bits first, so on little endian machines the halves are swapped.
+pub fn from_asio_samples(raw: i64) -> i64 {
+	(raw as u64).rotate_left(32) as i64
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FrameRate {
+	Fps23976,		// 24000/1001, non drop frame
+	Fps24,
+	Fps25,
+	Fps2997,		// 30000/1001, non drop frame
+	Fps2997Drop,	// 30000/1001, frame numbers 0 and 1 skipped each minute but every tenth
+	Fps30,
+	Fps5994,		// 60000/1001, non drop frame
+	Fps5994Drop		// 60000/1001, frame numbers 0 to 3 skipped each minute but every tenth
+}
+
+impl FrameRate {
+	/// Exact frame rate as numerator and denominator
+	pub fn ratio(&self) -> (i64, i64) {
+		match self {
+			FrameRate::Fps23976 => (24000, 1001),
+			FrameRate::Fps24 => (24, 1),
+			FrameRate::Fps25 => (25, 1),
+			FrameRate::Fps2997 | FrameRate::Fps2997Drop => (30000, 1001),
+			FrameRate::Fps30 => (30, 1),
+			FrameRate::Fps5994 | FrameRate::Fps5994Drop => (60000, 1001)
+		}
+	}
+
+	/// Frames per second as counted in timecode
+	pub fn nominal(&self) -> i64 {
+		match self {
+			FrameRate::Fps23976 | FrameRate::Fps24 => 24,
+			FrameRate::Fps25 => 25,
+			FrameRate::Fps2997 | FrameRate::Fps2997Drop | FrameRate::Fps30 => 30,
+			FrameRate::Fps5994 | FrameRate::Fps5994Drop => 60
+		}
+	}
+
+	/// Frame numbers skipped at the start of every minute not divisible by ten
+	pub fn dropped_per_minute(&self) -> i64 {
+		match self {
+			FrameRate::Fps2997Drop => 2,
+			FrameRate::Fps5994Drop => 4,
+			_ => 0
+		}
+	}
+
+	pub fn fps(&self) -> f64 {
+		let (num, den) = self.ratio();
+		num as f64 / den as f64
+	}
+}
+
+/// SMPTE timecode, a position of at least zero
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Smpte {
+	pub hours: u32,
+	pub minutes: u32,
+	pub seconds: u32,
+	pub frames: u32,
+	pub frame_rate: FrameRate
+}
+
+impl Smpte {
+	pub fn new(hours: u32, minutes: u32, seconds: u32, frames: u32, frame_rate: FrameRate) -> Smpte {
+		let dropped = seconds == 0 && (frames as i64) < frame_rate.dropped_per_minute() && !minutes.is_multiple_of(10);
+
+		if minutes >= 60 || seconds >= 60 || frames as i64 >= frame_rate.nominal() || dropped {
+			panic!("Invalid timecode {:02}:{:02}:{:02}:{:02} at {:?}", hours, minutes, seconds, frames, frame_rate);
+		}
+
+		Smpte {
+			hours,
+			minutes,
+			seconds,
+			frames,
+			frame_rate
+		}
+	}
+
+	/// Timecode of the frame containing `samples`, which must not be negative
+	pub fn from_samples(samples: i64, sample_rate: f64, frame_rate: FrameRate) -> Smpte {
+		if samples < 0 {
+			panic!("Cannot express sample position {} as timecode", samples);
+		}
+
+		let (num, den) = frame_rate.ratio();
+		let frame = (samples as i128 * num as i128 / (integral_rate(sample_rate) as i128 * den as i128)) as i64;
+
+		Smpte::from_frame_number(frame, frame_rate)
+	}
+
+	/// First sample of the frame
+	pub fn to_samples(self, sample_rate: f64) -> i64 {
+		let (num, den) = self.frame_rate.ratio();
+		let scaled = self.frame_number() as i128 * integral_rate(sample_rate) as i128 * den as i128;
+
+		((scaled + num as i128 - 1) / num as i128) as i64
+	}
+
+	/// Number of frames since 00:00:00:00
+	pub fn frame_number(&self) -> i64 {
+		let nominal = self.frame_rate.nominal();
+		let total_minutes = self.hours as i64 * 60 + self.minutes as i64;
+		let frame = (total_minutes * 60 + self.seconds as i64) * nominal + self.frames as i64;
+
+		frame - self.frame_rate.dropped_per_minute() * (total_minutes - total_minutes / 10)
+	}
+
+	pub fn from_frame_number(frame: i64, frame_rate: FrameRate) -> Smpte {
+		let nominal = frame_rate.nominal();
+		let dropped = frame_rate.dropped_per_minute();
+
+		// Re-insert the skipped frame numbers, so that the count can be split up as usual
+		let frame = match dropped {
+			0 => frame,
+			_ => {
+				let per_10_minutes = 600 * nominal - 9 * dropped;
+				let per_minute = 60 * nominal - dropped;
+				let tens = frame / per_10_minutes;
+				let rest = frame % per_10_minutes;
+				let minutes = if rest < dropped { 0 } else { (rest - dropped) / per_minute };
+				frame + 9 * dropped * tens + dropped * minutes
+			}
+		};
+
+		Smpte {
+			hours: (frame / (nominal * 3600)) as u32,
+			minutes: (frame / (nominal * 60) % 60) as u32,
+			seconds: (frame / nominal % 60) as u32,
+			frames: (frame % nominal) as u32,
+			frame_rate
+		}
+	}
+}
+
+impl fmt::Display for Smpte {
+	/// Formatted as HH:MM:SS:FF, or HH:MM:SS;FF for drop frame timecode
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let separator = match self.frame_rate.dropped_per_minute() {
+			0 => ':',
+			_ => ';'
+		};
+		write!(f, "{:02}:{:02}:{:02}{}{:02}", self.hours, self.minutes, self.seconds, separator, self.frames)
+	}
+}
+
+fn integral_rate(sample_rate: f64) -> i64 {
+	if sample_rate <= 0.0 || sample_rate.fract() != 0.0 {
+		panic!("Timecode conversion needs a positive integral sample rate, got {}", sample_rate);
+	}
+	sample_rate as i64
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::asio_core::random::Random;
+
+	const FRAME_RATES: [FrameRate; 8] = [
+		FrameRate::Fps23976,
+		FrameRate::Fps24,
+		FrameRate::Fps25,
+		FrameRate::Fps2997,
+		FrameRate::Fps2997Drop,
+		FrameRate::Fps30,
+		FrameRate::Fps5994,
+		FrameRate::Fps5994Drop
+	];
+
+	const SAMPLE_RATES: [f64; 4] = [44100.0, 48000.0, 88200.0, 96000.0];
+
+	/// `ASIOSamples` as the driver writes it, high half first, read back as an i64
+	fn asio_samples(position: i64) -> i64 {
+		let hi = ((position as u64) >> 32) as u32;
+		let lo = position as u32;
+		let mut bytes = [0u8; 8];
+		bytes[..4].copy_from_slice(&hi.to_ne_bytes());
+		bytes[4..].copy_from_slice(&lo.to_ne_bytes());
+		i64::from_ne_bytes(bytes)
+	}
+
+	#[test]
+	fn asio_samples_round_trip() {
+		assert_eq!(from_asio_samples(asio_samples(0)), 0);
+		assert_eq!(from_asio_samples(asio_samples(1)), 1);
+		assert_eq!(from_asio_samples(asio_samples(1 << 32)), 1 << 32);
+		assert_eq!(from_asio_samples(asio_samples(0x1234_5678_9abc_def0)), 0x1234_5678_9abc_def0);
+
+		let mut random = Random::new(1);
+		for _ in 0..1000 {
+			let position = (random.next_u64() >> 1) as i64;
+			assert_eq!(from_asio_samples(asio_samples(position)), position);
+		}
+	}
+
+	#[test]
+	fn seconds_and_ms_round_trip() {
+		let mut random = Random::new(2);
+		for &sample_rate in SAMPLE_RATES.iter() {
+			for _ in 0..1000 {
+				// Up to a day of samples
+				let samples = (random.next_u64() % (sample_rate as u64 * 86400)) as i64;
+				assert_eq!(seconds_to_samples(samples_to_seconds(samples, sample_rate), sample_rate), samples);
+				assert_eq!(ms_to_samples(samples_to_ms(samples, sample_rate), sample_rate), samples);
+				assert_eq!(seconds_to_samples(samples_to_seconds(-samples, sample_rate), sample_rate), -samples);
+			}
+		}
+	}
+
+	#[test]
+	fn rounding_to_samples() {
+		assert_eq!(ms_to_samples(2.4, 1000.0), 2);
+		assert_eq!(ms_to_samples(2.5, 1000.0), 3);
+		assert_eq!(ms_to_samples(-2.5, 1000.0), -3);
+		assert_eq!(seconds_to_samples(0.25, 2.0), 1);
+		assert_eq!(seconds_to_samples(-0.25, 2.0), -1);
+		assert_eq!(seconds_to_samples(1.0, 48000.0), 48000);
+	}
+
+	#[test]
+	fn smpte_samples_round_trip() {
+		let mut random = Random::new(3);
+		for &frame_rate in FRAME_RATES.iter() {
+			for &sample_rate in SAMPLE_RATES.iter() {
+				for _ in 0..1000 {
+					let samples = (random.next_u64() % (sample_rate as u64 * 86400)) as i64;
+					let smpte = Smpte::from_samples(samples, sample_rate, frame_rate);
+					let start = smpte.to_samples(sample_rate);
+					let next = Smpte::from_frame_number(smpte.frame_number() + 1, frame_rate).to_samples(sample_rate);
+
+					// The sample lies within the frame found for it
+					assert!(start <= samples && samples < next, "{} at {} for sample {}", smpte, sample_rate, samples);
+					assert_eq!(Smpte::from_samples(start, sample_rate, frame_rate), smpte);
+					assert_eq!(Smpte::from_samples(next - 1, sample_rate, frame_rate), smpte);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn frame_numbers_round_trip() {
+		for &frame_rate in FRAME_RATES.iter() {
+			// Two hours, frame by frame, passing every minute and ten minute boundary
+			let frames = frame_rate.fps() as i64 * 7200;
+			let mut previous = Smpte::from_frame_number(0, frame_rate);
+			assert_eq!(previous, Smpte::new(0, 0, 0, 0, frame_rate));
+
+			for frame in 1..frames {
+				let smpte = Smpte::from_frame_number(frame, frame_rate);
+				assert_eq!(smpte.frame_number(), frame, "{}", smpte);
+
+				// Panics if the timecode is one of the dropped ones
+				let checked = Smpte::new(smpte.hours, smpte.minutes, smpte.seconds, smpte.frames, frame_rate);
+				assert_eq!(checked, smpte);
+
+				let next_second = previous.frames as i64 + 1 == frame_rate.nominal();
+				if next_second {
+					let minute = (previous.hours * 60 + previous.minutes + (previous.seconds + 1) / 60) % 60;
+					let dropped = if previous.seconds == 59 && !minute.is_multiple_of(10) { frame_rate.dropped_per_minute() } else { 0 };
+					assert_eq!(smpte.seconds, (previous.seconds + 1) % 60);
+					assert_eq!(smpte.frames as i64, dropped, "{} after {}", smpte, previous);
+				} else {
+					assert_eq!(smpte.frames, previous.frames + 1);
+				}
+				previous = smpte;
+			}
+		}
+	}
+
+	#[test]
+	fn drop_frame_boundaries() {
+		let successor = |smpte: Smpte| Smpte::from_frame_number(smpte.frame_number() + 1, smpte.frame_rate).to_string();
+
+		let fps2997 = FrameRate::Fps2997Drop;
+		assert_eq!(successor(Smpte::new(0, 0, 59, 29, fps2997)), "00:01:00;02");
+		assert_eq!(successor(Smpte::new(0, 9, 59, 29, fps2997)), "00:10:00;00");
+		assert_eq!(successor(Smpte::new(0, 10, 59, 29, fps2997)), "00:11:00;02");
+		assert_eq!(successor(Smpte::new(0, 59, 59, 29, fps2997)), "01:00:00;00");
+		assert_eq!(Smpte::new(0, 1, 0, 2, fps2997).frame_number(), 1800);
+		assert_eq!(Smpte::new(0, 10, 0, 0, fps2997).frame_number(), 17982);
+		assert_eq!(Smpte::new(1, 0, 0, 0, fps2997).frame_number(), 107892);
+
+		let fps5994 = FrameRate::Fps5994Drop;
+		assert_eq!(successor(Smpte::new(0, 0, 59, 59, fps5994)), "00:01:00;04");
+		assert_eq!(successor(Smpte::new(0, 9, 59, 59, fps5994)), "00:10:00;00");
+		assert_eq!(successor(Smpte::new(0, 10, 59, 59, fps5994)), "00:11:00;04");
+		assert_eq!(successor(Smpte::new(0, 59, 59, 59, fps5994)), "01:00:00;00");
+		assert_eq!(Smpte::new(0, 1, 0, 4, fps5994).frame_number(), 3600);
+		assert_eq!(Smpte::new(0, 10, 0, 0, fps5994).frame_number(), 35964);
+		assert_eq!(Smpte::new(1, 0, 0, 0, fps5994).frame_number(), 215784);
+
+		// Drop frame timecode is 3.6 ms early after an hour, within a frame of the clock
+		for &frame_rate in [fps2997, fps5994].iter() {
+			let hour = Smpte::new(1, 0, 0, 0, frame_rate).to_samples(48000.0);
+			assert_eq!(3600 * 48000 - hour, 172);
+		}
+	}
+
+	#[test]
+	#[should_panic]
+	fn dropped_timecode_is_rejected() {
+		Smpte::new(0, 1, 0, 3, FrameRate::Fps5994Drop);
+	}
+
+	#[test]
+	fn byte_sizes() {
+		assert_eq!(frames_to_bytes(100, ASIOSampleType::Int24LSB), 300);
+		assert_eq!(bytes_to_frames(301, ASIOSampleType::Int24LSB), 100);
+		assert_eq!(frames_to_bytes(100, ASIOSampleType::Float64LSB), 800);
+		assert_eq!(frames_to_bytes(100, ASIOSampleType::DSDInt8MSB1), 13);
+		assert_eq!(bytes_to_frames(13, ASIOSampleType::DSDInt8MSB1), 104);
+		assert_eq!(frames_to_bytes(100, ASIOSampleType::DSDInt8NER8), 100);
+		assert_eq!(ms_to_bytes(10.0, 48000.0, ASIOSampleType::Int32LSB), 1920);
+		assert_eq!(ms_to_bytes(-10.0, 48000.0, ASIOSampleType::Int32LSB), 0);
+	}
+}
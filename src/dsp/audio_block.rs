@@ -0,0 +1,530 @@
+use crate::dsp::sample::Sample;
+use std::alloc::{self, Layout};
+use std::marker::PhantomData;
+use std::ptr::NonNull;
+use std::slice;
+
+/// Alignment of every channel within an `AudioBuffer`, enough for any vector register
+const ALIGNMENT: usize = 64;
+
+/// Identifies a channel of a block, e.g. by the driver's channel index and name
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChannelLabel {
+	pub index: usize,
+	pub name: String
+}
+
+impl ChannelLabel {
+	pub fn new(index: usize, name: &str) -> ChannelLabel {
+		ChannelLabel {
+			index,
+			name: String::from(name)
+		}
+	}
+
+	/// Labels channels 0..count as "1", "2", ...
+	pub fn numbered(count: usize) -> Vec<ChannelLabel> {
+		(0..count).map(|index| ChannelLabel::new(index, &(index + 1).to_string())).collect()
+	}
+}
+
+/// Planar audio data for several channels in one contiguous, aligned allocation.
+/// Each channel starts on an `ALIGNMENT` boundary; its capacity is fixed at construction.
+pub struct AudioBuffer<S: Sample> {
+	data: NonNull<S>,
+	labels: Vec<ChannelLabel>,
+	stride: usize,
+	capacity: usize,
+	frames: usize
+}
+
+unsafe impl<S: Sample> Send for AudioBuffer<S> {}
+unsafe impl<S: Sample> Sync for AudioBuffer<S> {}
+
+impl<S: Sample> AudioBuffer<S> {
+	pub fn new(labels: Vec<ChannelLabel>, capacity: usize) -> AudioBuffer<S> {
+		let per_line = ALIGNMENT / std::mem::size_of::<S>();
+		let stride = capacity.div_ceil(per_line).max(1) * per_line;
+		let layout = AudioBuffer::<S>::layout(labels.len() * stride);
+
+		// f32 and f64 zero are all bits zero, so a zeroed allocation is a silent buffer
+		let data = unsafe { alloc::alloc_zeroed(layout) } as *mut S;
+		let data = match NonNull::new(data) {
+			Some(data) => data,
+			None => alloc::handle_alloc_error(layout)
+		};
+
+		AudioBuffer {
+			data,
+			labels,
+			stride,
+			capacity,
+			frames: capacity
+		}
+	}
+
+	pub fn with_channels(num_channels: usize, capacity: usize) -> AudioBuffer<S> {
+		AudioBuffer::new(ChannelLabel::numbered(num_channels), capacity)
+	}
+
+	pub fn num_channels(&self) -> usize {
+		self.labels.len()
+	}
+
+	pub fn num_frames(&self) -> usize {
+		self.frames
+	}
+
+	pub fn capacity(&self) -> usize {
+		self.capacity
+	}
+
+	/// Changes the number of valid frames, e.g. after a driver reset. Never allocates.
+	pub fn set_num_frames(&mut self, frames: usize) {
+		if frames > self.capacity {
+			panic!("Cannot use {} frames in a buffer of {} frames", frames, self.capacity);
+		}
+		self.frames = frames;
+	}
+
+	pub fn labels(&self) -> &[ChannelLabel] {
+		&self.labels
+	}
+
+	pub fn as_block(&self) -> AudioBlock<'_, S> {
+		AudioBlock {
+			phantom: PhantomData,
+			data: self.data.as_ptr(),
+			labels: &self.labels,
+			stride: self.stride,
+			frames: self.frames
+		}
+	}
+
+	pub fn as_block_mut(&mut self) -> AudioBlockMut<'_, S> {
+		AudioBlockMut {
+			phantom: PhantomData,
+			data: self.data.as_ptr(),
+			labels: &self.labels,
+			stride: self.stride,
+			frames: self.frames
+		}
+	}
+
+	fn layout(len: usize) -> Layout {
+		let size = (len * std::mem::size_of::<S>()).max(ALIGNMENT);
+		Layout::from_size_align(size, ALIGNMENT).expect("Audio buffer size fits into memory")
+	}
+}
+
+impl<S: Sample> Drop for AudioBuffer<S> {
+	fn drop(&mut self) {
+		unsafe {
+			alloc::dealloc(self.data.as_ptr() as *mut u8, AudioBuffer::<S>::layout(self.labels.len() * self.stride));
+		}
+	}
+}
+
+/// Read-only view of a range of frames of an `AudioBuffer`
+#[derive(Copy, Clone)]
+pub struct AudioBlock<'a, S> {
+	phantom: PhantomData<&'a [S]>,
+	data: *const S,
+	labels: &'a [ChannelLabel],
+	stride: usize,
+	frames: usize
+}
+
+unsafe impl<'a, S: Sample> Send for AudioBlock<'a, S> {}
+unsafe impl<'a, S: Sample> Sync for AudioBlock<'a, S> {}
+
+impl<'a, S: Sample> AudioBlock<'a, S> {
+	pub fn num_channels(&self) -> usize {
+		self.labels.len()
+	}
+
+	pub fn num_frames(&self) -> usize {
+		self.frames
+	}
+
+	pub fn labels(&self) -> &'a [ChannelLabel] {
+		self.labels
+	}
+
+	/// Position of the channel with the given name within this block
+	pub fn find_channel(&self, name: &str) -> Option<usize> {
+		self.labels.iter().position(|label| label.name == name)
+	}
+
+	pub fn channel(&self, channel: usize) -> &'a [S] {
+		if channel >= self.num_channels() {
+			panic!("Channel {} is out of range, the block has {} channels", channel, self.num_channels());
+		}
+		unsafe { slice::from_raw_parts(self.data.add(channel * self.stride), self.frames) }
+	}
+
+	pub fn channels(&self) -> impl Iterator<Item = &'a [S]> + 'a {
+		let block = *self;
+		(0..block.num_channels()).map(move |channel| block.channel(channel))
+	}
+
+	pub fn frames(&self) -> impl Iterator<Item = Frame<'a, S>> + 'a {
+		let block = *self;
+		(0..block.frames).map(move |index| Frame { block, index })
+	}
+
+	/// Writes the samples frame by frame, `dst` must hold channels * frames samples
+	pub fn copy_to_interleaved(&self, dst: &mut [S]) {
+		let num_channels = self.num_channels();
+		AudioBlock::<S>::check_interleaved(dst.len(), num_channels, self.frames);
+
+		for (channel, source) in self.channels().enumerate() {
+			for (frame, sample) in source.iter().enumerate() {
+				dst[frame * num_channels + channel] = *sample;
+			}
+		}
+	}
+
+	/// View of `len` frames starting at frame `start`
+	pub fn sub_block(&self, start: usize, len: usize) -> AudioBlock<'a, S> {
+		AudioBlock::<S>::check_range(start, len, self.frames);
+
+		AudioBlock {
+			phantom: PhantomData,
+			data: unsafe { self.data.add(start) },
+			labels: self.labels,
+			stride: self.stride,
+			frames: len
+		}
+	}
+
+	pub fn split_at(&self, frame: usize) -> (AudioBlock<'a, S>, AudioBlock<'a, S>) {
+		(self.sub_block(0, frame), self.sub_block(frame, self.frames - frame))
+	}
+
+	fn check_range(start: usize, len: usize, frames: usize) {
+		if start + len > frames {
+			panic!("Frames {}..{} are out of range, the block has {} frames", start, start + len, frames);
+		}
+	}
+
+	fn check_interleaved(len: usize, num_channels: usize, frames: usize) {
+		if len != num_channels * frames {
+			panic!("Interleaved buffer holds {} samples, expected {} channels x {} frames", len, num_channels, frames);
+		}
+	}
+}
+
+/// The samples of all channels at one point in time
+#[derive(Copy, Clone)]
+pub struct Frame<'a, S> {
+	block: AudioBlock<'a, S>,
+	index: usize
+}
+
+impl<'a, S: Sample> Frame<'a, S> {
+	pub fn index(&self) -> usize {
+		self.index
+	}
+
+	pub fn sample(&self, channel: usize) -> S {
+		self.block.channel(channel)[self.index]
+	}
+
+	pub fn iter(&self) -> impl Iterator<Item = S> + 'a {
+		let frame = *self;
+		(0..frame.block.num_channels()).map(move |channel| frame.sample(channel))
+	}
+}
+
+/// Writable view of a range of frames of an `AudioBuffer`
+pub struct AudioBlockMut<'a, S> {
+	phantom: PhantomData<&'a mut [S]>,
+	data: *mut S,
+	labels: &'a [ChannelLabel],
+	stride: usize,
+	frames: usize
+}
+
+unsafe impl<'a, S: Sample> Send for AudioBlockMut<'a, S> {}
+
+impl<'a, S: Sample> AudioBlockMut<'a, S> {
+	pub fn num_channels(&self) -> usize {
+		self.labels.len()
+	}
+
+	pub fn num_frames(&self) -> usize {
+		self.frames
+	}
+
+	pub fn labels(&self) -> &'a [ChannelLabel] {
+		self.labels
+	}
+
+	pub fn find_channel(&self, name: &str) -> Option<usize> {
+		self.labels.iter().position(|label| label.name == name)
+	}
+
+	pub fn as_block(&self) -> AudioBlock<'_, S> {
+		AudioBlock {
+			phantom: PhantomData,
+			data: self.data,
+			labels: self.labels,
+			stride: self.stride,
+			frames: self.frames
+		}
+	}
+
+	pub fn channel(&self, channel: usize) -> &[S] {
+		self.as_block().channel(channel)
+	}
+
+	pub fn channel_mut(&mut self, channel: usize) -> &mut [S] {
+		if channel >= self.num_channels() {
+			panic!("Channel {} is out of range, the block has {} channels", channel, self.num_channels());
+		}
+		unsafe { slice::from_raw_parts_mut(self.data.add(channel * self.stride), self.frames) }
+	}
+
+	/// All channels at once, as they never overlap
+	pub fn channels_mut(&mut self) -> impl Iterator<Item = &mut [S]> + '_ {
+		let (data, stride, frames) = (self.data, self.stride, self.frames);
+
+		(0..self.num_channels()).map(move |channel| unsafe {
+			slice::from_raw_parts_mut(data.add(channel * stride), frames)
+		})
+	}
+
+	pub fn fill(&mut self, value: S) {
+		for channel in self.channels_mut() {
+			channel.fill(value);
+		}
+	}
+
+	/// Copies all channels of `src`, which must have the same shape
+	pub fn copy_from(&mut self, src: &AudioBlock<S>) {
+		if src.num_channels() != self.num_channels() || src.num_frames() != self.frames {
+			panic!(
+				"Cannot copy {} x {} samples into a block of {} x {}",
+				src.num_channels(), src.num_frames(), self.num_channels(), self.frames
+			);
+		}
+		for (target, source) in self.channels_mut().zip(src.channels()) {
+			target.copy_from_slice(source);
+		}
+	}
+
+	/// Reads samples stored frame by frame, `src` must hold channels * frames samples
+	pub fn copy_from_interleaved(&mut self, src: &[S]) {
+		let num_channels = self.num_channels();
+		AudioBlock::<S>::check_interleaved(src.len(), num_channels, self.frames);
+
+		for (channel, target) in self.channels_mut().enumerate() {
+			for (frame, sample) in target.iter_mut().enumerate() {
+				*sample = src[frame * num_channels + channel];
+			}
+		}
+	}
+
+	pub fn copy_to_interleaved(&self, dst: &mut [S]) {
+		self.as_block().copy_to_interleaved(dst);
+	}
+
+	/// Writable view of `len` frames starting at frame `start`
+	pub fn sub_block_mut(&mut self, start: usize, len: usize) -> AudioBlockMut<'_, S> {
+		AudioBlock::<S>::check_range(start, len, self.frames);
+
+		AudioBlockMut {
+			phantom: PhantomData,
+			data: unsafe { self.data.add(start) },
+			labels: self.labels,
+			stride: self.stride,
+			frames: len
+		}
+	}
+
+	/// Two writable views of the frames before and after `frame`
+	pub fn split_at_mut(&mut self, frame: usize) -> (AudioBlockMut<'_, S>, AudioBlockMut<'_, S>) {
+		AudioBlock::<S>::check_range(0, frame, self.frames);
+
+		let head = AudioBlockMut {
+			phantom: PhantomData,
+			data: self.data,
+			labels: self.labels,
+			stride: self.stride,
+			frames: frame
+		};
+		let tail = AudioBlockMut {
+			phantom: PhantomData,
+			data: unsafe { self.data.add(frame) },
+			labels: self.labels,
+			stride: self.stride,
+			frames: self.frames - frame
+		};
+		(head, tail)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::thread;
+
+	fn ramp(buffer: &mut AudioBuffer<f32>) {
+		let frames = buffer.num_frames();
+		for (channel, samples) in buffer.as_block_mut().channels_mut().enumerate() {
+			for (frame, sample) in samples.iter_mut().enumerate() {
+				*sample = (channel * frames + frame) as f32;
+			}
+		}
+	}
+
+	#[test]
+	fn channels_are_aligned_and_strided() {
+		for capacity in [0, 1, 13, 16, 100, 1027] {
+			let buffer = AudioBuffer::<f32>::with_channels(3, capacity);
+			let wide = AudioBuffer::<f64>::with_channels(3, capacity);
+			let block = buffer.as_block();
+			let wide_block = wide.as_block();
+
+			for channel in 0..3 {
+				assert_eq!(block.channel(channel).as_ptr() as usize % ALIGNMENT, 0);
+				assert_eq!(wide_block.channel(channel).as_ptr() as usize % ALIGNMENT, 0);
+			}
+
+			// Channels follow each other at the stride, which holds the capacity
+			let stride = (block.channel(1).as_ptr() as usize - block.channel(0).as_ptr() as usize) / 4;
+			assert_eq!(stride, buffer.stride);
+			assert!(stride >= capacity && (stride * 4).is_multiple_of(ALIGNMENT));
+			assert_eq!(block.channel(2).as_ptr() as usize - block.channel(1).as_ptr() as usize, stride * 4);
+			assert!(block.channels().all(|samples| samples.len() == capacity && samples.iter().all(|sample| *sample == 0.0)));
+		}
+	}
+
+	#[test]
+	fn channels_do_not_overlap() {
+		let mut buffer = AudioBuffer::<f32>::with_channels(4, 37);
+		ramp(&mut buffer);
+
+		let block = buffer.as_block();
+		for channel in 0..4 {
+			assert!(block.channel(channel).iter().enumerate().all(|(frame, sample)| *sample == (channel * 37 + frame) as f32));
+		}
+		assert_eq!(block.frames().nth(5).unwrap().iter().collect::<Vec<_>>(), vec![5.0, 42.0, 79.0, 116.0]);
+	}
+
+	#[test]
+	fn sub_blocks_view_the_frames() {
+		let mut buffer = AudioBuffer::<f32>::with_channels(2, 20);
+		ramp(&mut buffer);
+
+		let block = buffer.as_block();
+		let sub = block.sub_block(5, 10);
+		assert_eq!(sub.num_frames(), 10);
+		assert_eq!(sub.channel(0)[0], 5.0);
+		assert_eq!(sub.channel(1)[9], 34.0);
+		assert_eq!(block.sub_block(20, 0).num_frames(), 0);
+
+		let (head, tail) = block.split_at(8);
+		assert_eq!((head.num_frames(), tail.num_frames()), (8, 12));
+		assert_eq!(tail.channel(1)[0], 28.0);
+	}
+
+	#[test]
+	#[should_panic(expected = "out of range")]
+	fn sub_block_past_the_end_panics() {
+		let buffer = AudioBuffer::<f32>::with_channels(2, 20);
+		buffer.as_block().sub_block(15, 6);
+	}
+
+	#[test]
+	#[should_panic(expected = "out of range")]
+	fn sub_block_mut_past_the_end_panics() {
+		let mut buffer = AudioBuffer::<f32>::with_channels(2, 20);
+		buffer.as_block_mut().sub_block_mut(21, 0);
+	}
+
+	#[test]
+	#[should_panic(expected = "out of range")]
+	fn split_past_the_end_panics() {
+		let mut buffer = AudioBuffer::<f32>::with_channels(2, 20);
+		buffer.as_block_mut().split_at_mut(21);
+	}
+
+	#[test]
+	#[should_panic(expected = "out of range")]
+	fn missing_channel_panics() {
+		let buffer = AudioBuffer::<f32>::with_channels(2, 20);
+		buffer.as_block().channel(2);
+	}
+
+	#[test]
+	#[should_panic(expected = "Cannot use 21 frames")]
+	fn frames_beyond_capacity_panic() {
+		AudioBuffer::<f32>::with_channels(2, 20).set_num_frames(21);
+	}
+
+	#[test]
+	fn split_halves_are_disjoint() {
+		let mut buffer = AudioBuffer::<f32>::with_channels(3, 64);
+		buffer.set_num_frames(50);
+
+		{
+			let mut block = buffer.as_block_mut();
+			let (mut head, mut tail) = block.split_at_mut(20);
+			// Both halves are written at once from threads of their own
+			thread::scope(|scope| {
+				scope.spawn(|| head.fill(1.0));
+				scope.spawn(|| tail.fill(2.0));
+			});
+		}
+
+		buffer.set_num_frames(64);
+		for samples in buffer.as_block().channels() {
+			assert!(samples[..20].iter().all(|sample| *sample == 1.0));
+			assert!(samples[20..50].iter().all(|sample| *sample == 2.0));
+			assert!(samples[50..].iter().all(|sample| *sample == 0.0));
+		}
+	}
+
+	#[test]
+	fn interleaved_round_trip() {
+		let mut buffer = AudioBuffer::<f64>::with_channels(3, 16);
+		buffer.set_num_frames(5);
+		let interleaved: Vec<f64> = (0..15).map(|index| index as f64).collect();
+
+		buffer.as_block_mut().copy_from_interleaved(&interleaved);
+		assert_eq!(buffer.as_block().channel(1), &[1.0, 4.0, 7.0, 10.0, 13.0]);
+
+		let mut copy = vec![0.0; 15];
+		buffer.as_block().copy_to_interleaved(&mut copy);
+		assert_eq!(copy, interleaved);
+
+		let mut other = AudioBuffer::<f64>::with_channels(3, 5);
+		other.as_block_mut().copy_from(&buffer.as_block());
+		other.as_block_mut().copy_to_interleaved(&mut copy);
+		assert_eq!(copy, interleaved);
+	}
+
+	#[test]
+	#[should_panic(expected = "expected 2 channels x 4 frames")]
+	fn interleaved_length_is_checked() {
+		let mut buffer = AudioBuffer::<f32>::with_channels(2, 4);
+		buffer.as_block_mut().copy_from_interleaved(&[0.0; 7]);
+	}
+
+	#[test]
+	fn labels_come_from_channel_names() {
+		let labels = vec![ChannelLabel::new(4, "Mic L"), ChannelLabel::new(5, "Mic R"), ChannelLabel::new(9, "Talkback")];
+		let mut buffer = AudioBuffer::<f32>::new(labels.clone(), 8);
+
+		assert_eq!(buffer.labels(), &labels[..]);
+		assert_eq!(buffer.as_block().find_channel("Talkback"), Some(2));
+		assert_eq!(buffer.as_block().sub_block(2, 4).find_channel("Mic R"), Some(1));
+		assert_eq!(buffer.as_block_mut().find_channel("Mic X"), None);
+		assert_eq!(buffer.as_block_mut().split_at_mut(3).1.labels()[2].index, 9);
+
+		let numbered = ChannelLabel::numbered(2);
+		assert_eq!(numbered, vec![ChannelLabel::new(0, "1"), ChannelLabel::new(1, "2")]);
+	}
+}
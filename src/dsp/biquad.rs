@@ -0,0 +1,237 @@
+use std::f64::consts::PI;
+
+/// Highest frequency a filter is tuned to, as a fraction of the sample rate
+const MAX_FREQUENCY: f64 = 0.49;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FilterType {
+	Bell,			// boost or cut around the frequency
+	LowShelf,		// boost or cut below the frequency
+	HighShelf,		// boost or cut above the frequency
+	LowPass,
+	HighPass,
+	Notch,
+	AllPass			// unity gain, phase shift around the frequency
+}
+
+impl FilterType {
+	pub const ALL: [FilterType; 7] = [
+		FilterType::Bell,
+		FilterType::LowShelf,
+		FilterType::HighShelf,
+		FilterType::LowPass,
+		FilterType::HighPass,
+		FilterType::Notch,
+		FilterType::AllPass
+	];
+
+	pub fn index(&self) -> usize {
+		FilterType::ALL.iter().position(|filter_type| filter_type == self).unwrap()
+	}
+
+	/// Filter type for a parameter value, rounded and clamped to the valid indices
+	pub fn from_index(index: f64) -> FilterType {
+		FilterType::ALL[(index.round().max(0.0) as usize).min(FilterType::ALL.len() - 1)]
+	}
+
+	/// Whether `gain_db` changes the response
+	pub fn uses_gain(&self) -> bool {
+		matches!(self, FilterType::Bell | FilterType::LowShelf | FilterType::HighShelf)
+	}
+}
+
+/// Coefficients of a second order section normalized to a0 = 1, designed after the
+/// Audio EQ Cookbook by Robert Bristow-Johnson. Shelves use `q` like the other types,
+/// 0.707 gives the steepest shelf without overshoot.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Coefficients {
+	pub b0: f64,
+	pub b1: f64,
+	pub b2: f64,
+	pub a1: f64,
+	pub a2: f64
+}
+
+impl Coefficients {
+	pub fn new(filter_type: FilterType, sample_rate: f64, frequency: f64, q: f64, gain_db: f64) -> Coefficients {
+		let frequency = frequency.clamp(1.0, sample_rate * MAX_FREQUENCY);
+		let omega = 2.0 * PI * frequency / sample_rate;
+		let (sin, cos) = omega.sin_cos();
+		let alpha = sin / (2.0 * q.max(1e-3));
+		let a = 10.0f64.powf(gain_db / 40.0);
+		let shelf = 2.0 * a.sqrt() * alpha;
+
+		let (b0, b1, b2, a0, a1, a2) = match filter_type {
+			FilterType::Bell => (
+				1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a,
+				1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a
+			),
+			FilterType::LowShelf => (
+				a * ((a + 1.0) - (a - 1.0) * cos + shelf),
+				2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+				a * ((a + 1.0) - (a - 1.0) * cos - shelf),
+				(a + 1.0) + (a - 1.0) * cos + shelf,
+				-2.0 * ((a - 1.0) + (a + 1.0) * cos),
+				(a + 1.0) + (a - 1.0) * cos - shelf
+			),
+			FilterType::HighShelf => (
+				a * ((a + 1.0) + (a - 1.0) * cos + shelf),
+				-2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+				a * ((a + 1.0) + (a - 1.0) * cos - shelf),
+				(a + 1.0) - (a - 1.0) * cos + shelf,
+				2.0 * ((a - 1.0) - (a + 1.0) * cos),
+				(a + 1.0) - (a - 1.0) * cos - shelf
+			),
+			FilterType::LowPass => (
+				(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0,
+				1.0 + alpha, -2.0 * cos, 1.0 - alpha
+			),
+			FilterType::HighPass => (
+				(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0,
+				1.0 + alpha, -2.0 * cos, 1.0 - alpha
+			),
+			FilterType::Notch => (
+				1.0, -2.0 * cos, 1.0,
+				1.0 + alpha, -2.0 * cos, 1.0 - alpha
+			),
+			FilterType::AllPass => (
+				1.0 - alpha, -2.0 * cos, 1.0 + alpha,
+				1.0 + alpha, -2.0 * cos, 1.0 - alpha
+			)
+		};
+
+		Coefficients {
+			b0: b0 / a0,
+			b1: b1 / a0,
+			b2: b2 / a0,
+			a1: a1 / a0,
+			a2: a2 / a0
+		}
+	}
+
+	/// Passes the signal unchanged
+	pub fn identity() -> Coefficients {
+		Coefficients {
+			b0: 1.0,
+			b1: 0.0,
+			b2: 0.0,
+			a1: 0.0,
+			a2: 0.0
+		}
+	}
+
+	/// Linear gain at `frequency`, evaluating the transfer function on the unit circle
+	pub fn magnitude(&self, frequency: f64, sample_rate: f64) -> f64 {
+		let omega = 2.0 * PI * frequency / sample_rate;
+		let (sin, cos) = omega.sin_cos();
+		let (sin2, cos2) = (2.0 * omega).sin_cos();
+
+		let numerator_re = self.b0 + self.b1 * cos + self.b2 * cos2;
+		let numerator_im = -(self.b1 * sin + self.b2 * sin2);
+		let denominator_re = 1.0 + self.a1 * cos + self.a2 * cos2;
+		let denominator_im = -(self.a1 * sin + self.a2 * sin2);
+
+		((numerator_re * numerator_re + numerator_im * numerator_im)
+			/ (denominator_re * denominator_re + denominator_im * denominator_im)).sqrt()
+	}
+
+	pub fn magnitude_db(&self, frequency: f64, sample_rate: f64) -> f64 {
+		20.0 * self.magnitude(frequency, sample_rate).log10()
+	}
+}
+
+/// State of one channel, in transposed direct form II. The state stays valid when the
+/// coefficients change between samples, so filters can be modulated.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Biquad {
+	s1: f64,
+	s2: f64
+}
+
+impl Biquad {
+	pub fn new() -> Biquad {
+		Biquad::default()
+	}
+
+	pub fn reset(&mut self) {
+		self.s1 = 0.0;
+		self.s2 = 0.0;
+	}
+
+	#[inline]
+	pub fn process(&mut self, coefficients: &Coefficients, input: f64) -> f64 {
+		let output = coefficients.b0 * input + self.s1;
+		self.s1 = coefficients.b1 * input - coefficients.a1 * output + self.s2;
+		self.s2 = coefficients.b2 * input - coefficients.a2 * output;
+		output
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const SAMPLE_RATE: f64 = 48000.0;
+	const FREQUENCIES: [f64; 4] = [50.0, 1000.0, 5000.0, 15000.0];
+
+	#[test]
+	fn gain_at_the_frequency() {
+		for &frequency in FREQUENCIES.iter() {
+			for &gain_db in [-12.0, -3.0, 6.0, 18.0].iter() {
+				for &q in [0.5, 0.707, 2.0].iter() {
+					let at = |filter_type| Coefficients::new(filter_type, SAMPLE_RATE, frequency, q, gain_db).magnitude_db(frequency, SAMPLE_RATE);
+
+					assert!((at(FilterType::Bell) - gain_db).abs() < 1e-9, "bell at {} Hz", frequency);
+					assert!((at(FilterType::LowShelf) - gain_db / 2.0).abs() < 1e-9, "low shelf at {} Hz", frequency);
+					assert!((at(FilterType::HighShelf) - gain_db / 2.0).abs() < 1e-9, "high shelf at {} Hz", frequency);
+					assert!(at(FilterType::AllPass).abs() < 1e-9, "all pass at {} Hz", frequency);
+				}
+			}
+
+			let at = |filter_type| Coefficients::new(filter_type, SAMPLE_RATE, frequency, 0.707, 0.0).magnitude_db(frequency, SAMPLE_RATE);
+			assert!((at(FilterType::LowPass) + 3.01).abs() < 0.01, "low pass at {} Hz", frequency);
+			assert!((at(FilterType::HighPass) + 3.01).abs() < 0.01, "high pass at {} Hz", frequency);
+			assert!(at(FilterType::Notch) < -200.0, "notch at {} Hz", frequency);
+		}
+	}
+
+	#[test]
+	fn gain_away_from_the_frequency() {
+		let coefficients = |filter_type| Coefficients::new(filter_type, SAMPLE_RATE, 1000.0, 0.707, 12.0);
+
+		assert!(coefficients(FilterType::Bell).magnitude_db(20.0, SAMPLE_RATE).abs() < 0.1);
+		assert!((coefficients(FilterType::LowShelf).magnitude_db(20.0, SAMPLE_RATE) - 12.0).abs() < 0.1);
+		assert!(coefficients(FilterType::LowShelf).magnitude_db(20000.0, SAMPLE_RATE).abs() < 0.1);
+		assert!(coefficients(FilterType::HighShelf).magnitude_db(20.0, SAMPLE_RATE).abs() < 0.1);
+		assert!((coefficients(FilterType::HighShelf).magnitude_db(20000.0, SAMPLE_RATE) - 12.0).abs() < 0.1);
+		assert!(coefficients(FilterType::LowPass).magnitude_db(20.0, SAMPLE_RATE).abs() < 0.01);
+		assert!(coefficients(FilterType::LowPass).magnitude_db(10000.0, SAMPLE_RATE) < -40.0);
+		assert!(coefficients(FilterType::HighPass).magnitude_db(20000.0, SAMPLE_RATE).abs() < 0.01);
+		assert!(coefficients(FilterType::HighPass).magnitude_db(100.0, SAMPLE_RATE) < -40.0);
+		assert!(coefficients(FilterType::Notch).magnitude_db(20.0, SAMPLE_RATE).abs() < 0.01);
+	}
+
+	#[test]
+	fn magnitude_matches_filtering() {
+		for &filter_type in FilterType::ALL.iter() {
+			let coefficients = Coefficients::new(filter_type, SAMPLE_RATE, 1000.0, 1.5, 9.0);
+
+			for &frequency in [300.0, 1000.0, 2500.0].iter() {
+				let mut biquad = Biquad::new();
+				let mut peak = 0.0f64;
+
+				// Skip the transient, then measure the peak over many periods
+				for frame in 0..48000 {
+					let input = (2.0 * PI * frequency * frame as f64 / SAMPLE_RATE).sin();
+					let output = biquad.process(&coefficients, input);
+					if frame >= 24000 {
+						peak = peak.max(output.abs());
+					}
+				}
+
+				let expected = coefficients.magnitude(frequency, SAMPLE_RATE);
+				assert!((peak - expected).abs() < 1e-3 * expected.max(1e-3), "{:?} at {} Hz: {} vs {}", filter_type, frequency, peak, expected);
+			}
+		}
+	}
+}
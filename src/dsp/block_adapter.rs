@@ -0,0 +1,241 @@
+use crate::dsp::audio_block::{AudioBlock, AudioBlockMut, AudioBuffer, ChannelLabel};
+use crate::dsp::processor::Processor;
+use crate::dsp::sample::Sample;
+
+/// Calls a processor with blocks of a fixed size, independent of the driver's buffer size.
+///
+/// If the driver buffer is a multiple of the block size, the processor runs directly on
+/// sub-blocks without added latency. Otherwise input is collected in a FIFO and each block
+/// is processed as soon as it is complete. The output is then delayed by the block size
+/// minus the greatest common divisor of both sizes, the least delay that never runs out of
+/// processed frames, e.g. 192 frames for blocks of 256 and driver buffers of 64.
+pub struct BlockAdapter<S: Sample> {
+	processor: Box<dyn Processor<S>>,
+	block_size: usize,
+	host_buffer_size: usize,
+	latency: usize,
+	input_fifo: AudioBuffer<S>,
+	output_fifo: AudioBuffer<S>,
+	position: usize
+}
+
+fn greatest_common_divisor(mut a: usize, mut b: usize) -> usize {
+	while b != 0 {
+		(a, b) = (b, a % b);
+	}
+	a
+}
+
+impl<S: Sample> BlockAdapter<S> {
+	pub fn new(
+		processor: Box<dyn Processor<S>>,
+		block_size: usize,
+		input_labels: Vec<ChannelLabel>,
+		output_labels: Vec<ChannelLabel>,
+		host_buffer_size: usize
+	) -> BlockAdapter<S> {
+		if block_size == 0 {
+			panic!("Block size must not be zero");
+		}
+
+		let mut adapter = BlockAdapter {
+			processor,
+			block_size,
+			host_buffer_size,
+			latency: 0,
+			input_fifo: AudioBuffer::new(input_labels, block_size),
+			output_fifo: AudioBuffer::new(output_labels, block_size),
+			position: 0
+		};
+		adapter.set_host_buffer_size(host_buffer_size);
+		adapter
+	}
+
+	pub fn block_size(&self) -> usize {
+		self.block_size
+	}
+
+	/// Changes the size of the blocks passed to the processor. Allocates, so do not call
+	/// while the device is running.
+	pub fn set_block_size(&mut self, block_size: usize) {
+		if block_size == 0 {
+			panic!("Block size must not be zero");
+		}
+
+		self.block_size = block_size;
+		self.input_fifo = AudioBuffer::new(self.input_fifo.labels().to_vec(), block_size);
+		self.output_fifo = AudioBuffer::new(self.output_fifo.labels().to_vec(), block_size);
+		self.set_host_buffer_size(self.host_buffer_size);
+	}
+
+	/// Latency added by re-blocking, not including the processor's own latency
+	pub fn added_latency(&self) -> usize {
+		self.latency
+	}
+
+	/// Adapts to a new driver buffer size, e.g. after a reset, and starts over with empty
+	/// FIFOs. Never allocates.
+	pub fn set_host_buffer_size(&mut self, host_buffer_size: usize) {
+		self.host_buffer_size = host_buffer_size;
+		self.latency = self.block_size - greatest_common_divisor(host_buffer_size, self.block_size);
+		self.position = 0;
+		self.input_fifo.as_block_mut().fill(S::ZERO);
+		self.output_fifo.as_block_mut().fill(S::ZERO);
+	}
+
+	fn process_direct(&mut self, input: &AudioBlock<S>, output: &mut AudioBlockMut<S>) {
+		for start in (0..input.num_frames()).step_by(self.block_size) {
+			let input = input.sub_block(start, self.block_size);
+			let mut output = output.sub_block_mut(start, self.block_size);
+			self.processor.process(&input, &mut output);
+		}
+	}
+
+	fn process_buffered(&mut self, input: &AudioBlock<S>, output: &mut AudioBlockMut<S>) {
+		let frames = input.num_frames();
+		let mut done = 0;
+
+		// The frames collected and the processed frames not yet played out always add up to
+		// the latency. The processed ones are the last of the output FIFO, so they are played
+		// before a completed block overwrites it.
+		while done < frames {
+			let len = (frames - done).min(self.block_size - self.position);
+			let ready = self.latency - self.position;
+			let played = len.min(ready);
+
+			let mut fifo_in = self.input_fifo.as_block_mut();
+			fifo_in.sub_block_mut(self.position, len).copy_from(&input.sub_block(done, len));
+			output.sub_block_mut(done, played).copy_from(&self.output_fifo.as_block().sub_block(self.block_size - ready, played));
+
+			self.position += len;
+			if self.position == self.block_size {
+				self.processor.process(&self.input_fifo.as_block(), &mut self.output_fifo.as_block_mut());
+				self.position = 0;
+				output.sub_block_mut(done + played, len - played).copy_from(&self.output_fifo.as_block().sub_block(0, len - played));
+			}
+			done += len;
+		}
+	}
+}
+
+impl<S: Sample> Processor<S> for BlockAdapter<S> {
+	fn process(&mut self, input: &AudioBlock<S>, output: &mut AudioBlockMut<S>) {
+		// A callback of another size goes through the FIFOs unless it ends where the
+		// collected frames exceed the latency. Only then the adapter starts over.
+		if (self.position + input.num_frames()) % self.block_size > self.latency {
+			self.set_host_buffer_size(input.num_frames());
+		}
+
+		if self.latency == 0 {
+			self.process_direct(input, output);
+		} else {
+			self.process_buffered(input, output);
+		}
+	}
+
+	fn latency(&self) -> usize {
+		self.added_latency() + self.processor.latency()
+	}
+
+	fn prepare(&mut self, sample_rate: f64, _max_block_size: usize) {
+		self.processor.prepare(sample_rate, self.block_size);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Doubles the samples, checking that every block has the configured size
+	fn doubler(block_size: usize) -> Box<dyn Processor<f64>> {
+		Box::new(move |input: &AudioBlock<f64>, output: &mut AudioBlockMut<f64>| {
+			assert_eq!(input.num_frames(), block_size);
+			for (target, source) in output.channels_mut().zip(input.channels()) {
+				for (out, sample) in target.iter_mut().zip(source.iter()) {
+					*out = 2.0 * sample;
+				}
+			}
+		})
+	}
+
+	fn adapter(block_size: usize, host_buffer_size: usize) -> BlockAdapter<f64> {
+		BlockAdapter::new(doubler(block_size), block_size, ChannelLabel::numbered(1), ChannelLabel::numbered(1), host_buffer_size)
+	}
+
+	/// Passes a ramp starting at 1 through the adapter in callbacks of the given sizes,
+	/// continuing after the frames already `played`
+	fn run(adapter: &mut BlockAdapter<f64>, played: &mut Vec<f64>, callbacks: &[usize]) {
+		let mut input = AudioBuffer::<f64>::with_channels(1, 4096);
+		let mut output = AudioBuffer::<f64>::with_channels(1, 4096);
+
+		for &frames in callbacks {
+			input.set_num_frames(frames);
+			output.set_num_frames(frames);
+			let start = played.len();
+			for (index, sample) in input.as_block_mut().channel_mut(0).iter_mut().enumerate() {
+				*sample = (start + index + 1) as f64;
+			}
+			adapter.process(&input.as_block(), &mut output.as_block_mut());
+			played.extend_from_slice(output.as_block().channel(0));
+		}
+	}
+
+	/// Checks that `played[from..]` is the doubled ramp delayed by `latency`, silence before it
+	fn assert_delayed(played: &[f64], from: usize, latency: usize) {
+		for (frame, sample) in played.iter().enumerate().skip(from) {
+			let expected = if frame >= from + latency { 2.0 * (frame - latency + 1) as f64 } else { 0.0 };
+			assert_eq!(*sample, expected, "frame {} with a latency of {}", frame, latency);
+		}
+	}
+
+	#[test]
+	fn multiples_of_the_block_size_run_directly() {
+		let mut adapter = adapter(64, 256);
+		assert_eq!(adapter.added_latency(), 0);
+
+		let mut played = Vec::new();
+		run(&mut adapter, &mut played, &[256, 256, 128, 256]);
+		assert_delayed(&played, 0, 0);
+		assert_eq!(adapter.added_latency(), 0);
+	}
+
+	#[test]
+	fn other_sizes_are_buffered_with_the_least_latency() {
+		// Driver buffers dividing the block take block - driver, others block - gcd
+		for &(block_size, host_buffer_size, latency) in [(256, 64, 192), (256, 128, 128), (256, 1, 255), (64, 100, 60), (128, 48, 112), (128, 300, 124), (256, 255, 255)].iter() {
+			let mut adapter = adapter(block_size, host_buffer_size);
+			assert_eq!(adapter.added_latency(), latency);
+			assert_eq!(adapter.latency(), latency);
+
+			let mut played = Vec::new();
+		run(&mut adapter, &mut played, &vec![host_buffer_size; 4000 / host_buffer_size]);
+			assert_delayed(&played, 0, latency);
+		}
+	}
+
+	#[test]
+	fn other_callback_sizes_keep_the_fifos_when_they_can() {
+		// Callbacks of 128 or 192 frames end where 64 frame callbacks do
+		let mut adapter = adapter(256, 64);
+		let mut played = Vec::new();
+		run(&mut adapter, &mut played, &[64, 64, 128, 64, 192, 64, 64, 64, 256, 64]);
+		assert_delayed(&played, 0, 192);
+		assert_eq!(adapter.added_latency(), 192);
+	}
+
+	#[test]
+	fn host_buffer_size_change_starts_over() {
+		let mut adapter = adapter(64, 256);
+		let mut played = Vec::new();
+		run(&mut adapter, &mut played, &[256, 256]);
+		assert_delayed(&played, 0, 0);
+
+		// Buffers of 100 frames after a driver reset need a latency of 64 - gcd(100, 64)
+		run(&mut adapter, &mut played, &[100; 20]);
+		assert_eq!(adapter.added_latency(), 60);
+		assert_delayed(&played, 512, 60);
+
+		adapter.set_host_buffer_size(128);
+		assert_eq!(adapter.added_latency(), 0);
+	}
+}
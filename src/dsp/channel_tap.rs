@@ -0,0 +1,175 @@
+use crate::dsp::audio_block::AudioBlock;
+use crate::dsp::lock_free_queue::LockFreeQueue;
+use crate::dsp::sample::Sample;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Samples the callback hands to the worker thread at once
+const CHUNK_SIZE: usize = 64;
+
+/// Chunks the ring buffer holds, about a third of a second at 48 kHz
+const QUEUE_CHUNKS: usize = 256;
+
+/// Time the worker thread sleeps when the ring buffer is empty
+const IDLE_WAIT: Duration = Duration::from_millis(5);
+
+#[derive(Copy, Clone)]
+struct Chunk {
+	samples: [f32; CHUNK_SIZE],
+	len: usize
+}
+
+struct TapShared {
+	queue: LockFreeQueue<Chunk>,
+	channel: AtomicUsize,
+	dropped: AtomicU64,
+	stop: AtomicBool
+}
+
+/// Channel selection and statistics of a `ChannelTap`, for the UI
+#[derive(Clone)]
+pub struct TapControl {
+	shared: Arc<TapShared>
+}
+
+impl TapControl {
+	/// Channel of the processor's input to tap
+	pub fn set_channel(&self, channel: usize) {
+		self.shared.channel.store(channel, Ordering::Relaxed);
+	}
+
+	pub fn channel(&self) -> usize {
+		self.shared.channel.load(Ordering::Relaxed)
+	}
+
+	/// Chunks the callback dropped because the worker fell behind
+	pub fn dropped_chunks(&self) -> u64 {
+		self.shared.dropped.load(Ordering::Relaxed)
+	}
+}
+
+/// Copies one channel of the blocks passing through into a ring buffer, in chunks, without
+/// locks or allocation. A worker thread of its own hands the samples to a closure.
+pub struct ChannelTap {
+	control: TapControl,
+	chunk: Chunk
+}
+
+impl ChannelTap {
+	/// Starts the worker thread, which ends when the tap is dropped
+	pub fn new<F: FnMut(&[f32]) + Send + 'static>(name: &str, mut push: F) -> ChannelTap {
+		let shared = Arc::new(TapShared {
+			queue: LockFreeQueue::new(QUEUE_CHUNKS),
+			channel: AtomicUsize::new(0),
+			dropped: AtomicU64::new(0),
+			stop: AtomicBool::new(false)
+		});
+
+		let worker = shared.clone();
+		thread::Builder::new()
+			.name(String::from(name))
+			.spawn(move || {
+				while !worker.stop.load(Ordering::Relaxed) {
+					match worker.queue.pop() {
+						Some(chunk) => push(&chunk.samples[..chunk.len]),
+						None => thread::park_timeout(IDLE_WAIT)
+					}
+				}
+			})
+			.unwrap_or_else(|_| panic!("Failed to start the thread \"{}\"", name));
+
+		ChannelTap {
+			control: TapControl { shared },
+			chunk: Chunk {
+				samples: [0.0; CHUNK_SIZE],
+				len: 0
+			}
+		}
+	}
+
+	pub fn control(&self) -> TapControl {
+		self.control.clone()
+	}
+
+	/// Copies the selected channel of `input`, if it has that channel
+	pub fn process<S: Sample>(&mut self, input: &AudioBlock<S>) {
+		let shared = &self.control.shared;
+		let channel = shared.channel.load(Ordering::Relaxed);
+		if channel >= input.num_channels() {
+			return;
+		}
+
+		for sample in input.channel(channel) {
+			self.chunk.samples[self.chunk.len] = sample.to_f64() as f32;
+			self.chunk.len += 1;
+
+			if self.chunk.len == CHUNK_SIZE {
+				if !shared.queue.push(self.chunk) {
+					shared.dropped.fetch_add(1, Ordering::Relaxed);
+				}
+				self.chunk.len = 0;
+			}
+		}
+	}
+}
+
+impl Drop for ChannelTap {
+	fn drop(&mut self) {
+		self.control.shared.stop.store(true, Ordering::Relaxed);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::dsp::audio_block::AudioBuffer;
+	use std::sync::mpsc;
+	use std::sync::Mutex;
+
+	#[test]
+	fn full_queue_drops_chunks() {
+		let (started, wait_start) = mpsc::channel();
+		let (release, wait_release) = mpsc::channel::<()>();
+		let received = Arc::new(Mutex::new(Vec::new()));
+		let sink = received.clone();
+
+		// The worker takes the first chunk, then stalls until released
+		let mut tap = ChannelTap::new("lobster tap test", move |samples| {
+			if sink.lock().unwrap().is_empty() {
+				started.send(()).unwrap();
+				let _ = wait_release.recv();
+			}
+			sink.lock().unwrap().extend_from_slice(samples);
+		});
+		let control = tap.control();
+		control.set_channel(1);
+
+		let mut buffer = AudioBuffer::<f64>::with_channels(2, CHUNK_SIZE);
+		let mut next = 0.0;
+		let mut push_chunk = |tap: &mut ChannelTap| {
+			for sample in buffer.as_block_mut().channel_mut(1).iter_mut() {
+				*sample = next;
+				next += 1.0;
+			}
+			tap.process(&buffer.as_block());
+		};
+
+		push_chunk(&mut tap);
+		wait_start.recv().unwrap();
+		for _ in 0..QUEUE_CHUNKS + 5 {
+			push_chunk(&mut tap);
+		}
+		assert_eq!(control.dropped_chunks(), 5);
+
+		// The chunks that fit arrive in order once the worker catches up
+		drop(release);
+		let expected: Vec<f32> = (0..(QUEUE_CHUNKS + 1) * CHUNK_SIZE).map(|sample| sample as f32).collect();
+		while received.lock().unwrap().len() < expected.len() {
+			thread::sleep(IDLE_WAIT);
+		}
+		assert_eq!(*received.lock().unwrap(), expected);
+		assert_eq!(control.dropped_chunks(), 5);
+	}
+}
@@ -0,0 +1,734 @@
+use crate::dsp::audio_block::{AudioBlock, AudioBlockMut, AudioBuffer};
+use crate::dsp::fft::{Complex, RealFft};
+use crate::dsp::parameters::AtomicF64;
+use crate::dsp::processor::Processor;
+use crate::dsp::resampler::{Resampler, ResamplerQuality};
+use crate::dsp::sample::Sample;
+use crate::dsp::wav::{Wav, WavError};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Duration of the crossfade when a new impulse response takes over
+const CROSSFADE_TIME: f64 = 0.02;
+
+/// Capacity for engines replaced in the callback, until the loader drops them
+const RETIRED_ENGINES: usize = 2;
+
+/// Pause between attempts to hand over an engine, or to see running loads finish
+const LOAD_WAIT: Duration = Duration::from_millis(1);
+
+/// How the channels of an impulse response map onto the processor's channels
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum IrLayout {
+	Mono,			// one response for every channel
+	Stereo,			// one response per channel, the last one repeats for further channels
+	TrueStereo		// four responses LL, LR, RL, RR from the first two inputs to the first two outputs
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImpulseResponse {
+	pub sample_rate: f64,
+	pub channels: Vec<Vec<f64>>
+}
+
+impl ImpulseResponse {
+	pub fn new(sample_rate: f64, channels: Vec<Vec<f64>>) -> ImpulseResponse {
+		if channels.is_empty() {
+			panic!("An impulse response needs at least one channel");
+		}
+
+		ImpulseResponse {
+			sample_rate,
+			channels
+		}
+	}
+
+	pub fn from_wav(wav: Wav) -> ImpulseResponse {
+		ImpulseResponse::new(wav.sample_rate, wav.channels)
+	}
+
+	/// Four channels are a true stereo response, a single channel is mono
+	pub fn layout(&self) -> IrLayout {
+		match self.channels.len() {
+			1 => IrLayout::Mono,
+			4 => IrLayout::TrueStereo,
+			_ => IrLayout::Stereo
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.channels.iter().map(|channel| channel.len()).max().unwrap_or(0)
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// The same response at another sample rate. The level is corrected for the changed
+	/// number of samples, so the frequency response stays the same.
+	pub fn resampled(&self, sample_rate: f64) -> ImpulseResponse {
+		if sample_rate == self.sample_rate {
+			return self.clone();
+		}
+
+		let mut resampler = Resampler::<f64>::new(self.channels.len(), self.sample_rate, sample_rate, ResamplerQuality::High);
+		let gain = self.sample_rate / sample_rate;
+		let channels = resampler.process_all(&self.channels).into_iter()
+			.map(|channel| channel.into_iter().map(|sample| sample * gain).collect())
+			.collect();
+
+		ImpulseResponse::new(sample_rate, channels)
+	}
+
+	/// Input, output and response channel of every convolution
+	fn paths(&self, num_inputs: usize, num_outputs: usize) -> Vec<(usize, usize, usize)> {
+		match self.layout() {
+			IrLayout::Mono => (0..num_inputs.min(num_outputs)).map(|channel| (channel, channel, 0)).collect(),
+			IrLayout::Stereo => (0..num_inputs.min(num_outputs))
+				.map(|channel| (channel, channel, channel.min(self.channels.len() - 1)))
+				.collect(),
+			IrLayout::TrueStereo => (0..num_inputs.min(2))
+				.flat_map(|input| (0..num_outputs.min(2)).map(move |output| (input, output, 2 * input + output)))
+				.collect()
+		}
+	}
+}
+
+struct Path {
+	input: usize,
+	output: usize,
+	partitions: Vec<Vec<Complex>>
+}
+
+/// Spectra of the recent blocks of one input, the slot `current` holds the block being filled
+struct History {
+	window: Vec<f64>,
+	spectra: Vec<Vec<Complex>>,
+	current: usize
+}
+
+/// Uniformly partitioned convolution with one impulse response. Input blocks overlap by
+/// one partition and are transformed as they arrive, so each call yields the output of
+/// its own frames and there is no added latency, whatever the block size. The older
+/// partitions are summed once per partition and only the newest is applied per call.
+pub struct ConvolutionEngine {
+	source: Arc<ImpulseResponse>,
+	sample_rate: f64,
+	partition: usize,
+	fft: RealFft,
+	paths: Vec<Path>,
+	histories: Vec<History>,
+	tails: Vec<Vec<Complex>>,
+	accumulator: Vec<Complex>,
+	time: Vec<f64>,
+	fill: usize
+}
+
+impl ConvolutionEngine {
+	/// `partition` frames per partition, a power of two. `source` is resampled to `sample_rate`.
+	pub fn new(source: Arc<ImpulseResponse>, sample_rate: f64, num_inputs: usize, num_outputs: usize, partition: usize) -> ConvolutionEngine {
+		if !partition.is_power_of_two() {
+			panic!("Partition size {} is not a power of two", partition);
+		}
+
+		let ir = source.resampled(sample_rate);
+		let mut fft = RealFft::new(2 * partition);
+		let bins = fft.spectrum_len();
+		let num_partitions = ir.len().div_ceil(partition).max(1);
+		let mut time = vec![0.0; 2 * partition];
+
+		let paths = ir.paths(num_inputs, num_outputs).into_iter().map(|(input, output, channel)| {
+			let response = &ir.channels[channel];
+			let partitions = (0..num_partitions).map(|index| {
+				let start = (index * partition).min(response.len());
+				let end = (start + partition).min(response.len());
+
+				time.fill(0.0);
+				time[..end - start].copy_from_slice(&response[start..end]);
+				let mut spectrum = vec![Complex::ZERO; bins];
+				fft.forward(&time, &mut spectrum);
+				spectrum
+			}).collect();
+
+			Path {
+				input,
+				output,
+				partitions
+			}
+		}).collect();
+
+		ConvolutionEngine {
+			source,
+			sample_rate,
+			partition,
+			fft,
+			paths,
+			histories: (0..num_inputs).map(|_| History {
+				window: vec![0.0; 2 * partition],
+				spectra: vec![vec![Complex::ZERO; bins]; num_partitions],
+				current: 0
+			}).collect(),
+			tails: vec![vec![Complex::ZERO; bins]; num_outputs],
+			accumulator: vec![Complex::ZERO; bins],
+			time,
+			fill: 0
+		}
+	}
+
+	/// The response as loaded, before resampling
+	pub fn source(&self) -> &Arc<ImpulseResponse> {
+		&self.source
+	}
+
+	pub fn sample_rate(&self) -> f64 {
+		self.sample_rate
+	}
+
+	pub fn partition(&self) -> usize {
+		self.partition
+	}
+
+	/// Replaces the output with the convolved input
+	pub fn process<S: Sample>(&mut self, input: &AudioBlock<S>, output: &mut AudioBlockMut<S>) {
+		let frames = output.num_frames();
+		let mut done = 0;
+
+		while done < frames {
+			let len = (self.partition - self.fill).min(frames - done);
+			let start = self.partition + self.fill;
+
+			for (index, history) in self.histories.iter_mut().enumerate() {
+				let window = &mut history.window[start..start + len];
+				if index < input.num_channels() {
+					for (target, sample) in window.iter_mut().zip(&input.channel(index)[done..done + len]) {
+						*target = sample.to_f64();
+					}
+				}
+				self.fft.forward(&history.window, &mut history.spectra[history.current]);
+			}
+
+			for (index, tail) in self.tails.iter().enumerate() {
+				if index >= output.num_channels() {
+					break;
+				}
+
+				self.accumulator.copy_from_slice(tail);
+				for path in self.paths.iter().filter(|path| path.output == index) {
+					let history = &self.histories[path.input];
+					for ((sum, x), h) in self.accumulator.iter_mut().zip(&history.spectra[history.current]).zip(&path.partitions[0]) {
+						*sum += *x * *h;
+					}
+				}
+
+				self.fft.inverse(&self.accumulator, &mut self.time);
+				for (target, value) in output.channel_mut(index)[done..done + len].iter_mut().zip(&self.time[start..start + len]) {
+					*target = S::from_f64(*value);
+				}
+			}
+			for channel in self.tails.len()..output.num_channels() {
+				output.channel_mut(channel)[done..done + len].fill(S::ZERO);
+			}
+
+			self.fill += len;
+			done += len;
+			if self.fill == self.partition {
+				self.next_partition();
+			}
+		}
+	}
+
+	pub fn reset(&mut self) {
+		for history in self.histories.iter_mut() {
+			history.window.fill(0.0);
+			for spectrum in history.spectra.iter_mut() {
+				spectrum.fill(Complex::ZERO);
+			}
+		}
+		for tail in self.tails.iter_mut() {
+			tail.fill(Complex::ZERO);
+		}
+		self.fill = 0;
+	}
+
+	/// Moves on after a complete partition and sums the older partitions for the next
+	fn next_partition(&mut self) {
+		let partition = self.partition;
+
+		for history in self.histories.iter_mut() {
+			history.window.copy_within(partition.., 0);
+			history.window[partition..].fill(0.0);
+			history.current = (history.current + 1) % history.spectra.len();
+		}
+
+		for tail in self.tails.iter_mut() {
+			tail.fill(Complex::ZERO);
+		}
+		for path in self.paths.iter() {
+			let history = &self.histories[path.input];
+			let count = history.spectra.len();
+			let tail = &mut self.tails[path.output];
+
+			for (age, response) in path.partitions.iter().enumerate().skip(1) {
+				let spectrum = &history.spectra[(history.current + count - age) % count];
+				for ((sum, x), h) in tail.iter_mut().zip(spectrum).zip(response) {
+					*sum += *x * *h;
+				}
+			}
+		}
+
+		self.fill = 0;
+	}
+}
+
+/// Settings of the last `prepare`, engines are built for them
+struct Config {
+	sample_rate: AtomicF64,
+	partition: AtomicUsize,
+	num_inputs: usize,
+	num_outputs: usize,
+	/// Loads whose engine has not been handed to the callback yet
+	loading: AtomicUsize
+}
+
+impl Config {
+	fn build(&self, source: Arc<ImpulseResponse>) -> ConvolutionEngine {
+		ConvolutionEngine::new(source, self.sample_rate.load(), self.num_inputs, self.num_outputs, self.partition.load(Ordering::Relaxed))
+	}
+}
+
+/// Counts a load in `Config::loading` from its start until its thread is done
+struct Loading(Arc<Config>);
+
+impl Loading {
+	fn start(config: Arc<Config>) -> Loading {
+		config.loading.fetch_add(1, Ordering::SeqCst);
+		Loading(config)
+	}
+
+	fn config(&self) -> &Config {
+		&self.0
+	}
+}
+
+impl Drop for Loading {
+	fn drop(&mut self) {
+		self.0.loading.fetch_sub(1, Ordering::SeqCst);
+	}
+}
+
+/// Convolves its input with an impulse response, loaded at any time with the
+/// `ConvolutionLoader`. The output is silent until a response is loaded. A new response
+/// crossfades with the previous one.
+pub struct Convolution<S: Sample> {
+	engine: Option<Box<ConvolutionEngine>>,
+	fading: Option<Box<ConvolutionEngine>>,
+	fade_position: usize,
+	fade_length: usize,
+	scratch: AudioBuffer<S>,
+	pending: Receiver<Box<ConvolutionEngine>>,
+	retired: SyncSender<Box<ConvolutionEngine>>,
+	/// A replaced engine that found `retired` full, no new engine is taken until it is sent
+	retiring: Option<Box<ConvolutionEngine>>,
+	config: Arc<Config>
+}
+
+/// Control side of a `Convolution`, loads responses on background threads
+pub struct ConvolutionLoader {
+	pending: SyncSender<Box<ConvolutionEngine>>,
+	retired: Arc<Mutex<Receiver<Box<ConvolutionEngine>>>>,
+	config: Arc<Config>
+}
+
+impl<S: Sample> Convolution<S> {
+	pub fn new(num_inputs: usize, num_outputs: usize, sample_rate: f64, max_block_size: usize) -> (Convolution<S>, ConvolutionLoader) {
+		let (pending_sender, pending_receiver) = mpsc::sync_channel(1);
+		let (retired_sender, retired_receiver) = mpsc::sync_channel(RETIRED_ENGINES);
+		let config = Arc::new(Config {
+			sample_rate: AtomicF64::new(sample_rate),
+			partition: AtomicUsize::new(max_block_size.next_power_of_two()),
+			num_inputs,
+			num_outputs,
+			loading: AtomicUsize::new(0)
+		});
+
+		let convolution = Convolution {
+			engine: None,
+			fading: None,
+			fade_position: 0,
+			fade_length: (CROSSFADE_TIME * sample_rate) as usize,
+			scratch: AudioBuffer::with_channels(num_outputs, max_block_size),
+			pending: pending_receiver,
+			retired: retired_sender,
+			retiring: None,
+			config: config.clone()
+		};
+		let loader = ConvolutionLoader {
+			pending: pending_sender,
+			retired: Arc::new(Mutex::new(retired_receiver)),
+			config
+		};
+		(convolution, loader)
+	}
+
+	/// Hands a replaced engine to the loader, or keeps it until there is room
+	fn retire(&mut self, engine: Box<ConvolutionEngine>) {
+		if let Err(TrySendError::Full(engine)) = self.retired.try_send(engine) {
+			self.retiring = Some(engine);
+		}
+	}
+}
+
+impl<S: Sample> Processor<S> for Convolution<S> {
+	fn process(&mut self, input: &AudioBlock<S>, output: &mut AudioBlockMut<S>) {
+		if let Some(engine) = self.retiring.take() {
+			self.retire(engine);
+		}
+
+		// A new response waits while the previous one fades out or cannot be handed back
+		if self.fading.is_none() && self.retiring.is_none() {
+			if let Ok(engine) = self.pending.try_recv() {
+				self.fading = self.engine.replace(engine);
+				self.fade_position = 0;
+			}
+		}
+
+		match self.engine.as_mut() {
+			Some(engine) => engine.process(input, output),
+			None => output.fill(S::ZERO)
+		}
+
+		if let Some(fading) = self.fading.as_mut() {
+			let frames = output.num_frames();
+			let mut scratch = self.scratch.as_block_mut();
+			let mut scratch = scratch.sub_block_mut(0, frames);
+			fading.process(input, &mut scratch);
+
+			for (target, old) in output.channels_mut().zip(scratch.as_block().channels()) {
+				for (frame, (sample, old)) in target.iter_mut().zip(old).enumerate() {
+					let gain = ((self.fade_position + frame) as f64 / self.fade_length as f64).min(1.0);
+					*sample = *old + (*sample - *old) * S::from_f64(gain);
+				}
+			}
+
+			self.fade_position += frames;
+			if self.fade_position >= self.fade_length {
+				let fading = self.fading.take().unwrap();
+				self.retire(fading);
+			}
+		}
+	}
+
+	fn prepare(&mut self, sample_rate: f64, max_block_size: usize) {
+		let partition = max_block_size.next_power_of_two();
+		self.config.sample_rate.store(sample_rate);
+		self.config.partition.store(partition, Ordering::Relaxed);
+		self.fade_length = (CROSSFADE_TIME * sample_rate) as usize;
+		self.scratch = AudioBuffer::with_channels(self.config.num_outputs, max_block_size);
+
+		// Loads running now may build for the previous settings, so the callback could get an
+		// engine it cannot use. Wait for them and keep the latest response, then rebuild it
+		// here, where allocating is fine.
+		loop {
+			let finished = self.config.loading.load(Ordering::SeqCst) == 0;
+			while let Ok(engine) = self.pending.try_recv() {
+				self.engine = Some(engine);
+			}
+			if finished {
+				break;
+			}
+			thread::sleep(LOAD_WAIT);
+		}
+		self.fading = None;
+		self.retiring = None;
+		if let Some(engine) = self.engine.as_mut() {
+			if engine.partition() != partition || engine.sample_rate() != sample_rate {
+				**engine = self.config.build(engine.source().clone());
+			}
+		}
+	}
+}
+
+impl ConvolutionLoader {
+	/// Prepares `ir` on a new thread and hands it to the callback, which fades over to it.
+	/// The thread waits while a previously loaded response has not been picked up yet.
+	pub fn load(&self, ir: ImpulseResponse) -> JoinHandle<()> {
+		self.collect_garbage();
+
+		let loading = Loading::start(self.config.clone());
+		let pending = self.pending.clone();
+		let retired = self.retired.clone();
+
+		thread::spawn(move || {
+			let engine = loading.config().build(Arc::new(ir));
+			ConvolutionLoader::hand_over(engine, &pending, &retired);
+		})
+	}
+
+	/// Reads a WAVE file on a new thread and loads it like `load`
+	pub fn load_file(&self, path: impl Into<PathBuf>) -> JoinHandle<Result<(), WavError>> {
+		self.collect_garbage();
+
+		let path = path.into();
+		let loading = Loading::start(self.config.clone());
+		let pending = self.pending.clone();
+		let retired = self.retired.clone();
+
+		thread::spawn(move || {
+			let ir = ImpulseResponse::from_wav(Wav::read(path)?);
+			let engine = loading.config().build(Arc::new(ir));
+			ConvolutionLoader::hand_over(engine, &pending, &retired);
+			Ok(())
+		})
+	}
+
+	/// Drops engines the callback has replaced
+	pub fn collect_garbage(&self) {
+		ConvolutionLoader::drop_retired(&self.retired);
+	}
+
+	/// Waits until the callback takes `engine`. Engines it retires meanwhile are dropped,
+	/// as the callback only takes a new engine once it could hand back the previous one.
+	fn hand_over(engine: ConvolutionEngine, pending: &SyncSender<Box<ConvolutionEngine>>, retired: &Mutex<Receiver<Box<ConvolutionEngine>>>) {
+		let mut engine = Box::new(engine);
+
+		loop {
+			ConvolutionLoader::drop_retired(retired);
+
+			match pending.try_send(engine) {
+				Ok(()) => return,
+				Err(TrySendError::Full(unsent)) => engine = unsent,
+				// The callback is gone, the response is not needed anymore
+				Err(TrySendError::Disconnected(_)) => return
+			}
+			thread::sleep(LOAD_WAIT);
+		}
+	}
+
+	fn drop_retired(retired: &Mutex<Receiver<Box<ConvolutionEngine>>>) {
+		let retired = retired.lock().unwrap();
+		while retired.try_recv().is_ok() {}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::asio_core::random::Random;
+	use crate::dsp::parameters::gain_to_db;
+	use std::f64::consts::{PI, SQRT_2};
+
+	fn noise(random: &mut Random, len: usize) -> Vec<f64> {
+		(0..len).map(|_| random.next_f64() - 0.5).collect()
+	}
+
+	/// Adds `input` convolved with `response` to `output`
+	fn direct(input: &[f64], response: &[f64], output: &mut [f64]) {
+		for (n, sample) in output.iter_mut().enumerate() {
+			for (k, h) in response.iter().enumerate().take(n + 1) {
+				*sample += h * input[n - k];
+			}
+		}
+	}
+
+	/// Runs the engine over `inputs` in calls of varying size
+	fn run_engine(engine: &mut ConvolutionEngine, inputs: &[Vec<f64>], num_outputs: usize) -> Vec<Vec<f64>> {
+		let frames = inputs[0].len();
+		let sizes = [1, 7, 64, 13, 150, 64, 3, 200, 31];
+		let mut input = AudioBuffer::with_channels(inputs.len(), 200);
+		let mut output = AudioBuffer::with_channels(num_outputs, 200);
+		let mut result = vec![Vec::with_capacity(frames); num_outputs];
+		let mut done = 0;
+
+		for size in sizes.iter().cycle() {
+			if done == frames {
+				break;
+			}
+			let len = (*size).min(frames - done);
+
+			input.set_num_frames(len);
+			output.set_num_frames(len);
+			for (channel, samples) in inputs.iter().enumerate() {
+				input.as_block_mut().channel_mut(channel).copy_from_slice(&samples[done..done + len]);
+			}
+			engine.process(&input.as_block(), &mut output.as_block_mut());
+			for (channel, samples) in result.iter_mut().enumerate() {
+				samples.extend_from_slice(output.as_block().channel(channel));
+			}
+			done += len;
+		}
+		result
+	}
+
+	fn assert_close(actual: &[f64], expected: &[f64]) {
+		for (frame, (a, e)) in actual.iter().zip(expected).enumerate() {
+			assert!((a - e).abs() < 1e-9, "frame {}: {} instead of {}", frame, a, e);
+		}
+	}
+
+	#[test]
+	fn engine_matches_direct_convolution() {
+		let mut random = Random::new(45);
+		let frames = 3000;
+		let inputs = vec![noise(&mut random, frames), noise(&mut random, frames)];
+
+		for &(num_channels, ir_len) in [(1, 1), (1, 300), (2, 64), (2, 1000), (4, 257)].iter() {
+			let channels: Vec<Vec<f64>> = (0..num_channels).map(|_| noise(&mut random, ir_len)).collect();
+			let ir = Arc::new(ImpulseResponse::new(48000.0, channels.clone()));
+			let mut engine = ConvolutionEngine::new(ir.clone(), 48000.0, 2, 2, 64);
+			let outputs = run_engine(&mut engine, &inputs, 2);
+
+			let mut expected = vec![vec![0.0; frames]; 2];
+			match ir.layout() {
+				IrLayout::Mono => {
+					direct(&inputs[0], &channels[0], &mut expected[0]);
+					direct(&inputs[1], &channels[0], &mut expected[1]);
+				},
+				IrLayout::Stereo => {
+					direct(&inputs[0], &channels[0], &mut expected[0]);
+					direct(&inputs[1], &channels[1], &mut expected[1]);
+				},
+				IrLayout::TrueStereo => {
+					direct(&inputs[0], &channels[0], &mut expected[0]);
+					direct(&inputs[0], &channels[1], &mut expected[1]);
+					direct(&inputs[1], &channels[2], &mut expected[0]);
+					direct(&inputs[1], &channels[3], &mut expected[1]);
+				}
+			}
+
+			for channel in 0..2 {
+				assert_close(&outputs[channel], &expected[channel]);
+			}
+		}
+	}
+
+	#[test]
+	fn extra_outputs_are_silent() {
+		let ir = Arc::new(ImpulseResponse::new(48000.0, vec![vec![1.0, 0.5]]));
+		let mut engine = ConvolutionEngine::new(ir, 48000.0, 1, 1, 16);
+		let outputs = run_engine(&mut engine, &[vec![1.0; 100], vec![1.0; 100]], 2);
+
+		assert!(outputs[0][1..].iter().all(|sample| (sample - 1.5).abs() < 1e-12));
+		assert!(outputs[1].iter().all(|sample| *sample == 0.0));
+	}
+
+	#[test]
+	fn responses_are_resampled_to_the_engine_rate() {
+		// 100 ms of exponential decay at 44.1 kHz
+		let response: Vec<f64> = (0..4410).map(|frame| 0.01 * (-(frame as f64) / 441.0).exp()).collect();
+		let ir = Arc::new(ImpulseResponse::new(44100.0, vec![response.clone()]));
+		let resampled = ir.resampled(48000.0);
+		assert_eq!(resampled.sample_rate, 48000.0);
+		assert_eq!(resampled.len(), 4800);
+
+		let mut engine = ConvolutionEngine::new(ir, 48000.0, 1, 1, 64);
+		assert_eq!(engine.sample_rate(), 48000.0);
+
+		// The engine convolves with the resampled response, which is as long as the engine's
+		let mut impulse = vec![0.0; 6000];
+		impulse[0] = 1.0;
+		let output = run_engine(&mut engine, &[impulse], 1).remove(0);
+		assert_close(&output[..4800], &resampled.channels[0]);
+		assert!(output[4800..].iter().all(|sample| sample.abs() < 1e-12));
+
+		// And sines pass with the gain of the response as loaded
+		for frequency in [100.0, 1000.0, 5000.0] {
+			let expected = response.iter().enumerate()
+				.map(|(frame, sample)| Complex::from_angle(-2.0 * PI * frequency * frame as f64 / 44100.0).scale(*sample))
+				.fold(Complex::ZERO, |sum, term| sum + term)
+				.abs();
+
+			engine.reset();
+			let input: Vec<f64> = (0..14400).map(|frame| (2.0 * PI * frequency * frame as f64 / 48000.0).sin()).collect();
+			let output = run_engine(&mut engine, &[input], 1).remove(0);
+			let rms = (output[4800..].iter().map(|sample| sample * sample).sum::<f64>() / 9600.0).sqrt();
+			assert!((gain_to_db(rms * SQRT_2) - gain_to_db(expected)).abs() < 0.01, "{} Hz", frequency);
+		}
+	}
+
+	/// Processes silence with an impulse at the start, returns the gain of the response
+	fn response_gain(convolution: &mut Convolution<f64>) -> f64 {
+		let mut input = AudioBuffer::with_channels(1, 64);
+		let mut output = AudioBuffer::with_channels(1, 64);
+		convolution.engine.as_mut().unwrap().reset();
+
+		input.as_block_mut().channel_mut(0)[0] = 1.0;
+		convolution.process(&input.as_block(), &mut output.as_block_mut());
+		output.as_block().channel(0)[0]
+	}
+
+	#[test]
+	fn loads_keep_every_engine() {
+		let (mut convolution, loader) = Convolution::<f64>::new(1, 1, 48000.0, 64);
+		let gains = [0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8];
+		let handles: Vec<JoinHandle<()>> = gains.iter()
+			.map(|gain| loader.load(ImpulseResponse::new(48000.0, vec![vec![*gain]])))
+			.collect();
+
+		// Nothing collects garbage on this side, the loading threads have to
+		let input = AudioBuffer::with_channels(1, 64);
+		let mut output = AudioBuffer::with_channels(1, 64);
+		let mut blocks = 0;
+		while !handles.iter().all(|handle| handle.is_finished()) || convolution.fading.is_some() {
+			convolution.process(&input.as_block(), &mut output.as_block_mut());
+			blocks += 1;
+			assert!(blocks < 100000, "The loads did not finish");
+		}
+		for handle in handles {
+			handle.join().unwrap();
+		}
+
+		let gain = response_gain(&mut convolution);
+		assert!(gains.iter().any(|loaded| (loaded - gain).abs() < 1e-12), "Unexpected gain {}", gain);
+	}
+
+	#[test]
+	fn prepare_rebuilds_running_loads() {
+		let (mut convolution, loader) = Convolution::<f64>::new(1, 1, 48000.0, 64);
+
+		// Long enough that the load is still running when the settings change
+		let mut response = vec![0.0; 480000];
+		response[0] = 0.5;
+		let handle = loader.load(ImpulseResponse::new(48000.0, vec![response]));
+		convolution.prepare(48000.0, 256);
+		handle.join().unwrap();
+
+		let engine = convolution.engine.as_ref().expect("The response was lost");
+		assert_eq!(engine.partition(), 256);
+		assert!((response_gain(&mut convolution) - 0.5).abs() < 1e-9);
+	}
+
+	#[test]
+	fn full_retired_channel_holds_back_new_engines() {
+		let (mut convolution, loader) = Convolution::<f64>::new(1, 1, 48000.0, 64);
+		let engine = |gain: f64| Box::new(loader.config.build(Arc::new(ImpulseResponse::new(48000.0, vec![vec![gain]]))));
+		let input = AudioBuffer::with_channels(1, 64);
+		let mut output = AudioBuffer::with_channels(1, 64);
+		let mut run = |convolution: &mut Convolution<f64>, blocks: usize| {
+			for _ in 0..blocks {
+				convolution.process(&input.as_block(), &mut output.as_block_mut());
+			}
+		};
+		let fade_blocks = convolution.fade_length / 64 + 1;
+
+		loader.pending.send(engine(0.1)).unwrap();
+		run(&mut convolution, 1);
+		loader.pending.send(engine(0.2)).unwrap();
+		while convolution.retired.try_send(engine(0.0)).is_ok() {}
+
+		// The faded out engine waits for room, and the next one waits for it
+		run(&mut convolution, fade_blocks);
+		assert!(convolution.retiring.is_some());
+		loader.pending.send(engine(0.3)).unwrap();
+		run(&mut convolution, fade_blocks);
+		assert!((response_gain(&mut convolution) - 0.2).abs() < 1e-12);
+
+		loader.collect_garbage();
+		run(&mut convolution, fade_blocks);
+		assert!(convolution.retiring.is_none());
+		assert!((response_gain(&mut convolution) - 0.3).abs() < 1e-12);
+	}
+}
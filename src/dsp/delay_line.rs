@@ -0,0 +1,41 @@
+use crate::dsp::sample::Sample;
+
+/// Delays a single channel by a whole number of frames
+pub struct DelayLine<S: Sample> {
+	buffer: Vec<S>,
+	position: usize
+}
+
+impl<S: Sample> DelayLine<S> {
+	pub fn new(delay: usize) -> DelayLine<S> {
+		DelayLine {
+			buffer: vec![S::ZERO; delay],
+			position: 0
+		}
+	}
+
+	pub fn delay(&self) -> usize {
+		self.buffer.len()
+	}
+
+	pub fn reset(&mut self) {
+		self.buffer.fill(S::ZERO);
+		self.position = 0;
+	}
+
+	/// Adds `input`, delayed, to `output`
+	pub fn add_to(&mut self, input: &[S], output: &mut [S]) {
+		if self.buffer.is_empty() {
+			for (sample, value) in output.iter_mut().zip(input) {
+				*sample += *value;
+			}
+			return;
+		}
+
+		for (sample, value) in output.iter_mut().zip(input) {
+			*sample += self.buffer[self.position];
+			self.buffer[self.position] = *value;
+			self.position = (self.position + 1) % self.buffer.len();
+		}
+	}
+}
@@ -0,0 +1,566 @@
+use crate::dsp::audio_block::{AudioBlock, AudioBlockMut};
+use crate::dsp::parameters::{db_to_gain, gain_to_db, AtomicF64, ParameterId, ParameterInfo, ParameterRegistry, Parameters, SmoothedParameter, Smoothing, Unit};
+use crate::dsp::processor::{pass_through, Processor};
+use crate::dsp::sample::Sample;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Levels below this are treated as silence by the detectors
+const SILENCE_DB: f64 = -120.0;
+
+/// `DynamicsControl::sidechain` value when the processed channels drive the detector
+const NO_SIDECHAIN: usize = usize::MAX;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DynamicsMode {
+	Compressor,		// reduces levels above the threshold by the ratio
+	Expander,		// reduces levels below the threshold by the ratio, down to the range
+	Gate			// attenuates by the range below the threshold
+}
+
+/// Shared state of a dynamics processor, for the UI
+#[derive(Clone)]
+pub struct DynamicsControl {
+	parameters: Parameters,
+	sidechain: Arc<AtomicUsize>,
+	gain_reduction: Arc<AtomicF64>
+}
+
+impl DynamicsControl {
+	fn new(parameters: Parameters) -> DynamicsControl {
+		DynamicsControl {
+			parameters,
+			sidechain: Arc::new(AtomicUsize::new(NO_SIDECHAIN)),
+			gain_reduction: Arc::new(AtomicF64::new(0.0))
+		}
+	}
+
+	pub fn parameters(&self) -> &Parameters {
+		&self.parameters
+	}
+
+	/// Drives the detector from the given input channel instead of the processed channels,
+	/// which are then passed on unchanged apart from the gain. A channel the processor does
+	/// not receive falls back to the processed channels.
+	pub fn set_sidechain(&self, channel: Option<usize>) {
+		self.sidechain.store(channel.unwrap_or(NO_SIDECHAIN), Ordering::Relaxed);
+	}
+
+	pub fn sidechain(&self) -> Option<usize> {
+		match self.sidechain.load(Ordering::Relaxed) {
+			NO_SIDECHAIN => None,
+			channel => Some(channel)
+		}
+	}
+
+	/// Largest gain reduction during the last block, in dB as a positive number
+	pub fn gain_reduction_db(&self) -> f64 {
+		self.gain_reduction.load()
+	}
+}
+
+/// Copies the processed channels and reads the sidechain channel for the detector
+fn detector_input<'a, S: Sample>(control: &DynamicsControl, input: &AudioBlock<'a, S>, output: &mut AudioBlockMut<S>) -> Option<&'a [S]> {
+	pass_through(input, output);
+
+	control.sidechain().filter(|channel| *channel < input.num_channels()).map(|channel| input.channel(channel))
+}
+
+/// Peak level of a frame in dB, the sidechain if there is one or the loudest of the first
+/// `num_channels` channels
+fn detector_level<S: Sample>(sidechain: Option<&[S]>, output: &AudioBlockMut<S>, num_channels: usize, frame: usize) -> f64 {
+	let peak = match sidechain {
+		Some(channel) => channel[frame].abs().to_f64(),
+		None => (0..num_channels).map(|channel| output.channel(channel)[frame].abs().to_f64()).fold(0.0, f64::max)
+	};
+
+	gain_to_db(peak).max(SILENCE_DB)
+}
+
+/// Coefficient of a one-pole smoother settling within about `time` seconds
+fn time_coefficient(time: f64, sample_rate: f64) -> f64 {
+	let samples = time * sample_rate;
+	if samples > 0.0 { (-1.0 / samples).exp() } else { 0.0 }
+}
+
+fn ms_parameter(name: &str, min: f64, max: f64, default: f64) -> ParameterInfo {
+	ParameterInfo::new(name, min, max, default, Unit::Milliseconds).with_smoothing(Smoothing::None, 0.0)
+}
+
+fn db_parameter(name: &str, min: f64, max: f64, default: f64) -> ParameterInfo {
+	ParameterInfo::new(name, min, max, default, Unit::Decibels).with_smoothing(Smoothing::None, 0.0)
+}
+
+struct DynamicsIds {
+	threshold: ParameterId,
+	ratio: ParameterId,
+	knee: ParameterId,
+	range: ParameterId,
+	attack: ParameterId,
+	hold: ParameterId,
+	release: ParameterId,
+	makeup: ParameterId
+}
+
+/// Feed-forward compressor, expander or gate on peak levels. The gain is smoothed in dB,
+/// with the attack time while a compressor reduces the gain or an expander or gate opens,
+/// and with the release time otherwise. All channels receive the same gain.
+pub struct Dynamics<S: Sample> {
+	mode: DynamicsMode,
+	control: DynamicsControl,
+	ids: DynamicsIds,
+	makeup: SmoothedParameter<f64>,
+	sample_rate: f64,
+	reduction: f64,
+	hold_remaining: usize,
+	phantom: std::marker::PhantomData<S>
+}
+
+impl<S: Sample> Dynamics<S> {
+	/// Parameters "Threshold", "Ratio", "Knee", "Range", "Attack", "Hold", "Release" and
+	/// "Makeup", with defaults suiting the mode. Compressors ignore range and hold, gates
+	/// ignore ratio and knee.
+	pub fn new(mode: DynamicsMode, sample_rate: f64) -> Dynamics<S> {
+		let (threshold, ratio, range, attack, hold, release) = match mode {
+			DynamicsMode::Compressor => (-20.0, 4.0, -100.0, 10.0, 0.0, 100.0),
+			DynamicsMode::Expander => (-40.0, 2.0, -40.0, 1.0, 20.0, 100.0),
+			DynamicsMode::Gate => (-50.0, 100.0, -80.0, 0.5, 50.0, 100.0)
+		};
+
+		let mut registry = ParameterRegistry::new();
+		let ids = DynamicsIds {
+			threshold: registry.add(db_parameter("Threshold", -80.0, 0.0, threshold)),
+			ratio: registry.add(ParameterInfo::new("Ratio", 1.0, 100.0, ratio, Unit::None).with_smoothing(Smoothing::None, 0.0)),
+			knee: registry.add(db_parameter("Knee", 0.0, 24.0, 6.0)),
+			range: registry.add(db_parameter("Range", -100.0, 0.0, range)),
+			attack: registry.add(ms_parameter("Attack", 0.01, 500.0, attack)),
+			hold: registry.add(ms_parameter("Hold", 0.0, 2000.0, hold)),
+			release: registry.add(ms_parameter("Release", 1.0, 5000.0, release)),
+			makeup: registry.add(ParameterInfo::new("Makeup", 0.0, 40.0, 0.0, Unit::Decibels))
+		};
+		let parameters = registry.build();
+
+		Dynamics {
+			mode,
+			makeup: parameters.smoother(ids.makeup, sample_rate),
+			control: DynamicsControl::new(parameters),
+			ids,
+			sample_rate,
+			reduction: 0.0,
+			hold_remaining: 0,
+			phantom: std::marker::PhantomData
+		}
+	}
+
+	pub fn mode(&self) -> DynamicsMode {
+		self.mode
+	}
+
+	/// Handle for the parameters, sidechain and metering
+	pub fn control(&self) -> DynamicsControl {
+		self.control.clone()
+	}
+
+	/// Gain change in dB for a detector level, never positive
+	pub fn static_gain(&self, level: f64) -> f64 {
+		let parameters = &self.control.parameters;
+		let over = level - parameters.get(self.ids.threshold);
+		let ratio = parameters.get(self.ids.ratio);
+		let knee = parameters.get(self.ids.knee);
+		let range = parameters.get(self.ids.range);
+
+		match self.mode {
+			DynamicsMode::Compressor => {
+				if 2.0 * over <= -knee {
+					0.0
+				} else if 2.0 * over < knee {
+					(1.0 / ratio - 1.0) * (over + knee / 2.0).powi(2) / (2.0 * knee)
+				} else {
+					(1.0 / ratio - 1.0) * over
+				}
+			},
+			DynamicsMode::Expander => {
+				let gain = if 2.0 * over >= knee {
+					0.0
+				} else if 2.0 * over > -knee {
+					-(ratio - 1.0) * (over - knee / 2.0).powi(2) / (2.0 * knee)
+				} else {
+					(ratio - 1.0) * over
+				};
+				gain.max(range)
+			},
+			DynamicsMode::Gate => if over < 0.0 { range } else { 0.0 }
+		}
+	}
+}
+
+impl<S: Sample> Processor<S> for Dynamics<S> {
+	fn process(&mut self, input: &AudioBlock<S>, output: &mut AudioBlockMut<S>) {
+		let sidechain = detector_input(&self.control, input, output);
+		let parameters = &self.control.parameters;
+		let attack = time_coefficient(parameters.get(self.ids.attack) / 1000.0, self.sample_rate);
+		let release = time_coefficient(parameters.get(self.ids.release) / 1000.0, self.sample_rate);
+		let hold = (parameters.get(self.ids.hold) / 1000.0 * self.sample_rate) as usize;
+		let threshold = parameters.get(self.ids.threshold);
+		let mut largest = 0.0f64;
+
+		self.makeup.update(output.num_frames());
+
+		for frame in 0..output.num_frames() {
+			let level = detector_level(sidechain, output, output.num_channels(), frame);
+			let target = self.static_gain(level);
+			let closing = target < self.reduction;
+
+			if level >= threshold {
+				self.hold_remaining = hold;
+			}
+
+			let coefficient = match self.mode {
+				DynamicsMode::Compressor => if closing { attack } else { release },
+				_ if closing && self.hold_remaining > 0 => {
+					self.hold_remaining -= 1;
+					1.0
+				},
+				_ => if closing { release } else { attack }
+			};
+			self.reduction = target + (self.reduction - target) * coefficient;
+			largest = largest.max(-self.reduction);
+
+			let gain = S::from_f64(db_to_gain(self.reduction + self.makeup.next_value()));
+			for channel in output.channels_mut() {
+				channel[frame] *= gain;
+			}
+		}
+
+		self.control.gain_reduction.store(largest);
+	}
+
+	fn prepare(&mut self, sample_rate: f64, _max_block_size: usize) {
+		self.sample_rate = sample_rate;
+		self.makeup = self.control.parameters.smoother(self.ids.makeup, sample_rate);
+	}
+}
+
+/// Brickwall limiter that sees peaks coming by delaying the signal by the lookahead time.
+/// The gain needed for each frame is held for the lookahead and averaged over it, so the
+/// gain has ramped down by the time a peak leaves the delay. The lookahead in frames, and
+/// with it the latency, follows the sample rate.
+pub struct Limiter<S: Sample> {
+	control: DynamicsControl,
+	ceiling: ParameterId,
+	release: ParameterId,
+	lookahead_time: f64,
+	lookahead: usize,
+	sample_rate: f64,
+	delay: Vec<Vec<S>>,
+	delay_position: usize,
+	minimum: VecDeque<(usize, f64)>,
+	frame: usize,
+	released: f64,
+	window: Vec<f64>,
+	window_position: usize,
+	window_sum: f64
+}
+
+impl<S: Sample> Limiter<S> {
+	/// Parameters "Ceiling" and "Release"
+	pub fn new(num_channels: usize, lookahead_ms: f64, sample_rate: f64) -> Limiter<S> {
+		let mut registry = ParameterRegistry::new();
+		let ceiling = registry.add(db_parameter("Ceiling", -24.0, 0.0, -0.3));
+		let release = registry.add(ms_parameter("Release", 1.0, 1000.0, 50.0));
+
+		let mut limiter = Limiter {
+			control: DynamicsControl::new(registry.build()),
+			ceiling,
+			release,
+			lookahead_time: lookahead_ms / 1000.0,
+			lookahead: 0,
+			sample_rate,
+			delay: vec![Vec::new(); num_channels],
+			delay_position: 0,
+			minimum: VecDeque::new(),
+			frame: 0,
+			released: 1.0,
+			window: Vec::new(),
+			window_position: 0,
+			window_sum: 0.0
+		};
+		limiter.allocate();
+		limiter
+	}
+
+	/// Handle for the parameters, sidechain and metering
+	pub fn control(&self) -> DynamicsControl {
+		self.control.clone()
+	}
+
+	pub fn reset(&mut self) {
+		for channel in self.delay.iter_mut() {
+			channel.fill(S::ZERO);
+		}
+		self.delay_position = 0;
+		self.minimum.clear();
+		self.frame = 0;
+		self.released = 1.0;
+		self.window.fill(1.0);
+		self.window_position = 0;
+		self.window_sum = self.window.len() as f64;
+	}
+
+	fn allocate(&mut self) {
+		self.lookahead = (self.lookahead_time * self.sample_rate).round() as usize;
+
+		for channel in self.delay.iter_mut() {
+			*channel = vec![S::ZERO; self.lookahead];
+		}
+		self.minimum = VecDeque::with_capacity(self.lookahead + 1);
+		self.window = vec![1.0; self.lookahead + 1];
+		self.reset();
+	}
+
+	/// Gain for the frame leaving the delay, given the gain the newest frame needs
+	fn next_gain(&mut self, required: f64, release: f64) -> f64 {
+		while self.minimum.back().is_some_and(|(_, gain)| *gain >= required) {
+			self.minimum.pop_back();
+		}
+		self.minimum.push_back((self.frame, required));
+		while self.minimum.front().is_some_and(|(frame, _)| frame + self.lookahead < self.frame) {
+			self.minimum.pop_front();
+		}
+		self.frame = self.frame.wrapping_add(1);
+
+		// Gain may recover no faster than the release, and never above the held minimum
+		let held = self.minimum.front().map_or(1.0, |(_, gain)| *gain);
+		self.released = if held < self.released { held } else { held + (self.released - held) * release };
+
+		self.window_sum += self.released - self.window[self.window_position];
+		self.window[self.window_position] = self.released;
+		self.window_position += 1;
+		if self.window_position == self.window.len() {
+			// Start over from the exact sum, so rounding errors cannot add up
+			self.window_position = 0;
+			self.window_sum = self.window.iter().sum();
+		}
+
+		self.window_sum / self.window.len() as f64
+	}
+}
+
+impl<S: Sample> Processor<S> for Limiter<S> {
+	/// Channels beyond those given to `new` are passed through, neither delayed nor limited
+	fn process(&mut self, input: &AudioBlock<S>, output: &mut AudioBlockMut<S>) {
+		let sidechain = detector_input(&self.control, input, output);
+		let ceiling = db_to_gain(self.control.parameters.get(self.ceiling));
+		let release = time_coefficient(self.control.parameters.get(self.release) / 1000.0, self.sample_rate);
+		let num_channels = self.delay.len().min(output.num_channels());
+		let mut smallest = 1.0f64;
+
+		for frame in 0..output.num_frames() {
+			let peak = db_to_gain(detector_level(sidechain, output, num_channels, frame));
+			let required = if peak > ceiling { ceiling / peak } else { 1.0 };
+			let gain = self.next_gain(required, release);
+			smallest = smallest.min(gain);
+
+			let gain = S::from_f64(gain);
+			for (channel, delay) in output.channels_mut().zip(self.delay.iter_mut()) {
+				let mut sample = channel[frame];
+				if self.lookahead > 0 {
+					sample = std::mem::replace(&mut delay[self.delay_position], sample);
+				}
+				channel[frame] = sample * gain;
+			}
+			if self.lookahead > 0 {
+				self.delay_position = (self.delay_position + 1) % self.lookahead;
+			}
+		}
+
+		self.control.gain_reduction.store(-gain_to_db(smallest));
+	}
+
+	fn latency(&self) -> usize {
+		self.lookahead
+	}
+
+	fn prepare(&mut self, sample_rate: f64, _max_block_size: usize) {
+		if sample_rate != self.sample_rate {
+			self.sample_rate = sample_rate;
+			self.allocate();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::asio_core::random::Random;
+	use crate::dsp::audio_block::AudioBuffer;
+
+	const SAMPLE_RATE: f64 = 48000.0;
+
+	/// Passes the channels through the processor in blocks of `block_size` frames
+	fn run<P: Processor<f64>>(processor: &mut P, channels: &[&[f64]], block_size: usize) -> Vec<Vec<f64>> {
+		let len = channels[0].len();
+		let mut input = AudioBuffer::<f64>::with_channels(channels.len(), block_size);
+		let mut output = AudioBuffer::<f64>::with_channels(channels.len(), block_size);
+		let mut played = vec![Vec::with_capacity(len); channels.len()];
+
+		for start in (0..len).step_by(block_size) {
+			let frames = block_size.min(len - start);
+			input.set_num_frames(frames);
+			output.set_num_frames(frames);
+			for (channel, samples) in channels.iter().enumerate() {
+				input.as_block_mut().channel_mut(channel).copy_from_slice(&samples[start..start + frames]);
+			}
+			processor.process(&input.as_block(), &mut output.as_block_mut());
+			for (samples, channel) in played.iter_mut().zip(output.as_block().channels()) {
+				samples.extend_from_slice(channel);
+			}
+		}
+		played
+	}
+
+	#[test]
+	fn compressor_static_curve() {
+		// Threshold -20 dB, ratio 4, knee 6 dB
+		let compressor = Dynamics::<f64>::new(DynamicsMode::Compressor, SAMPLE_RATE);
+		let expected = [(-40.0, 0.0), (-23.0, 0.0), (-20.0, -0.75 * 9.0 / 12.0), (-17.0, -2.25), (-8.0, -9.0), (0.0, -15.0)];
+		for (level, gain) in expected {
+			assert!((compressor.static_gain(level) - gain).abs() < 1e-12, "{} dB at {} dB", compressor.static_gain(level), level);
+		}
+
+		// The soft knee is continuous and its slope runs from 0 to 1 / ratio - 1
+		let slope = |level: f64| (compressor.static_gain(level + 1e-6) - compressor.static_gain(level - 1e-6)) / 2e-6;
+		assert!(slope(-23.0 + 1e-5).abs() < 1e-4);
+		assert!((slope(-20.0) + 0.375).abs() < 1e-4);
+		assert!((slope(-17.0 - 1e-5) + 0.75).abs() < 1e-4);
+
+		// Without a knee the curve bends at the threshold
+		compressor.control.parameters.set(compressor.ids.knee, 0.0);
+		assert_eq!(compressor.static_gain(-20.1), 0.0);
+		assert!((compressor.static_gain(-19.9) + 0.075).abs() < 1e-12);
+	}
+
+	#[test]
+	fn compressor_reports_its_gain_reduction() {
+		let mut compressor = Dynamics::<f64>::new(DynamicsMode::Compressor, SAMPLE_RATE);
+		let control = compressor.control();
+		let input = vec![0.5; 24000];
+		let output = run(&mut compressor, &[&input], 256).remove(0);
+
+		// 0.5 is 13.98 dB over the threshold, reduced to 3.5 dB over it
+		let reduction = 0.75 * (gain_to_db(0.5) + 20.0);
+		assert!((control.gain_reduction_db() - reduction).abs() < 1e-6);
+		assert!((gain_to_db(output[23999] / 0.5) + reduction).abs() < 1e-6);
+	}
+
+	#[test]
+	fn gate_holds_then_closes_to_the_range() {
+		// Threshold -50 dB, range -80 dB, hold 50 ms
+		let mut gate = Dynamics::<f64>::new(DynamicsMode::Gate, SAMPLE_RATE);
+		let control = gate.control();
+		let hold = 2400;
+		let mut input = vec![0.5; 4800];
+		input.resize(4800 + 96000, 0.001);
+		let output = run(&mut gate, &[&input], 256).remove(0);
+
+		// Open while the signal is loud and for the hold time after it drops
+		assert_eq!(output[..4800 + hold], input[..4800 + hold]);
+		assert!(output[4800 + hold] < input[4800 + hold]);
+
+		// Then closes with the release time, no further than the range
+		assert!((gain_to_db(output[100799] / 0.001) + 80.0).abs() < 1e-3);
+		assert!(output.iter().zip(input.iter()).all(|(output, input)| gain_to_db(output / input) >= -80.0 - 1e-9));
+		assert!((control.gain_reduction_db() - 80.0).abs() < 1e-3);
+	}
+
+	#[test]
+	fn sidechain_selects_the_detector_channel() {
+		let quiet = vec![0.01; 24000];
+		let loud = vec![0.5; 24000];
+
+		// The loudest channel drives the detector, and both channels are reduced alike
+		let mut compressor = Dynamics::<f64>::new(DynamicsMode::Compressor, SAMPLE_RATE);
+		let output = run(&mut compressor, &[&quiet, &loud], 256);
+		assert!((output[0][23999] / 0.01 - output[1][23999] / 0.5).abs() < 1e-12);
+		assert!(output[1][23999] < 0.5 * db_to_gain(-10.0));
+
+		// A quiet sidechain leaves both channels alone
+		let mut compressor = Dynamics::<f64>::new(DynamicsMode::Compressor, SAMPLE_RATE);
+		compressor.control().set_sidechain(Some(0));
+		let output = run(&mut compressor, &[&quiet, &loud], 256);
+		assert_eq!(output[0], quiet);
+		assert_eq!(output[1], loud);
+
+		// A channel the processor does not receive falls back to the processed channels
+		let mut compressor = Dynamics::<f64>::new(DynamicsMode::Compressor, SAMPLE_RATE);
+		compressor.control().set_sidechain(Some(2));
+		let output = run(&mut compressor, &[&quiet, &loud], 256);
+		assert!(output[1][23999] < 0.5 * db_to_gain(-10.0));
+	}
+
+	#[test]
+	fn limiter_never_exceeds_the_ceiling() {
+		let mut limiter = Limiter::<f64>::new(2, 1.0, SAMPLE_RATE);
+		let ceiling = db_to_gain(-0.3);
+		let mut random = Random::new(7);
+
+		// Noise with bursts up to 18 dB over full scale, in blocks of any size
+		let channels: Vec<Vec<f64>> = (0..2).map(|_| (0..96000).map(|frame| {
+			let burst = if (frame / 4800) % 3 == 1 { 8.0 } else { 0.5 };
+			burst * (2.0 * random.next_f64() - 1.0)
+		}).collect()).collect();
+		let mut output = vec![Vec::new(); 2];
+		let mut start = 0;
+		while start < 96000 {
+			let frames = (1 + random.next_u64() as usize % 512).min(96000 - start);
+			let block = run(&mut limiter, &[&channels[0][start..start + frames], &channels[1][start..start + frames]], frames);
+			for (output, block) in output.iter_mut().zip(block) {
+				output.extend(block);
+			}
+			start += frames;
+		}
+
+		let peak = output.iter().flatten().fold(0.0f64, |peak, sample| peak.max(sample.abs()));
+		assert!(peak <= ceiling + 1e-12, "peak {}", peak);
+		assert!(peak > ceiling * 0.99);
+	}
+
+	#[test]
+	fn limiter_latency_is_the_lookahead() {
+		let mut limiter = Limiter::<f64>::new(1, 1.0, SAMPLE_RATE);
+		assert_eq!(limiter.latency(), 48);
+
+		// Below the ceiling the signal only comes out late
+		let input: Vec<f64> = (0..4800).map(|frame| 0.5 * (frame as f64 * 0.01).sin()).collect();
+		let output = run(&mut limiter, &[&input], 100).remove(0);
+		assert!(output[..48].iter().all(|sample| *sample == 0.0));
+		assert_eq!(output[48..], input[..4800 - 48]);
+
+		// Peaks have been ramped down to by the time they leave the delay
+		let control = limiter.control();
+		let mut input = vec![0.0; 480];
+		input[200] = 2.0;
+		let output = run(&mut limiter, &[&input], 480).remove(0);
+		assert!((output[248] - db_to_gain(-0.3)).abs() < 1e-12);
+		assert!((control.gain_reduction_db() - gain_to_db(2.0) - 0.3).abs() < 1e-9);
+
+		limiter.prepare(96000.0, 256);
+		assert_eq!(limiter.latency(), 96);
+	}
+
+	#[test]
+	fn limiter_passes_extra_channels_through() {
+		let mut limiter = Limiter::<f64>::new(1, 1.0, SAMPLE_RATE);
+		let control = limiter.control();
+		let quiet = vec![0.5; 4800];
+		let loud = vec![2.0; 4800];
+		let output = run(&mut limiter, &[&quiet, &loud], 256);
+
+		// The loud channel is neither delayed nor limited, and does not drive the detector
+		assert!(output[0][..48].iter().all(|sample| *sample == 0.0));
+		assert!(output[0][48..].iter().all(|sample| *sample == 0.5));
+		assert_eq!(output[1], loud);
+		assert_eq!(control.gain_reduction_db(), 0.0);
+	}
+}
@@ -0,0 +1,281 @@
+use std::f64::consts::PI;
+use std::ops::{Add, AddAssign, Mul, Sub};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Complex {
+	pub re: f64,
+	pub im: f64
+}
+
+impl Complex {
+	pub const ZERO: Complex = Complex { re: 0.0, im: 0.0 };
+
+	pub fn new(re: f64, im: f64) -> Complex {
+		Complex { re, im }
+	}
+
+	/// e^(i * angle)
+	pub fn from_angle(angle: f64) -> Complex {
+		let (sin, cos) = angle.sin_cos();
+		Complex { re: cos, im: sin }
+	}
+
+	pub fn conj(&self) -> Complex {
+		Complex { re: self.re, im: -self.im }
+	}
+
+	pub fn norm_sqr(&self) -> f64 {
+		self.re * self.re + self.im * self.im
+	}
+
+	pub fn abs(&self) -> f64 {
+		self.norm_sqr().sqrt()
+	}
+
+	pub fn scale(&self, factor: f64) -> Complex {
+		Complex { re: self.re * factor, im: self.im * factor }
+	}
+}
+
+impl Add for Complex {
+	type Output = Complex;
+
+	fn add(self, other: Complex) -> Complex {
+		Complex { re: self.re + other.re, im: self.im + other.im }
+	}
+}
+
+impl AddAssign for Complex {
+	fn add_assign(&mut self, other: Complex) {
+		self.re += other.re;
+		self.im += other.im;
+	}
+}
+
+impl Sub for Complex {
+	type Output = Complex;
+
+	fn sub(self, other: Complex) -> Complex {
+		Complex { re: self.re - other.re, im: self.im - other.im }
+	}
+}
+
+impl Mul for Complex {
+	type Output = Complex;
+
+	fn mul(self, other: Complex) -> Complex {
+		Complex {
+			re: self.re * other.re - self.im * other.im,
+			im: self.re * other.im + self.im * other.re
+		}
+	}
+}
+
+/// In-place radix-2 FFT of a fixed power of two size. Tables are computed once, so
+/// transforms do not allocate and can run in the callback.
+pub struct Fft {
+	twiddles: Vec<Complex>,
+	bit_reverse: Vec<usize>
+}
+
+impl Fft {
+	pub fn new(size: usize) -> Fft {
+		if !size.is_power_of_two() {
+			panic!("FFT size {} is not a power of two", size);
+		}
+
+		let bits = size.trailing_zeros();
+		Fft {
+			twiddles: (0..size / 2).map(|k| Complex::from_angle(-2.0 * PI * k as f64 / size as f64)).collect(),
+			bit_reverse: (0..size).map(|index| if bits == 0 { 0 } else { index.reverse_bits() >> (usize::BITS - bits) }).collect()
+		}
+	}
+
+	pub fn size(&self) -> usize {
+		self.bit_reverse.len()
+	}
+
+	/// X[k] = sum of x[n] * e^(-2 pi i k n / N), unscaled
+	pub fn forward(&self, data: &mut [Complex]) {
+		self.transform(data, false);
+	}
+
+	/// Inverse of `forward`, scaled by 1 / N
+	pub fn inverse(&self, data: &mut [Complex]) {
+		self.transform(data, true);
+
+		let scale = 1.0 / self.size() as f64;
+		for value in data.iter_mut() {
+			*value = value.scale(scale);
+		}
+	}
+
+	fn transform(&self, data: &mut [Complex], inverse: bool) {
+		let size = self.size();
+		if data.len() != size {
+			panic!("FFT of size {} cannot transform {} values", size, data.len());
+		}
+
+		for (index, reversed) in self.bit_reverse.iter().enumerate() {
+			if index < *reversed {
+				data.swap(index, *reversed);
+			}
+		}
+
+		let mut length = 2;
+		while length <= size {
+			let half = length / 2;
+			let stride = size / length;
+
+			for start in (0..size).step_by(length) {
+				for k in 0..half {
+					let twiddle = self.twiddles[k * stride];
+					let twiddle = if inverse { twiddle.conj() } else { twiddle };
+					let even = data[start + k];
+					let odd = data[start + k + half] * twiddle;
+					data[start + k] = even + odd;
+					data[start + k + half] = even - odd;
+				}
+			}
+			length *= 2;
+		}
+	}
+}
+
+/// FFT of real signals of a power of two size N, computed with a complex FFT of N / 2.
+/// Spectra hold the N / 2 + 1 bins from DC to Nyquist.
+pub struct RealFft {
+	half: Fft,
+	twiddles: Vec<Complex>,
+	scratch: Vec<Complex>
+}
+
+impl RealFft {
+	pub fn new(size: usize) -> RealFft {
+		if size < 2 || !size.is_power_of_two() {
+			panic!("Real FFT size {} is not a power of two of at least 2", size);
+		}
+
+		RealFft {
+			half: Fft::new(size / 2),
+			twiddles: (0..size / 2).map(|k| Complex::from_angle(-2.0 * PI * k as f64 / size as f64)).collect(),
+			scratch: vec![Complex::ZERO; size / 2]
+		}
+	}
+
+	pub fn size(&self) -> usize {
+		self.half.size() * 2
+	}
+
+	/// Number of bins of a spectrum
+	pub fn spectrum_len(&self) -> usize {
+		self.half.size() + 1
+	}
+
+	pub fn forward(&mut self, input: &[f64], spectrum: &mut [Complex]) {
+		let half = self.half.size();
+		if input.len() != 2 * half || spectrum.len() != half + 1 {
+			panic!("Real FFT of size {} cannot transform {} samples into {} bins", 2 * half, input.len(), spectrum.len());
+		}
+
+		for (index, value) in self.scratch.iter_mut().enumerate() {
+			*value = Complex::new(input[2 * index], input[2 * index + 1]);
+		}
+		self.half.forward(&mut self.scratch);
+
+		// Separate the transforms of the even and odd samples and combine them
+		for (k, bin) in spectrum.iter_mut().enumerate() {
+			let z = self.scratch[k % half];
+			let mirrored = self.scratch[(half - k) % half].conj();
+			let even = (z + mirrored).scale(0.5);
+			let odd = (z - mirrored) * Complex::new(0.0, -0.5);
+			let twiddle = if k < half { self.twiddles[k] } else { Complex::new(-1.0, 0.0) };
+			*bin = even + twiddle * odd;
+		}
+	}
+
+	/// Inverse of `forward`, scaled by 1 / N. The imaginary parts of DC and Nyquist are ignored.
+	pub fn inverse(&mut self, spectrum: &[Complex], output: &mut [f64]) {
+		let half = self.half.size();
+		if output.len() != 2 * half || spectrum.len() != half + 1 {
+			panic!("Real FFT of size {} cannot transform {} bins into {} samples", 2 * half, spectrum.len(), output.len());
+		}
+
+		for k in 0..half {
+			let value = spectrum[k];
+			let mirrored = spectrum[half - k].conj();
+			let even = (value + mirrored).scale(0.5);
+			let odd = (value - mirrored) * self.twiddles[k].conj().scale(0.5);
+			self.scratch[k] = even + Complex::new(-odd.im, odd.re);
+		}
+		self.half.inverse(&mut self.scratch);
+
+		for (index, value) in self.scratch.iter().enumerate() {
+			output[2 * index] = value.re;
+			output[2 * index + 1] = value.im;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::asio_core::random::Random;
+
+	fn naive_dft(input: &[Complex], inverse: bool) -> Vec<Complex> {
+		let size = input.len();
+		let sign = if inverse { 2.0 } else { -2.0 };
+
+		(0..size).map(|k| {
+			input.iter().enumerate().fold(Complex::ZERO, |sum, (n, value)| {
+				sum + *value * Complex::from_angle(sign * PI * ((k * n) % size) as f64 / size as f64)
+			})
+		}).collect()
+	}
+
+	fn assert_close(actual: &[Complex], expected: &[Complex], tolerance: f64) {
+		for (index, (a, e)) in actual.iter().zip(expected).enumerate() {
+			assert!((*a - *e).abs() < tolerance, "bin {}: {:?} instead of {:?}", index, a, e);
+		}
+	}
+
+	#[test]
+	fn complex_matches_naive_dft() {
+		let mut random = Random::new(45);
+
+		for bits in 0..10 {
+			let size = 1 << bits;
+			let fft = Fft::new(size);
+			let input: Vec<Complex> = (0..size).map(|_| Complex::new(random.next_f64() - 0.5, random.next_f64() - 0.5)).collect();
+
+			let mut data = input.clone();
+			fft.forward(&mut data);
+			assert_close(&data, &naive_dft(&input, false), 1e-9 * size as f64);
+
+			fft.inverse(&mut data);
+			assert_close(&data, &input, 1e-12 * size as f64);
+		}
+	}
+
+	#[test]
+	fn real_matches_naive_dft() {
+		let mut random = Random::new(46);
+
+		for bits in 1..11 {
+			let size = 1 << bits;
+			let mut fft = RealFft::new(size);
+			let input: Vec<f64> = (0..size).map(|_| random.next_f64() - 0.5).collect();
+			let complex: Vec<Complex> = input.iter().map(|value| Complex::new(*value, 0.0)).collect();
+
+			let mut spectrum = vec![Complex::ZERO; fft.spectrum_len()];
+			fft.forward(&input, &mut spectrum);
+			assert_close(&spectrum, &naive_dft(&complex, false)[..size / 2 + 1], 1e-9 * size as f64);
+
+			let mut output = vec![0.0; size];
+			fft.inverse(&spectrum, &mut output);
+			for (index, (a, e)) in output.iter().zip(&input).enumerate() {
+				assert!((a - e).abs() < 1e-12 * size as f64, "sample {} of {}: {} instead of {}", index, size, a, e);
+			}
+		}
+	}
+}
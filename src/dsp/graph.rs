@@ -0,0 +1,864 @@
+use crate::dsp::audio_block::{AudioBlock, AudioBlockMut, AudioBuffer, ChannelLabel};
+use crate::dsp::delay_line::DelayLine;
+use crate::dsp::parameters::AtomicF64;
+use crate::dsp::processor::Processor;
+use crate::dsp::sample::Sample;
+use crate::dsp::worker_pool::{Job, WorkerPool};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Channel layout of a port, connected ports must have the same type
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PortType {
+	Mono,
+	Stereo,
+	Channels(usize)
+}
+
+impl PortType {
+	pub fn num_channels(&self) -> usize {
+		match self {
+			PortType::Mono => 1,
+			PortType::Stereo => 2,
+			PortType::Channels(count) => *count
+		}
+	}
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+/// An output port of one node feeding an input port of another
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Connection {
+	pub from: NodeId,
+	pub from_port: usize,
+	pub to: NodeId,
+	pub to_port: usize
+}
+
+struct NodeSpec<S: Sample> {
+	name: String,
+	processor: Option<Box<dyn Processor<S>>>,
+	inputs: Vec<PortType>,
+	outputs: Vec<PortType>
+}
+
+impl<S: Sample> NodeSpec<S> {
+	/// First channel of each port within the node's block, followed by the total
+	fn offsets(ports: &[PortType]) -> Vec<usize> {
+		let mut offsets = Vec::with_capacity(ports.len() + 1);
+		let mut offset = 0;
+
+		for port in ports {
+			offsets.push(offset);
+			offset += port.num_channels();
+		}
+		offsets.push(offset);
+		offsets
+	}
+}
+
+/// Describes nodes and their connections, typically assembled off the audio thread.
+/// The graph's input and output are nodes as well: `GraphBuilder::INPUT` has an output port
+/// per given input type, `GraphBuilder::OUTPUT` an input port per given output type.
+pub struct GraphBuilder<S: Sample> {
+	nodes: Vec<NodeSpec<S>>,
+	connections: Vec<Connection>
+}
+
+impl<S: Sample> GraphBuilder<S> {
+	pub const INPUT: NodeId = NodeId(0);
+	pub const OUTPUT: NodeId = NodeId(1);
+
+	pub fn new(inputs: Vec<PortType>, outputs: Vec<PortType>) -> GraphBuilder<S> {
+		let input = NodeSpec {
+			name: String::from("Input"),
+			processor: None,
+			inputs: Vec::new(),
+			outputs: inputs
+		};
+		let output = NodeSpec {
+			name: String::from("Output"),
+			processor: None,
+			inputs: outputs,
+			outputs: Vec::new()
+		};
+
+		GraphBuilder {
+			nodes: vec![input, output],
+			connections: Vec::new()
+		}
+	}
+
+	/// The processor sees the channels of all input ports, and writes the channels of all
+	/// output ports, one after the other
+	pub fn add_node(
+		&mut self,
+		name: &str,
+		processor: Box<dyn Processor<S>>,
+		inputs: Vec<PortType>,
+		outputs: Vec<PortType>
+	) -> NodeId {
+		self.nodes.push(NodeSpec {
+			name: String::from(name),
+			processor: Some(processor),
+			inputs,
+			outputs
+		});
+		NodeId(self.nodes.len() - 1)
+	}
+
+	/// Adds a node that passes on the sum of everything connected to its single input port
+	pub fn add_bus(&mut self, name: &str, port_type: PortType) -> NodeId {
+		let pass = |input: &AudioBlock<S>, output: &mut AudioBlockMut<S>| output.copy_from(input);
+		self.add_node(name, Box::new(pass), vec![port_type], vec![port_type])
+	}
+
+	/// Several connections to the same input port are summed
+	pub fn connect(&mut self, from: NodeId, from_port: usize, to: NodeId, to_port: usize) {
+		let source = self.port(from, from_port, false);
+		let target = self.port(to, to_port, true);
+
+		if source != target {
+			panic!(
+				"Cannot connect {:?} port {} of '{}' to {:?} port {} of '{}'",
+				source, from_port, self.nodes[from.0].name, target, to_port, self.nodes[to.0].name
+			);
+		}
+
+		self.connections.push(Connection { from, from_port, to, to_port });
+	}
+
+	pub fn connections(&self) -> &[Connection] {
+		&self.connections
+	}
+
+	/// Sorts the nodes topologically and allocates all buffers for blocks of up to
+	/// `max_block_size` frames. Panics if the connections form a cycle.
+	///
+	/// Connections on paths with less latency than others into the same node are delayed,
+	/// so that all paths stay in phase. Processors must report their latency at this point,
+	/// `Graph::prepare` updates the delays when it changes with the sample rate.
+	pub fn build(self, max_block_size: usize) -> Graph<S> {
+		let order = self.schedule();
+		let mut position = vec![0; self.nodes.len()];
+
+		for (index, node) in order.iter().enumerate() {
+			position[*node] = index;
+		}
+
+		// Latency at each node's output, following the processing order
+		let mut latencies = vec![0; self.nodes.len()];
+
+		for node in order.iter() {
+			let processor = self.nodes[*node].processor.as_ref().expect("Only input and output have no processor");
+			latencies[*node] = GraphBuilder::<S>::arrival(&self.connections, *node, &latencies) + processor.latency();
+		}
+
+		let latency = GraphBuilder::<S>::arrival(&self.connections, GraphBuilder::<S>::OUTPUT.0, &latencies);
+
+		let mut specs: Vec<Option<NodeSpec<S>>> = self.nodes.into_iter().map(Some).collect();
+		let input_offsets = NodeSpec::<S>::offsets(&specs[GraphBuilder::<S>::INPUT.0].as_ref().unwrap().outputs);
+		let output_offsets = NodeSpec::<S>::offsets(&specs[GraphBuilder::<S>::OUTPUT.0].as_ref().unwrap().inputs);
+
+		// Source offsets are needed for all nodes before any spec is consumed
+		let source_offsets: Vec<Vec<usize>> = specs.iter()
+			.map(|spec| NodeSpec::<S>::offsets(&spec.as_ref().unwrap().outputs))
+			.collect();
+		let routes_to = |node: usize, target_offsets: &[usize]| -> Vec<Route<S>> {
+			let arrival = GraphBuilder::<S>::arrival(&self.connections, node, &latencies);
+
+			self.connections.iter().filter(|connection| connection.to.0 == node).map(|connection| {
+				let source = if connection.from == GraphBuilder::<S>::INPUT {
+					Source::Input
+				} else {
+					Source::Node(position[connection.from.0])
+				};
+				let channels = target_offsets[connection.to_port + 1] - target_offsets[connection.to_port];
+				let delay = arrival - latencies[connection.from.0];
+
+				Route {
+					source,
+					source_offset: source_offsets[connection.from.0][connection.from_port],
+					target_offset: target_offsets[connection.to_port],
+					channels,
+					delays: (0..channels).map(|_| DelayLine::new(delay)).collect()
+				}
+			}).collect()
+		};
+
+		let mut nodes = Vec::with_capacity(order.len());
+		let mut inputs = Vec::with_capacity(order.len());
+		let mut outputs = Vec::with_capacity(order.len());
+
+		for index in order.iter() {
+			let spec = specs[*index].take().expect("Every node is scheduled once");
+			let offsets = NodeSpec::<S>::offsets(&spec.inputs);
+
+			inputs.push(AudioBuffer::new(GraphBuilder::<S>::labels(&spec.name, offsets[spec.inputs.len()]), max_block_size));
+			outputs.push(AudioBuffer::new(GraphBuilder::<S>::labels(&spec.name, source_offsets[*index][spec.outputs.len()]), max_block_size));
+			nodes.push(GraphNode {
+				name: spec.name,
+				processor: spec.processor.expect("Only input and output have no processor"),
+				routes: routes_to(*index, &offsets)
+			});
+		}
+
+		let schedule = Schedule::new(&nodes);
+
+		Graph {
+			nodes,
+			inputs,
+			outputs,
+			schedule,
+			workers: None,
+			output_routes: routes_to(GraphBuilder::<S>::OUTPUT.0, &output_offsets),
+			num_inputs: input_offsets[input_offsets.len() - 1],
+			num_outputs: output_offsets[output_offsets.len() - 1],
+			max_block_size,
+			latency
+		}
+	}
+
+	/// Latency of the longest path into `node`, given the latencies of all preceding nodes
+	fn arrival(connections: &[Connection], node: usize, latencies: &[usize]) -> usize {
+		connections.iter()
+			.filter(|connection| connection.to.0 == node)
+			.map(|connection| latencies[connection.from.0])
+			.max()
+			.unwrap_or(0)
+	}
+
+	/// Processing order of all nodes but the graph's input and output
+	fn schedule(&self) -> Vec<usize> {
+		let mut pending = vec![0; self.nodes.len()];
+
+		for connection in self.connections.iter() {
+			pending[connection.to.0] += 1;
+		}
+
+		let mut ready: Vec<usize> = (0..self.nodes.len()).filter(|node| pending[*node] == 0).collect();
+		let mut order = Vec::with_capacity(self.nodes.len());
+
+		while let Some(node) = ready.pop() {
+			order.push(node);
+
+			for connection in self.connections.iter().filter(|connection| connection.from.0 == node) {
+				pending[connection.to.0] -= 1;
+				if pending[connection.to.0] == 0 {
+					ready.push(connection.to.0);
+				}
+			}
+		}
+
+		if order.len() != self.nodes.len() {
+			let cyclic: Vec<&str> = (0..self.nodes.len())
+				.filter(|node| pending[*node] > 0)
+				.map(|node| self.nodes[node].name.as_str())
+				.collect();
+			panic!("The graph contains a cycle through {:?}", cyclic);
+		}
+
+		order.retain(|node| *node != GraphBuilder::<S>::INPUT.0 && *node != GraphBuilder::<S>::OUTPUT.0);
+		order
+	}
+
+	fn port(&self, node: NodeId, port: usize, is_input: bool) -> PortType {
+		let spec = match self.nodes.get(node.0) {
+			Some(spec) => spec,
+			None => panic!("Unknown node {:?}", node)
+		};
+		let ports = if is_input { &spec.inputs } else { &spec.outputs };
+
+		match ports.get(port) {
+			Some(port_type) => *port_type,
+			None => panic!("Node '{}' has no {} port {}", spec.name, if is_input { "input" } else { "output" }, port)
+		}
+	}
+
+	fn labels(name: &str, num_channels: usize) -> Vec<ChannelLabel> {
+		(0..num_channels).map(|index| ChannelLabel::new(index, &format!("{} {}", name, index + 1))).collect()
+	}
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Source {
+	Input,			// the graph's input block
+	Node(usize)		// the output of the node at this position in the processing order
+}
+
+/// Adds `channels` channels of a source to a node's input, starting at the given offsets,
+/// delayed to match the longest path into the node
+struct Route<S: Sample> {
+	source: Source,
+	source_offset: usize,
+	target_offset: usize,
+	channels: usize,
+	delays: Vec<DelayLine<S>>
+}
+
+struct GraphNode<S: Sample> {
+	name: String,
+	processor: Box<dyn Processor<S>>,
+	routes: Vec<Route<S>>
+}
+
+/// Nodes in processing order with preallocated buffers, runs without allocating
+pub struct Graph<S: Sample> {
+	nodes: Vec<GraphNode<S>>,
+	inputs: Vec<AudioBuffer<S>>,
+	outputs: Vec<AudioBuffer<S>>,
+	schedule: Schedule,
+	workers: Option<Arc<WorkerPool>>,
+	output_routes: Vec<Route<S>>,
+	num_inputs: usize,
+	num_outputs: usize,
+	max_block_size: usize,
+	latency: usize
+}
+
+impl<S: Sample> Graph<S> {
+	pub fn num_inputs(&self) -> usize {
+		self.num_inputs
+	}
+
+	pub fn num_outputs(&self) -> usize {
+		self.num_outputs
+	}
+
+	/// Node names in processing order
+	pub fn order(&self) -> impl Iterator<Item = &str> {
+		self.nodes.iter().map(|node| node.name.as_str())
+	}
+
+	/// Runs independent nodes on the pool's threads. Without workers, or while the pool
+	/// is busy with another graph, the nodes run one after the other with identical results.
+	pub fn set_worker_pool(&mut self, workers: Arc<WorkerPool>) {
+		self.workers = Some(workers);
+	}
+
+	/// Recomputes the latency at each node and the delays keeping all paths in phase,
+	/// as `GraphBuilder::build` does
+	fn compensate(&mut self) {
+		let mut latencies = Vec::with_capacity(self.nodes.len());
+
+		for node in self.nodes.iter_mut() {
+			let arrival = Graph::align(&mut node.routes, &latencies);
+			latencies.push(arrival + node.processor.latency());
+		}
+
+		self.latency = Graph::align(&mut self.output_routes, &latencies);
+	}
+
+	/// Delays each route to match the longest of them, given the latencies at the outputs of
+	/// the nodes so far. Returns the latency of the longest route.
+	fn align(routes: &mut [Route<S>], latencies: &[usize]) -> usize {
+		let source_latency = |route: &Route<S>| match route.source {
+			Source::Input => 0,
+			Source::Node(index) => latencies[index]
+		};
+		let arrival = routes.iter().map(source_latency).max().unwrap_or(0);
+
+		for route in routes.iter_mut() {
+			let delay = arrival - source_latency(route);
+
+			for line in route.delays.iter_mut().filter(|line| line.delay() != delay) {
+				*line = DelayLine::new(delay);
+			}
+		}
+		arrival
+	}
+
+	/// Sums the sources of all routes into `target`, channels missing on the graph's input are silent.
+	///
+	/// # Safety
+	/// `outputs` points to the output buffers of all nodes, those referenced by `routes`
+	/// must be complete and not written to concurrently.
+	unsafe fn mix(routes: &mut [Route<S>], input: &AudioBlock<S>, outputs: *const AudioBuffer<S>, target: &mut AudioBlockMut<S>) {
+		target.fill(S::ZERO);
+
+		for route in routes.iter_mut() {
+			let source = match route.source {
+				Source::Input => *input,
+				Source::Node(index) => (*outputs.add(index)).as_block()
+			};
+
+			for channel in 0..route.channels {
+				let (from, to) = (route.source_offset + channel, route.target_offset + channel);
+				if from >= source.num_channels() || to >= target.num_channels() {
+					continue;
+				}
+				route.delays[channel].add_to(source.channel(from), target.channel_mut(to));
+			}
+		}
+	}
+}
+
+impl<S: Sample> Processor<S> for Graph<S> {
+	fn process(&mut self, input: &AudioBlock<S>, output: &mut AudioBlockMut<S>) {
+		let frames = input.num_frames();
+
+		if frames > self.max_block_size {
+			panic!("Graph built for {} frames got a block of {} frames", self.max_block_size, frames);
+		}
+
+		let block = ParallelBlock {
+			nodes: self.nodes.as_mut_ptr(),
+			inputs: self.inputs.as_mut_ptr(),
+			outputs: self.outputs.as_mut_ptr(),
+			schedule: &self.schedule,
+			input
+		};
+
+		self.schedule.reset();
+
+		let job = Job {
+			run: ParallelBlock::<S>::run,
+			data: &block as *const ParallelBlock<S> as *const ()
+		};
+		let parallel = match &self.workers {
+			Some(workers) if self.nodes.len() > 1 => unsafe { workers.try_run(job) },
+			_ => false
+		};
+
+		if !parallel {
+			for index in 0..self.nodes.len() {
+				unsafe { block.run_node(index) };
+			}
+		}
+
+		unsafe { Graph::mix(&mut self.output_routes, input, self.outputs.as_ptr(), output) };
+	}
+
+	/// Latency of the longest path from input to output, including compensating delays
+	fn latency(&self) -> usize {
+		self.latency
+	}
+
+	fn prepare(&mut self, sample_rate: f64, max_block_size: usize) {
+		if max_block_size > self.max_block_size {
+			panic!("Graph built for {} frames cannot process blocks of {} frames", self.max_block_size, max_block_size);
+		}
+
+		for node in self.nodes.iter_mut() {
+			node.processor.prepare(sample_rate, max_block_size);
+		}
+
+		// Processors may change their latency with the sample rate
+		self.compensate();
+	}
+}
+
+/// Dependencies between nodes and the lock-free queue of nodes ready to run within a block
+struct Schedule {
+	dependents: Vec<Vec<usize>>,
+	dependencies: Vec<usize>,
+	remaining: Box<[AtomicUsize]>,
+	ready: Box<[AtomicUsize]>,
+	head: AtomicUsize,
+	tail: AtomicUsize,
+	finished: AtomicUsize
+}
+
+impl Schedule {
+	fn new<S: Sample>(nodes: &[GraphNode<S>]) -> Schedule {
+		let mut dependents = vec![Vec::new(); nodes.len()];
+		let mut dependencies = vec![0; nodes.len()];
+
+		for (index, node) in nodes.iter().enumerate() {
+			for route in node.routes.iter() {
+				if let Source::Node(source) = route.source {
+					if !dependents[source].contains(&index) {
+						dependents[source].push(index);
+						dependencies[index] += 1;
+					}
+				}
+			}
+		}
+
+		Schedule {
+			dependents,
+			dependencies,
+			remaining: (0..nodes.len()).map(|_| AtomicUsize::new(0)).collect(),
+			ready: (0..nodes.len()).map(|_| AtomicUsize::new(0)).collect(),
+			head: AtomicUsize::new(0),
+			tail: AtomicUsize::new(0),
+			finished: AtomicUsize::new(0)
+		}
+	}
+
+	/// Prepares the next block, only while no thread is working on the graph
+	fn reset(&self) {
+		self.head.store(0, Ordering::Relaxed);
+		self.tail.store(0, Ordering::Relaxed);
+		self.finished.store(0, Ordering::Relaxed);
+
+		for slot in self.ready.iter() {
+			slot.store(0, Ordering::Relaxed);
+		}
+		for (node, count) in self.dependencies.iter().enumerate() {
+			self.remaining[node].store(*count, Ordering::Relaxed);
+			if *count == 0 {
+				self.push(node);
+			}
+		}
+	}
+
+	/// Each node is pushed exactly once per block, so the queue never wraps around
+	fn push(&self, node: usize) {
+		let slot = self.tail.fetch_add(1, Ordering::AcqRel);
+		self.ready[slot].store(node + 1, Ordering::Release);
+	}
+
+	fn pop(&self) -> Option<usize> {
+		loop {
+			let head = self.head.load(Ordering::Acquire);
+			if head >= self.tail.load(Ordering::Acquire) {
+				return None;
+			}
+			if self.head.compare_exchange_weak(head, head + 1, Ordering::AcqRel, Ordering::Relaxed).is_ok() {
+				// The slot is reserved, but its node may not be stored yet
+				loop {
+					let node = self.ready[head].load(Ordering::Acquire);
+					if node != 0 {
+						return Some(node - 1);
+					}
+					std::hint::spin_loop();
+				}
+			}
+		}
+	}
+
+	/// Releases the dependents of a node that has been processed
+	fn complete(&self, node: usize) {
+		for dependent in self.dependents[node].iter() {
+			if self.remaining[*dependent].fetch_sub(1, Ordering::AcqRel) == 1 {
+				self.push(*dependent);
+			}
+		}
+		self.finished.fetch_add(1, Ordering::Release);
+	}
+
+	fn is_finished(&self) -> bool {
+		self.finished.load(Ordering::Acquire) == self.dependencies.len()
+	}
+}
+
+/// Everything the threads working on one block need, each node is accessed by one thread only
+struct ParallelBlock<'a, S: Sample> {
+	nodes: *mut GraphNode<S>,
+	inputs: *mut AudioBuffer<S>,
+	outputs: *mut AudioBuffer<S>,
+	schedule: &'a Schedule,
+	input: &'a AudioBlock<'a, S>
+}
+
+impl<'a, S: Sample> ParallelBlock<'a, S> {
+	/// # Safety
+	/// `data` points to a `ParallelBlock<S>`
+	unsafe fn run(data: *const ()) {
+		let block = &*(data as *const ParallelBlock<S>);
+
+		while !block.schedule.is_finished() {
+			match block.schedule.pop() {
+				Some(index) => {
+					block.run_node(index);
+					block.schedule.complete(index);
+				},
+				None => std::hint::spin_loop()
+			}
+		}
+	}
+
+	/// # Safety
+	/// All nodes feeding `index` must be complete, and no other thread may run `index`
+	unsafe fn run_node(&self, index: usize) {
+		let frames = self.input.num_frames();
+		let node = &mut *self.nodes.add(index);
+		let input = &mut *self.inputs.add(index);
+		let output = &mut *self.outputs.add(index);
+
+		input.set_num_frames(frames);
+		output.set_num_frames(frames);
+
+		Graph::mix(&mut node.routes, self.input, self.outputs, &mut input.as_block_mut());
+		node.processor.process(&input.as_block(), &mut output.as_block_mut());
+	}
+}
+
+/// Capacity for graphs replaced in the callback, until the control side drops them
+const RETIRED_GRAPHS: usize = 2;
+
+/// Pause between attempts to hand over a graph while the callback has not taken the previous one
+const SWAP_WAIT: Duration = Duration::from_millis(1);
+
+/// Runs a graph that can be replaced from another thread. The callback only exchanges
+/// boxes, building and dropping graphs happens on the control side.
+pub struct SwappableGraph<S: Sample> {
+	graph: Box<Graph<S>>,
+	pending: Receiver<Box<Graph<S>>>,
+	retired: SyncSender<Box<Graph<S>>>,
+	/// A replaced graph that found `retired` full, no new graph is taken until it is sent
+	retiring: Option<Box<Graph<S>>>,
+	prepared: Arc<PreparedFor>
+}
+
+/// Control side of a `SwappableGraph`
+pub struct GraphSwapper<S: Sample> {
+	pending: SyncSender<Box<Graph<S>>>,
+	retired: Receiver<Box<Graph<S>>>,
+	prepared: Arc<PreparedFor>
+}
+
+/// Settings of the last `prepare`, applied to new graphs before they are swapped in
+#[derive(Default)]
+struct PreparedFor {
+	sample_rate: AtomicF64,
+	max_block_size: AtomicUsize
+}
+
+impl<S: Sample> SwappableGraph<S> {
+	pub fn new(graph: Graph<S>) -> (SwappableGraph<S>, GraphSwapper<S>) {
+		let (pending_sender, pending_receiver) = mpsc::sync_channel(1);
+		let (retired_sender, retired_receiver) = mpsc::sync_channel(RETIRED_GRAPHS);
+		let prepared = Arc::new(PreparedFor::default());
+
+		let swappable = SwappableGraph {
+			graph: Box::new(graph),
+			pending: pending_receiver,
+			retired: retired_sender,
+			retiring: None,
+			prepared: prepared.clone()
+		};
+		let swapper = GraphSwapper {
+			pending: pending_sender,
+			retired: retired_receiver,
+			prepared
+		};
+		(swappable, swapper)
+	}
+
+	/// Hands a replaced graph to the control side, or keeps it until there is room
+	fn retire(&mut self, graph: Box<Graph<S>>) {
+		if let Err(TrySendError::Full(graph)) = self.retired.try_send(graph) {
+			self.retiring = Some(graph);
+		}
+	}
+}
+
+impl<S: Sample> Processor<S> for SwappableGraph<S> {
+	fn process(&mut self, input: &AudioBlock<S>, output: &mut AudioBlockMut<S>) {
+		if let Some(old) = self.retiring.take() {
+			self.retire(old);
+		}
+
+		// A new graph stays pending while the previous one cannot be handed back
+		if self.retiring.is_none() {
+			if let Ok(graph) = self.pending.try_recv() {
+				let old = std::mem::replace(&mut self.graph, graph);
+				self.retire(old);
+			}
+		}
+
+		self.graph.process(input, output);
+	}
+
+	fn latency(&self) -> usize {
+		self.graph.latency()
+	}
+
+	fn prepare(&mut self, sample_rate: f64, max_block_size: usize) {
+		self.prepared.sample_rate.store(sample_rate);
+		self.prepared.max_block_size.store(max_block_size, Ordering::Relaxed);
+		self.graph.prepare(sample_rate, max_block_size);
+	}
+}
+
+impl<S: Sample> GraphSwapper<S> {
+	/// Hands a new graph to the callback, which starts using it with the next block.
+	/// Waits while a previously sent graph has not been picked up yet.
+	pub fn swap(&self, mut graph: Graph<S>) {
+		let sample_rate = self.prepared.sample_rate.load();
+		if sample_rate > 0.0 {
+			graph.prepare(sample_rate, self.prepared.max_block_size.load(Ordering::Relaxed));
+		}
+
+		// Keeps collecting while waiting, the callback only takes the graph once it can retire the current one
+		let mut graph = Box::new(graph);
+		loop {
+			self.collect_garbage();
+
+			match self.pending.try_send(graph) {
+				Ok(()) => return,
+				Err(TrySendError::Full(unsent)) => graph = unsent,
+				Err(TrySendError::Disconnected(_)) => panic!("The graph is no longer running")
+			}
+			thread::sleep(SWAP_WAIT);
+		}
+	}
+
+	/// Drops graphs the callback has replaced
+	pub fn collect_garbage(&self) {
+		while self.retired.try_recv().is_ok() {}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::asio_core::random::Random;
+	use crate::dsp::dynamics::Limiter;
+
+	/// A mono graph whose output is `value` throughout
+	fn constant(value: f32) -> Graph<f32> {
+		let mut builder = GraphBuilder::new(vec![PortType::Mono], vec![PortType::Mono]);
+		let node = builder.add_node(
+			"Constant",
+			Box::new(move |_: &AudioBlock<f32>, output: &mut AudioBlockMut<f32>| output.fill(value)),
+			vec![PortType::Mono],
+			vec![PortType::Mono]
+		);
+		builder.connect(GraphBuilder::<f32>::INPUT, 0, node, 0);
+		builder.connect(node, 0, GraphBuilder::<f32>::OUTPUT, 0);
+		builder.build(16)
+	}
+
+	fn run(graph: &mut SwappableGraph<f32>) -> f32 {
+		let input = AudioBuffer::with_channels(1, 16);
+		let mut output = AudioBuffer::with_channels(1, 16);
+		graph.process(&input.as_block(), &mut output.as_block_mut());
+		output.as_block().channel(0)[0]
+	}
+
+	#[test]
+	fn swap_waits_for_room_to_retire() {
+		let (mut swappable, swapper) = SwappableGraph::new(constant(0.0));
+		assert_eq!(run(&mut swappable), 0.0);
+
+		swapper.swap(constant(1.0));
+		assert_eq!(run(&mut swappable), 1.0);
+
+		// Fill the retired channel as if the control side had not collected for a while,
+		// the graph replaced next is held back, and so is the one after it
+		while swappable.retired.try_send(Box::new(constant(-1.0))).is_ok() {}
+
+		swapper.pending.send(Box::new(constant(2.0))).unwrap();
+		assert_eq!(run(&mut swappable), 2.0);
+		assert!(swappable.retiring.is_some());
+
+		swapper.pending.send(Box::new(constant(3.0))).unwrap();
+		assert_eq!(run(&mut swappable), 2.0);
+		assert_eq!(run(&mut swappable), 2.0);
+
+		swapper.collect_garbage();
+		assert_eq!(run(&mut swappable), 3.0);
+		assert!(swappable.retiring.is_none());
+	}
+
+	#[test]
+	fn prepare_recompensates_latency() {
+		// A limiter with 1 ms lookahead in parallel with a plain bus
+		let mut builder = GraphBuilder::new(vec![PortType::Mono], vec![PortType::Mono]);
+		let limiter = builder.add_node("Limiter", Box::new(Limiter::new(1, 1.0, 48000.0)), vec![PortType::Mono], vec![PortType::Mono]);
+		let bus = builder.add_bus("Bus", PortType::Mono);
+		builder.connect(GraphBuilder::<f32>::INPUT, 0, limiter, 0);
+		builder.connect(GraphBuilder::<f32>::INPUT, 0, bus, 0);
+		builder.connect(limiter, 0, GraphBuilder::<f32>::OUTPUT, 0);
+		builder.connect(bus, 0, GraphBuilder::<f32>::OUTPUT, 0);
+		let mut graph = builder.build(64);
+		assert_eq!(graph.latency(), 48);
+
+		for &(sample_rate, latency) in [(96000.0, 96), (44100.0, 44)].iter() {
+			graph.prepare(sample_rate, 64);
+			assert_eq!(graph.latency(), latency);
+
+			// Both paths of an impulse arrive at the same frame
+			let mut input = AudioBuffer::with_channels(1, 64);
+			let mut output = AudioBuffer::with_channels(1, 64);
+			let mut response = Vec::new();
+
+			for block in 0..4 {
+				input.as_block_mut().fill(0.0);
+				if block == 0 {
+					input.as_block_mut().channel_mut(0)[0] = 0.1;
+				}
+				graph.process(&input.as_block(), &mut output.as_block_mut());
+				response.extend_from_slice(output.as_block().channel(0));
+			}
+
+			for (frame, value) in response.iter().enumerate() {
+				let expected = if frame == latency { 0.2 } else { 0.0 };
+				assert!((value - expected).abs() < 1e-6, "{} at frame {} for {} Hz", value, frame, sample_rate);
+			}
+		}
+	}
+
+	/// Splits the input into a limiter and three one-pole lowpasses, whose outputs are
+	/// mixed and shaped again, so that branches can run in parallel and meet in one node
+	fn diamond() -> Graph<f32> {
+		let mut builder = GraphBuilder::new(vec![PortType::Mono], vec![PortType::Mono]);
+		let split = builder.add_bus("Split", PortType::Mono);
+		let mix = builder.add_bus("Mix", PortType::Mono);
+		builder.connect(GraphBuilder::<f32>::INPUT, 0, split, 0);
+
+		let limiter = builder.add_node("Limiter", Box::new(Limiter::new(1, 1.0, 48000.0)), vec![PortType::Mono], vec![PortType::Mono]);
+		builder.connect(split, 0, limiter, 0);
+		builder.connect(limiter, 0, mix, 0);
+
+		for &coefficient in [0.1f32, 0.3, 0.7].iter() {
+			let mut state = 0.0f32;
+			let lowpass = move |input: &AudioBlock<f32>, output: &mut AudioBlockMut<f32>| {
+				for (sample, value) in output.channel_mut(0).iter_mut().zip(input.channel(0)) {
+					state += coefficient * (value - state);
+					*sample = state;
+				}
+			};
+			let node = builder.add_node("Lowpass", Box::new(lowpass), vec![PortType::Mono], vec![PortType::Mono]);
+			builder.connect(split, 0, node, 0);
+			builder.connect(node, 0, mix, 0);
+		}
+
+		let shape = |input: &AudioBlock<f32>, output: &mut AudioBlockMut<f32>| {
+			for (sample, value) in output.channel_mut(0).iter_mut().zip(input.channel(0)) {
+				*sample = value.tanh();
+			}
+		};
+		let shaper = builder.add_node("Shaper", Box::new(shape), vec![PortType::Mono], vec![PortType::Mono]);
+		builder.connect(mix, 0, shaper, 0);
+		builder.connect(shaper, 0, GraphBuilder::<f32>::OUTPUT, 0);
+		builder.build(64)
+	}
+
+	#[test]
+	fn parallel_matches_serial() {
+		let mut serial = diamond();
+		let mut parallel = diamond();
+		parallel.set_worker_pool(Arc::new(WorkerPool::new(3)));
+
+		let mut random = Random::new(40);
+		let mut input = AudioBuffer::with_channels(1, 64);
+		let mut serial_output = AudioBuffer::with_channels(1, 64);
+		let mut parallel_output = AudioBuffer::with_channels(1, 64);
+
+		for _ in 0..500 {
+			let frames = 1 + (random.next_u64() % 64) as usize;
+			for buffer in [&mut input, &mut serial_output, &mut parallel_output] {
+				buffer.set_num_frames(frames);
+			}
+			for sample in input.as_block_mut().channel_mut(0).iter_mut() {
+				*sample = (random.next_f64() * 4.0 - 2.0) as f32;
+			}
+
+			serial.process(&input.as_block(), &mut serial_output.as_block_mut());
+			parallel.process(&input.as_block(), &mut parallel_output.as_block_mut());
+
+			let serial_bits = serial_output.as_block().channel(0).iter().map(|value| value.to_bits()).collect::<Vec<u32>>();
+			let parallel_bits = parallel_output.as_block().channel(0).iter().map(|value| value.to_bits()).collect::<Vec<u32>>();
+			assert_eq!(serial_bits, parallel_bits);
+		}
+	}
+}
@@ -0,0 +1,185 @@
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct Slot<T> {
+	sequence: AtomicUsize,
+	value: UnsafeCell<MaybeUninit<T>>
+}
+
+/// Bounded queue for any number of producers and consumers that never blocks or allocates
+/// after construction. Each slot carries a sequence number telling whether it is free for
+/// the producer or filled for the consumer at a given position.
+pub struct LockFreeQueue<T: Copy> {
+	slots: Box<[Slot<T>]>,
+	mask: usize,
+	enqueue: AtomicUsize,
+	dequeue: AtomicUsize
+}
+
+unsafe impl<T: Copy + Send> Send for LockFreeQueue<T> {}
+unsafe impl<T: Copy + Send> Sync for LockFreeQueue<T> {}
+
+impl<T: Copy> LockFreeQueue<T> {
+	/// The capacity is rounded up to a power of two
+	pub fn new(capacity: usize) -> LockFreeQueue<T> {
+		let capacity = capacity.max(2).next_power_of_two();
+
+		LockFreeQueue {
+			slots: (0..capacity).map(|index| Slot {
+				sequence: AtomicUsize::new(index),
+				value: UnsafeCell::new(MaybeUninit::uninit())
+			}).collect(),
+			mask: capacity - 1,
+			enqueue: AtomicUsize::new(0),
+			dequeue: AtomicUsize::new(0)
+		}
+	}
+
+	pub fn capacity(&self) -> usize {
+		self.slots.len()
+	}
+
+	/// Returns false if the queue is full
+	pub fn push(&self, value: T) -> bool {
+		let mut position = self.enqueue.load(Ordering::Relaxed);
+
+		loop {
+			let slot = &self.slots[position & self.mask];
+			let sequence = slot.sequence.load(Ordering::Acquire);
+			let difference = sequence as isize - position as isize;
+
+			if difference == 0 {
+				match self.enqueue.compare_exchange_weak(position, position + 1, Ordering::Relaxed, Ordering::Relaxed) {
+					Ok(_) => {
+						unsafe { (*slot.value.get()).write(value) };
+						slot.sequence.store(position + 1, Ordering::Release);
+						return true;
+					},
+					Err(current) => position = current
+				}
+			} else if difference < 0 {
+				return false;
+			} else {
+				position = self.enqueue.load(Ordering::Relaxed);
+			}
+		}
+	}
+
+	pub fn pop(&self) -> Option<T> {
+		let mut position = self.dequeue.load(Ordering::Relaxed);
+
+		loop {
+			let slot = &self.slots[position & self.mask];
+			let sequence = slot.sequence.load(Ordering::Acquire);
+			let difference = sequence as isize - (position + 1) as isize;
+
+			if difference == 0 {
+				match self.dequeue.compare_exchange_weak(position, position + 1, Ordering::Relaxed, Ordering::Relaxed) {
+					Ok(_) => {
+						let value = unsafe { (*slot.value.get()).assume_init() };
+						slot.sequence.store(position + self.mask + 1, Ordering::Release);
+						return Some(value);
+					},
+					Err(current) => position = current
+				}
+			} else if difference < 0 {
+				return None;
+			} else {
+				position = self.dequeue.load(Ordering::Relaxed);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::Arc;
+	use std::thread;
+
+	#[test]
+	fn fills_up_and_keeps_the_order() {
+		assert_eq!(LockFreeQueue::<u32>::new(0).capacity(), 2);
+		assert_eq!(LockFreeQueue::<u32>::new(5).capacity(), 8);
+
+		let queue = LockFreeQueue::new(8);
+		assert_eq!(queue.pop(), None);
+
+		// Several rounds wrap the positions around the slots
+		for round in 0..5u32 {
+			for value in 0..8 {
+				assert!(queue.push(round * 100 + value));
+			}
+			assert!(!queue.push(999));
+			assert_eq!(queue.pop(), Some(round * 100));
+			assert!(queue.push(round * 100 + 8));
+			assert!(!queue.push(999));
+
+			for value in 1..9 {
+				assert_eq!(queue.pop(), Some(round * 100 + value));
+			}
+			assert_eq!(queue.pop(), None);
+		}
+	}
+
+	#[test]
+	fn many_producers_and_consumers() {
+		const PRODUCERS: u64 = 4;
+		const CONSUMERS: usize = 4;
+		const VALUES: u64 = 50_000;
+
+		let queue = Arc::new(LockFreeQueue::<u64>::new(64));
+		let producers: Vec<_> = (0..PRODUCERS).map(|producer| {
+			let queue = queue.clone();
+			thread::spawn(move || {
+				for sequence in 0..VALUES {
+					while !queue.push(producer << 32 | sequence) {
+						thread::yield_now();
+					}
+				}
+			})
+		}).collect();
+
+		let popped = Arc::new(AtomicUsize::new(0));
+		let consumers: Vec<_> = (0..CONSUMERS).map(|_| {
+			let (queue, popped) = (queue.clone(), popped.clone());
+			thread::spawn(move || {
+				let mut received = Vec::new();
+				while popped.load(Ordering::Relaxed) < (PRODUCERS * VALUES) as usize {
+					match queue.pop() {
+						Some(value) => {
+							received.push(value);
+							popped.fetch_add(1, Ordering::Relaxed);
+						},
+						None => thread::yield_now()
+					}
+				}
+				received
+			})
+		}).collect();
+
+		for producer in producers {
+			producer.join().unwrap();
+		}
+		let mut seen = vec![vec![false; VALUES as usize]; PRODUCERS as usize];
+
+		for consumer in consumers {
+			let received = consumer.join().unwrap();
+
+			// Each consumer sees the values of a producer in the order they were pushed
+			let mut last = vec![None; PRODUCERS as usize];
+			for value in received {
+				let (producer, sequence) = ((value >> 32) as usize, value & 0xFFFF_FFFF);
+				assert!(last[producer].is_none_or(|previous| previous < sequence));
+				last[producer] = Some(sequence);
+
+				assert!(!seen[producer][sequence as usize], "{:x} popped twice", value);
+				seen[producer][sequence as usize] = true;
+			}
+		}
+
+		assert!(seen.iter().all(|values| values.iter().all(|value| *value)), "values were lost");
+		assert_eq!(queue.pop(), None);
+	}
+}
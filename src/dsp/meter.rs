@@ -0,0 +1,571 @@
+use crate::dsp::audio_block::{AudioBlock, AudioBlockMut};
+use crate::dsp::biquad::{Biquad, Coefficients};
+use crate::dsp::parameters::{gain_to_db, AtomicF64};
+use crate::dsp::processor::{pass_through, Processor};
+use crate::dsp::sample::Sample;
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Time a peak is held before the hold follows the falling peak, unless changed
+const DEFAULT_HOLD_TIME: f64 = 2.0;
+
+/// Fall back of the peak readings in dB per second
+const PEAK_FALL: f64 = 20.0;
+
+/// Time constant of the RMS readings
+const RMS_TIME: f64 = 0.3;
+
+/// EBU R128 loudness is updated in steps of 100 ms. Momentary loudness averages the last
+/// 4 steps, short-term loudness the last 30, and integrated loudness gates the 400 ms
+/// blocks of 4 steps.
+const LOUDNESS_STEP: f64 = 0.1;
+const MOMENTARY_STEPS: usize = 4;
+const SHORT_TERM_STEPS: usize = 30;
+
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+
+/// Gated blocks are counted in a histogram of their loudness, so that integrated loudness
+/// needs fixed memory however long the program runs
+const HISTOGRAM_RESOLUTION: f64 = 0.01;
+const HISTOGRAM_MAX: f64 = 10.0;
+
+/// True peak is measured on the signal interpolated to 4 times the sample rate
+const OVERSAMPLING: usize = 4;
+const TAPS_PER_PHASE: usize = 16;
+
+/// Readings of one channel in dBFS, negative infinity for silence
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ChannelReading {
+	pub peak: f64,
+	pub peak_hold: f64,
+	pub rms: f64,
+	pub true_peak: f64,
+	pub true_peak_hold: f64
+}
+
+/// EBU R128 loudness of all metered channels in LUFS, negative infinity before there is
+/// anything to measure
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Loudness {
+	pub momentary: f64,
+	pub short_term: f64,
+	pub integrated: f64
+}
+
+struct ChannelValues {
+	peak: AtomicF64,
+	peak_hold: AtomicF64,
+	rms: AtomicF64,
+	true_peak: AtomicF64,
+	true_peak_hold: AtomicF64
+}
+
+impl ChannelValues {
+	fn new() -> ChannelValues {
+		ChannelValues {
+			peak: AtomicF64::new(f64::NEG_INFINITY),
+			peak_hold: AtomicF64::new(f64::NEG_INFINITY),
+			rms: AtomicF64::new(f64::NEG_INFINITY),
+			true_peak: AtomicF64::new(f64::NEG_INFINITY),
+			true_peak_hold: AtomicF64::new(f64::NEG_INFINITY)
+		}
+	}
+}
+
+struct Shared {
+	channels: Box<[ChannelValues]>,
+	momentary: AtomicF64,
+	short_term: AtomicF64,
+	integrated: AtomicF64,
+	hold_time: AtomicF64,
+	reset_peaks: AtomicBool,
+	reset_integrated: AtomicBool
+}
+
+/// Readings of a `Meter`, published by the callback after every block and readable from
+/// any thread without locks
+#[derive(Clone)]
+pub struct MeterControl {
+	shared: Arc<Shared>
+}
+
+impl MeterControl {
+	pub fn num_channels(&self) -> usize {
+		self.shared.channels.len()
+	}
+
+	pub fn channel(&self, channel: usize) -> ChannelReading {
+		let values = &self.shared.channels[channel];
+
+		ChannelReading {
+			peak: values.peak.load(),
+			peak_hold: values.peak_hold.load(),
+			rms: values.rms.load(),
+			true_peak: values.true_peak.load(),
+			true_peak_hold: values.true_peak_hold.load()
+		}
+	}
+
+	pub fn loudness(&self) -> Loudness {
+		Loudness {
+			momentary: self.shared.momentary.load(),
+			short_term: self.shared.short_term.load(),
+			integrated: self.shared.integrated.load()
+		}
+	}
+
+	/// Seconds a peak is held, infinity holds it until `reset_peaks`
+	pub fn set_hold_time(&self, seconds: f64) {
+		self.shared.hold_time.store(seconds);
+	}
+
+	pub fn hold_time(&self) -> f64 {
+		self.shared.hold_time.load()
+	}
+
+	/// Clears the held peaks with the next block
+	pub fn reset_peaks(&self) {
+		self.shared.reset_peaks.store(true, Ordering::Relaxed);
+	}
+
+	/// Starts a new integrated loudness measurement with the next block
+	pub fn reset_integrated(&self) {
+		self.shared.reset_integrated.store(true, Ordering::Relaxed);
+	}
+}
+
+/// Pre-filter and RLB weighting of ITU-R BS.1770, designed for any sample rate
+fn k_weighting(sample_rate: f64) -> (Coefficients, Coefficients) {
+	let k = (PI * 1681.974450955533 / sample_rate).tan();
+	let q = 0.7071752369554196;
+	let vh = 10.0f64.powf(3.999843853973347 / 20.0);
+	let vb = vh.powf(0.4996667741545416);
+	let a0 = 1.0 + k / q + k * k;
+	let shelf = Coefficients {
+		b0: (vh + vb * k / q + k * k) / a0,
+		b1: 2.0 * (k * k - vh) / a0,
+		b2: (vh - vb * k / q + k * k) / a0,
+		a1: 2.0 * (k * k - 1.0) / a0,
+		a2: (1.0 - k / q + k * k) / a0
+	};
+
+	let k = (PI * 38.13547087602444 / sample_rate).tan();
+	let q = 0.5003270373238773;
+	let a0 = 1.0 + k / q + k * k;
+	let high_pass = Coefficients {
+		b0: 1.0,
+		b1: -2.0,
+		b2: 1.0,
+		a1: 2.0 * (k * k - 1.0) / a0,
+		a2: (1.0 - k / q + k * k) / a0
+	};
+
+	(shelf, high_pass)
+}
+
+/// Polyphase interpolation filter, a Blackman windowed sinc with each phase normalized to
+/// unity gain
+fn interpolation_phases() -> [[f64; TAPS_PER_PHASE]; OVERSAMPLING] {
+	let length = OVERSAMPLING * TAPS_PER_PHASE;
+	let center = (length - 1) as f64 / 2.0;
+	let mut phases = [[0.0; TAPS_PER_PHASE]; OVERSAMPLING];
+
+	for (phase, taps) in phases.iter_mut().enumerate() {
+		for (tap, value) in taps.iter_mut().enumerate() {
+			let n = tap * OVERSAMPLING + phase;
+			let x = (n as f64 - center) / OVERSAMPLING as f64;
+			let sinc = if x == 0.0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+			let window = 0.42 - 0.5 * (2.0 * PI * n as f64 / (length - 1) as f64).cos() + 0.08 * (4.0 * PI * n as f64 / (length - 1) as f64).cos();
+			*value = sinc * window;
+		}
+
+		let sum: f64 = taps.iter().sum();
+		for value in taps.iter_mut() {
+			*value /= sum;
+		}
+	}
+	phases
+}
+
+fn loudness(mean_square: f64) -> f64 {
+	-0.691 + 10.0 * mean_square.log10()
+}
+
+/// Linear peak with a falling reading and a held maximum
+struct PeakHold {
+	value: f64,
+	hold: f64,
+	age: usize
+}
+
+impl PeakHold {
+	fn new() -> PeakHold {
+		PeakHold {
+			value: 0.0,
+			hold: 0.0,
+			age: 0
+		}
+	}
+
+	fn update(&mut self, block_peak: f64, frames: usize, fall: f64, hold_frames: f64) {
+		self.value = block_peak.max(self.value * fall);
+
+		if block_peak >= self.hold {
+			self.hold = block_peak;
+			self.age = 0;
+		} else {
+			self.age += frames;
+			if self.age as f64 > hold_frames {
+				self.hold = self.value;
+			}
+		}
+	}
+}
+
+struct ChannelState {
+	weight: f64,
+	shelf: Biquad,
+	high_pass: Biquad,
+	mean_square: f64,
+	peak: PeakHold,
+	true_peak: PeakHold,
+	history: [f64; 2 * TAPS_PER_PHASE],
+	history_position: usize
+}
+
+impl ChannelState {
+	fn new() -> ChannelState {
+		ChannelState {
+			weight: 1.0,
+			shelf: Biquad::new(),
+			high_pass: Biquad::new(),
+			mean_square: 0.0,
+			peak: PeakHold::new(),
+			true_peak: PeakHold::new(),
+			history: [0.0; 2 * TAPS_PER_PHASE],
+			history_position: 0
+		}
+	}
+
+	/// Largest magnitude of the signal interpolated between the last samples
+	fn interpolated_peak(&mut self, phases: &[[f64; TAPS_PER_PHASE]; OVERSAMPLING], x: f64) -> f64 {
+		// The history is stored twice, so that the newest samples are always contiguous
+		self.history_position = (self.history_position + TAPS_PER_PHASE - 1) % TAPS_PER_PHASE;
+		self.history[self.history_position] = x;
+		self.history[self.history_position + TAPS_PER_PHASE] = x;
+		let recent = &self.history[self.history_position..self.history_position + TAPS_PER_PHASE];
+
+		phases.iter().fold(0.0f64, |peak, taps| {
+			let value: f64 = taps.iter().zip(recent).map(|(tap, sample)| tap * sample).sum();
+			peak.max(value.abs())
+		})
+	}
+}
+
+/// Meters for the channels passing through, which it leaves unchanged. Per channel it
+/// measures the sample peak, the RMS and the true peak after ITU-R BS.1770, with the peaks
+/// falling back and held. Over all channels it measures EBU R128 momentary, short-term and
+/// integrated loudness, the latter gated absolutely at -70 LUFS and 10 LU below the
+/// ungated level.
+pub struct Meter<S: Sample> {
+	control: MeterControl,
+	sample_rate: f64,
+	channels: Vec<ChannelState>,
+	phases: [[f64; TAPS_PER_PHASE]; OVERSAMPLING],
+	k_weighting: (Coefficients, Coefficients),
+	rms_coefficient: f64,
+	step_length: usize,
+	step_energy: f64,
+	step_frames: usize,
+	steps: [f64; SHORT_TERM_STEPS],
+	step_index: usize,
+	steps_seen: usize,
+	histogram: Vec<(f64, u64)>,
+	_sample: std::marker::PhantomData<S>
+}
+
+impl<S: Sample> Meter<S> {
+	pub fn new(num_channels: usize, sample_rate: f64) -> Meter<S> {
+		let bins = ((HISTOGRAM_MAX - ABSOLUTE_GATE) / HISTOGRAM_RESOLUTION).ceil() as usize + 1;
+
+		let mut meter = Meter {
+			control: MeterControl {
+				shared: Arc::new(Shared {
+					channels: (0..num_channels).map(|_| ChannelValues::new()).collect(),
+					momentary: AtomicF64::new(f64::NEG_INFINITY),
+					short_term: AtomicF64::new(f64::NEG_INFINITY),
+					integrated: AtomicF64::new(f64::NEG_INFINITY),
+					hold_time: AtomicF64::new(DEFAULT_HOLD_TIME),
+					reset_peaks: AtomicBool::new(false),
+					reset_integrated: AtomicBool::new(false)
+				})
+			},
+			sample_rate,
+			channels: (0..num_channels).map(|_| ChannelState::new()).collect(),
+			phases: interpolation_phases(),
+			k_weighting: k_weighting(sample_rate),
+			rms_coefficient: 0.0,
+			step_length: 0,
+			step_energy: 0.0,
+			step_frames: 0,
+			steps: [0.0; SHORT_TERM_STEPS],
+			step_index: 0,
+			steps_seen: 0,
+			histogram: vec![(0.0, 0); bins],
+			_sample: std::marker::PhantomData
+		};
+		meter.reset();
+		meter
+	}
+
+	pub fn control(&self) -> MeterControl {
+		self.control.clone()
+	}
+
+	/// Weight of a channel in the loudness, after ITU-R BS.1770 1.0 for the front channels,
+	/// 1.41 for the surround channels and 0.0 to leave out an LFE channel
+	pub fn set_weight(&mut self, channel: usize, weight: f64) {
+		self.channels[channel].weight = weight;
+	}
+
+	/// Clears all readings and starts new measurements
+	pub fn reset(&mut self) {
+		self.k_weighting = k_weighting(self.sample_rate);
+		self.rms_coefficient = (-1.0 / (RMS_TIME * self.sample_rate)).exp();
+		self.step_length = (LOUDNESS_STEP * self.sample_rate).round() as usize;
+
+		for channel in self.channels.iter_mut() {
+			*channel = ChannelState {
+				weight: channel.weight,
+				..ChannelState::new()
+			};
+		}
+		self.reset_loudness();
+	}
+
+	fn reset_loudness(&mut self) {
+		self.step_energy = 0.0;
+		self.step_frames = 0;
+		self.steps = [0.0; SHORT_TERM_STEPS];
+		self.step_index = 0;
+		self.steps_seen = 0;
+		self.histogram.fill((0.0, 0));
+	}
+
+	/// Mean square over the last `count` steps
+	fn mean_of_steps(&self, count: usize) -> f64 {
+		let sum: f64 = (0..count).map(|back| self.steps[(self.step_index + SHORT_TERM_STEPS - 1 - back) % SHORT_TERM_STEPS]).sum();
+		sum / count as f64
+	}
+
+	fn finish_step(&mut self) {
+		self.steps[self.step_index] = self.step_energy / self.step_frames as f64;
+		self.step_index = (self.step_index + 1) % SHORT_TERM_STEPS;
+		self.steps_seen += 1;
+		self.step_energy = 0.0;
+		self.step_frames = 0;
+
+		let momentary = self.mean_of_steps(MOMENTARY_STEPS);
+		let shared = &self.control.shared;
+		shared.momentary.store(loudness(momentary));
+		shared.short_term.store(loudness(self.mean_of_steps(SHORT_TERM_STEPS)));
+
+		// Every step completes a gating block of the last 4 steps
+		let block_loudness = loudness(momentary);
+		if self.steps_seen < MOMENTARY_STEPS || block_loudness < ABSOLUTE_GATE {
+			return;
+		}
+
+		let bin = (((block_loudness - ABSOLUTE_GATE) / HISTOGRAM_RESOLUTION) as usize).min(self.histogram.len() - 1);
+		self.histogram[bin].0 += momentary;
+		self.histogram[bin].1 += 1;
+
+		let (energy, count) = self.histogram.iter().fold((0.0, 0), |sum, bin| (sum.0 + bin.0, sum.1 + bin.1));
+		let threshold = loudness(energy / count as f64) + RELATIVE_GATE;
+		let first = ((threshold - ABSOLUTE_GATE) / HISTOGRAM_RESOLUTION).max(0.0).ceil() as usize;
+		let (energy, count) = self.histogram.iter().skip(first).fold((0.0, 0), |sum, bin| (sum.0 + bin.0, sum.1 + bin.1));
+
+		if count > 0 {
+			shared.integrated.store(loudness(energy / count as f64));
+		}
+	}
+}
+
+impl<S: Sample> Processor<S> for Meter<S> {
+	fn process(&mut self, input: &AudioBlock<S>, output: &mut AudioBlockMut<S>) {
+		let frames = output.num_frames();
+		let num_channels = self.channels.len().min(input.num_channels());
+
+		pass_through(input, output);
+
+		let shared = self.control.shared.clone();
+		if shared.reset_peaks.swap(false, Ordering::Relaxed) {
+			for channel in self.channels.iter_mut() {
+				channel.peak = PeakHold::new();
+				channel.true_peak = PeakHold::new();
+			}
+		}
+		if shared.reset_integrated.swap(false, Ordering::Relaxed) {
+			self.reset_loudness();
+			shared.integrated.store(f64::NEG_INFINITY);
+		}
+
+		let fall = 10.0f64.powf(-PEAK_FALL * frames as f64 / self.sample_rate / 20.0);
+		let hold_frames = shared.hold_time.load() * self.sample_rate;
+
+		let mut start = 0;
+		while start < frames {
+			// Loudness steps end within the block, the channels are metered up to each end
+			let len = (self.step_length - self.step_frames).min(frames - start);
+
+			for (index, channel) in self.channels.iter_mut().enumerate().take(num_channels) {
+				let mut energy = 0.0;
+				for sample in input.channel(index)[start..start + len].iter() {
+					let x = sample.to_f64();
+					let weighted = channel.high_pass.process(&self.k_weighting.1, channel.shelf.process(&self.k_weighting.0, x));
+					energy += weighted * weighted;
+				}
+				self.step_energy += channel.weight * energy;
+			}
+
+			self.step_frames += len;
+			if self.step_frames == self.step_length {
+				self.finish_step();
+			}
+			start += len;
+		}
+
+		for (index, channel) in self.channels.iter_mut().enumerate().take(num_channels) {
+			let mut peak = 0.0f64;
+			let mut true_peak = 0.0f64;
+
+			for sample in input.channel(index).iter() {
+				let x = sample.to_f64();
+				peak = peak.max(x.abs());
+				true_peak = true_peak.max(channel.interpolated_peak(&self.phases, x));
+				channel.mean_square = x * x + (channel.mean_square - x * x) * self.rms_coefficient;
+			}
+
+			channel.peak.update(peak, frames, fall, hold_frames);
+			channel.true_peak.update(true_peak.max(peak), frames, fall, hold_frames);
+
+			let values = &shared.channels[index];
+			values.peak.store(gain_to_db(channel.peak.value));
+			values.peak_hold.store(gain_to_db(channel.peak.hold));
+			values.rms.store(gain_to_db(channel.mean_square.sqrt()));
+			values.true_peak.store(gain_to_db(channel.true_peak.value));
+			values.true_peak_hold.store(gain_to_db(channel.true_peak.hold));
+		}
+	}
+
+	fn prepare(&mut self, sample_rate: f64, _max_block_size: usize) {
+		if sample_rate != self.sample_rate {
+			self.sample_rate = sample_rate;
+			self.reset();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::dsp::audio_block::AudioBuffer;
+
+	const SAMPLE_RATE: f64 = 48000.0;
+
+	/// Feeds sines to the meter, one level in dBFS per channel for each segment of seconds
+	fn feed(meter: &mut Meter<f64>, frequency: f64, phase: f64, segments: &[(&[f64], f64)]) {
+		let num_channels = segments[0].0.len();
+		let mut buffer = AudioBuffer::with_channels(num_channels, 480);
+		let mut output = AudioBuffer::with_channels(num_channels, 480);
+		let mut frame = 0usize;
+
+		for (levels, seconds) in segments {
+			let end = frame + (seconds * SAMPLE_RATE).round() as usize;
+			while frame < end {
+				let len = 480.min(end - frame);
+				buffer.set_num_frames(len);
+				output.set_num_frames(len);
+
+				for (channel, level) in levels.iter().enumerate() {
+					let amplitude = 10.0f64.powf(level / 20.0);
+					for (offset, sample) in buffer.as_block_mut().channel_mut(channel).iter_mut().enumerate() {
+						*sample = amplitude * (2.0 * PI * frequency * (frame + offset) as f64 / SAMPLE_RATE + phase).sin();
+					}
+				}
+				meter.process(&buffer.as_block(), &mut output.as_block_mut());
+				frame += len;
+			}
+		}
+	}
+
+	fn integrated(num_channels: usize, segments: &[(&[f64], f64)]) -> f64 {
+		let mut meter = Meter::new(num_channels, SAMPLE_RATE);
+		if num_channels == 5 {
+			meter.set_weight(3, 1.41);
+			meter.set_weight(4, 1.41);
+		}
+		feed(&mut meter, 1000.0, 0.0, segments);
+		meter.control().loudness().integrated
+	}
+
+	fn assert_lufs(measured: f64, expected: f64, case: &str) {
+		assert!((measured - expected).abs() <= 0.1, "{}: {} LUFS instead of {}", case, measured, expected);
+	}
+
+	#[test]
+	fn ebu_tech_3341_levels() {
+		for &level in [-23.0, -33.0].iter() {
+			let mut meter = Meter::new(2, SAMPLE_RATE);
+			feed(&mut meter, 1000.0, 0.0, &[(&[level, level], 20.0)]);
+			let loudness = meter.control().loudness();
+			assert_lufs(loudness.momentary, level, "momentary");
+			assert_lufs(loudness.short_term, level, "short-term");
+			assert_lufs(loudness.integrated, level, "integrated");
+		}
+	}
+
+	#[test]
+	fn ebu_tech_3341_relative_gate() {
+		assert_lufs(integrated(2, &[(&[-36.0; 2], 10.0), (&[-23.0; 2], 60.0), (&[-36.0; 2], 10.0)]), -23.0, "case 3");
+	}
+
+	#[test]
+	fn ebu_tech_3341_absolute_gate() {
+		assert_lufs(
+			integrated(2, &[(&[-72.0; 2], 10.0), (&[-36.0; 2], 10.0), (&[-23.0; 2], 60.0), (&[-36.0; 2], 10.0), (&[-72.0; 2], 10.0)]),
+			-23.0,
+			"case 4"
+		);
+	}
+
+	#[test]
+	fn ebu_tech_3341_gated_mean() {
+		assert_lufs(integrated(2, &[(&[-26.0; 2], 20.0), (&[-20.0; 2], 20.1), (&[-26.0; 2], 20.0)]), -23.0, "case 5");
+	}
+
+	#[test]
+	fn ebu_tech_3341_channel_weights() {
+		assert_lufs(integrated(5, &[(&[-28.0, -28.0, -24.0, -30.0, -30.0], 20.0)]), -23.0, "case 6");
+	}
+
+	#[test]
+	fn true_peak_between_samples() {
+		// Sines whose peaks fall between the samples, at -6 dBTP, after EBU Tech 3341 cases 15 to 18
+		let cases: [(f64, f64); 4] = [(4.0, 0.0), (4.0, 45.0), (6.0, 60.0), (8.0, 67.5)];
+
+		for &(divisor, phase) in cases.iter() {
+			let mut meter = Meter::new(1, SAMPLE_RATE);
+			// The abrupt onset rings in the interpolator, so the peaks are measured from the second half second on,
+			// which continues the sine as the first half second holds whole periods
+			feed(&mut meter, SAMPLE_RATE / divisor, phase.to_radians(), &[(&[-6.0], 0.5)]);
+			meter.control().reset_peaks();
+			feed(&mut meter, SAMPLE_RATE / divisor, phase.to_radians(), &[(&[-6.0], 0.5)]);
+			let reading = meter.control().channel(0);
+			assert!(reading.true_peak_hold > -6.4 && reading.true_peak_hold < -5.8, "{} dBTP at fs/{} and {} degrees", reading.true_peak_hold, divisor, phase);
+			assert!(reading.true_peak_hold >= reading.peak_hold);
+		}
+	}
+}
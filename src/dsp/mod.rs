@@ -0,0 +1,25 @@
+pub mod audio_block;
+pub mod biquad;
+pub mod block_adapter;
+pub mod channel_tap;
+pub mod convolution;
+pub mod delay_line;
+pub mod dynamics;
+pub mod fft;
+pub mod graph;
+pub mod lock_free_queue;
+pub mod meter;
+pub mod multi_tap_delay;
+pub mod parameters;
+pub mod parametric_eq;
+pub mod processor;
+pub mod resampler;
+pub mod reverb;
+pub mod routing_matrix;
+pub mod sample;
+pub mod signal_generator;
+pub mod spectrum_analyzer;
+pub mod transport;
+pub mod tuner;
+pub mod wav;
+pub mod worker_pool;
@@ -0,0 +1,535 @@
+use crate::dsp::audio_block::{AudioBlock, AudioBlockMut};
+use crate::dsp::biquad::{Biquad, Coefficients, FilterType};
+use crate::dsp::parameters::{ParameterId, ParameterInfo, ParameterRegistry, Parameters, SmoothedParameter, Smoothing, Unit};
+use crate::dsp::processor::{pass_through, Processor};
+use crate::dsp::sample::Sample;
+use crate::dsp::transport::{Feel, NoteValue, Transport};
+use std::f64::consts::{FRAC_1_SQRT_2, PI};
+
+/// Time constant of delay time changes, which glide like a tape delay
+const TIME_SMOOTHING: f64 = 0.05;
+
+/// Largest modulation depth in milliseconds, the delay lines are longer by this
+const MAX_DEPTH_MS: f64 = 20.0;
+
+/// Shortest delay in frames, so the interpolation never reads frames not yet written
+const MIN_DELAY: f64 = 3.0;
+
+struct TapIds {
+	time: ParameterId,
+	note: ParameterId,
+	feel: ParameterId,
+	level: ParameterId,
+	pan: ParameterId
+}
+
+struct DelayIds {
+	taps: Vec<TapIds>,
+	feedback: ParameterId,
+	low_cut: ParameterId,
+	high_cut: ParameterId,
+	ping_pong: ParameterId,
+	rate: ParameterId,
+	rate_note: ParameterId,
+	depth: ParameterId,
+	mix: ParameterId
+}
+
+/// Delay line of one channel with a power of two length
+struct Line<S: Sample> {
+	buffer: Vec<S>,
+	mask: usize
+}
+
+impl<S: Sample> Line<S> {
+	fn new(min_length: usize) -> Line<S> {
+		let length = min_length.next_power_of_two();
+
+		Line {
+			buffer: vec![S::ZERO; length],
+			mask: length - 1
+		}
+	}
+
+	/// Cubic Hermite interpolation `delay` frames before `write`
+	fn read(&self, write: usize, delay: f64) -> f64 {
+		let position = write as f64 - delay;
+		let index = position.floor();
+		let t = position - index;
+		let index = index as isize as usize;
+
+		let y0 = self.buffer[index.wrapping_sub(1) & self.mask].to_f64();
+		let y1 = self.buffer[index & self.mask].to_f64();
+		let y2 = self.buffer[index.wrapping_add(1) & self.mask].to_f64();
+		let y3 = self.buffer[index.wrapping_add(2) & self.mask].to_f64();
+
+		let c1 = 0.5 * (y2 - y0);
+		let c2 = y0 - 2.5 * y1 + 2.0 * y2 - 0.5 * y3;
+		let c3 = 0.5 * (y3 - y0) + 1.5 * (y1 - y2);
+		((c3 * t + c2) * t + c1) * t + y1
+	}
+}
+
+/// Delay with several taps reading one line per channel. The first tap feeds back through
+/// a low cut and a high cut filter. In ping-pong mode the first two channels feed each
+/// other, and the sum of their inputs enters the first. A sine LFO modulates all taps,
+/// spread in phase. Taps and the LFO can follow the tempo of a `Transport`, the LFO then
+/// keeps its phase locked to the beats counted from the driver's sample position.
+pub struct MultiTapDelay<S: Sample> {
+	parameters: Parameters,
+	ids: DelayIds,
+	transport: Transport,
+	num_channels: usize,
+	max_delay: f64,
+	sample_rate: f64,
+	lines: Vec<Line<S>>,
+	write: usize,
+	delays: Vec<f64>,
+	targets: Vec<f64>,
+	modulated: Vec<f64>,
+	gains: Vec<(f64, f64, f64)>,
+	time_coefficient: f64,
+	low_cut: Vec<Biquad>,
+	high_cut: Vec<Biquad>,
+	filters: (Coefficients, Coefficients),
+	designed: (f64, f64),
+	feedback: SmoothedParameter<f64>,
+	mix: SmoothedParameter<f64>,
+	lfo_phase: f64,
+	position: i64,
+	published_position: i64
+}
+
+impl<S: Sample> MultiTapDelay<S> {
+	/// Per tap the parameters "Tap <n> Time", "Tap <n> Note" (0 for the free time, otherwise
+	/// 1 + a `NoteValue` index), "Tap <n> Feel", "Tap <n> Level" and "Tap <n> Pan", counting
+	/// from 1. Then "Feedback", "Low Cut", "High Cut", "Ping Pong", "Rate", "Rate Note"
+	/// (like "Tap <n> Note"), "Depth" and "Mix".
+	pub fn new(num_channels: usize, num_taps: usize, max_delay: f64, transport: Transport, sample_rate: f64) -> MultiTapDelay<S> {
+		let max_ms = max_delay * 1000.0;
+		let last_note = NoteValue::ALL.len() as f64;
+		let last_feel = (Feel::ALL.len() - 1) as f64;
+		let fixed = |info: ParameterInfo| info.with_smoothing(Smoothing::None, 0.0);
+
+		let mut registry = ParameterRegistry::new();
+		let taps = (0..num_taps).map(|index| {
+			let name = |parameter: &str| format!("Tap {} {}", index + 1, parameter);
+			let time = max_ms.min(250.0) * (index + 1) as f64;
+
+			TapIds {
+				time: registry.add(fixed(ParameterInfo::new(&name("Time"), 1.0, max_ms, time, Unit::Milliseconds))),
+				note: registry.add(fixed(ParameterInfo::new(&name("Note"), 0.0, last_note, 0.0, Unit::None))),
+				feel: registry.add(fixed(ParameterInfo::new(&name("Feel"), 0.0, last_feel, 0.0, Unit::None))),
+				level: registry.add(ParameterInfo::new(&name("Level"), 0.0, 100.0, 100.0 / (index + 1) as f64, Unit::Percent)),
+				pan: registry.add(ParameterInfo::new(&name("Pan"), -1.0, 1.0, 0.0, Unit::Pan))
+			}
+		}).collect();
+
+		let ids = DelayIds {
+			taps,
+			feedback: registry.add(ParameterInfo::new("Feedback", 0.0, 95.0, 30.0, Unit::Percent)),
+			low_cut: registry.add(ParameterInfo::new("Low Cut", 20.0, 2000.0, 80.0, Unit::Hertz).with_smoothing(Smoothing::PerBlock, 0.02)),
+			high_cut: registry.add(ParameterInfo::new("High Cut", 500.0, 20000.0, 8000.0, Unit::Hertz).with_smoothing(Smoothing::PerBlock, 0.02)),
+			ping_pong: registry.add(fixed(ParameterInfo::new("Ping Pong", 0.0, 1.0, 0.0, Unit::None))),
+			rate: registry.add(fixed(ParameterInfo::new("Rate", 0.01, 10.0, 0.5, Unit::Hertz))),
+			rate_note: registry.add(fixed(ParameterInfo::new("Rate Note", 0.0, last_note, 0.0, Unit::None))),
+			depth: registry.add(ParameterInfo::new("Depth", 0.0, MAX_DEPTH_MS, 0.0, Unit::Milliseconds).with_smoothing(Smoothing::PerBlock, 0.05)),
+			mix: registry.add(ParameterInfo::new("Mix", 0.0, 100.0, 30.0, Unit::Percent))
+		};
+		let parameters = registry.build();
+
+		let mut delay = MultiTapDelay {
+			feedback: parameters.smoother(ids.feedback, sample_rate),
+			mix: parameters.smoother(ids.mix, sample_rate),
+			parameters,
+			ids,
+			transport,
+			num_channels,
+			max_delay,
+			sample_rate,
+			lines: Vec::new(),
+			write: 0,
+			delays: vec![0.0; num_taps],
+			targets: vec![0.0; num_taps],
+			modulated: vec![0.0; num_taps],
+			gains: vec![(0.0, 0.0, 0.0); num_taps],
+			time_coefficient: 0.0,
+			low_cut: vec![Biquad::new(); num_channels],
+			high_cut: vec![Biquad::new(); num_channels],
+			filters: (Coefficients::identity(), Coefficients::identity()),
+			designed: (0.0, 0.0),
+			lfo_phase: 0.0,
+			position: 0,
+			published_position: i64::MIN
+		};
+		delay.allocate();
+		delay
+	}
+
+	/// Values can be changed from any thread
+	pub fn parameters(&self) -> Parameters {
+		self.parameters.clone()
+	}
+
+	/// Clears the delay lines and jumps to the current parameter values
+	pub fn reset(&mut self) {
+		self.feedback = self.parameters.smoother(self.ids.feedback, self.sample_rate);
+		self.mix = self.parameters.smoother(self.ids.mix, self.sample_rate);
+		for line in self.lines.iter_mut() {
+			line.buffer.fill(S::ZERO);
+		}
+		for filter in self.low_cut.iter_mut().chain(self.high_cut.iter_mut()) {
+			filter.reset();
+		}
+		for (tap, delay) in self.delays.iter_mut().enumerate() {
+			*delay = MultiTapDelay::<S>::tap_target(&self.parameters, &self.ids.taps[tap], &self.transport, self.max_delay, self.sample_rate);
+		}
+	}
+
+	fn allocate(&mut self) {
+		let length = ((self.max_delay + MAX_DEPTH_MS / 1000.0) * self.sample_rate).ceil() as usize + 4;
+
+		self.lines = (0..self.num_channels).map(|_| Line::new(length)).collect();
+		self.write = 0;
+		self.time_coefficient = (-1.0 / (TIME_SMOOTHING * self.sample_rate)).exp();
+		self.designed = (0.0, 0.0);
+		self.reset();
+	}
+
+	/// Delay of a tap in frames, from its time or note value
+	fn tap_target(parameters: &Parameters, tap: &TapIds, transport: &Transport, max_delay: f64, sample_rate: f64) -> f64 {
+		let note = parameters.get(tap.note);
+		let frames = if note >= 0.5 {
+			let feel = Feel::from_index(parameters.get(tap.feel));
+			transport.note_samples(NoteValue::from_index(note - 1.0), feel, sample_rate)
+		} else {
+			parameters.get(tap.time) / 1000.0 * sample_rate
+		};
+
+		frames.clamp(MIN_DELAY, max_delay * sample_rate)
+	}
+
+	/// LFO phase at the start of the block and its increment per frame
+	fn lfo(&mut self, frames: usize) -> (f64, f64) {
+		// A new position from the driver wins, otherwise count the frames processed since
+		let published = self.transport.position();
+		if published != self.published_position {
+			self.published_position = published;
+			self.position = published;
+		}
+		let position = self.position;
+		self.position += frames as i64;
+
+		let note = self.parameters.get(self.ids.rate_note);
+		if note >= 0.5 {
+			let cycle = NoteValue::from_index(note - 1.0).beats();
+			let beat = self.transport.beat_at(position, self.sample_rate);
+			((beat / cycle).fract(), 1.0 / (cycle * self.transport.samples_per_beat(self.sample_rate)))
+		} else {
+			let increment = self.parameters.get(self.ids.rate) / self.sample_rate;
+			let phase = self.lfo_phase;
+			self.lfo_phase = (phase + increment * frames as f64).fract();
+			(phase, increment)
+		}
+	}
+
+	fn design_filters(&mut self) {
+		let wanted = (self.parameters.get(self.ids.low_cut), self.parameters.get(self.ids.high_cut));
+
+		if wanted != self.designed {
+			self.designed = wanted;
+			self.filters = (
+				Coefficients::new(FilterType::HighPass, self.sample_rate, wanted.0, FRAC_1_SQRT_2, 0.0),
+				Coefficients::new(FilterType::LowPass, self.sample_rate, wanted.1, FRAC_1_SQRT_2, 0.0)
+			);
+		}
+	}
+}
+
+impl<S: Sample> Processor<S> for MultiTapDelay<S> {
+	/// Channels beyond those given to `new` are passed through dry
+	fn process(&mut self, input: &AudioBlock<S>, output: &mut AudioBlockMut<S>) {
+		let frames = output.num_frames();
+		let num_channels = self.num_channels.min(output.num_channels());
+		let num_taps = self.delays.len();
+		let ping_pong = num_channels >= 2 && self.parameters.get(self.ids.ping_pong) >= 0.5;
+
+		pass_through(input, output);
+
+		let (mut phase, increment) = self.lfo(frames);
+		let depth = self.parameters.get(self.ids.depth) / 1000.0 * self.sample_rate;
+		self.feedback.update(frames);
+		self.mix.update(frames);
+		self.design_filters();
+
+		for (tap, ids) in self.ids.taps.iter().enumerate() {
+			let level = self.parameters.get(ids.level) / 100.0;
+			let pan = self.parameters.get(ids.pan);
+
+			self.targets[tap] = MultiTapDelay::<S>::tap_target(&self.parameters, ids, &self.transport, self.max_delay, self.sample_rate);
+			// Panned for the first two channels, channels after them get the plain level
+			self.gains[tap] = if num_channels == 1 {
+				(level, level, level)
+			} else {
+				(level * (1.0 - pan).min(1.0), level * (1.0 + pan).min(1.0), level)
+			};
+		}
+
+		for frame in 0..frames {
+			let feedback = self.feedback.next_value() / 100.0;
+			let mix = self.mix.next_value() / 100.0;
+			let mut returns = [0.0f64; 2];
+
+			for tap in 0..num_taps {
+				let target = self.targets[tap];
+				self.delays[tap] = target + (self.delays[tap] - target) * self.time_coefficient;
+
+				let offset = tap as f64 / num_taps as f64;
+				let modulation = depth * 0.5 * (1.0 + (2.0 * PI * (phase + offset)).sin());
+				self.modulated[tap] = self.delays[tap] + modulation;
+			}
+
+			let channels = self.lines.iter_mut().zip(self.low_cut.iter_mut().zip(self.high_cut.iter_mut()));
+			for (channel, (line, (low_cut, high_cut))) in channels.enumerate().take(num_channels) {
+				let mut wet = 0.0;
+
+				for (delay, (left, right, level)) in self.modulated.iter().zip(self.gains.iter()) {
+					let gain = match channel {
+						0 => *left,
+						1 => *right,
+						_ => *level
+					};
+					wet += line.read(self.write, *delay) * gain;
+				}
+
+				let first = self.modulated.first().map_or(0.0, |delay| line.read(self.write, *delay));
+				let filtered = high_cut.process(&self.filters.1, low_cut.process(&self.filters.0, first));
+				let samples = output.channel_mut(channel);
+				let dry = samples[frame].to_f64();
+				samples[frame] = S::from_f64(dry + (wet - dry) * mix);
+
+				if ping_pong && channel < 2 {
+					returns[channel] = filtered * feedback;
+				} else {
+					line.buffer[self.write & line.mask] = S::from_f64(dry + filtered * feedback);
+				}
+			}
+
+			if ping_pong {
+				let left = input.channel(0)[frame].to_f64();
+				let right = if input.num_channels() > 1 { input.channel(1)[frame].to_f64() } else { 0.0 };
+				let mask = self.lines[0].mask;
+
+				self.lines[0].buffer[self.write & mask] = S::from_f64(0.5 * (left + right) + returns[1]);
+				self.lines[1].buffer[self.write & mask] = S::from_f64(returns[0]);
+			}
+
+			self.write = self.write.wrapping_add(1);
+			phase = (phase + increment).fract();
+		}
+	}
+
+	fn prepare(&mut self, sample_rate: f64, _max_block_size: usize) {
+		if sample_rate != self.sample_rate {
+			self.sample_rate = sample_rate;
+			self.allocate();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::dsp::audio_block::AudioBuffer;
+
+	/// Frames of the mono impulse response after the delay jumped to its settings
+	fn impulse_response(delay: &mut MultiTapDelay<f64>, frames: usize) -> Vec<f64> {
+		delay.reset();
+
+		let mut input = AudioBuffer::with_channels(1, 512);
+		let mut output = AudioBuffer::with_channels(1, 512);
+		let mut response = Vec::with_capacity(frames);
+
+		while response.len() < frames {
+			input.as_block_mut().fill(0.0);
+			if response.is_empty() {
+				input.as_block_mut().channel_mut(0)[0] = 1.0;
+			}
+			delay.process(&input.as_block(), &mut output.as_block_mut());
+			response.extend_from_slice(output.as_block().channel(0));
+		}
+		response.truncate(frames);
+		response
+	}
+
+	/// Passes the channels through the delay in blocks of 512 frames
+	fn run(delay: &mut MultiTapDelay<f64>, channels: &[&[f64]]) -> Vec<Vec<f64>> {
+		let len = channels[0].len();
+		let mut input = AudioBuffer::<f64>::with_channels(channels.len(), 512);
+		let mut output = AudioBuffer::<f64>::with_channels(channels.len(), 512);
+		let mut played = vec![Vec::with_capacity(len); channels.len()];
+
+		for start in (0..len).step_by(512) {
+			let frames = 512.min(len - start);
+			input.set_num_frames(frames);
+			output.set_num_frames(frames);
+			for (channel, samples) in channels.iter().enumerate() {
+				input.as_block_mut().channel_mut(channel).copy_from_slice(&samples[start..start + frames]);
+			}
+			delay.process(&input.as_block(), &mut output.as_block_mut());
+			for (samples, channel) in played.iter_mut().zip(output.as_block().channels()) {
+				samples.extend_from_slice(channel);
+			}
+		}
+		played
+	}
+
+	/// A delay of one tap at `time_ms`, fully wet and without feedback
+	fn single_tap(num_channels: usize, time_ms: f64) -> MultiTapDelay<f64> {
+		let mut delay = MultiTapDelay::<f64>::new(num_channels, 1, 1.0, Transport::new(120.0), 48000.0);
+		let parameters = delay.parameters();
+		let set = |name: &str, value: f64| parameters.set(parameters.find(name).unwrap(), value);
+		set("Tap 1 Time", time_ms);
+		set("Feedback", 0.0);
+		set("Mix", 100.0);
+		delay.reset();
+		delay
+	}
+
+	#[test]
+	fn synced_taps_sit_at_note_lengths() {
+		let sample_rate = 48000.0;
+		let transport = Transport::new(100.0);
+		let mut delay = MultiTapDelay::<f64>::new(1, 3, 4.0, transport.clone(), sample_rate);
+		let parameters = delay.parameters();
+		let set = |name: &str, value: f64| parameters.set(parameters.find(name).unwrap(), value);
+
+		let notes = [
+			(NoteValue::Quarter, Feel::Straight),
+			(NoteValue::Eighth, Feel::Dotted),
+			(NoteValue::Sixteenth, Feel::Triplet)
+		];
+		for (tap, (note, feel)) in notes.iter().enumerate() {
+			let index = NoteValue::ALL.iter().position(|value| value == note).unwrap();
+			set(&format!("Tap {} Note", tap + 1), 1.0 + index as f64);
+			set(&format!("Tap {} Feel", tap + 1), Feel::ALL.iter().position(|value| value == feel).unwrap() as f64);
+			set(&format!("Tap {} Level", tap + 1), 100.0);
+		}
+		set("Feedback", 0.0);
+		set("Mix", 100.0);
+
+		for &tempo in [100.0, 150.0].iter() {
+			transport.set_tempo(tempo);
+
+			let mut expected: Vec<usize> = notes.iter()
+				.map(|(note, feel)| transport.note_samples(*note, *feel, sample_rate) as usize)
+				.collect();
+			expected.sort();
+
+			let response = impulse_response(&mut delay, 30000);
+			let taps: Vec<usize> = (0..response.len()).filter(|frame| response[*frame].abs() > 1e-9).collect();
+			assert_eq!(taps, expected, "at {} bpm", tempo);
+
+			for frame in taps {
+				assert!((response[frame] - 1.0).abs() < 1e-9);
+			}
+		}
+	}
+
+	#[test]
+	fn fractional_delays_interpolate() {
+		// 48.5 frames: the cubic Hermite kernel half way between two frames
+		let mut delay = single_tap(1, 48.5 / 48.0);
+		let response = impulse_response(&mut delay, 100);
+
+		for (sample, expected) in response[47..51].iter().zip([-0.0625, 0.5625, 0.5625, -0.0625]) {
+			assert!((sample - expected).abs() < 1e-9, "{} instead of {}", sample, expected);
+		}
+		assert!(response[..47].iter().chain(response[51..].iter()).all(|sample| *sample == 0.0));
+	}
+
+	#[test]
+	fn modulation_moves_the_read_position() {
+		// 10 ms plus a 5 ms sine sweep at 5 Hz, read from a ramp, which the interpolation
+		// reproduces exactly at any fractional position
+		let mut delay = single_tap(1, 10.0);
+		let parameters = delay.parameters();
+		parameters.set(parameters.find("Rate").unwrap(), 5.0);
+		parameters.set(parameters.find("Depth").unwrap(), 5.0);
+
+		let ramp: Vec<f64> = (0..24000).map(|frame| frame as f64).collect();
+		let output = run(&mut delay, &[&ramp]).remove(0);
+
+		for (frame, sample) in output.iter().enumerate().skip(1000) {
+			let phase = 5.0 * frame as f64 / 48000.0;
+			let delayed = 480.0 + 240.0 * 0.5 * (1.0 + (2.0 * PI * phase).sin());
+			assert!((sample - (frame as f64 - delayed)).abs() < 1e-6, "frame {}", frame);
+		}
+	}
+
+	#[test]
+	fn feedback_passes_the_filters() {
+		let mut delay = single_tap(1, 100.0);
+		let parameters = delay.parameters();
+		parameters.set(parameters.find("Feedback").unwrap(), 50.0);
+		parameters.set(parameters.find("Low Cut").unwrap(), 200.0);
+		parameters.set(parameters.find("High Cut").unwrap(), 2000.0);
+		let response = impulse_response(&mut delay, 14400);
+
+		// The first echo is the plain impulse, each later one went once more through both filters
+		let low_cut = Coefficients::new(FilterType::HighPass, 48000.0, 200.0, FRAC_1_SQRT_2, 0.0);
+		let high_cut = Coefficients::new(FilterType::LowPass, 48000.0, 2000.0, FRAC_1_SQRT_2, 0.0);
+		let mut filters = (Biquad::new(), Biquad::new());
+		let mut echo: Vec<f64> = (0..4800).map(|frame| if frame == 0 { 1.0 } else { 0.0 }).collect();
+
+		for start in [4800, 9600] {
+			for (frame, expected) in echo.iter().enumerate() {
+				assert!((response[start + frame] - expected).abs() < 1e-9, "frame {}", start + frame);
+			}
+			echo = echo.iter().map(|sample| 0.5 * filters.1.process(&high_cut, filters.0.process(&low_cut, *sample))).collect();
+		}
+
+		// Neither DC nor Nyquist come back
+		let second = &response[9600..14400];
+		assert!(second.iter().sum::<f64>().abs() < 1e-6);
+		assert!(second.iter().enumerate().map(|(frame, sample)| if frame % 2 == 0 { *sample } else { -sample }).sum::<f64>().abs() < 1e-6);
+	}
+
+	#[test]
+	fn ping_pong_alternates_the_channels() {
+		let mut delay = single_tap(2, 100.0);
+		let parameters = delay.parameters();
+		parameters.set(parameters.find("Feedback").unwrap(), 50.0);
+		parameters.set(parameters.find("Ping Pong").unwrap(), 1.0);
+		delay.reset();
+
+		let mut left = vec![0.0; 24000];
+		left[0] = 1.0;
+		let output = run(&mut delay, &[&left, &vec![0.0; 24000]]);
+
+		// Echoes leave from the left, then bounce between the channels, with only the filters'
+		// ringing left behind
+		let energy = |channel: usize, echo: usize| output[channel][echo * 4800..(echo + 1) * 4800].iter().map(|sample| sample * sample).sum::<f64>();
+		for echo in 1..5 {
+			let (sounding, silent) = if echo % 2 == 1 { (0, 1) } else { (1, 0) };
+			assert!(energy(sounding, echo) > 0.01 * 0.25f64.powi(echo as i32), "echo {}", echo);
+			assert!(energy(silent, echo) < 1e-12 * energy(sounding, echo), "echo {}", echo);
+		}
+	}
+
+	#[test]
+	fn channels_after_the_second_get_the_tap_level() {
+		let mut delay = single_tap(3, 10.0);
+		let parameters = delay.parameters();
+		parameters.set(parameters.find("Tap 1 Level").unwrap(), 50.0);
+		parameters.set(parameters.find("Tap 1 Pan").unwrap(), -1.0);
+
+		let mut impulse = vec![0.0; 1024];
+		impulse[0] = 1.0;
+		let output = run(&mut delay, &[&impulse, &impulse, &impulse]);
+
+		assert_eq!(output[0][480], 0.5);
+		assert_eq!(output[1][480], 0.0);
+		assert_eq!(output[2][480], 0.5);
+	}
+}
@@ -0,0 +1,465 @@
+use crate::dsp::lock_free_queue::LockFreeQueue;
+use crate::dsp::sample::Sample;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Changes from the callback the UI has not picked up yet, further changes are dropped
+const CHANGE_QUEUE_CAPACITY: usize = 1024;
+
+/// f32 stored as its bits, loads and stores are atomic
+#[derive(Default)]
+pub struct AtomicF32(AtomicU32);
+
+impl AtomicF32 {
+	pub fn new(value: f32) -> AtomicF32 {
+		AtomicF32(AtomicU32::new(value.to_bits()))
+	}
+
+	pub fn load(&self) -> f32 {
+		f32::from_bits(self.0.load(Ordering::Relaxed))
+	}
+
+	pub fn store(&self, value: f32) {
+		self.0.store(value.to_bits(), Ordering::Relaxed);
+	}
+}
+
+/// f64 stored as its bits, loads and stores are atomic
+#[derive(Default)]
+pub struct AtomicF64(AtomicU64);
+
+impl AtomicF64 {
+	pub fn new(value: f64) -> AtomicF64 {
+		AtomicF64(AtomicU64::new(value.to_bits()))
+	}
+
+	pub fn load(&self) -> f64 {
+		f64::from_bits(self.0.load(Ordering::Relaxed))
+	}
+
+	pub fn store(&self, value: f64) {
+		self.0.store(value.to_bits(), Ordering::Relaxed);
+	}
+}
+
+/// Linear gain of a value in `Unit::Decibels`
+pub fn db_to_gain(db: f64) -> f64 {
+	10.0f64.powf(db / 20.0)
+}
+
+/// Level in dB of a linear gain, silence gives negative infinity
+pub fn gain_to_db(gain: f64) -> f64 {
+	20.0 * gain.log10()
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Unit {
+	None,
+	Decibels,
+	Hertz,
+	Milliseconds,
+	Seconds,
+	Percent,
+	Pan				// PAN_LEFT to PAN_RIGHT
+}
+
+impl Unit {
+	pub fn symbol(&self) -> &'static str {
+		match self {
+			Unit::None | Unit::Pan => "",
+			Unit::Decibels => "dB",
+			Unit::Hertz => "Hz",
+			Unit::Milliseconds => "ms",
+			Unit::Seconds => "s",
+			Unit::Percent => "%"
+		}
+	}
+}
+
+/// How the callback follows changes of a parameter
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Smoothing {
+	None,			// jump to the new value with the next block
+	PerBlock,		// one value per block, approaching the new value block by block
+	PerSample		// approach the new value sample by sample
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParameterInfo {
+	pub name: String,
+	pub min: f64,
+	pub max: f64,
+	pub default: f64,
+	pub unit: Unit,
+	pub smoothing: Smoothing,
+	pub smoothing_time: f64			// time constant in seconds
+}
+
+impl ParameterInfo {
+	pub fn new(name: &str, min: f64, max: f64, default: f64, unit: Unit) -> ParameterInfo {
+		ParameterInfo {
+			name: String::from(name),
+			min,
+			max,
+			default: default.clamp(min, max),
+			unit,
+			smoothing: Smoothing::PerSample,
+			smoothing_time: 0.005
+		}
+	}
+
+	pub fn with_smoothing(mut self, smoothing: Smoothing, smoothing_time: f64) -> ParameterInfo {
+		self.smoothing = smoothing;
+		self.smoothing_time = smoothing_time;
+		self
+	}
+
+	/// Position of `value` within the range as 0.0 to 1.0, e.g. for a UI control
+	pub fn normalize(&self, value: f64) -> f64 {
+		if self.max == self.min {
+			0.0
+		} else {
+			(value.clamp(self.min, self.max) - self.min) / (self.max - self.min)
+		}
+	}
+
+	/// Value at a position within the range, the inverse of `normalize`
+	pub fn denormalize(&self, normalized: f64) -> f64 {
+		self.min + normalized.clamp(0.0, 1.0) * (self.max - self.min)
+	}
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ParameterId(usize);
+
+/// Collects the parameters before they are shared with the callback
+#[derive(Default)]
+pub struct ParameterRegistry {
+	infos: Vec<ParameterInfo>
+}
+
+impl ParameterRegistry {
+	pub fn new() -> ParameterRegistry {
+		ParameterRegistry::default()
+	}
+
+	pub fn add(&mut self, info: ParameterInfo) -> ParameterId {
+		if info.min > info.max {
+			panic!("Parameter '{}' has an empty range {}..{}", info.name, info.min, info.max);
+		}
+
+		self.infos.push(info);
+		ParameterId(self.infos.len() - 1)
+	}
+
+	pub fn build(self) -> Parameters {
+		let values = self.infos.iter().map(|info| AtomicF64::new(info.default)).collect();
+
+		Parameters {
+			infos: self.infos.into(),
+			values,
+			changes: Arc::new(LockFreeQueue::new(CHANGE_QUEUE_CAPACITY)),
+			dropped: Arc::new(AtomicU64::new(0))
+		}
+	}
+}
+
+/// Current values of all parameters, shared between the UI and the callback without locks
+#[derive(Clone)]
+pub struct Parameters {
+	infos: Arc<[ParameterInfo]>,
+	values: Arc<[AtomicF64]>,
+	changes: Arc<LockFreeQueue<(ParameterId, f64)>>,
+	dropped: Arc<AtomicU64>
+}
+
+impl Parameters {
+	pub fn len(&self) -> usize {
+		self.infos.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.infos.is_empty()
+	}
+
+	pub fn info(&self, id: ParameterId) -> &ParameterInfo {
+		&self.infos[id.0]
+	}
+
+	pub fn find(&self, name: &str) -> Option<ParameterId> {
+		self.infos.iter().position(|info| info.name == name).map(ParameterId)
+	}
+
+	pub fn get(&self, id: ParameterId) -> f64 {
+		self.values[id.0].load()
+	}
+
+	pub fn get_f32(&self, id: ParameterId) -> f32 {
+		self.get(id) as f32
+	}
+
+	/// Sets the value clamped to the parameter's range, e.g. from the UI
+	pub fn set(&self, id: ParameterId, value: f64) {
+		let info = &self.infos[id.0];
+		self.values[id.0].store(value.clamp(info.min, info.max));
+	}
+
+	/// Sets the value from the callback, e.g. for automation, and reports it to `poll_changes`
+	pub fn set_and_notify(&self, id: ParameterId, value: f64) {
+		self.set(id, value);
+
+		if !self.changes.push((id, self.get(id))) {
+			self.dropped.fetch_add(1, Ordering::Relaxed);
+		}
+	}
+
+	/// Next change made with `set_and_notify`, oldest first
+	pub fn poll_changes(&self) -> Option<(ParameterId, f64)> {
+		self.changes.pop()
+	}
+
+	/// Number of changes so far that did not fit into the queue. When it grows, the UI should
+	/// re-read all values.
+	pub fn dropped_changes(&self) -> u64 {
+		self.dropped.load(Ordering::Relaxed)
+	}
+
+	/// Follows the parameter in the callback according to its smoothing
+	pub fn smoother<S: Sample>(&self, id: ParameterId, sample_rate: f64) -> SmoothedParameter<S> {
+		let info = &self.infos[id.0];
+		let value = self.get(id);
+		let samples = info.smoothing_time * sample_rate;
+
+		SmoothedParameter {
+			parameters: self.clone(),
+			id,
+			smoothing: info.smoothing,
+			samples,
+			coefficient: if samples > 0.0 { (-1.0 / samples).exp() } else { 0.0 },
+			current: value,
+			target: value,
+			value: S::from_f64(value)
+		}
+	}
+}
+
+/// Distance to the target below which smoothing ends
+const SETTLED: f64 = 1e-9;
+
+/// A parameter's value as seen by the callback. Call `update` once per block, then `next_value`
+/// for every sample with `Smoothing::PerSample`, or `value` otherwise.
+pub struct SmoothedParameter<S: Sample> {
+	parameters: Parameters,
+	id: ParameterId,
+	smoothing: Smoothing,
+	samples: f64,
+	coefficient: f64,
+	current: f64,
+	target: f64,
+	value: S
+}
+
+impl<S: Sample> SmoothedParameter<S> {
+	pub fn id(&self) -> ParameterId {
+		self.id
+	}
+
+	/// Picks up the latest value for a block of `frames` frames
+	pub fn update(&mut self, frames: usize) {
+		self.target = self.parameters.get(self.id);
+
+		match self.smoothing {
+			Smoothing::None => self.current = self.target,
+			Smoothing::PerBlock if self.samples > 0.0 => {
+				let coefficient = (-(frames as f64) / self.samples).exp();
+				self.current = self.target + (self.current - self.target) * coefficient;
+			},
+			Smoothing::PerBlock => self.current = self.target,
+			Smoothing::PerSample => {}
+		}
+
+		if (self.current - self.target).abs() < SETTLED {
+			self.current = self.target;
+		}
+		self.value = S::from_f64(self.current);
+	}
+
+	pub fn is_smoothing(&self) -> bool {
+		self.current != self.target
+	}
+
+	/// Value for the whole block, or the value reached so far with per sample smoothing
+	pub fn value(&self) -> S {
+		self.value
+	}
+
+	/// Value for the next sample
+	pub fn next_value(&mut self) -> S {
+		if self.smoothing == Smoothing::PerSample && self.current != self.target {
+			self.current = self.target + (self.current - self.target) * self.coefficient;
+			if (self.current - self.target).abs() < SETTLED {
+				self.current = self.target;
+			}
+			self.value = S::from_f64(self.current);
+		}
+		self.value
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::thread;
+
+	const SAMPLE_RATE: f64 = 48000.0;
+
+	fn single(info: ParameterInfo) -> (Parameters, ParameterId) {
+		let mut registry = ParameterRegistry::new();
+		let id = registry.add(info);
+		(registry.build(), id)
+	}
+
+	#[test]
+	fn values_are_clamped() {
+		let info = ParameterInfo::new("Gain", -60.0, 12.0, 20.0, Unit::Decibels);
+		assert_eq!(info.default, 12.0);
+
+		let (parameters, id) = single(info);
+		assert_eq!(parameters.find("Gain"), Some(id));
+		assert_eq!(parameters.find("Gain "), None);
+		assert_eq!(parameters.get(id), 12.0);
+
+		parameters.set(id, -100.0);
+		assert_eq!(parameters.get(id), -60.0);
+		parameters.set(id, 3.5);
+		assert_eq!(parameters.get(id), 3.5);
+		assert_eq!(parameters.get_f32(id), 3.5);
+	}
+
+	#[test]
+	#[should_panic(expected = "empty range")]
+	fn empty_range_panics() {
+		let info = ParameterInfo::new("Broken", 0.0, 1.0, 0.5, Unit::None);
+		ParameterRegistry::new().add(ParameterInfo { min: 1.0, max: 0.0, ..info });
+	}
+
+	#[test]
+	fn normalization() {
+		let info = ParameterInfo::new("Frequency", 20.0, 220.0, 1000.0, Unit::Hertz);
+
+		assert_eq!(info.normalize(20.0), 0.0);
+		assert_eq!(info.normalize(70.0), 0.25);
+		assert_eq!(info.normalize(500.0), 1.0);
+		assert_eq!(info.denormalize(0.75), 170.0);
+		assert_eq!(info.denormalize(-1.0), 20.0);
+		assert_eq!(info.denormalize(2.0), 220.0);
+		for value in [20.0, 33.3, 120.0, 219.9] {
+			assert!((info.denormalize(info.normalize(value)) - value).abs() < 1e-12);
+		}
+
+		let fixed = ParameterInfo::new("Fixed", 1.0, 1.0, 1.0, Unit::None);
+		assert_eq!(fixed.normalize(1.0), 0.0);
+		assert_eq!(fixed.denormalize(0.5), 1.0);
+
+		assert!((db_to_gain(-6.0) - 0.501187).abs() < 1e-6);
+		assert!((gain_to_db(db_to_gain(-23.5)) + 23.5).abs() < 1e-12);
+		assert_eq!(gain_to_db(0.0), f64::NEG_INFINITY);
+	}
+
+	#[test]
+	fn per_sample_smoothing_reaches_the_target() {
+		let (parameters, id) = single(ParameterInfo::new("Level", 0.0, 1.0, 0.0, Unit::None));
+		let mut smoother = parameters.smoother::<f64>(id, SAMPLE_RATE);
+		parameters.set(id, 1.0);
+		smoother.update(64);
+		assert!(smoother.is_smoothing());
+		assert_eq!(smoother.value(), 0.0);
+
+		// One time constant of 5 ms brings it to 1 - 1/e
+		let values: Vec<f64> = (0..240).map(|_| smoother.next_value()).collect();
+		assert!(values.windows(2).all(|pair| pair[1] > pair[0]));
+		assert!((values[239] - (1.0 - (-1.0f64).exp())).abs() < 1e-9, "{}", values[239]);
+
+		let mut samples = 240;
+		while smoother.is_smoothing() {
+			smoother.next_value();
+			samples += 1;
+		}
+		assert_eq!(smoother.value(), 1.0);
+		assert!(samples < 240 * 25, "{} samples", samples);
+	}
+
+	#[test]
+	fn per_block_smoothing_reaches_the_target() {
+		let info = ParameterInfo::new("Level", 0.0, 1.0, 0.0, Unit::None).with_smoothing(Smoothing::PerBlock, 0.01);
+		let (parameters, id) = single(info);
+		let mut smoother = parameters.smoother::<f64>(id, SAMPLE_RATE);
+		parameters.set(id, 1.0);
+
+		// 480 frames are one time constant, the value holds for the whole block
+		smoother.update(480);
+		let first = smoother.value();
+		assert!((first - (1.0 - (-1.0f64).exp())).abs() < 1e-12);
+		assert_eq!(smoother.next_value(), first);
+		assert_eq!(smoother.next_value(), first);
+
+		let mut blocks = 1;
+		while smoother.is_smoothing() {
+			smoother.update(480);
+			blocks += 1;
+		}
+		assert_eq!(smoother.value(), 1.0);
+		assert!(blocks <= 21, "{} blocks", blocks);
+	}
+
+	#[test]
+	fn unsmoothed_values_jump() {
+		let info = ParameterInfo::new("Mode", 0.0, 3.0, 0.0, Unit::None).with_smoothing(Smoothing::None, 0.0);
+		let (parameters, id) = single(info);
+		let mut smoother = parameters.smoother::<f32>(id, SAMPLE_RATE);
+		parameters.set(id, 2.0);
+
+		assert_eq!(smoother.value(), 0.0);
+		smoother.update(64);
+		assert!(!smoother.is_smoothing());
+		assert_eq!(smoother.value(), 2.0);
+		assert_eq!(smoother.next_value(), 2.0);
+	}
+
+	#[test]
+	fn callback_changes_reach_the_ui() {
+		let mut registry = ParameterRegistry::new();
+		let gain = registry.add(ParameterInfo::new("Gain", -60.0, 12.0, 0.0, Unit::Decibels));
+		let pan = registry.add(ParameterInfo::new("Pan", -1.0, 1.0, 0.0, Unit::Pan));
+		let parameters = registry.build();
+
+		let callback = parameters.clone();
+		thread::spawn(move || {
+			callback.set_and_notify(gain, -6.0);
+			callback.set_and_notify(pan, 2.0);
+			callback.set_and_notify(gain, -3.0);
+		}).join().unwrap();
+
+		assert_eq!(parameters.poll_changes(), Some((gain, -6.0)));
+		assert_eq!(parameters.poll_changes(), Some((pan, 1.0)));
+		assert_eq!(parameters.poll_changes(), Some((gain, -3.0)));
+		assert_eq!(parameters.poll_changes(), None);
+		assert_eq!(parameters.get(gain), -3.0);
+		assert_eq!(parameters.dropped_changes(), 0);
+
+		// Changes beyond the queue's capacity are counted, reading the count keeps it
+		for index in 0..CHANGE_QUEUE_CAPACITY + 10 {
+			parameters.set_and_notify(gain, -(index as f64) / 100.0);
+		}
+		assert_eq!(parameters.dropped_changes(), 10);
+		assert_eq!(parameters.clone().dropped_changes(), 10);
+		assert_eq!(parameters.get(gain), -((CHANGE_QUEUE_CAPACITY + 9) as f64) / 100.0);
+
+		let mut received = 0;
+		while let Some((id, value)) = parameters.poll_changes() {
+			assert_eq!((id, value), (gain, -(received as f64) / 100.0));
+			received += 1;
+		}
+		assert_eq!(received, CHANGE_QUEUE_CAPACITY);
+	}
+}
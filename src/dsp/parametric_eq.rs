@@ -0,0 +1,443 @@
+use crate::dsp::audio_block::{AudioBlock, AudioBlockMut};
+use crate::dsp::biquad::{Biquad, Coefficients, FilterType};
+use crate::dsp::parameters::{AtomicF64, ParameterId, ParameterInfo, ParameterRegistry, Parameters, SmoothedParameter, Smoothing, Unit};
+use crate::dsp::processor::{pass_through, Processor};
+use crate::dsp::sample::Sample;
+use std::sync::Arc;
+
+/// Frames processed with the same coefficients while parameters move
+const SUB_BLOCK: usize = 32;
+
+/// Time constant of frequency, gain and Q changes
+const SMOOTHING_TIME: f64 = 0.02;
+
+/// Duration of the fade when a band is switched on or off or changes its type
+const CROSSFADE_TIME: f64 = 0.01;
+
+/// Settings of one band
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Band {
+	pub filter_type: FilterType,
+	pub frequency: f64,
+	pub gain_db: f64,				// only used by bells and shelves
+	pub q: f64,
+	pub enabled: bool
+}
+
+impl Band {
+	pub fn new(filter_type: FilterType, frequency: f64, gain_db: f64, q: f64) -> Band {
+		Band {
+			filter_type,
+			frequency,
+			gain_db,
+			q,
+			enabled: true
+		}
+	}
+}
+
+#[derive(Copy, Clone, Debug)]
+struct BandIds {
+	filter_type: ParameterId,
+	frequency: ParameterId,
+	gain: ParameterId,
+	q: ParameterId,
+	enabled: ParameterId
+}
+
+/// Parameters of a `ParametricEq`, shared with the UI
+#[derive(Clone)]
+pub struct EqControl {
+	parameters: Parameters,
+	bands: Arc<[BandIds]>,
+	sample_rate: Arc<AtomicF64>
+}
+
+impl EqControl {
+	/// Parameters named "Band <n> Type", "Band <n> Frequency", "Band <n> Gain", "Band <n> Q"
+	/// and "Band <n> Enabled", counting from 1
+	pub fn parameters(&self) -> &Parameters {
+		&self.parameters
+	}
+
+	pub fn num_bands(&self) -> usize {
+		self.bands.len()
+	}
+
+	pub fn band(&self, band: usize) -> Band {
+		let ids = &self.bands[band];
+
+		Band {
+			filter_type: FilterType::from_index(self.parameters.get(ids.filter_type)),
+			frequency: self.parameters.get(ids.frequency),
+			gain_db: self.parameters.get(ids.gain),
+			q: self.parameters.get(ids.q),
+			enabled: self.parameters.get(ids.enabled) >= 0.5
+		}
+	}
+
+	/// Values are clamped to the parameter ranges
+	pub fn set_band(&self, band: usize, settings: &Band) {
+		let ids = &self.bands[band];
+
+		self.parameters.set(ids.filter_type, settings.filter_type.index() as f64);
+		self.parameters.set(ids.frequency, settings.frequency);
+		self.parameters.set(ids.gain, settings.gain_db);
+		self.parameters.set(ids.q, settings.q);
+		self.parameters.set(ids.enabled, if settings.enabled { 1.0 } else { 0.0 });
+	}
+
+	/// Response of the whole EQ at `frequency` once all changes have settled
+	pub fn magnitude_db(&self, frequency: f64) -> f64 {
+		(0..self.num_bands()).map(|band| self.band_magnitude_db(band, frequency)).sum()
+	}
+
+	/// Response of a single band, 0 dB if it is switched off
+	pub fn band_magnitude_db(&self, band: usize, frequency: f64) -> f64 {
+		let settings = self.band(band);
+		if !settings.enabled {
+			return 0.0;
+		}
+
+		let sample_rate = self.sample_rate.load();
+		Coefficients::new(settings.filter_type, sample_rate, settings.frequency, settings.q, settings.gain_db)
+			.magnitude_db(frequency, sample_rate)
+	}
+}
+
+/// Callback side of a band
+struct BandState {
+	ids: BandIds,
+	filter_type: FilterType,
+	frequency: SmoothedParameter<f64>,
+	gain: SmoothedParameter<f64>,
+	q: SmoothedParameter<f64>,
+	designed: (f64, f64, f64),
+	coefficients: Coefficients,
+	filters: Vec<Biquad>,
+	mix: f64
+}
+
+impl BandState {
+	fn new(parameters: &Parameters, ids: BandIds, num_channels: usize, sample_rate: f64) -> BandState {
+		let mut band = BandState {
+			ids,
+			filter_type: FilterType::from_index(parameters.get(ids.filter_type)),
+			frequency: parameters.smoother(ids.frequency, sample_rate),
+			gain: parameters.smoother(ids.gain, sample_rate),
+			q: parameters.smoother(ids.q, sample_rate),
+			designed: (0.0, 0.0, 0.0),
+			coefficients: Coefficients::identity(),
+			filters: vec![Biquad::new(); num_channels],
+			mix: if parameters.get(ids.enabled) >= 0.5 { 1.0 } else { 0.0 }
+		};
+		band.design(sample_rate);
+		band
+	}
+
+	fn design(&mut self, sample_rate: f64) {
+		let wanted = (self.frequency.value(), self.gain.value(), self.q.value());
+
+		if wanted != self.designed {
+			self.designed = wanted;
+			self.coefficients = Coefficients::new(self.filter_type, sample_rate, wanted.0, wanted.2, wanted.1);
+		}
+	}
+
+	/// Follows the parameters for the next `frames` frames and returns the mix at their start
+	fn update(&mut self, parameters: &Parameters, frames: usize, sample_rate: f64) -> f64 {
+		let wanted_type = FilterType::from_index(parameters.get(self.ids.filter_type));
+		let enabled = parameters.get(self.ids.enabled) >= 0.5;
+
+		// A new type only takes over once the old one has faded out
+		if self.mix == 0.0 && wanted_type != self.filter_type {
+			self.filter_type = wanted_type;
+			self.designed = (0.0, 0.0, 0.0);
+		}
+		let target = if enabled && wanted_type == self.filter_type { 1.0 } else { 0.0 };
+
+		let start = self.mix;
+		if start == 0.0 && target > 0.0 {
+			for filter in self.filters.iter_mut() {
+				filter.reset();
+			}
+		}
+
+		let step = frames as f64 / (CROSSFADE_TIME * sample_rate);
+		self.mix = if target > start { (start + step).min(target) } else { (start - step).max(target) };
+
+		self.frequency.update(frames);
+		self.gain.update(frames);
+		self.q.update(frames);
+		self.design(sample_rate);
+
+		start
+	}
+}
+
+/// Multi-band EQ of biquad filters in series, applied to every channel alike.
+/// Parameter changes are smoothed and applied every few frames, switching a band on
+/// or off or changing its type crossfades between the filtered and unfiltered signal.
+pub struct ParametricEq<S: Sample> {
+	control: EqControl,
+	bands: Vec<BandState>,
+	num_channels: usize,
+	sample_rate: f64,
+	phantom: std::marker::PhantomData<S>
+}
+
+impl<S: Sample> ParametricEq<S> {
+	pub fn new(num_channels: usize, bands: &[Band], sample_rate: f64) -> ParametricEq<S> {
+		let mut registry = ParameterRegistry::new();
+		let smoothed = |info: ParameterInfo| info.with_smoothing(Smoothing::PerBlock, SMOOTHING_TIME);
+
+		let ids: Vec<BandIds> = bands.iter().enumerate().map(|(index, band)| {
+			let name = |parameter: &str| format!("Band {} {}", index + 1, parameter);
+			let last_type = (FilterType::ALL.len() - 1) as f64;
+
+			BandIds {
+				filter_type: registry.add(ParameterInfo::new(&name("Type"), 0.0, last_type, band.filter_type.index() as f64, Unit::None)
+					.with_smoothing(Smoothing::None, 0.0)),
+				frequency: registry.add(smoothed(ParameterInfo::new(&name("Frequency"), 20.0, 20000.0, band.frequency, Unit::Hertz))),
+				gain: registry.add(smoothed(ParameterInfo::new(&name("Gain"), -24.0, 24.0, band.gain_db, Unit::Decibels))),
+				q: registry.add(smoothed(ParameterInfo::new(&name("Q"), 0.1, 18.0, band.q, Unit::None))),
+				enabled: registry.add(ParameterInfo::new(&name("Enabled"), 0.0, 1.0, if band.enabled { 1.0 } else { 0.0 }, Unit::None)
+					.with_smoothing(Smoothing::None, 0.0))
+			}
+		}).collect();
+
+		let parameters = registry.build();
+		let states = ids.iter().map(|ids| BandState::new(&parameters, *ids, num_channels, sample_rate)).collect();
+
+		ParametricEq {
+			control: EqControl {
+				parameters,
+				bands: ids.into(),
+				sample_rate: Arc::new(AtomicF64::new(sample_rate))
+			},
+			bands: states,
+			num_channels,
+			sample_rate,
+			phantom: std::marker::PhantomData
+		}
+	}
+
+	/// Handle for changing bands and drawing the response from other threads
+	pub fn control(&self) -> EqControl {
+		self.control.clone()
+	}
+
+	pub fn reset(&mut self) {
+		for band in self.bands.iter_mut() {
+			for filter in band.filters.iter_mut() {
+				filter.reset();
+			}
+		}
+	}
+}
+
+impl<S: Sample> Processor<S> for ParametricEq<S> {
+	/// Channels beyond those given to `new` are passed through unfiltered
+	fn process(&mut self, input: &AudioBlock<S>, output: &mut AudioBlockMut<S>) {
+		let frames = output.num_frames();
+		let num_channels = self.num_channels.min(output.num_channels());
+
+		pass_through(input, output);
+
+		let mut start = 0;
+		while start < frames {
+			let len = SUB_BLOCK.min(frames - start);
+
+			for band in self.bands.iter_mut() {
+				let from = band.update(&self.control.parameters, len, self.sample_rate);
+				let to = band.mix;
+				if from == 0.0 && to == 0.0 {
+					continue;
+				}
+
+				let step = (to - from) / len as f64;
+				for (channel, filter) in output.channels_mut().take(num_channels).zip(band.filters.iter_mut()) {
+					let mut mix = from;
+					for sample in channel[start..start + len].iter_mut() {
+						mix += step;
+						let dry = sample.to_f64();
+						let wet = filter.process(&band.coefficients, dry);
+						*sample = S::from_f64(dry + (wet - dry) * mix);
+					}
+				}
+			}
+
+			start += len;
+		}
+	}
+
+	fn prepare(&mut self, sample_rate: f64, _max_block_size: usize) {
+		if sample_rate == self.sample_rate {
+			return;
+		}
+
+		self.sample_rate = sample_rate;
+		self.control.sample_rate.store(sample_rate);
+		self.bands = self.control.bands.iter()
+			.map(|ids| BandState::new(&self.control.parameters, *ids, self.num_channels, sample_rate))
+			.collect();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::dsp::audio_block::AudioBuffer;
+	use std::f64::consts::PI;
+
+	const SAMPLE_RATE: f64 = 48000.0;
+
+	fn sine(frequency: f64, sample_rate: f64, len: usize) -> Vec<f64> {
+		(0..len).map(|frame| 0.5 * (2.0 * PI * frequency * frame as f64 / sample_rate).sin()).collect()
+	}
+
+	/// Passes the channels through the EQ in blocks of `block_size` frames
+	fn run(eq: &mut ParametricEq<f64>, channels: &[&[f64]], block_size: usize) -> Vec<Vec<f64>> {
+		let len = channels[0].len();
+		let mut input = AudioBuffer::<f64>::with_channels(channels.len(), block_size);
+		let mut output = AudioBuffer::<f64>::with_channels(channels.len(), block_size);
+		let mut played = vec![Vec::with_capacity(len); channels.len()];
+
+		for start in (0..len).step_by(block_size) {
+			let frames = block_size.min(len - start);
+			input.set_num_frames(frames);
+			output.set_num_frames(frames);
+			for (channel, samples) in channels.iter().enumerate() {
+				input.as_block_mut().channel_mut(channel).copy_from_slice(&samples[start..start + frames]);
+			}
+			eq.process(&input.as_block(), &mut output.as_block_mut());
+			for (samples, channel) in played.iter_mut().zip(output.as_block().channels()) {
+				samples.extend_from_slice(channel);
+			}
+		}
+		played
+	}
+
+	/// Gain in dB of the output over the input within `range`
+	fn gain_db(input: &[f64], output: &[f64], range: std::ops::Range<usize>) -> f64 {
+		let power = |samples: &[f64]| samples[range.clone()].iter().map(|sample| sample * sample).sum::<f64>();
+		10.0 * (power(output) / power(input)).log10()
+	}
+
+	/// Largest change from one sample to the next
+	fn largest_step(samples: &[f64]) -> f64 {
+		samples.windows(2).map(|pair| (pair[1] - pair[0]).abs()).fold(0.0, f64::max)
+	}
+
+	fn bell() -> Band {
+		Band::new(FilterType::Bell, 1000.0, 12.0, 1.0)
+	}
+
+	#[test]
+	fn filters_as_designed() {
+		let mut eq = ParametricEq::<f64>::new(1, &[bell(), Band::new(FilterType::HighPass, 100.0, 0.0, 0.707)], SAMPLE_RATE);
+		let control = eq.control();
+
+		for frequency in [50.0, 400.0, 1000.0, 3000.0] {
+			let input = sine(frequency, SAMPLE_RATE, 24000);
+			let output = run(&mut eq, &[&input], 256).remove(0);
+			let measured = gain_db(&input, &output, 12000..24000);
+			assert!((measured - control.magnitude_db(frequency)).abs() < 0.05, "{} dB at {} Hz", measured, frequency);
+		}
+	}
+
+	#[test]
+	fn switching_a_band_crossfades() {
+		let mut eq = ParametricEq::<f64>::new(1, &[bell()], SAMPLE_RATE);
+		let control = eq.control();
+		let input = sine(1000.0, SAMPLE_RATE, 4800);
+		let wet = run(&mut eq, &[&input], 256).remove(0);
+		let steady_step = largest_step(&wet[2400..]);
+
+		// Off: the band fades out within the crossfade time, then the input passes unchanged
+		control.set_band(0, &Band { enabled: false, ..bell() });
+		let fade_out = run(&mut eq, &[&input], 256).remove(0);
+		let fade = (CROSSFADE_TIME * SAMPLE_RATE) as usize;
+		let middle = fade / 2 - SUB_BLOCK..fade / 2 + SUB_BLOCK;
+		assert!(fade_out[middle.clone()].iter().zip(input[middle].iter()).any(|(out, dry)| (out - dry).abs() > 0.1));
+		assert_eq!(&fade_out[fade + SUB_BLOCK..], &input[fade + SUB_BLOCK..]);
+		assert!(largest_step(&fade_out) <= steady_step * 1.01);
+
+		// On again: the band fades in from a reset filter and reaches the full boost
+		control.set_band(0, &bell());
+		let fade_in = run(&mut eq, &[&input], 256).remove(0);
+		assert!(largest_step(&fade_in) <= steady_step * 1.01);
+		assert!((gain_db(&input, &fade_in, 2400..4800) - 12.0).abs() < 0.05);
+	}
+
+	#[test]
+	fn changing_the_type_crossfades() {
+		let mut eq = ParametricEq::<f64>::new(1, &[bell()], SAMPLE_RATE);
+		let control = eq.control();
+		let input = sine(200.0, SAMPLE_RATE, 9600);
+		let bell_output = run(&mut eq, &[&input], 256).remove(0);
+		let steady_step = largest_step(&bell_output[4800..]).max(largest_step(&input));
+
+		control.set_band(0, &Band::new(FilterType::HighPass, 1000.0, 12.0, 0.707));
+		assert_eq!(control.band(0).filter_type, FilterType::HighPass);
+
+		// The bell fades out before the high pass fades in, both without a step
+		let output = run(&mut eq, &[&input], 256).remove(0);
+		assert!(largest_step(&output) <= steady_step * 1.01);
+		let expected = control.magnitude_db(200.0);
+		assert!(expected < -20.0);
+		assert!((gain_db(&input, &output, 4800..9600) - expected).abs() < 0.1);
+	}
+
+	#[test]
+	fn parameters_move_every_sub_block() {
+		let mut eq = ParametricEq::<f64>::new(1, &[bell()], SAMPLE_RATE);
+		let mut same = ParametricEq::<f64>::new(1, &[bell()], SAMPLE_RATE);
+		let input = sine(1000.0, SAMPLE_RATE, 4800);
+		for eq in [&mut eq, &mut same] {
+			eq.control().set_band(0, &Band { frequency: 4000.0, ..bell() });
+		}
+
+		// The frequency approaches its target sub-block by sub-block, at the same pace whatever
+		// the block size
+		run(&mut eq, &[&input], 480);
+		run(&mut same, &[&input], 7);
+		let expected = 4000.0 - 3000.0 * (-4800.0 / (SMOOTHING_TIME * SAMPLE_RATE)).exp();
+		for eq in [&eq, &same] {
+			assert!((eq.bands[0].designed.0 - expected).abs() < 1e-6, "{} Hz instead of {}", eq.bands[0].designed.0, expected);
+		}
+
+		run(&mut eq, &[&sine(1000.0, SAMPLE_RATE, 48000)], 480);
+		assert_eq!(eq.bands[0].designed.0, 4000.0);
+	}
+
+	#[test]
+	fn extra_channels_pass_through() {
+		let mut eq = ParametricEq::<f64>::new(1, &[bell()], SAMPLE_RATE);
+		let input = sine(1000.0, SAMPLE_RATE, 4800);
+		let output = run(&mut eq, &[&input, &input, &input], 256);
+
+		assert!((gain_db(&input, &output[0], 2400..4800) - 12.0).abs() < 0.05);
+		assert_eq!(output[1], input);
+		assert_eq!(output[2], input);
+	}
+
+	#[test]
+	fn prepare_rebuilds_for_the_sample_rate() {
+		let mut eq = ParametricEq::<f64>::new(1, &[bell()], SAMPLE_RATE);
+		let control = eq.control();
+		eq.prepare(96000.0, 256);
+
+		// The boost stays at 1 kHz rather than moving with the sample rate
+		let input = sine(1000.0, 96000.0, 19200);
+		let output = run(&mut eq, &[&input], 256).remove(0);
+		assert!((gain_db(&input, &output, 9600..19200) - 12.0).abs() < 0.05);
+
+		// The drawn response follows the new rate as well
+		let input = sine(3000.0, 96000.0, 19200);
+		let output = run(&mut eq, &[&input], 256).remove(0);
+		let at_48k = Coefficients::new(FilterType::Bell, SAMPLE_RATE, 1000.0, 1.0, 12.0).magnitude_db(3000.0, SAMPLE_RATE);
+		assert!((gain_db(&input, &output, 9600..19200) - control.magnitude_db(3000.0)).abs() < 0.05);
+		assert!((control.magnitude_db(3000.0) - at_48k).abs() > 0.01);
+	}
+}
@@ -0,0 +1,36 @@
+use crate::dsp::audio_block::{AudioBlock, AudioBlockMut};
+use crate::dsp::sample::Sample;
+
+/// Produces the output block from the input block, called once per buffer switch
+pub trait Processor<S: Sample>: Send {
+	fn process(&mut self, input: &AudioBlock<S>, output: &mut AudioBlockMut<S>);
+
+	/// Number of frames the output lags behind the input
+	fn latency(&self) -> usize {
+		0
+	}
+
+	/// Called before processing starts and whenever the sample rate changes, never from the
+	/// callback. Blocks passed to `process` have at most `max_block_size` frames.
+	fn prepare(&mut self, _sample_rate: f64, _max_block_size: usize) {
+	}
+}
+
+/// Plain functions and closures are processors without latency
+impl<S: Sample, F: FnMut(&AudioBlock<S>, &mut AudioBlockMut<S>) + Send> Processor<S> for F {
+	fn process(&mut self, input: &AudioBlock<S>, output: &mut AudioBlockMut<S>) {
+		self(input, output)
+	}
+}
+
+/// Copies the input to the output as the starting point for processing in place. Output
+/// channels without a matching input channel are silent.
+pub fn pass_through<S: Sample>(input: &AudioBlock<S>, output: &mut AudioBlockMut<S>) {
+	for (index, target) in output.channels_mut().enumerate() {
+		if index < input.num_channels() {
+			target.copy_from_slice(input.channel(index));
+		} else {
+			target.fill(S::ZERO);
+		}
+	}
+}
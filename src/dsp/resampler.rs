@@ -0,0 +1,492 @@
+use crate::dsp::audio_block::{AudioBlock, AudioBlockMut, AudioBuffer, ChannelLabel};
+use crate::dsp::processor::Processor;
+use crate::dsp::sample::Sample;
+use std::f64::consts::PI;
+
+/// Trade-off between processing cost and stop band rejection
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ResamplerQuality {
+	Fast,			// 16 taps, about 70 dB signal to noise ratio
+	Balanced,		// 32 taps, about 90 dB signal to noise ratio
+	High			// 64 taps, about 120 dB signal to noise ratio
+}
+
+impl ResamplerQuality {
+	/// Taps, phases, Kaiser beta and cutoff relative to the lower Nyquist frequency
+	fn parameters(&self) -> (usize, usize, f64, f64) {
+		match self {
+			ResamplerQuality::Fast => (16, 64, 7.0, 0.86),
+			ResamplerQuality::Balanced => (32, 256, 9.0, 0.91),
+			ResamplerQuality::High => (64, 1024, 12.0, 0.94)
+		}
+	}
+}
+
+/// Kaiser windowed sinc, tabulated for `phases + 1` fractional positions
+struct PolyphaseFilter {
+	taps: usize,
+	phases: usize,
+	coefficients: Vec<f64>
+}
+
+impl PolyphaseFilter {
+	fn new(quality: ResamplerQuality, step: f64) -> PolyphaseFilter {
+		let (taps, phases, beta, rolloff) = quality.parameters();
+		let half = (taps / 2) as f64;
+
+		// When reducing the rate, the cutoff follows the output's Nyquist frequency
+		let cutoff = rolloff * (1.0 / step).min(1.0);
+		let mut coefficients = vec![0.0; (phases + 1) * taps];
+
+		for phase in 0..=phases {
+			let frac = phase as f64 / phases as f64;
+			let row = &mut coefficients[phase * taps..(phase + 1) * taps];
+
+			for (tap, coefficient) in row.iter_mut().enumerate() {
+				let offset = half - 1.0 - tap as f64 + frac;
+				*coefficient = cutoff * sinc(cutoff * offset) * kaiser(offset / half, beta);
+			}
+
+			// Unity gain at DC for every phase
+			let sum: f64 = row.iter().sum();
+			row.iter_mut().for_each(|coefficient| *coefficient /= sum);
+		}
+
+		PolyphaseFilter {
+			taps,
+			phases,
+			coefficients
+		}
+	}
+
+	/// Value at `frac` past the newest but `taps / 2 - 1` samples of `window` (oldest first)
+	fn interpolate<S: Sample>(&self, window: &[S], frac: f64) -> S {
+		let position = frac * self.phases as f64;
+		let phase = (position as usize).min(self.phases - 1);
+		let t = position - phase as f64;
+
+		let lower = &self.coefficients[phase * self.taps..(phase + 1) * self.taps];
+		let upper = &self.coefficients[(phase + 1) * self.taps..(phase + 2) * self.taps];
+		let mut a = 0.0;
+		let mut b = 0.0;
+
+		for ((x, l), u) in window.iter().zip(lower).zip(upper) {
+			let x = x.to_f64();
+			a += x * l;
+			b += x * u;
+		}
+		S::from_f64(a + t * (b - a))
+	}
+}
+
+fn sinc(x: f64) -> f64 {
+	if x.abs() < 1e-12 {
+		1.0
+	} else {
+		(PI * x).sin() / (PI * x)
+	}
+}
+
+/// Kaiser window at `x` in [-1.0, 1.0]
+fn kaiser(x: f64, beta: f64) -> f64 {
+	bessel_i0(beta * (1.0 - x * x).max(0.0).sqrt()) / bessel_i0(beta)
+}
+
+/// Modified Bessel function of the first kind, order zero
+fn bessel_i0(x: f64) -> f64 {
+	let mut sum = 1.0;
+	let mut term = 1.0;
+	let mut k = 1.0;
+
+	while term > sum * 1e-16 {
+		term *= (x / (2.0 * k)) * (x / (2.0 * k));
+		sum += term;
+		k += 1.0;
+	}
+	sum
+}
+
+/// Converts a stream of blocks from one sample rate to another with a polyphase filter.
+/// All channels share the same time base, the latency is fixed at `taps / 2` input frames.
+pub struct Resampler<S: Sample> {
+	filter: PolyphaseFilter,
+	step: f64,
+	history: Vec<Vec<S>>,
+	position: usize,
+	frac: f64
+}
+
+impl<S: Sample> Resampler<S> {
+	pub fn new(num_channels: usize, input_rate: f64, output_rate: f64, quality: ResamplerQuality) -> Resampler<S> {
+		if input_rate <= 0.0 || output_rate <= 0.0 {
+			panic!("Cannot resample from {} Hz to {} Hz", input_rate, output_rate);
+		}
+
+		let step = input_rate / output_rate;
+		let filter = PolyphaseFilter::new(quality, step);
+
+		// Each history holds the window twice, so that it can always be read contiguously
+		let history = vec![vec![S::ZERO; 2 * filter.taps]; num_channels];
+
+		Resampler {
+			filter,
+			step,
+			history,
+			position: 0,
+			frac: 0.0
+		}
+	}
+
+	pub fn num_channels(&self) -> usize {
+		self.history.len()
+	}
+
+	/// Output frames per input frame
+	pub fn ratio(&self) -> f64 {
+		1.0 / self.step
+	}
+
+	/// Delay of the output in input frames
+	pub fn latency(&self) -> usize {
+		self.filter.taps / 2
+	}
+
+	/// Most output frames `process` can write for the given number of input frames
+	pub fn max_output_frames(&self, input_frames: usize) -> usize {
+		(input_frames as f64 / self.step).ceil() as usize + 1
+	}
+
+	pub fn reset(&mut self) {
+		for history in self.history.iter_mut() {
+			history.fill(S::ZERO);
+		}
+		self.position = 0;
+		self.frac = 0.0;
+	}
+
+	/// Consumes all frames of `input` and returns the number of frames written to `output`,
+	/// which must hold at least `max_output_frames(input.num_frames())` frames
+	pub fn process(&mut self, input: &AudioBlock<S>, output: &mut AudioBlockMut<S>) -> usize {
+		if input.num_channels() != self.num_channels() || output.num_channels() != self.num_channels() {
+			panic!("Resampler for {} channels got {} input and {} output channels",
+				self.num_channels(), input.num_channels(), output.num_channels());
+		}
+
+		let mut result = (0, self.position, self.frac);
+
+		for (channel, target) in output.channels_mut().enumerate() {
+			result = resample(
+				&self.filter, self.step, &mut self.history[channel], self.position, self.frac,
+				input.channel(channel), target
+			);
+		}
+
+		let (written, position, frac) = result;
+		self.position = position;
+		self.frac = frac;
+		written
+	}
+
+	/// Resamples complete signals, e.g. the channels of a file. The output starts at the
+	/// same point in time as the input, without the latency of streaming operation.
+	pub fn process_all(&mut self, input: &[Vec<S>]) -> Vec<Vec<S>> {
+		if input.len() != self.num_channels() {
+			panic!("Resampler for {} channels got {} channels", self.num_channels(), input.len());
+		}
+
+		let len = input.iter().map(|channel| channel.len()).max().unwrap_or(0);
+		let half = self.latency() as f64;
+		let wanted = (len as f64 / self.step).ceil() as usize;
+
+		// Start the time base so that one output frame falls exactly on input frame 0,
+		// and drop the output frames before it
+		let skip = (half / self.step).floor() as usize;
+		self.reset();
+		self.frac = half - skip as f64 * self.step;
+
+		let flushed = len + self.latency() + 1;
+		let mut outputs = Vec::with_capacity(input.len());
+
+		for (channel, samples) in input.iter().enumerate() {
+			let mut padded = samples.clone();
+			padded.resize(flushed, S::ZERO);
+
+			let mut output = vec![S::ZERO; self.max_output_frames(flushed) + 1];
+			let (written, _, _) =
+				resample(&self.filter, self.step, &mut self.history[channel], self.position, self.frac, &padded, &mut output);
+
+			output.truncate(written);
+			output.drain(..skip.min(output.len()));
+			output.resize(wanted, S::ZERO);
+			outputs.push(output);
+		}
+
+		self.reset();
+		outputs
+	}
+}
+
+/// Pushes `input` through the history of one channel, returning the frames written and the
+/// new read position and fractional time
+fn resample<S: Sample>(
+	filter: &PolyphaseFilter,
+	step: f64,
+	history: &mut [S],
+	mut position: usize,
+	mut frac: f64,
+	input: &[S],
+	output: &mut [S]
+) -> (usize, usize, f64) {
+	let taps = filter.taps;
+	let mut written = 0;
+
+	for sample in input {
+		history[position] = *sample;
+		history[position + taps] = *sample;
+		position = (position + 1) % taps;
+
+		while frac < 1.0 {
+			if written == output.len() {
+				panic!("Output block of {} frames is too small for {} input frames", output.len(), input.len());
+			}
+			output[written] = filter.interpolate(&history[position..position + taps], frac);
+			written += 1;
+			frac += step;
+		}
+		frac -= 1.0;
+	}
+	(written, position, frac)
+}
+
+/// Runs a processor at a different sample rate than the device, e.g. a 48 kHz pipeline on
+/// a driver locked to 44.1 kHz. The latency is fixed and includes both conversions, the
+/// reported value is rounded to whole device frames.
+pub struct ResamplingProcessor<S: Sample> {
+	processor: Box<dyn Processor<S>>,
+	upsampler: Resampler<S>,
+	downsampler: Resampler<S>,
+	inner_input: AudioBuffer<S>,
+	inner_output: AudioBuffer<S>,
+	converted: AudioBuffer<S>,
+	fifo: AudioBuffer<S>,
+	available: usize,
+	device_rate: f64,
+	inner_rate: f64,
+	quality: ResamplerQuality
+}
+
+/// Frames of silence ahead of the output, covering the jitter of the converted frame count
+const FIFO_PRIMING: usize = 4;
+
+impl<S: Sample> ResamplingProcessor<S> {
+	pub fn new(
+		processor: Box<dyn Processor<S>>,
+		device_rate: f64,
+		inner_rate: f64,
+		quality: ResamplerQuality,
+		input_labels: Vec<ChannelLabel>,
+		output_labels: Vec<ChannelLabel>,
+		max_block_size: usize
+	) -> ResamplingProcessor<S> {
+		let upsampler = Resampler::new(input_labels.len(), device_rate, inner_rate, quality);
+		let downsampler = Resampler::new(output_labels.len(), inner_rate, device_rate, quality);
+		let inner_size = upsampler.max_output_frames(max_block_size);
+		let converted_size = downsampler.max_output_frames(inner_size);
+
+		ResamplingProcessor {
+			processor,
+			inner_input: AudioBuffer::new(input_labels, inner_size),
+			inner_output: AudioBuffer::new(output_labels.clone(), inner_size),
+			converted: AudioBuffer::new(output_labels.clone(), converted_size),
+			fifo: AudioBuffer::new(output_labels, converted_size + max_block_size + FIFO_PRIMING),
+			available: FIFO_PRIMING,
+			upsampler,
+			downsampler,
+			device_rate,
+			inner_rate,
+			quality
+		}
+	}
+
+	pub fn reset(&mut self) {
+		self.upsampler.reset();
+		self.downsampler.reset();
+		self.fifo.as_block_mut().fill(S::ZERO);
+		self.available = FIFO_PRIMING;
+	}
+}
+
+impl<S: Sample> Processor<S> for ResamplingProcessor<S> {
+	fn process(&mut self, input: &AudioBlock<S>, output: &mut AudioBlockMut<S>) {
+		let frames = input.num_frames();
+
+		let inner_frames = self.upsampler.process(input, &mut self.inner_input.as_block_mut());
+		let inner_input = self.inner_input.as_block().sub_block(0, inner_frames);
+		let mut inner_output = self.inner_output.as_block_mut();
+		let mut inner_output = inner_output.sub_block_mut(0, inner_frames);
+		self.processor.process(&inner_input, &mut inner_output);
+
+		let converted = self.downsampler.process(&inner_output.as_block(), &mut self.converted.as_block_mut());
+		let mut fifo = self.fifo.as_block_mut();
+		fifo.sub_block_mut(self.available, converted).copy_from(&self.converted.as_block().sub_block(0, converted));
+		self.available += converted;
+
+		// The priming makes an underrun impossible in practice, pad with silence regardless
+		let len = frames.min(self.available);
+		output.sub_block_mut(0, len).copy_from(&fifo.as_block().sub_block(0, len));
+		output.sub_block_mut(len, frames - len).fill(S::ZERO);
+
+		for channel in fifo.channels_mut() {
+			channel.copy_within(len..self.available, 0);
+		}
+		self.available -= len;
+	}
+
+	/// Follows a change of the device's sample rate, the processor keeps running at its rate
+	fn prepare(&mut self, sample_rate: f64, max_block_size: usize) {
+		let labels = |buffer: &AudioBuffer<S>| buffer.labels().to_vec();
+
+		self.device_rate = sample_rate;
+		self.upsampler = Resampler::new(self.upsampler.num_channels(), sample_rate, self.inner_rate, self.quality);
+		self.downsampler = Resampler::new(self.downsampler.num_channels(), self.inner_rate, sample_rate, self.quality);
+
+		let inner_size = self.upsampler.max_output_frames(max_block_size);
+		let converted_size = self.downsampler.max_output_frames(inner_size);
+
+		self.inner_input = AudioBuffer::new(labels(&self.inner_input), inner_size);
+		self.inner_output = AudioBuffer::new(labels(&self.inner_output), inner_size);
+		self.converted = AudioBuffer::new(labels(&self.converted), converted_size);
+		self.fifo = AudioBuffer::new(labels(&self.fifo), converted_size + max_block_size + FIFO_PRIMING);
+		self.available = FIFO_PRIMING;
+
+		self.processor.prepare(self.inner_rate, inner_size);
+	}
+
+	fn latency(&self) -> usize {
+		let inner_latency = (self.downsampler.latency() + self.processor.latency()) as f64;
+		let converted = inner_latency * self.device_rate / self.inner_rate;
+
+		(self.upsampler.latency() as f64 + converted).round() as usize + FIFO_PRIMING
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::asio_core::random::Random;
+
+	/// Signal to noise ratio in dB of a resampled sine. The noise is what remains after removing
+	/// the best fitting sine of the same frequency, so the passband gain and the latency do not
+	/// count, only aliases, images and interpolation errors do.
+	fn sine_snr(quality: ResamplerQuality, input_rate: f64, output_rate: f64, frequency: f64) -> f64 {
+		let len = input_rate as usize / 2;
+		let input: Vec<f64> = (0..len).map(|index| 0.5 * (2.0 * PI * frequency * index as f64 / input_rate).sin()).collect();
+		let mut resampler = Resampler::<f64>::new(1, input_rate, output_rate, quality);
+		let output = resampler.process_all(&[input]).remove(0);
+
+		// Leave out the edges, where the filter sees the silence around the signal
+		let edge = 200;
+		let range = edge..output.len() - edge;
+		let omega = 2.0 * PI * frequency / output_rate;
+
+		// Least squares fit of a sine and a cosine
+		let (mut ss, mut cc, mut sc, mut ys, mut yc) = (0.0, 0.0, 0.0, 0.0, 0.0);
+		for index in range.clone() {
+			let (s, c) = (omega * index as f64).sin_cos();
+			ss += s * s;
+			cc += c * c;
+			sc += s * c;
+			ys += output[index] * s;
+			yc += output[index] * c;
+		}
+		let determinant = ss * cc - sc * sc;
+		let a = (ys * cc - yc * sc) / determinant;
+		let b = (yc * ss - ys * sc) / determinant;
+
+		let (mut signal, mut noise) = (0.0, 0.0);
+		for index in range {
+			let (s, c) = (omega * index as f64).sin_cos();
+			let fitted = a * s + b * c;
+			signal += fitted * fitted;
+			noise += (output[index] - fitted) * (output[index] - fitted);
+		}
+		10.0 * (signal / noise).log10()
+	}
+
+	#[test]
+	fn quality_presets_reach_their_snr() {
+		for (quality, minimum) in [(ResamplerQuality::Fast, 70.0), (ResamplerQuality::Balanced, 90.0), (ResamplerQuality::High, 120.0)] {
+			for (input_rate, output_rate) in [(44100.0, 48000.0), (48000.0, 44100.0), (48000.0, 96000.0), (96000.0, 48000.0)] {
+				for frequency in [100.0, 1000.0, 5000.0, 10000.0, 15000.0] {
+					let snr = sine_snr(quality, input_rate, output_rate, frequency);
+					assert!(snr >= minimum, "{:?} {} -> {} Hz at {} Hz: {:.1} dB", quality, input_rate, output_rate, frequency, snr);
+				}
+			}
+		}
+	}
+
+	/// Delay in frames of a sine of `omega` radians per frame relative to `sin(omega * index)`,
+	/// found by a least squares fit over `range`, between 0 and one period
+	fn sine_delay(output: &[f64], omega: f64, range: std::ops::Range<usize>) -> f64 {
+		let (mut ss, mut cc, mut sc, mut ys, mut yc) = (0.0, 0.0, 0.0, 0.0, 0.0);
+		for index in range {
+			let (s, c) = (omega * index as f64).sin_cos();
+			ss += s * s;
+			cc += c * c;
+			sc += s * c;
+			ys += output[index] * s;
+			yc += output[index] * c;
+		}
+		let determinant = ss * cc - sc * sc;
+		let a = (ys * cc - yc * sc) / determinant;
+		let b = (yc * ss - ys * sc) / determinant;
+
+		// a sin(x) + b cos(x) = sin(x - omega delay) scaled, so a ~ cos(omega delay) and b ~ -sin(omega delay)
+		(-b).atan2(a).rem_euclid(2.0 * PI) / omega
+	}
+
+	#[test]
+	fn processor_keeps_a_fixed_latency() {
+		let mut random = Random::new(35);
+
+		for (device_rate, inner_rate) in [(44100.0, 48000.0), (48000.0, 44100.0)] {
+			let identity = Box::new(|input: &AudioBlock<f64>, output: &mut AudioBlockMut<f64>| output.copy_from(input));
+			let mut processor = ResamplingProcessor::new(
+				identity, device_rate, inner_rate, ResamplerQuality::Balanced, ChannelLabel::numbered(1), ChannelLabel::numbered(1), 512
+			);
+			let latency = processor.latency();
+
+			// A sine well below the passband edge, with a period longer than the latency
+			let omega = 2.0 * PI * 40.0 / device_rate;
+			let len = device_rate as usize * 2;
+			let mut input = AudioBuffer::<f64>::with_channels(1, 512);
+			let mut output = AudioBuffer::<f64>::with_channels(1, 512);
+			let mut played = Vec::with_capacity(len);
+			let mut least_available = usize::MAX;
+
+			while played.len() < len {
+				let frames = 1 + (random.next_u64() % 512) as usize;
+				input.set_num_frames(frames);
+				output.set_num_frames(frames);
+				let start = played.len();
+				for (index, sample) in input.as_block_mut().channel_mut(0).iter_mut().enumerate() {
+					*sample = 0.5 * (omega * (start + index) as f64).sin();
+				}
+
+				processor.process(&input.as_block(), &mut output.as_block_mut());
+				played.extend_from_slice(output.as_block().channel(0));
+
+				// Frames left over after every block show that no block was padded with silence
+				least_available = least_available.min(processor.available);
+				assert_eq!(processor.latency(), latency);
+			}
+			assert!(least_available > 0, "underrun at {} -> {} Hz", device_rate, inner_rate);
+
+			// The delay stays at the reported latency, within the rounding to whole frames
+			for start in [latency + 2000, len / 2, len - 12000] {
+				let delay = sine_delay(&played, omega, start..start + 10000);
+				assert!((delay - latency as f64).abs() <= 0.5, "{} -> {} Hz: delay {} instead of {}", device_rate, inner_rate, delay, latency);
+			}
+		}
+	}
+}
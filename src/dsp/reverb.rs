@@ -0,0 +1,458 @@
+use crate::dsp::audio_block::{AudioBlock, AudioBlockMut};
+use crate::dsp::parameters::{ParameterId, ParameterInfo, ParameterRegistry, Parameters, SmoothedParameter, Smoothing, Unit};
+use crate::dsp::processor::{pass_through, Processor};
+use crate::dsp::sample::Sample;
+use std::f64::consts::PI;
+
+const NUM_LINES: usize = 8;
+
+/// Frames processed with the same decay gains and damping, line delays move linearly within
+const SUB_BLOCK: usize = 32;
+
+/// Line lengths in milliseconds at full size, spread so that their echoes rarely coincide
+const LINE_LENGTHS_MS: [f64; NUM_LINES] = [31.3, 37.9, 41.9, 47.3, 53.9, 59.3, 67.1, 73.7];
+
+/// Rates of the LFOs modulating the lines, in Hz
+const LFO_RATES: [f64; NUM_LINES] = [0.31, 0.43, 0.37, 0.53, 0.47, 0.61, 0.41, 0.57];
+
+/// Line lengths at size 0 as a fraction of the lengths at full size
+const MIN_SCALE: f64 = 0.15;
+
+/// Deviation of the line lengths at full modulation
+const MAX_DEPTH_MS: f64 = 1.0;
+
+const MAX_PRE_DELAY_MS: f64 = 250.0;
+
+/// Delays in milliseconds of the allpasses diffusing the input of each channel
+const DIFFUSER_DELAYS_MS: [[f64; 4]; 2] = [[4.77, 3.59, 2.73, 1.49], [5.03, 3.81, 2.51, 1.63]];
+
+const DIFFUSION: f64 = 0.6;
+
+/// Signs with which the channels feed the lines and the lines feed the channels. The two
+/// patterns are orthogonal, so that left and right are uncorrelated.
+const SIGNS: [[f64; NUM_LINES]; 2] = [
+	[1.0, 1.0, -1.0, 1.0, -1.0, -1.0, 1.0, -1.0],
+	[1.0, -1.0, 1.0, 1.0, -1.0, 1.0, -1.0, -1.0]
+];
+
+/// 1 / sqrt(NUM_LINES), which makes the sign patterns and the Hadamard matrix unit norm
+const NORM: f64 = 0.353_553_390_593_273_8;
+
+/// Keeps decaying tails out of the slow subnormal range
+const ANTI_DENORMAL: f64 = 1e-20;
+
+struct ReverbIds {
+	size: ParameterId,
+	decay: ParameterId,
+	damping: ParameterId,
+	pre_delay: ParameterId,
+	modulation: ParameterId,
+	width: ParameterId,
+	mix: ParameterId
+}
+
+/// Delay line with a power of two length, written at a position shared by all lines
+struct Line {
+	buffer: Vec<f64>,
+	mask: usize
+}
+
+impl Line {
+	fn new(min_length: usize) -> Line {
+		let length = min_length.next_power_of_two();
+
+		Line {
+			buffer: vec![0.0; length],
+			mask: length - 1
+		}
+	}
+
+	fn write(&mut self, position: usize, value: f64) {
+		self.buffer[position & self.mask] = value;
+	}
+
+	/// Linear interpolation `delay` frames before `position`
+	fn read(&self, position: usize, delay: f64) -> f64 {
+		let whole = delay.floor();
+		let t = delay - whole;
+		let index = position.wrapping_sub(whole as usize);
+
+		let y0 = self.buffer[index & self.mask];
+		let y1 = self.buffer[index.wrapping_sub(1) & self.mask];
+		y0 + (y1 - y0) * t
+	}
+
+	/// Cubic Hermite interpolation `delay` frames before `position`, for delays of at least
+	/// two frames. Loses far less of the high frequencies than linear interpolation, which
+	/// would shorten the tail on every pass through a modulated line.
+	fn read_cubic(&self, position: usize, delay: f64) -> f64 {
+		let whole = delay.floor();
+		let t = 1.0 - (delay - whole);
+		let index = position.wrapping_sub(whole as usize + 1);
+
+		let y0 = self.buffer[index.wrapping_sub(1) & self.mask];
+		let y1 = self.buffer[index & self.mask];
+		let y2 = self.buffer[index.wrapping_add(1) & self.mask];
+		let y3 = self.buffer[index.wrapping_add(2) & self.mask];
+
+		let c1 = 0.5 * (y2 - y0);
+		let c2 = y0 - 2.5 * y1 + 2.0 * y2 - 0.5 * y3;
+		let c3 = 0.5 * (y3 - y0) + 1.5 * (y1 - y2);
+		((c3 * t + c2) * t + c1) * t + y1
+	}
+}
+
+/// Schroeder allpass
+struct Allpass {
+	buffer: Vec<f64>,
+	position: usize
+}
+
+impl Allpass {
+	fn new(delay: usize) -> Allpass {
+		Allpass {
+			buffer: vec![0.0; delay.max(1)],
+			position: 0
+		}
+	}
+
+	fn process(&mut self, x: f64) -> f64 {
+		let delayed = self.buffer[self.position];
+		let v = x + DIFFUSION * delayed;
+		self.buffer[self.position] = v;
+		self.position = (self.position + 1) % self.buffer.len();
+		delayed - DIFFUSION * v
+	}
+}
+
+/// Multiplies by the normalized Hadamard matrix, which is orthogonal and mixes every line
+/// into every other
+fn hadamard(values: &mut [f64; NUM_LINES]) {
+	let mut length = 1;
+	while length < NUM_LINES {
+		for start in (0..NUM_LINES).step_by(2 * length) {
+			for index in start..start + length {
+				let (a, b) = (values[index], values[index + length]);
+				values[index] = a + b;
+				values[index + length] = a - b;
+			}
+		}
+		length *= 2;
+	}
+
+	for value in values.iter_mut() {
+		*value *= NORM;
+	}
+}
+
+/// Algorithmic reverb of one or two channels built from a feedback delay network. Eight
+/// delay lines, slowly modulated against metallic ringing, feed each other through a
+/// Hadamard matrix. A low pass in every line damps the high frequencies, and the line
+/// gains follow from the decay time, so that the tail falls by 60 dB within "Decay" at
+/// any size. The input passes a pre-delay and allpass diffusers before entering the lines.
+/// Processing costs a fixed amount per frame and never allocates, for use on aux buses.
+pub struct Reverb<S: Sample> {
+	parameters: Parameters,
+	ids: ReverbIds,
+	num_channels: usize,
+	sample_rate: f64,
+	lines: Vec<Line>,
+	delays: [f64; NUM_LINES],
+	lfo_phases: [f64; NUM_LINES],
+	damping_states: [f64; NUM_LINES],
+	pre_delays: Vec<Line>,
+	diffusers: Vec<Vec<Allpass>>,
+	position: usize,
+	size: SmoothedParameter<f64>,
+	decay: SmoothedParameter<f64>,
+	damping: SmoothedParameter<f64>,
+	pre_delay: SmoothedParameter<f64>,
+	modulation: SmoothedParameter<f64>,
+	width: SmoothedParameter<f64>,
+	mix: SmoothedParameter<f64>,
+	_sample: std::marker::PhantomData<S>
+}
+
+impl<S: Sample> Reverb<S> {
+	/// Parameters "Size", "Decay" (the time to fall by 60 dB), "Damping" (the cutoff of the
+	/// low pass in the lines), "Pre-delay", "Modulation", "Width" and "Mix". Mix defaults
+	/// to fully wet, as on an aux bus.
+	pub fn new(num_channels: usize, sample_rate: f64) -> Reverb<S> {
+		if num_channels == 0 || num_channels > 2 {
+			panic!("Reverb supports 1 or 2 channels, not {}", num_channels);
+		}
+
+		let mut registry = ParameterRegistry::new();
+		let ids = ReverbIds {
+			size: registry.add(ParameterInfo::new("Size", 0.0, 100.0, 50.0, Unit::Percent).with_smoothing(Smoothing::PerBlock, 0.1)),
+			decay: registry.add(ParameterInfo::new("Decay", 0.1, 20.0, 2.0, Unit::Seconds).with_smoothing(Smoothing::PerBlock, 0.05)),
+			damping: registry.add(ParameterInfo::new("Damping", 1000.0, 20000.0, 8000.0, Unit::Hertz).with_smoothing(Smoothing::PerBlock, 0.02)),
+			pre_delay: registry.add(ParameterInfo::new("Pre-delay", 0.0, MAX_PRE_DELAY_MS, 10.0, Unit::Milliseconds).with_smoothing(Smoothing::PerBlock, 0.05)),
+			modulation: registry.add(ParameterInfo::new("Modulation", 0.0, 100.0, 30.0, Unit::Percent).with_smoothing(Smoothing::PerBlock, 0.05)),
+			width: registry.add(ParameterInfo::new("Width", 0.0, 100.0, 100.0, Unit::Percent).with_smoothing(Smoothing::PerBlock, 0.02)),
+			mix: registry.add(ParameterInfo::new("Mix", 0.0, 100.0, 100.0, Unit::Percent).with_smoothing(Smoothing::PerBlock, 0.02))
+		};
+		let parameters = registry.build();
+
+		let mut reverb = Reverb {
+			size: parameters.smoother(ids.size, sample_rate),
+			decay: parameters.smoother(ids.decay, sample_rate),
+			damping: parameters.smoother(ids.damping, sample_rate),
+			pre_delay: parameters.smoother(ids.pre_delay, sample_rate),
+			modulation: parameters.smoother(ids.modulation, sample_rate),
+			width: parameters.smoother(ids.width, sample_rate),
+			mix: parameters.smoother(ids.mix, sample_rate),
+			parameters,
+			ids,
+			num_channels,
+			sample_rate,
+			lines: Vec::new(),
+			delays: [0.0; NUM_LINES],
+			lfo_phases: [0.0; NUM_LINES],
+			damping_states: [0.0; NUM_LINES],
+			pre_delays: Vec::new(),
+			diffusers: Vec::new(),
+			position: 0,
+			_sample: std::marker::PhantomData
+		};
+		reverb.allocate();
+		reverb
+	}
+
+	/// Values can be changed from any thread
+	pub fn parameters(&self) -> Parameters {
+		self.parameters.clone()
+	}
+
+	/// Clears the tail and jumps to the current parameter values
+	pub fn reset(&mut self) {
+		self.size = self.parameters.smoother(self.ids.size, self.sample_rate);
+		self.decay = self.parameters.smoother(self.ids.decay, self.sample_rate);
+		self.damping = self.parameters.smoother(self.ids.damping, self.sample_rate);
+		self.pre_delay = self.parameters.smoother(self.ids.pre_delay, self.sample_rate);
+		self.modulation = self.parameters.smoother(self.ids.modulation, self.sample_rate);
+		self.width = self.parameters.smoother(self.ids.width, self.sample_rate);
+		self.mix = self.parameters.smoother(self.ids.mix, self.sample_rate);
+
+		for line in self.lines.iter_mut().chain(self.pre_delays.iter_mut()) {
+			line.buffer.fill(0.0);
+		}
+		for diffuser in self.diffusers.iter_mut().flatten() {
+			diffuser.buffer.fill(0.0);
+		}
+		self.damping_states = [0.0; NUM_LINES];
+		self.lfo_phases = [0.0; NUM_LINES];
+
+		let scale = self.scale();
+		for (delay, length) in self.delays.iter_mut().zip(LINE_LENGTHS_MS.iter()) {
+			*delay = length / 1000.0 * self.sample_rate * scale;
+		}
+	}
+
+	fn allocate(&mut self) {
+		let frames = |ms: f64| (ms / 1000.0 * self.sample_rate).ceil() as usize;
+		let longest = LINE_LENGTHS_MS.iter().fold(0.0f64, |longest, length| longest.max(*length));
+
+		self.lines = (0..NUM_LINES).map(|_| Line::new(frames(longest + MAX_DEPTH_MS) + 2)).collect();
+		self.pre_delays = (0..self.num_channels).map(|_| Line::new(frames(MAX_PRE_DELAY_MS) + 2)).collect();
+		self.diffusers = DIFFUSER_DELAYS_MS.iter().take(self.num_channels)
+			.map(|delays| delays.iter().map(|delay| Allpass::new(frames(*delay))).collect())
+			.collect();
+		self.position = 0;
+		self.reset();
+	}
+
+	/// Line lengths relative to full size
+	fn scale(&self) -> f64 {
+		MIN_SCALE + (1.0 - MIN_SCALE) * self.size.value() / 100.0
+	}
+}
+
+impl<S: Sample> Processor<S> for Reverb<S> {
+	/// Channels beyond those given to `new` are passed through dry
+	fn process(&mut self, input: &AudioBlock<S>, output: &mut AudioBlockMut<S>) {
+		let frames = output.num_frames();
+		let num_channels = self.num_channels.min(output.num_channels());
+
+		pass_through(input, output);
+
+		let mut start = 0;
+		while start < frames {
+			let len = SUB_BLOCK.min(frames - start);
+
+			for smoother in [&mut self.size, &mut self.decay, &mut self.damping, &mut self.pre_delay, &mut self.modulation, &mut self.width, &mut self.mix] {
+				smoother.update(len);
+			}
+
+			// Each line moves linearly to its modulated length at the end of the sub-block,
+			// with a gain that loses 60 dB over the decay time
+			let scale = self.scale();
+			let depth = self.modulation.value() / 100.0 * MAX_DEPTH_MS / 1000.0 * self.sample_rate;
+			let decay = self.decay.value() * self.sample_rate;
+			let mut steps = [0.0; NUM_LINES];
+			let mut gains = [0.0; NUM_LINES];
+
+			for line in 0..NUM_LINES {
+				let length = LINE_LENGTHS_MS[line] / 1000.0 * self.sample_rate * scale;
+				self.lfo_phases[line] = (self.lfo_phases[line] + LFO_RATES[line] * len as f64 / self.sample_rate).fract();
+				let target = length + depth * (2.0 * PI * self.lfo_phases[line]).sin();
+
+				steps[line] = (target - self.delays[line]) / len as f64;
+				gains[line] = 10.0f64.powf(-3.0 * length / decay);
+			}
+
+			let damping = (-2.0 * PI * self.damping.value() / self.sample_rate).exp();
+			let pre_delay = self.pre_delay.value() / 1000.0 * self.sample_rate;
+			let width = self.width.value() / 100.0;
+			let mix = self.mix.value() / 100.0;
+
+			for frame in start..start + len {
+				let mut injected = [0.0; 2];
+				for (channel, value) in injected.iter_mut().enumerate().take(num_channels) {
+					let x = if channel < input.num_channels() { input.channel(channel)[frame].to_f64() } else { 0.0 };
+					let pre_delay_line = &mut self.pre_delays[channel];
+					pre_delay_line.write(self.position, x);
+
+					let mut diffused = pre_delay_line.read(self.position, pre_delay);
+					for diffuser in self.diffusers[channel].iter_mut() {
+						diffused = diffuser.process(diffused);
+					}
+					*value = diffused * NORM;
+				}
+
+				let mut outputs = [0.0; NUM_LINES];
+				let mut feedback = [0.0; NUM_LINES];
+				for line in 0..NUM_LINES {
+					self.delays[line] += steps[line];
+					outputs[line] = self.lines[line].read_cubic(self.position, self.delays[line]);
+
+					let state = &mut self.damping_states[line];
+					*state = outputs[line] + (*state - outputs[line]) * damping + ANTI_DENORMAL;
+					feedback[line] = *state * gains[line];
+				}
+
+				hadamard(&mut feedback);
+
+				let mut wet = [0.0; 2];
+				for line in 0..NUM_LINES {
+					self.lines[line].write(self.position, feedback[line] + SIGNS[0][line] * injected[0] + SIGNS[1][line] * injected[1]);
+					wet[0] += SIGNS[0][line] * outputs[line];
+					wet[1] += SIGNS[1][line] * outputs[line];
+				}
+
+				if num_channels == 2 {
+					let mid = 0.5 * (wet[0] + wet[1]);
+					let side = 0.5 * (wet[0] - wet[1]) * width;
+					wet = [mid + side, mid - side];
+				}
+
+				for (channel, wet) in wet.iter().enumerate().take(num_channels) {
+					let sample = &mut output.channel_mut(channel)[frame];
+					let dry = sample.to_f64();
+					*sample = S::from_f64(dry + (wet * NORM - dry) * mix);
+				}
+
+				self.position = self.position.wrapping_add(1);
+			}
+
+			start += len;
+		}
+	}
+
+	fn prepare(&mut self, sample_rate: f64, _max_block_size: usize) {
+		if sample_rate != self.sample_rate {
+			self.sample_rate = sample_rate;
+			self.allocate();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::asio_core::random::Random;
+	use crate::dsp::audio_block::AudioBuffer;
+	use crate::dsp::biquad::{Biquad, Coefficients, FilterType};
+
+	const SAMPLE_RATE: f64 = 48000.0;
+
+	/// Mono output for `input`, processed in blocks of 256 frames
+	fn run(reverb: &mut Reverb<f64>, input: &[f64]) -> Vec<f64> {
+		let mut buffer = AudioBuffer::with_channels(1, 256);
+		let mut output = AudioBuffer::with_channels(1, 256);
+		let mut result = Vec::with_capacity(input.len());
+
+		for chunk in input.chunks(256) {
+			buffer.set_num_frames(chunk.len());
+			output.set_num_frames(chunk.len());
+			buffer.as_block_mut().channel_mut(0).copy_from_slice(chunk);
+			reverb.process(&buffer.as_block(), &mut output.as_block_mut());
+			result.extend_from_slice(output.as_block().channel(0));
+		}
+		result
+	}
+
+	/// Time to fall by 60 dB between 250 Hz and 1 kHz, fitted to the Schroeder backward
+	/// integral between -5 and -35 dB like T30. The damping shortens the decay above.
+	fn decay_time(response: &[f64]) -> f64 {
+		let high_pass = Coefficients::new(FilterType::HighPass, SAMPLE_RATE, 250.0, 0.707, 0.0);
+		let low_pass = Coefficients::new(FilterType::LowPass, SAMPLE_RATE, 1000.0, 0.707, 0.0);
+		let (mut first, mut second) = (Biquad::new(), Biquad::new());
+		let band: Vec<f64> = response.iter().map(|sample| second.process(&low_pass, first.process(&high_pass, *sample))).collect();
+
+		let mut energy = vec![0.0; band.len()];
+		let mut sum = 0.0;
+		for (frame, sample) in band.iter().enumerate().rev() {
+			sum += sample * sample;
+			energy[frame] = sum;
+		}
+
+		let points: Vec<(f64, f64)> = energy.iter().enumerate()
+			.map(|(frame, value)| (frame as f64 / SAMPLE_RATE, 10.0 * (value / energy[0]).log10()))
+			.filter(|(_, level)| *level <= -5.0 && *level >= -35.0)
+			.collect();
+		let count = points.len() as f64;
+		let mean_time = points.iter().map(|(time, _)| time).sum::<f64>() / count;
+		let mean_level = points.iter().map(|(_, level)| level).sum::<f64>() / count;
+		let covariance: f64 = points.iter().map(|(time, level)| (time - mean_time) * (level - mean_level)).sum();
+		let variance: f64 = points.iter().map(|(time, _)| (time - mean_time) * (time - mean_time)).sum();
+
+		-60.0 / (covariance / variance)
+	}
+
+	#[test]
+	fn tail_decays_within_the_decay_time() {
+		for &size in [20.0, 100.0].iter() {
+			for &decay in [0.5, 2.0, 5.0].iter() {
+				let mut reverb = Reverb::<f64>::new(1, SAMPLE_RATE);
+				let parameters = reverb.parameters();
+				let set = |name: &str, value: f64| parameters.set(parameters.find(name).unwrap(), value);
+				set("Size", size);
+				set("Decay", decay);
+				set("Damping", 20000.0);
+				reverb.reset();
+
+				let mut input = vec![0.0; (1.5 * decay * SAMPLE_RATE) as usize];
+				input[0] = 1.0;
+				let measured = decay_time(&run(&mut reverb, &input));
+				assert!((measured / decay - 1.0).abs() < 0.1, "{} s instead of {} s at size {}", measured, decay, size);
+			}
+		}
+	}
+
+	#[test]
+	fn reset_silences_the_tail() {
+		let mut reverb = Reverb::<f64>::new(1, SAMPLE_RATE);
+		let mut random = Random::new(46);
+		let noise: Vec<f64> = (0..48000).map(|_| random.next_f64() - 0.5).collect();
+		let silence = vec![0.0; 48000];
+
+		run(&mut reverb, &noise);
+		let tail = run(&mut reverb, &silence[..4800]);
+		assert!(tail.iter().any(|sample| sample.abs() > 1e-3));
+
+		reverb.reset();
+		let after = run(&mut reverb, &silence);
+		assert!(after.iter().all(|sample| sample.abs() < 1e-15), "peak {}", after.iter().fold(0.0f64, |peak, sample| peak.max(sample.abs())));
+	}
+}
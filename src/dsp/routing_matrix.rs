@@ -0,0 +1,338 @@
+use crate::dsp::audio_block::{AudioBlock, AudioBlockMut};
+use crate::dsp::processor::Processor;
+use crate::dsp::sample::Sample;
+use std::f64::consts::FRAC_PI_4;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+pub const PAN_LEFT: f64 = -1.0f64;
+pub const PAN_CENTER: f64 = 0.0f64;
+pub const PAN_RIGHT: f64 = 1.0f64;
+
+/// Time constant of the gain smoothing, changes settle within about five times this
+const SMOOTHING_TIME: f64 = 0.005;
+
+/// Gain changes below this are applied without smoothing
+const SMOOTHING_THRESHOLD: f64 = 1e-6;
+
+/// Parameters of the route from one input to one output
+struct Crosspoint {
+	gain: AtomicU64,
+	pan: AtomicU64,
+	mute: AtomicBool,
+	solo: AtomicBool
+}
+
+impl Crosspoint {
+	fn new() -> Crosspoint {
+		Crosspoint {
+			gain: AtomicU64::new(0.0f64.to_bits()),
+			pan: AtomicU64::new(PAN_CENTER.to_bits()),
+			mute: AtomicBool::new(false),
+			solo: AtomicBool::new(false)
+		}
+	}
+
+	/// Gain including pan for an output on the given side of a stereo pair
+	fn target_gain(&self, side: Side, any_solo: bool) -> f64 {
+		if self.mute.load(Ordering::Relaxed) || (any_solo && !self.solo.load(Ordering::Relaxed)) {
+			return 0.0;
+		}
+
+		let gain = f64::from_bits(self.gain.load(Ordering::Relaxed));
+		let angle = (f64::from_bits(self.pan.load(Ordering::Relaxed)) + 1.0) * FRAC_PI_4;
+
+		match side {
+			Side::Left => gain * angle.cos(),
+			Side::Right => gain * angle.sin(),
+			Side::Mono => gain
+		}
+	}
+}
+
+/// Position of an output within a stereo pair, the last of an odd number of outputs is not panned
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Side {
+	Left,
+	Right,
+	Mono
+}
+
+/// Parameters of a `RoutingMatrix`, shared with other threads. All setters take effect
+/// with the next block and are smoothed in the callback.
+#[derive(Clone)]
+pub struct RoutingControl {
+	num_inputs: usize,
+	num_outputs: usize,
+	crosspoints: Arc<[Crosspoint]>
+}
+
+impl RoutingControl {
+	pub fn num_inputs(&self) -> usize {
+		self.num_inputs
+	}
+
+	pub fn num_outputs(&self) -> usize {
+		self.num_outputs
+	}
+
+	/// Linear gain from `input` to `output`, all routes start at 0.0
+	pub fn set_gain(&self, input: usize, output: usize, gain: f64) {
+		self.crosspoint(input, output).gain.store(gain.to_bits(), Ordering::Relaxed);
+	}
+
+	pub fn get_gain(&self, input: usize, output: usize) -> f64 {
+		f64::from_bits(self.crosspoint(input, output).gain.load(Ordering::Relaxed))
+	}
+
+	/// Constant power pan between `PAN_LEFT` and `PAN_RIGHT`. Outputs are paired as
+	/// left (even) and right (odd), a centered route plays 3 dB lower on either side.
+	pub fn set_pan(&self, input: usize, output: usize, pan: f64) {
+		let pan = pan.clamp(PAN_LEFT, PAN_RIGHT);
+		self.crosspoint(input, output).pan.store(pan.to_bits(), Ordering::Relaxed);
+	}
+
+	/// Pans all routes of `input`
+	pub fn set_input_pan(&self, input: usize, pan: f64) {
+		for output in 0..self.num_outputs {
+			self.set_pan(input, output, pan);
+		}
+	}
+
+	pub fn get_pan(&self, input: usize, output: usize) -> f64 {
+		f64::from_bits(self.crosspoint(input, output).pan.load(Ordering::Relaxed))
+	}
+
+	pub fn set_mute(&self, input: usize, output: usize, mute: bool) {
+		self.crosspoint(input, output).mute.store(mute, Ordering::Relaxed);
+	}
+
+	pub fn is_muted(&self, input: usize, output: usize) -> bool {
+		self.crosspoint(input, output).mute.load(Ordering::Relaxed)
+	}
+
+	/// As long as any route is soloed, only soloed routes are heard
+	pub fn set_solo(&self, input: usize, output: usize, solo: bool) {
+		self.crosspoint(input, output).solo.store(solo, Ordering::Relaxed);
+	}
+
+	pub fn is_soloed(&self, input: usize, output: usize) -> bool {
+		self.crosspoint(input, output).solo.load(Ordering::Relaxed)
+	}
+
+	fn crosspoint(&self, input: usize, output: usize) -> &Crosspoint {
+		if input >= self.num_inputs || output >= self.num_outputs {
+			panic!("No route from input {} to output {} in a {}x{} matrix", input, output, self.num_inputs, self.num_outputs);
+		}
+		&self.crosspoints[input * self.num_outputs + output]
+	}
+}
+
+/// Mixes N inputs onto M outputs with a gain per route
+pub struct RoutingMatrix<S: Sample> {
+	control: RoutingControl,
+	current: Vec<f64>,
+	smoothing: f64,
+	sample_rate: f64,
+	phantom: std::marker::PhantomData<S>
+}
+
+impl<S: Sample> RoutingMatrix<S> {
+	pub fn new(num_inputs: usize, num_outputs: usize, sample_rate: f64) -> RoutingMatrix<S> {
+		let crosspoints: Vec<Crosspoint> = (0..num_inputs * num_outputs).map(|_| Crosspoint::new()).collect();
+
+		RoutingMatrix {
+			control: RoutingControl {
+				num_inputs,
+				num_outputs,
+				crosspoints: crosspoints.into()
+			},
+			current: vec![0.0; num_inputs * num_outputs],
+			smoothing: (-1.0 / (SMOOTHING_TIME * sample_rate)).exp(),
+			sample_rate,
+			phantom: std::marker::PhantomData
+		}
+	}
+
+	/// Handle for changing parameters from other threads
+	pub fn control(&self) -> RoutingControl {
+		self.control.clone()
+	}
+
+	fn side(output: usize, num_outputs: usize) -> Side {
+		if output % 2 == 1 {
+			Side::Right
+		} else if output + 1 < num_outputs {
+			Side::Left
+		} else {
+			Side::Mono
+		}
+	}
+}
+
+impl<S: Sample> Processor<S> for RoutingMatrix<S> {
+	/// Inputs or outputs beyond the matrix size are ignored or left silent, respectively
+	fn process(&mut self, input: &AudioBlock<S>, output: &mut AudioBlockMut<S>) {
+		let num_inputs = self.control.num_inputs.min(input.num_channels());
+		let num_outputs = self.control.num_outputs;
+		let any_solo = self.control.crosspoints.iter().any(|crosspoint| crosspoint.solo.load(Ordering::Relaxed));
+
+		output.fill(S::ZERO);
+
+		for (index, target) in output.channels_mut().enumerate().take(num_outputs) {
+			let side = RoutingMatrix::<S>::side(index, num_outputs);
+
+			for channel in 0..num_inputs {
+				let route = channel * num_outputs + index;
+				let wanted = self.control.crosspoints[route].target_gain(side, any_solo);
+				let mut gain = self.current[route];
+				let source = input.channel(channel);
+
+				if (wanted - gain).abs() < SMOOTHING_THRESHOLD {
+					gain = wanted;
+					if gain != 0.0 {
+						let gain = S::from_f64(gain);
+						for (sample, value) in target.iter_mut().zip(source) {
+							*sample += *value * gain;
+						}
+					}
+				} else {
+					for (sample, value) in target.iter_mut().zip(source) {
+						gain = wanted + (gain - wanted) * self.smoothing;
+						*sample += *value * S::from_f64(gain);
+					}
+				}
+				self.current[route] = gain;
+			}
+		}
+	}
+
+	fn prepare(&mut self, sample_rate: f64, _max_block_size: usize) {
+		if sample_rate != self.sample_rate {
+			self.sample_rate = sample_rate;
+			self.smoothing = (-1.0 / (SMOOTHING_TIME * sample_rate)).exp();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::dsp::audio_block::AudioBuffer;
+
+	const SAMPLE_RATE: f64 = 48000.0;
+
+	/// Output samples for `blocks` blocks of 480 frames of constant `levels` on the inputs
+	fn run(matrix: &mut RoutingMatrix<f64>, levels: &[f64], num_outputs: usize, blocks: usize) -> Vec<Vec<f64>> {
+		let mut input = AudioBuffer::<f64>::with_channels(levels.len(), 480);
+		let mut output = AudioBuffer::<f64>::with_channels(num_outputs, 480);
+		let mut played = vec![Vec::new(); num_outputs];
+
+		for (channel, level) in levels.iter().enumerate() {
+			input.as_block_mut().channel_mut(channel).fill(*level);
+		}
+		for _ in 0..blocks {
+			matrix.process(&input.as_block(), &mut output.as_block_mut());
+			for (samples, channel) in played.iter_mut().zip(output.as_block().channels()) {
+				samples.extend_from_slice(channel);
+			}
+		}
+		played
+	}
+
+	/// Settled level of every output
+	fn settled(matrix: &mut RoutingMatrix<f64>, levels: &[f64], num_outputs: usize) -> Vec<f64> {
+		run(matrix, levels, num_outputs, 50).iter().map(|samples| *samples.last().unwrap()).collect()
+	}
+
+	fn decibels(gain: f64) -> f64 {
+		20.0 * gain.log10()
+	}
+
+	#[test]
+	fn pan_law() {
+		let mut matrix = RoutingMatrix::<f64>::new(1, 2, SAMPLE_RATE);
+		let control = matrix.control();
+		control.set_gain(0, 0, 1.0);
+		control.set_gain(0, 1, 1.0);
+
+		let centered = settled(&mut matrix, &[1.0], 2);
+		assert!((decibels(centered[0]) + 3.0103).abs() < 0.001, "{:?}", centered);
+		assert!((centered[0] - centered[1]).abs() < 1e-12);
+
+		control.set_input_pan(0, PAN_LEFT);
+		let left = settled(&mut matrix, &[1.0], 2);
+		assert!((left[0] - 1.0).abs() < 1e-9 && left[1].abs() < 1e-9, "{:?}", left);
+
+		control.set_input_pan(0, 5.0);
+		assert_eq!(control.get_pan(0, 1), PAN_RIGHT);
+		let right = settled(&mut matrix, &[1.0], 2);
+		assert!(right[0].abs() < 1e-9 && (right[1] - 1.0).abs() < 1e-9, "{:?}", right);
+	}
+
+	#[test]
+	fn last_odd_output_is_not_panned() {
+		let mut matrix = RoutingMatrix::<f64>::new(1, 3, SAMPLE_RATE);
+		let control = matrix.control();
+		for output in 0..3 {
+			control.set_gain(0, output, 0.5);
+		}
+		control.set_input_pan(0, PAN_LEFT);
+
+		let levels = settled(&mut matrix, &[1.0], 3);
+		assert!((levels[0] - 0.5).abs() < 1e-9 && levels[1].abs() < 1e-9, "{:?}", levels);
+		assert_eq!(levels[2], 0.5);
+	}
+
+	#[test]
+	fn mute_and_solo() {
+		let mut matrix = RoutingMatrix::<f64>::new(2, 2, SAMPLE_RATE);
+		let control = matrix.control();
+		for input in 0..2 {
+			for output in 0..2 {
+				control.set_gain(input, output, 1.0);
+				control.set_pan(input, output, if output == 0 { PAN_LEFT } else { PAN_RIGHT });
+			}
+		}
+		assert_eq!(settled(&mut matrix, &[1.0, 2.0], 2), vec![3.0, 3.0]);
+
+		control.set_mute(1, 0, true);
+		assert!(control.is_muted(1, 0));
+		assert_eq!(settled(&mut matrix, &[1.0, 2.0], 2), vec![1.0, 3.0]);
+
+		// A solo anywhere silences every route that is not soloed, muted ones stay muted
+		control.set_solo(1, 1, true);
+		control.set_solo(1, 0, true);
+		assert!(control.is_soloed(1, 1));
+		assert_eq!(settled(&mut matrix, &[1.0, 2.0], 2), vec![0.0, 2.0]);
+
+		control.set_mute(1, 0, false);
+		assert_eq!(settled(&mut matrix, &[1.0, 2.0], 2), vec![2.0, 2.0]);
+
+		control.set_solo(1, 0, false);
+		control.set_solo(1, 1, false);
+		assert_eq!(settled(&mut matrix, &[1.0, 2.0], 2), vec![3.0, 3.0]);
+	}
+
+	#[test]
+	fn gain_changes_are_smoothed() {
+		let mut matrix = RoutingMatrix::<f64>::new(1, 1, SAMPLE_RATE);
+		let control = matrix.control();
+		control.set_gain(0, 0, 1.0);
+
+		let ramp = run(&mut matrix, &[1.0], 1, 20).remove(0);
+		let largest_step = 1.0 / (SMOOTHING_TIME * SAMPLE_RATE);
+
+		// Starting from silence, the gain rises without a step and settles
+		assert!(ramp[0] > 0.0 && ramp[0] <= largest_step);
+		assert!(ramp.windows(2).all(|pair| pair[1] >= pair[0] && pair[1] - pair[0] <= largest_step));
+		assert!(ramp[(5.0 * SMOOTHING_TIME * SAMPLE_RATE) as usize] > 0.99);
+		assert_eq!(*ramp.last().unwrap(), 1.0);
+
+		control.set_gain(0, 0, 0.25);
+		let fall = run(&mut matrix, &[1.0], 1, 20).remove(0);
+		assert!(fall[0] < 1.0 && 1.0 - fall[0] <= largest_step);
+		assert!(fall.windows(2).all(|pair| pair[1] <= pair[0]));
+		assert_eq!(*fall.last().unwrap(), 0.25);
+	}
+}
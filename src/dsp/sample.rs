@@ -0,0 +1,87 @@
+use crate::asio_core::bulk_convert::BulkConverter;
+use crate::asio_core::sample_convert::Scaling;
+use crate::asio_core::ASIOSampleType;
+use std::fmt::Debug;
+use std::ops::{Add, AddAssign, Div, Mul, MulAssign, Neg, Sub, SubAssign};
+
+/// Floating point type used for processing, either f32 or f64
+pub trait Sample:
+	'static
+	+ Copy
+	+ Default
+	+ Debug
+	+ PartialOrd
+	+ Send
+	+ Sync
+	+ Add<Output = Self>
+	+ Sub<Output = Self>
+	+ Mul<Output = Self>
+	+ Div<Output = Self>
+	+ Neg<Output = Self>
+	+ AddAssign
+	+ SubAssign
+	+ MulAssign
+{
+	const ZERO: Self;
+	const ONE: Self;
+
+	fn from_f64(value: f64) -> Self;
+	fn to_f64(self) -> f64;
+	fn abs(self) -> Self;
+
+	/// Converts `dst.len()` native samples of the given type directly into this precision
+	fn decode(converter: &BulkConverter, sample_type: ASIOSampleType, scaling: Scaling, src: &[u8], dst: &mut [Self]);
+
+	/// Converts all samples of `src` into native samples of the given type
+	fn encode(converter: &BulkConverter, sample_type: ASIOSampleType, scaling: Scaling, src: &[Self], dst: &mut [u8]);
+}
+
+impl Sample for f32 {
+	const ZERO: f32 = 0.0;
+	const ONE: f32 = 1.0;
+
+	fn from_f64(value: f64) -> f32 {
+		value as f32
+	}
+
+	fn to_f64(self) -> f64 {
+		self as f64
+	}
+
+	fn abs(self) -> f32 {
+		f32::abs(self)
+	}
+
+	fn decode(converter: &BulkConverter, sample_type: ASIOSampleType, scaling: Scaling, src: &[u8], dst: &mut [f32]) {
+		converter.decode_f32(sample_type, scaling, src, dst);
+	}
+
+	fn encode(converter: &BulkConverter, sample_type: ASIOSampleType, scaling: Scaling, src: &[f32], dst: &mut [u8]) {
+		converter.encode_f32(sample_type, scaling, src, dst);
+	}
+}
+
+impl Sample for f64 {
+	const ZERO: f64 = 0.0;
+	const ONE: f64 = 1.0;
+
+	fn from_f64(value: f64) -> f64 {
+		value
+	}
+
+	fn to_f64(self) -> f64 {
+		self
+	}
+
+	fn abs(self) -> f64 {
+		f64::abs(self)
+	}
+
+	fn decode(converter: &BulkConverter, sample_type: ASIOSampleType, scaling: Scaling, src: &[u8], dst: &mut [f64]) {
+		converter.decode_f64(sample_type, scaling, src, dst);
+	}
+
+	fn encode(converter: &BulkConverter, sample_type: ASIOSampleType, scaling: Scaling, src: &[f64], dst: &mut [u8]) {
+		converter.encode_f64(sample_type, scaling, src, dst);
+	}
+}
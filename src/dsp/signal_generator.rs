@@ -0,0 +1,582 @@
+use crate::asio_core::random::Random;
+use crate::dsp::audio_block::{AudioBlock, AudioBlockMut};
+use crate::dsp::fft::Complex;
+use crate::dsp::parameters::{db_to_gain, ParameterId, ParameterInfo, ParameterRegistry, Parameters, SmoothedParameter, Smoothing, Unit};
+use crate::dsp::processor::{pass_through, Processor};
+use crate::dsp::sample::Sample;
+use crate::dsp::wav::{Wav, WavError};
+use std::f64::consts::PI;
+use std::path::Path;
+
+/// Highest frequency generated, as a fraction of the sample rate
+const MAX_FREQUENCY: f64 = 0.49;
+
+/// Scales the pink noise filter's output to unit RMS, measured with unit RMS white noise
+const PINK_GAIN: f64 = 1.0 / 3.046;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Waveform {
+	Sine,
+	Square,				// band limited
+	WhiteNoise,
+	PinkNoise,			// -3 dB per octave
+	Sweep,				// logarithmic sine sweep, repeated after a gap of silence
+	ImpulseTrain,		// single samples at the level, the period rounded to whole frames
+	Dc
+}
+
+impl Waveform {
+	pub const ALL: [Waveform; 7] = [
+		Waveform::Sine,
+		Waveform::Square,
+		Waveform::WhiteNoise,
+		Waveform::PinkNoise,
+		Waveform::Sweep,
+		Waveform::ImpulseTrain,
+		Waveform::Dc
+	];
+
+	pub fn index(&self) -> usize {
+		Waveform::ALL.iter().position(|waveform| waveform == self).unwrap()
+	}
+
+	/// Waveform for a parameter value, rounded and clamped to the valid indices
+	pub fn from_index(index: f64) -> Waveform {
+		Waveform::ALL[(index.round().max(0.0) as usize).min(Waveform::ALL.len() - 1)]
+	}
+
+	/// Whether the level sets the RMS rather than the peak
+	pub fn is_noise(&self) -> bool {
+		matches!(self, Waveform::WhiteNoise | Waveform::PinkNoise)
+	}
+}
+
+/// Exponential sine sweep after Farina. Convolving a recording of the sweep with the inverse
+/// filter gives the impulse response of the system it passed, with a gain of 1.0 between
+/// the start and end frequency, delayed by the length of the sweep. Harmonic distortion
+/// ends up before that delay.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Sweep {
+	pub start_frequency: f64,
+	pub end_frequency: f64,
+	pub duration: f64,				// in seconds
+	pub sample_rate: f64
+}
+
+impl Sweep {
+	pub fn new(start_frequency: f64, end_frequency: f64, duration: f64, sample_rate: f64) -> Sweep {
+		if start_frequency <= 0.0 || end_frequency <= 0.0 || duration <= 0.0 {
+			panic!("Invalid sweep from {} Hz to {} Hz over {} s", start_frequency, end_frequency, duration);
+		}
+
+		Sweep {
+			start_frequency,
+			end_frequency,
+			duration,
+			sample_rate
+		}
+	}
+
+	pub fn num_frames(&self) -> usize {
+		(self.duration * self.sample_rate).round() as usize
+	}
+
+	/// Time in seconds in which the frequency grows by a factor of e
+	fn rate_constant(&self) -> f64 {
+		self.duration / (self.end_frequency / self.start_frequency).ln()
+	}
+
+	/// Sample of the sweep at full scale, faded in and out
+	pub fn value_at(&self, frame: usize) -> f64 {
+		let time = frame as f64 / self.sample_rate;
+		let phase = if self.start_frequency == self.end_frequency {
+			2.0 * PI * self.start_frequency * time
+		} else {
+			let rate_constant = self.rate_constant();
+			2.0 * PI * self.start_frequency * rate_constant * ((time / rate_constant).exp() - 1.0)
+		};
+		phase.sin() * self.fade(time)
+	}
+
+	/// Raised cosine fade in and out over a sixth of an octave, the abrupt start and end
+	/// would otherwise ripple through the measured response
+	fn fade(&self, time: f64) -> f64 {
+		let length = (self.rate_constant().abs() * 2.0f64.ln() / 6.0).min(0.1 * self.duration);
+		let distance = time.min(self.duration - time);
+
+		if distance >= length {
+			1.0
+		} else {
+			0.5 - 0.5 * (PI * distance.max(0.0) / length).cos()
+		}
+	}
+
+	pub fn signal(&self) -> Vec<f64> {
+		(0..self.num_frames()).map(|frame| self.value_at(frame)).collect()
+	}
+
+	/// The sweep reversed in time, with its amplitude rising by 6 dB per octave to undo the
+	/// pink spectrum of the sweep
+	pub fn inverse(&self) -> Vec<f64> {
+		let num_frames = self.num_frames();
+		let rate_constant = self.rate_constant();
+		// Starting at the silent end of the fade out makes the delay exactly `num_frames`
+		let mut inverse: Vec<f64> = (0..=num_frames)
+			.map(|frame| self.value_at(num_frames - frame) * (-(frame as f64) / self.sample_rate / rate_constant).exp())
+			.collect();
+
+		// Normalize the gain at the geometric center frequency of the sweep
+		let center = (self.start_frequency * self.end_frequency).sqrt();
+		let response = |samples: &[f64]| {
+			samples.iter().enumerate().fold(Complex::ZERO, |sum, (frame, value)| {
+				sum + Complex::from_angle(-2.0 * PI * center * frame as f64 / self.sample_rate).scale(*value)
+			})
+		};
+		let gain = (response(&self.signal()) * response(&inverse)).abs();
+		for value in inverse.iter_mut() {
+			*value /= gain;
+		}
+		inverse
+	}
+
+	/// Writes the sweep at full scale and its inverse filter as mono 32 bit float files
+	pub fn write(&self, sweep_path: impl AsRef<Path>, inverse_path: impl AsRef<Path>) -> Result<(), WavError> {
+		Wav::new(self.sample_rate, vec![self.signal()]).write(sweep_path)?;
+		Wav::new(self.sample_rate, vec![self.inverse()]).write(inverse_path)
+	}
+}
+
+/// Paul Kellet's filter turning white into pink noise
+struct PinkFilter {
+	states: [f64; 7]
+}
+
+impl PinkFilter {
+	fn new() -> PinkFilter {
+		PinkFilter { states: [0.0; 7] }
+	}
+
+	fn process(&mut self, white: f64) -> f64 {
+		let s = &mut self.states;
+		s[0] = 0.99886 * s[0] + white * 0.0555179;
+		s[1] = 0.99332 * s[1] + white * 0.0750759;
+		s[2] = 0.96900 * s[2] + white * 0.1538520;
+		s[3] = 0.86650 * s[3] + white * 0.3104856;
+		s[4] = 0.55000 * s[4] + white * 0.5329522;
+		s[5] = -0.7616 * s[5] - white * 0.0168980;
+		let pink = s[0] + s[1] + s[2] + s[3] + s[4] + s[5] + s[6] + white * 0.5362;
+		s[6] = white * 0.115926;
+		pink * PINK_GAIN
+	}
+}
+
+/// Correction of a naive square wave's step at `phase`, see Valimaki's polyBLEP
+fn poly_blep(phase: f64, increment: f64) -> f64 {
+	if phase < increment {
+		let t = phase / increment;
+		2.0 * t - t * t - 1.0
+	} else if phase > 1.0 - increment {
+		let t = (phase - 1.0) / increment;
+		t * t + 2.0 * t + 1.0
+	} else {
+		0.0
+	}
+}
+
+struct GeneratorIds {
+	waveform: ParameterId,
+	frequency: ParameterId,
+	level: ParameterId,
+	sweep_start: ParameterId,
+	sweep_end: ParameterId,
+	sweep_duration: ParameterId,
+	sweep_gap: ParameterId,
+	outputs: Vec<ParameterId>
+}
+
+/// Test signals for line checks and measurements. The signal replaces the channels routed
+/// to it, the other channels pass the input through. Level is in dBFS and sets the peak,
+/// except for noise, where it sets the RMS.
+pub struct SignalGenerator<S: Sample> {
+	parameters: Parameters,
+	ids: GeneratorIds,
+	sample_rate: f64,
+	level: SmoothedParameter<f64>,
+	routed: Vec<bool>,
+	phase: f64,
+	sweep_frame: usize,
+	impulse_frame: usize,
+	random: Random,
+	pink: PinkFilter,
+	_sample: std::marker::PhantomData<S>
+}
+
+impl<S: Sample> SignalGenerator<S> {
+	/// Parameters "Waveform" (a `Waveform` index), "Frequency" (also the rate of the
+	/// impulse train), "Level", "Sweep Start", "Sweep End", "Sweep Duration", "Sweep Gap"
+	/// and "Output <n>" for each of `num_outputs` channels, counting from 1. All outputs
+	/// are routed at first.
+	pub fn new(num_outputs: usize, sample_rate: f64) -> SignalGenerator<S> {
+		let last_waveform = (Waveform::ALL.len() - 1) as f64;
+		let fixed = |info: ParameterInfo| info.with_smoothing(Smoothing::None, 0.0);
+
+		let mut registry = ParameterRegistry::new();
+		let ids = GeneratorIds {
+			waveform: registry.add(fixed(ParameterInfo::new("Waveform", 0.0, last_waveform, 0.0, Unit::None))),
+			frequency: registry.add(fixed(ParameterInfo::new("Frequency", 1.0, 20000.0, 1000.0, Unit::Hertz))),
+			level: registry.add(ParameterInfo::new("Level", -120.0, 0.0, -20.0, Unit::Decibels)),
+			sweep_start: registry.add(fixed(ParameterInfo::new("Sweep Start", 1.0, 24000.0, 20.0, Unit::Hertz))),
+			sweep_end: registry.add(fixed(ParameterInfo::new("Sweep End", 1.0, 24000.0, 20000.0, Unit::Hertz))),
+			sweep_duration: registry.add(fixed(ParameterInfo::new("Sweep Duration", 0.1, 60.0, 10.0, Unit::Seconds))),
+			sweep_gap: registry.add(fixed(ParameterInfo::new("Sweep Gap", 0.0, 10.0, 2.0, Unit::Seconds))),
+			outputs: (0..num_outputs)
+				.map(|index| registry.add(fixed(ParameterInfo::new(&format!("Output {}", index + 1), 0.0, 1.0, 1.0, Unit::None))))
+				.collect()
+		};
+		let parameters = registry.build();
+
+		SignalGenerator {
+			level: parameters.smoother(ids.level, sample_rate),
+			parameters,
+			ids,
+			sample_rate,
+			routed: vec![false; num_outputs],
+			phase: 0.0,
+			sweep_frame: 0,
+			impulse_frame: 0,
+			random: Random::new(1),
+			pink: PinkFilter::new(),
+			_sample: std::marker::PhantomData
+		}
+	}
+
+	/// Values can be changed from any thread
+	pub fn parameters(&self) -> Parameters {
+		self.parameters.clone()
+	}
+
+	/// The sweep as currently set, e.g. to export it with its inverse filter. The generator
+	/// plays it scaled by the level.
+	pub fn sweep(&self) -> Sweep {
+		SignalGenerator::<S>::current_sweep(&self.parameters, &self.ids, self.sample_rate)
+	}
+
+	/// Restarts the waveform and the sweep and jumps to the current level
+	pub fn reset(&mut self) {
+		self.level = self.parameters.smoother(self.ids.level, self.sample_rate);
+		self.phase = 0.0;
+		self.sweep_frame = 0;
+		self.impulse_frame = 0;
+		self.pink = PinkFilter::new();
+	}
+
+	fn current_sweep(parameters: &Parameters, ids: &GeneratorIds, sample_rate: f64) -> Sweep {
+		let highest = MAX_FREQUENCY * sample_rate;
+
+		Sweep::new(
+			parameters.get(ids.sweep_start).min(highest),
+			parameters.get(ids.sweep_end).min(highest),
+			parameters.get(ids.sweep_duration),
+			sample_rate
+		)
+	}
+
+	/// Next sample of the waveform at full scale
+	fn next(&mut self, waveform: Waveform, increment: f64, sweep: &Sweep, sweep_period: usize) -> f64 {
+		let value = match waveform {
+			Waveform::Sine => (2.0 * PI * self.phase).sin(),
+			Waveform::Square => {
+				let naive = if self.phase < 0.5 { 1.0 } else { -1.0 };
+				naive + poly_blep(self.phase, increment) - poly_blep((self.phase + 0.5).fract(), increment)
+			}
+			Waveform::WhiteNoise => (2.0 * self.random.next_f64() - 1.0) * 3.0f64.sqrt(),
+			Waveform::PinkNoise => {
+				let white = (2.0 * self.random.next_f64() - 1.0) * 3.0f64.sqrt();
+				self.pink.process(white)
+			}
+			Waveform::Sweep => {
+				let frame = self.sweep_frame;
+				self.sweep_frame = (frame + 1) % sweep_period;
+				if frame < sweep.num_frames() { sweep.value_at(frame) } else { 0.0 }
+			}
+			Waveform::ImpulseTrain => {
+				let frame = self.impulse_frame;
+				self.impulse_frame = (frame + 1) % (1.0 / increment).round() as usize;
+				if frame == 0 { 1.0 } else { 0.0 }
+			}
+			Waveform::Dc => 1.0
+		};
+
+		self.phase = (self.phase + increment).fract();
+		value
+	}
+}
+
+impl<S: Sample> Processor<S> for SignalGenerator<S> {
+	fn process(&mut self, input: &AudioBlock<S>, output: &mut AudioBlockMut<S>) {
+		let frames = output.num_frames();
+
+		pass_through(input, output);
+
+		for (routed, id) in self.routed.iter_mut().zip(self.ids.outputs.iter()) {
+			*routed = self.parameters.get(*id) >= 0.5;
+		}
+
+		let waveform = Waveform::from_index(self.parameters.get(self.ids.waveform));
+		let frequency = self.parameters.get(self.ids.frequency).min(MAX_FREQUENCY * self.sample_rate);
+		let increment = frequency / self.sample_rate;
+		let sweep = SignalGenerator::<S>::current_sweep(&self.parameters, &self.ids, self.sample_rate);
+		let sweep_period = sweep.num_frames() + (self.parameters.get(self.ids.sweep_gap) * self.sample_rate).round() as usize;
+		self.sweep_frame %= sweep_period;
+		self.level.update(frames);
+		let smoothing = self.level.is_smoothing();
+		let mut gain = db_to_gain(self.level.value());
+
+		for frame in 0..frames {
+			if smoothing {
+				gain = db_to_gain(self.level.next_value());
+			}
+			let value = S::from_f64(self.next(waveform, increment, &sweep, sweep_period) * gain);
+
+			for (channel, routed) in self.routed.iter().enumerate().take(output.num_channels()) {
+				if *routed {
+					output.channel_mut(channel)[frame] = value;
+				}
+			}
+		}
+	}
+
+	fn prepare(&mut self, sample_rate: f64, _max_block_size: usize) {
+		if sample_rate != self.sample_rate {
+			self.sample_rate = sample_rate;
+			self.reset();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::dsp::audio_block::AudioBuffer;
+	use crate::dsp::fft::RealFft;
+	use crate::dsp::parameters::gain_to_db;
+	use crate::dsp::spectrum_analyzer::Window;
+
+	const SAMPLE_RATE: f64 = 48000.0;
+
+	/// Passes the channels through the generator in blocks of 256 frames
+	fn run(generator: &mut SignalGenerator<f64>, channels: &[&[f64]]) -> Vec<Vec<f64>> {
+		let len = channels[0].len();
+		let mut input = AudioBuffer::<f64>::with_channels(channels.len(), 256);
+		let mut output = AudioBuffer::<f64>::with_channels(channels.len(), 256);
+		let mut played = vec![Vec::with_capacity(len); channels.len()];
+
+		for start in (0..len).step_by(256) {
+			let frames = 256.min(len - start);
+			input.set_num_frames(frames);
+			output.set_num_frames(frames);
+			for (channel, samples) in channels.iter().enumerate() {
+				input.as_block_mut().channel_mut(channel).copy_from_slice(&samples[start..start + frames]);
+			}
+			generator.process(&input.as_block(), &mut output.as_block_mut());
+			for (samples, channel) in played.iter_mut().zip(output.as_block().channels()) {
+				samples.extend_from_slice(channel);
+			}
+		}
+		played
+	}
+
+	/// `frames` of a waveform at `frequency` and `level`, on one output
+	fn play(waveform: Waveform, frequency: f64, level: f64, frames: usize) -> Vec<f64> {
+		let mut generator = SignalGenerator::<f64>::new(1, SAMPLE_RATE);
+		let parameters = generator.parameters();
+		parameters.set(parameters.find("Waveform").unwrap(), waveform.index() as f64);
+		parameters.set(parameters.find("Frequency").unwrap(), frequency);
+		parameters.set(parameters.find("Level").unwrap(), level);
+		generator.reset();
+		run(&mut generator, &[&vec![0.0; frames]]).remove(0)
+	}
+
+	fn peak(samples: &[f64]) -> f64 {
+		samples.iter().fold(0.0, |peak, sample| peak.max(sample.abs()))
+	}
+
+	fn rms(samples: &[f64]) -> f64 {
+		(samples.iter().map(|sample| sample * sample).sum::<f64>() / samples.len() as f64).sqrt()
+	}
+
+	/// Power per bin averaged over Hann windowed, half overlapping frames of `size`
+	fn power_spectrum(samples: &[f64], size: usize) -> Vec<f64> {
+		let window = Window::Hann.coefficients(size);
+		let mut fft = RealFft::new(size);
+		let mut frame = vec![0.0; size];
+		let mut spectrum = vec![Complex::ZERO; fft.spectrum_len()];
+		let mut power = vec![0.0; fft.spectrum_len()];
+
+		let starts: Vec<usize> = (0..=samples.len() - size).step_by(size / 2).collect();
+		for start in starts.iter() {
+			for ((frame, sample), weight) in frame.iter_mut().zip(&samples[*start..]).zip(window.iter()) {
+				*frame = sample * weight;
+			}
+			fft.forward(&frame, &mut spectrum);
+			for (power, bin) in power.iter_mut().zip(spectrum.iter()) {
+				*power += bin.norm_sqr() / starts.len() as f64;
+			}
+		}
+		power
+	}
+
+	/// Linear convolution of two signals through the FFT
+	fn convolve(a: &[f64], b: &[f64]) -> Vec<f64> {
+		let len = a.len() + b.len() - 1;
+		let mut fft = RealFft::new(len.next_power_of_two());
+		let transform = |fft: &mut RealFft, signal: &[f64]| {
+			let mut padded = vec![0.0; fft.size()];
+			padded[..signal.len()].copy_from_slice(signal);
+			let mut spectrum = vec![Complex::ZERO; fft.spectrum_len()];
+			fft.forward(&padded, &mut spectrum);
+			spectrum
+		};
+
+		let product: Vec<Complex> = transform(&mut fft, a).iter().zip(transform(&mut fft, b).iter()).map(|(x, y)| *x * *y).collect();
+		let mut result = vec![0.0; fft.size()];
+		fft.inverse(&product, &mut result);
+		result.truncate(len);
+		result
+	}
+
+	/// Gain of `response` at `frequency`, with its delay removed
+	fn gain_at(response: &[f64], frequency: f64, sample_rate: f64) -> f64 {
+		response.iter().enumerate().fold(Complex::ZERO, |sum, (frame, value)| {
+			sum + Complex::from_angle(-2.0 * PI * frequency * frame as f64 / sample_rate).scale(*value)
+		}).abs()
+	}
+
+	#[test]
+	fn sweep_and_inverse_give_an_impulse() {
+		for &(start, end, duration, sample_rate) in [(20.0, 20000.0, 1.0, 48000.0), (100.0, 10000.0, 0.5, 44100.0)].iter() {
+			let sweep = Sweep::new(start, end, duration, sample_rate);
+			let response = convolve(&sweep.signal(), &sweep.inverse());
+
+			let peak = (0..response.len()).max_by(|a, b| response[*a].abs().total_cmp(&response[*b].abs())).unwrap();
+			assert_eq!(peak, sweep.num_frames());
+			assert!(response[peak] > 0.0);
+
+			// Unity gain away from the fades at the ends of the sweep
+			let mut frequency = 8.0 * start;
+			while frequency < end / 2.0 {
+				let gain_db = 20.0 * gain_at(&response, frequency, sample_rate).log10();
+				assert!(gain_db.abs() < 0.05, "{} dB at {} Hz", gain_db, frequency);
+				frequency *= 2.0f64.sqrt();
+			}
+		}
+	}
+
+	#[test]
+	fn level_sets_the_peak_of_tones_and_the_rms_of_noise() {
+		for waveform in [Waveform::Sine, Waveform::Square, Waveform::ImpulseTrain, Waveform::Dc] {
+			let samples = play(waveform, 1000.0, -20.0, 48000);
+			assert!((peak(&samples) - 0.1).abs() < 1e-12, "{:?} peaks at {}", waveform, peak(&samples));
+		}
+
+		// 0.1 dB of the white noise's RMS, the estimate from 10 s is that accurate
+		for waveform in [Waveform::WhiteNoise, Waveform::PinkNoise] {
+			let samples = play(waveform, 1000.0, -20.0, 480000);
+			assert!((gain_to_db(rms(&samples)) + 20.0).abs() < 0.1, "{:?} at {} dB RMS", waveform, gain_to_db(rms(&samples)));
+		}
+	}
+
+	#[test]
+	fn pink_gain_gives_unit_rms() {
+		let mut random = Random::new(47);
+		let mut filter = PinkFilter::new();
+		let pink: Vec<f64> = (0..4800000).map(|_| filter.process((2.0 * random.next_f64() - 1.0) * 3.0f64.sqrt())).collect();
+
+		assert!((rms(&pink) - 1.0).abs() < 0.005, "RMS {}", rms(&pink));
+	}
+
+	#[test]
+	fn pink_noise_falls_by_3_db_per_octave() {
+		// Power times frequency is flat for pink noise, so its mean over each octave should be
+		let samples = play(Waveform::PinkNoise, 1000.0, 0.0, 960000);
+		let power = power_spectrum(&samples, 8192);
+		let bin = |frequency: f64| (frequency * 8192.0 / SAMPLE_RATE).round() as usize;
+		let octave = |low: f64| {
+			let bins = bin(low)..bin(2.0 * low);
+			let count = bins.len() as f64;
+			10.0 * (bins.map(|bin| power[bin] * bin as f64).sum::<f64>() / count).log10()
+		};
+
+		let reference = octave(1000.0);
+		let mut low = 62.5;
+		while low < 16000.0 {
+			assert!((octave(low) - reference).abs() < 0.3, "{} dB from {} Hz", octave(low) - reference, low);
+			low *= 2.0;
+		}
+	}
+
+	#[test]
+	fn square_is_band_limited() {
+		// 1367 cycles in 65536 frames, so every harmonic and alias falls on a bin of its own
+		let size = 65536;
+		let cycles = 1367;
+		let frequency = cycles as f64 * SAMPLE_RATE / size as f64;
+		let spectrum = |samples: &[f64]| {
+			let mut fft = RealFft::new(size);
+			let mut spectrum = vec![Complex::ZERO; fft.spectrum_len()];
+			fft.forward(samples, &mut spectrum);
+			spectrum.iter().map(|bin| 2.0 * bin.abs() / size as f64).collect::<Vec<f64>>()
+		};
+		let alias_power = |amplitudes: &[f64]| (1..size / 4).filter(|bin| bin % cycles != 0).map(|bin| amplitudes[bin].powi(2)).sum::<f64>();
+
+		let square = spectrum(&play(Waveform::Square, frequency, 0.0, size));
+		let naive = spectrum(&(0..size).map(|frame| if (frame * cycles % size) < size / 2 { 1.0 } else { -1.0 }).collect::<Vec<f64>>());
+
+		// The low odd harmonics have about the amplitudes of the ideal square, the even ones
+		// are missing
+		for harmonic in [1, 3] {
+			let error = gain_to_db(square[cycles * harmonic] * PI * harmonic as f64 / 4.0);
+			assert!(error.abs() < 0.15, "harmonic {} off by {} dB", harmonic, error);
+		}
+		assert!(square[cycles * 2] < 1e-9 && square[cycles * 4] < 1e-9);
+
+		// And far less folds back below half the Nyquist frequency than with the naive square
+		let reduction = 10.0 * (alias_power(&square) / alias_power(&naive)).log10();
+		assert!(reduction < -25.0, "aliases reduced by {} dB", reduction);
+	}
+
+	#[test]
+	fn impulse_train_period_is_rounded_to_frames() {
+		// 48 frames at 1 kHz, 68.57 rounded to 69 at 700 Hz
+		for (frequency, period) in [(1000.0, 48), (700.0, 69)] {
+			let samples = play(Waveform::ImpulseTrain, frequency, -6.0, 4800);
+			let impulses: Vec<usize> = (0..samples.len()).filter(|frame| samples[*frame] != 0.0).collect();
+			assert_eq!(impulses, (0..4800).step_by(period).collect::<Vec<usize>>());
+			assert!(impulses.iter().all(|frame| samples[*frame] == db_to_gain(-6.0)));
+		}
+	}
+
+	#[test]
+	fn dc_is_the_level() {
+		let samples = play(Waveform::Dc, 1000.0, -12.0, 1000);
+		assert!(samples.iter().all(|sample| *sample == db_to_gain(-12.0)));
+	}
+
+	#[test]
+	fn only_routed_outputs_are_replaced() {
+		let mut generator = SignalGenerator::<f64>::new(3, SAMPLE_RATE);
+		let parameters = generator.parameters();
+		parameters.set(parameters.find("Waveform").unwrap(), Waveform::Dc.index() as f64);
+		parameters.set(parameters.find("Output 2").unwrap(), 0.0);
+		generator.reset();
+
+		// The fourth channel has no output parameter and passes through as well
+		let input = vec![0.25; 1000];
+		let output = run(&mut generator, &[&input, &input, &input, &input]);
+		let level = db_to_gain(-20.0);
+		assert!(output[0].iter().all(|sample| *sample == level));
+		assert_eq!(output[1], input);
+		assert!(output[2].iter().all(|sample| *sample == level));
+		assert_eq!(output[3], input);
+	}
+}
@@ -0,0 +1,540 @@
+use crate::dsp::audio_block::{AudioBlock, AudioBlockMut};
+use crate::dsp::channel_tap::{ChannelTap, TapControl};
+use crate::dsp::fft::{Complex, RealFft};
+use crate::dsp::parameters::{AtomicF32, AtomicF64, ParameterId, ParameterInfo, ParameterRegistry, Parameters, Smoothing, Unit};
+use crate::dsp::processor::{pass_through, Processor};
+use crate::dsp::sample::Sample;
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+const MIN_FFT_SIZE: usize = 1024;
+const FFT_SIZES: usize = 6;
+const MAX_FFT_SIZE: usize = MIN_FFT_SIZE << (FFT_SIZES - 1);
+
+/// Fractions of an octave the spectrum can be smoothed over, 0 for none
+const SMOOTHING_FRACTIONS: [f64; 6] = [0.0, 1.0, 3.0, 6.0, 12.0, 24.0];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Window {
+	Rectangular,
+	Hann,
+	Hamming,
+	Blackman,
+	BlackmanHarris,		// 4 term, sidelobes below -92 dB
+	FlatTop				// accurate amplitudes between bins
+}
+
+impl Window {
+	pub const ALL: [Window; 6] = [
+		Window::Rectangular,
+		Window::Hann,
+		Window::Hamming,
+		Window::Blackman,
+		Window::BlackmanHarris,
+		Window::FlatTop
+	];
+
+	pub fn index(&self) -> usize {
+		Window::ALL.iter().position(|window| window == self).unwrap()
+	}
+
+	/// Window for a parameter value, rounded and clamped to the valid indices
+	pub fn from_index(index: f64) -> Window {
+		Window::ALL[(index.round().max(0.0) as usize).min(Window::ALL.len() - 1)]
+	}
+
+	/// Periodic window of `size` samples as a sum of cosines
+	pub fn coefficients(&self, size: usize) -> Vec<f64> {
+		let terms: &[f64] = match self {
+			Window::Rectangular => &[1.0],
+			Window::Hann => &[0.5, 0.5],
+			Window::Hamming => &[0.54, 0.46],
+			Window::Blackman => &[0.42, 0.5, 0.08],
+			Window::BlackmanHarris => &[0.35875, 0.48829, 0.14128, 0.01168],
+			Window::FlatTop => &[0.21557895, 0.41663158, 0.277263158, 0.083578947, 0.006947368]
+		};
+
+		(0..size).map(|index| {
+			let phase = 2.0 * PI * index as f64 / size as f64;
+			terms.iter().enumerate().fold(0.0, |sum, (order, term)| {
+				let sign = if order % 2 == 0 { 1.0 } else { -1.0 };
+				sum + sign * term * (order as f64 * phase).cos()
+			})
+		}).collect()
+	}
+}
+
+#[derive(Copy, Clone)]
+struct AnalyzerIds {
+	fft_size: ParameterId,
+	overlap: ParameterId,
+	window: ParameterId,
+	averaging: ParameterId,
+	smoothing: ParameterId,
+	peak_hold: ParameterId
+}
+
+struct Shared {
+	sample_rate: AtomicF64,
+	fft_size: AtomicUsize,
+	magnitudes: Box<[AtomicF32]>,
+	peaks: Box<[AtomicF32]>,
+	spectra: AtomicU64,
+	reset_peaks: AtomicBool
+}
+
+/// Settings and published spectra of a `SpectrumAnalyzer`, for the UI
+#[derive(Clone)]
+pub struct SpectrumControl {
+	parameters: Parameters,
+	shared: Arc<Shared>,
+	tap: TapControl
+}
+
+impl SpectrumControl {
+	/// Parameters "FFT Size" (1024 times 2 to the power of the value), "Overlap",
+	/// "Window" (a `Window` index), "Averaging" (the time constant of the power average),
+	/// "Smoothing" (none, 1, 1/3, 1/6, 1/12 or 1/24 octave) and "Peak Hold"
+	pub fn parameters(&self) -> &Parameters {
+		&self.parameters
+	}
+
+	/// Channel of the processor's input to analyze
+	pub fn set_channel(&self, channel: usize) {
+		self.tap.set_channel(channel);
+	}
+
+	pub fn channel(&self) -> usize {
+		self.tap.channel()
+	}
+
+	/// Bins of the published spectra, from DC to Nyquist
+	pub fn num_bins(&self) -> usize {
+		self.shared.fft_size.load(Ordering::Relaxed) / 2 + 1
+	}
+
+	pub fn bin_frequency(&self, bin: usize) -> f64 {
+		bin as f64 * self.shared.sample_rate.load() / self.shared.fft_size.load(Ordering::Relaxed) as f64
+	}
+
+	/// Averaged and smoothed spectrum in dBFS, a full scale sine reads 0 dB
+	pub fn magnitudes(&self) -> Vec<f32> {
+		self.shared.magnitudes[..self.num_bins()].iter().map(|value| value.load()).collect()
+	}
+
+	/// Highest magnitudes within the hold time, in dBFS
+	pub fn peaks(&self) -> Vec<f32> {
+		self.shared.peaks[..self.num_bins()].iter().map(|value| value.load()).collect()
+	}
+
+	/// Number of spectra published so far
+	pub fn spectra(&self) -> u64 {
+		self.shared.spectra.load(Ordering::Relaxed)
+	}
+
+	/// Chunks the callback dropped because the analysis fell behind
+	pub fn dropped_chunks(&self) -> u64 {
+		self.tap.dropped_chunks()
+	}
+
+	pub fn reset_peaks(&self) {
+		self.shared.reset_peaks.store(true, Ordering::Relaxed);
+	}
+}
+
+/// Sum of the values `start..end` stored in the leaves of a tree of partial sums, where
+/// node `n` holds the sum of nodes `2 n` and `2 n + 1`
+fn band_sum(sums: &[f64], start: usize, end: usize) -> f64 {
+	let leaves = sums.len() / 2;
+	let (mut start, mut end) = (start + leaves, end + leaves);
+	let mut sum = 0.0;
+
+	while start < end {
+		if start & 1 == 1 {
+			sum += sums[start];
+			start += 1;
+		}
+		if end & 1 == 1 {
+			end -= 1;
+			sum += sums[end];
+		}
+		start /= 2;
+		end /= 2;
+	}
+	sum
+}
+
+/// State of the analysis thread
+struct Analysis {
+	parameters: Parameters,
+	ids: AnalyzerIds,
+	shared: Arc<Shared>,
+	fft: RealFft,
+	window: Window,
+	coefficients: Vec<f64>,
+	history: Vec<f64>,
+	position: usize,
+	filled: usize,
+	since_transform: usize,
+	frame: Vec<f64>,
+	spectrum: Vec<Complex>,
+	average: Vec<f64>,
+	sums: Vec<f64>,
+	peaks: Vec<f64>,
+	peak_ages: Vec<f64>
+}
+
+impl Analysis {
+	fn new(parameters: Parameters, ids: AnalyzerIds, shared: Arc<Shared>) -> Analysis {
+		let mut analysis = Analysis {
+			parameters,
+			ids,
+			shared,
+			fft: RealFft::new(MIN_FFT_SIZE),
+			window: Window::Hann,
+			coefficients: Vec::new(),
+			history: Vec::new(),
+			position: 0,
+			filled: 0,
+			since_transform: 0,
+			frame: Vec::new(),
+			spectrum: Vec::new(),
+			average: Vec::new(),
+			sums: Vec::new(),
+			peaks: Vec::new(),
+			peak_ages: Vec::new()
+		};
+		analysis.configure(MIN_FFT_SIZE, Window::Hann);
+		analysis
+	}
+
+	/// Starts over with a new size or window
+	fn configure(&mut self, size: usize, window: Window) {
+		let bins = size / 2 + 1;
+
+		self.fft = RealFft::new(size);
+		self.window = window;
+		self.coefficients = window.coefficients(size);
+		self.history = vec![0.0; size];
+		self.position = 0;
+		self.filled = 0;
+		self.since_transform = 0;
+		self.frame = vec![0.0; size];
+		self.spectrum = vec![Complex::ZERO; bins];
+		self.average = vec![0.0; bins];
+		self.sums = vec![0.0; 2 * bins];
+		self.peaks = vec![0.0; bins];
+		self.peak_ages = vec![0.0; bins];
+		self.shared.fft_size.store(size, Ordering::Relaxed);
+	}
+
+	fn push(&mut self, samples: &[f32]) {
+		let parameters = &self.parameters;
+		let ids = self.ids;
+		let size = MIN_FFT_SIZE << parameters.get(ids.fft_size).round() as usize;
+		let window = Window::from_index(parameters.get(ids.window));
+		let overlap = parameters.get(ids.overlap) / 100.0;
+		if size != self.fft.size() || window != self.window {
+			self.configure(size, window);
+		}
+
+		let hop = ((size as f64 * (1.0 - overlap)).round() as usize).max(1);
+
+		for sample in samples {
+			self.history[self.position] = *sample as f64;
+			self.position = (self.position + 1) % size;
+			self.filled = (self.filled + 1).min(size);
+			self.since_transform += 1;
+
+			if self.filled == size && self.since_transform >= hop {
+				self.since_transform = 0;
+				self.transform(hop);
+			}
+		}
+	}
+
+	fn transform(&mut self, hop: usize) {
+		let size = self.fft.size();
+		let parameters = &self.parameters;
+		let ids = self.ids;
+		let shared = &self.shared;
+		let sample_rate = shared.sample_rate.load();
+
+		// The oldest sample is at the write position
+		for (index, value) in self.frame.iter_mut().enumerate() {
+			*value = self.history[(self.position + index) % size] * self.coefficients[index];
+		}
+		self.fft.forward(&self.frame, &mut self.spectrum);
+
+		// Power of a sine's amplitude: twice the magnitude over the window's sum
+		let gain = 2.0 / self.coefficients.iter().sum::<f64>();
+		let interval = hop as f64 / sample_rate;
+		let averaging = parameters.get(ids.averaging);
+		let weight = if averaging > 0.0 { 1.0 - (-interval / averaging).exp() } else { 1.0 };
+		let last = self.spectrum.len() - 1;
+
+		for (bin, (value, average)) in self.spectrum.iter().zip(self.average.iter_mut()).enumerate() {
+			let scale = if bin == 0 || bin == last { 0.5 * gain } else { gain };
+			let power = value.norm_sqr() * scale * scale;
+			*average += (power - *average) * weight;
+		}
+
+		// Fractional octave smoothing averages the power over bins within a band around each
+		// bin. The band sums come from a tree of partial sums, which unlike differences of
+		// running sums keeps quiet bins next to loud ones.
+		let bins = self.average.len();
+		self.sums[bins..].copy_from_slice(&self.average);
+		for node in (1..bins).rev() {
+			self.sums[node] = self.sums[2 * node] + self.sums[2 * node + 1];
+		}
+		let fraction = SMOOTHING_FRACTIONS[(parameters.get(ids.smoothing).round() as usize).min(SMOOTHING_FRACTIONS.len() - 1)];
+		let half_band = if fraction > 0.0 { 2.0f64.powf(0.5 / fraction) } else { 1.0 };
+
+		if shared.reset_peaks.swap(false, Ordering::Relaxed) {
+			self.peaks.fill(0.0);
+		}
+		let hold = parameters.get(ids.peak_hold);
+
+		for bin in 0..bins {
+			let low = ((bin as f64 / half_band).ceil() as usize).min(bin);
+			let high = ((bin as f64 * half_band).floor() as usize).clamp(bin, last);
+			let power = band_sum(&self.sums, low, high + 1) / (high + 1 - low) as f64;
+
+			if power >= self.peaks[bin] {
+				self.peaks[bin] = power;
+				self.peak_ages[bin] = 0.0;
+			} else {
+				self.peak_ages[bin] += interval;
+				if self.peak_ages[bin] > hold {
+					self.peaks[bin] = power;
+				}
+			}
+
+			shared.magnitudes[bin].store((10.0 * power.log10()) as f32);
+			shared.peaks[bin].store((10.0 * self.peaks[bin].log10()) as f32);
+		}
+		shared.spectra.fetch_add(1, Ordering::Relaxed);
+	}
+}
+
+/// Spectrum of one channel passing through, which it leaves unchanged. The callback only
+/// taps the channel. The tap's thread windows the samples, runs the overlapping FFTs,
+/// averages the power and publishes the spectra. Changes of the FFT size or window restart
+/// the averages.
+pub struct SpectrumAnalyzer<S: Sample> {
+	control: SpectrumControl,
+	tap: ChannelTap,
+	_sample: std::marker::PhantomData<S>
+}
+
+impl<S: Sample> SpectrumAnalyzer<S> {
+	/// Starts the analysis thread, which ends when the analyzer is dropped
+	pub fn new(sample_rate: f64) -> SpectrumAnalyzer<S> {
+		let fixed = |info: ParameterInfo| info.with_smoothing(Smoothing::None, 0.0);
+
+		let mut registry = ParameterRegistry::new();
+		let ids = AnalyzerIds {
+			fft_size: registry.add(fixed(ParameterInfo::new("FFT Size", 0.0, (FFT_SIZES - 1) as f64, 2.0, Unit::None))),
+			overlap: registry.add(fixed(ParameterInfo::new("Overlap", 0.0, 90.0, 75.0, Unit::Percent))),
+			window: registry.add(fixed(ParameterInfo::new("Window", 0.0, (Window::ALL.len() - 1) as f64, Window::Hann.index() as f64, Unit::None))),
+			averaging: registry.add(fixed(ParameterInfo::new("Averaging", 0.0, 10.0, 0.5, Unit::Seconds))),
+			smoothing: registry.add(fixed(ParameterInfo::new("Smoothing", 0.0, (SMOOTHING_FRACTIONS.len() - 1) as f64, 0.0, Unit::None))),
+			peak_hold: registry.add(fixed(ParameterInfo::new("Peak Hold", 0.0, 60.0, 2.0, Unit::Seconds)))
+		};
+
+		let bins = MAX_FFT_SIZE / 2 + 1;
+		let parameters = registry.build();
+		let shared = Arc::new(Shared {
+			sample_rate: AtomicF64::new(sample_rate),
+			fft_size: AtomicUsize::new(MIN_FFT_SIZE),
+			magnitudes: (0..bins).map(|_| AtomicF32::new(f32::NEG_INFINITY)).collect(),
+			peaks: (0..bins).map(|_| AtomicF32::new(f32::NEG_INFINITY)).collect(),
+			spectra: AtomicU64::new(0),
+			reset_peaks: AtomicBool::new(false)
+		});
+
+		let mut analysis = Analysis::new(parameters.clone(), ids, shared.clone());
+		let tap = ChannelTap::new("lobster spectrum", move |samples| analysis.push(samples));
+
+		SpectrumAnalyzer {
+			control: SpectrumControl {
+				parameters,
+				shared,
+				tap: tap.control()
+			},
+			tap,
+			_sample: std::marker::PhantomData
+		}
+	}
+
+	pub fn control(&self) -> SpectrumControl {
+		self.control.clone()
+	}
+}
+
+impl<S: Sample> Processor<S> for SpectrumAnalyzer<S> {
+	fn process(&mut self, input: &AudioBlock<S>, output: &mut AudioBlockMut<S>) {
+		pass_through(input, output);
+		self.tap.process(input);
+	}
+
+	fn prepare(&mut self, sample_rate: f64, _max_block_size: usize) {
+		self.control.shared.sample_rate.store(sample_rate);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::asio_core::random::Random;
+
+	const SAMPLE_RATE: f64 = 48000.0;
+
+	/// An analysis fed directly rather than through the tap's thread, publishing to `control`.
+	/// No averaging, so each spectrum shows only its own frame.
+	fn analysis() -> (SpectrumControl, Analysis) {
+		let control = SpectrumAnalyzer::<f64>::new(SAMPLE_RATE).control();
+		let parameters = control.parameters.clone();
+		let find = |name: &str| parameters.find(name).unwrap();
+		let ids = AnalyzerIds {
+			fft_size: find("FFT Size"),
+			overlap: find("Overlap"),
+			window: find("Window"),
+			averaging: find("Averaging"),
+			smoothing: find("Smoothing"),
+			peak_hold: find("Peak Hold")
+		};
+		parameters.set(ids.averaging, 0.0);
+
+		let analysis = Analysis::new(parameters, ids, control.shared.clone());
+		(control, analysis)
+	}
+
+	fn set(control: &SpectrumControl, name: &str, value: f64) {
+		control.parameters.set(control.parameters.find(name).unwrap(), value);
+	}
+
+	/// `frames` of a sine at `bin` of a 4096 point FFT, from `start` on
+	fn sine(bin: f64, amplitude: f64, start: usize, frames: usize) -> Vec<f32> {
+		(start..start + frames).map(|frame| (amplitude * (2.0 * PI * bin * frame as f64 / 4096.0).sin()) as f32).collect()
+	}
+
+	#[test]
+	fn full_scale_sine_reads_0_db() {
+		for window in Window::ALL {
+			let (control, mut analysis) = analysis();
+			set(&control, "Window", window.index() as f64);
+			analysis.push(&sine(100.0, 1.0, 0, 8192));
+
+			assert_eq!(control.bin_frequency(100), 100.0 * SAMPLE_RATE / 4096.0);
+			let magnitude = control.magnitudes()[100];
+			assert!(magnitude.abs() < 0.01, "{:?} reads {} dB", window, magnitude);
+		}
+
+		// Half way between two bins, the flat top window still reads the level
+		let (control, mut analysis) = analysis();
+		set(&control, "Window", Window::FlatTop.index() as f64);
+		analysis.push(&sine(100.5, 1.0, 0, 8192));
+		let magnitude = control.magnitudes()[100].max(control.magnitudes()[101]);
+		assert!(magnitude.abs() < 0.02, "flat top reads {} dB", magnitude);
+	}
+
+	#[test]
+	fn band_sums_match_direct_sums() {
+		let mut random = Random::new(49);
+		for leaves in [1, 2, 5, 13, 16] {
+			let values: Vec<f64> = (0..leaves).map(|_| random.next_f64()).collect();
+			let mut sums = vec![0.0; 2 * leaves];
+			sums[leaves..].copy_from_slice(&values);
+			for node in (1..leaves).rev() {
+				sums[node] = sums[2 * node] + sums[2 * node + 1];
+			}
+
+			for start in 0..=leaves {
+				for end in start..=leaves {
+					let direct: f64 = values[start..end].iter().sum();
+					assert!((band_sum(&sums, start, end) - direct).abs() < 1e-12, "{}..{} of {}", start, end, leaves);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn smoothing_averages_over_fractional_octaves() {
+		// A Hann windowed sine centered on a bin has 1/4 of its power in either neighbour
+		let (control, mut analysis) = analysis();
+		set(&control, "Smoothing", 2.0);
+		analysis.push(&sine(100.0, 1.0, 0, 8192));
+		let magnitudes = control.magnitudes();
+
+		// Third octave bands: bins 90 to 112 around bin 100, 80 to 99 around bin 89, 72 to 89
+		// around bin 80
+		assert!((magnitudes[100] as f64 - 10.0 * (1.5f64 / 23.0).log10()).abs() < 0.01);
+		assert!((magnitudes[89] as f64 - 10.0 * (0.25f64 / 20.0).log10()).abs() < 0.01);
+		assert!(magnitudes[80] < -100.0);
+
+		// Without smoothing each bin stands alone
+		set(&control, "Smoothing", 0.0);
+		analysis.push(&sine(100.0, 1.0, 8192, 4096));
+		let magnitudes = control.magnitudes();
+		assert!(magnitudes[100].abs() < 0.01);
+		assert!((magnitudes[101] as f64 - 10.0 * 0.25f64.log10()).abs() < 0.01);
+		assert!(magnitudes[102] < -100.0);
+	}
+
+	#[test]
+	fn hop_follows_the_overlap() {
+		// 4096 point FFTs: hops of 4096, 1024 and 410 frames
+		for (overlap, hop) in [(0.0, 4096), (75.0, 1024), (90.0, 410)] {
+			let (control, mut analysis) = analysis();
+			set(&control, "Overlap", overlap);
+
+			analysis.push(&vec![0.0; 4095]);
+			assert_eq!(control.spectra(), 0);
+			analysis.push(&[0.0]);
+			assert_eq!(control.spectra(), 1);
+
+			// Pushed in pieces that do not line up with the hop
+			for _ in 0..10 * hop / 7 {
+				analysis.push(&[0.0; 7]);
+			}
+			analysis.push(&vec![0.0; 10 * hop % 7]);
+			assert_eq!(control.spectra(), 11, "{} % overlap", overlap);
+		}
+	}
+
+	#[test]
+	fn peaks_hold_then_fall_to_the_level() {
+		// Hops of 1024 frames, 21.3 ms
+		let (control, mut analysis) = analysis();
+		set(&control, "Peak Hold", 0.5);
+		analysis.push(&sine(100.0, 1.0, 0, 8192));
+
+		// 0.4 s later the peak is held, although the level dropped by 40 dB
+		analysis.push(&sine(100.0, 0.01, 8192, 19200));
+		assert!(control.magnitudes()[100] < -39.9);
+		assert!(control.peaks()[100].abs() < 0.01);
+
+		// After the hold time the peak falls to the level
+		analysis.push(&sine(100.0, 0.01, 8192 + 19200, 9600));
+		assert!((control.peaks()[100] + 40.0).abs() < 0.01);
+		assert_eq!(control.peaks()[100], control.magnitudes()[100]);
+	}
+
+	#[test]
+	fn reset_peaks_starts_over_from_the_level() {
+		let (control, mut analysis) = analysis();
+		set(&control, "Peak Hold", 10.0);
+		analysis.push(&sine(100.0, 1.0, 0, 8192));
+		analysis.push(&sine(100.0, 0.01, 8192, 8192));
+		assert!(control.peaks()[100].abs() < 0.01);
+
+		// The request takes effect with the next spectrum
+		control.reset_peaks();
+		assert!(control.peaks()[100].abs() < 0.01);
+		analysis.push(&sine(100.0, 0.01, 16384, 1024));
+		assert!((control.peaks()[100] + 40.0).abs() < 0.01);
+		assert_eq!(control.peaks(), control.magnitudes());
+	}
+}
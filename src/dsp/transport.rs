@@ -0,0 +1,124 @@
+use crate::dsp::parameters::AtomicF64;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+
+pub const DEFAULT_TEMPO: f64 = 120.0;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NoteValue {
+	Whole,
+	Half,
+	Quarter,		// one beat
+	Eighth,
+	Sixteenth,
+	ThirtySecond
+}
+
+impl NoteValue {
+	pub const ALL: [NoteValue; 6] = [
+		NoteValue::Whole,
+		NoteValue::Half,
+		NoteValue::Quarter,
+		NoteValue::Eighth,
+		NoteValue::Sixteenth,
+		NoteValue::ThirtySecond
+	];
+
+	/// Note value for a parameter value, rounded and clamped to the valid indices
+	pub fn from_index(index: f64) -> NoteValue {
+		NoteValue::ALL[(index.round().max(0.0) as usize).min(NoteValue::ALL.len() - 1)]
+	}
+
+	pub fn beats(&self) -> f64 {
+		match self {
+			NoteValue::Whole => 4.0,
+			NoteValue::Half => 2.0,
+			NoteValue::Quarter => 1.0,
+			NoteValue::Eighth => 0.5,
+			NoteValue::Sixteenth => 0.25,
+			NoteValue::ThirtySecond => 0.125
+		}
+	}
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Feel {
+	Straight,
+	Dotted,			// one and a half times as long
+	Triplet			// three in the time of two
+}
+
+impl Feel {
+	pub const ALL: [Feel; 3] = [Feel::Straight, Feel::Dotted, Feel::Triplet];
+
+	pub fn from_index(index: f64) -> Feel {
+		Feel::ALL[(index.round().max(0.0) as usize).min(Feel::ALL.len() - 1)]
+	}
+
+	pub fn factor(&self) -> f64 {
+		match self {
+			Feel::Straight => 1.0,
+			Feel::Dotted => 1.5,
+			Feel::Triplet => 2.0 / 3.0
+		}
+	}
+}
+
+/// Tempo set by the user and the driver's sample position, shared between the device and
+/// tempo synced processors. The device publishes the position of every buffer switch, so
+/// beat 0 is where the driver started counting samples.
+#[derive(Clone)]
+pub struct Transport {
+	position: Arc<AtomicI64>,
+	tempo: Arc<AtomicF64>
+}
+
+impl Transport {
+	pub fn new(tempo: f64) -> Transport {
+		Transport {
+			position: Arc::new(AtomicI64::new(0)),
+			tempo: Arc::new(AtomicF64::new(tempo))
+		}
+	}
+
+	/// Beats per minute
+	pub fn set_tempo(&self, tempo: f64) {
+		if tempo <= 0.0 {
+			panic!("Invalid tempo {}", tempo);
+		}
+		self.tempo.store(tempo);
+	}
+
+	pub fn tempo(&self) -> f64 {
+		self.tempo.load()
+	}
+
+	/// Sample position of the first frame of the current buffer
+	pub fn set_position(&self, position: i64) {
+		self.position.store(position, Ordering::Relaxed);
+	}
+
+	pub fn position(&self) -> i64 {
+		self.position.load(Ordering::Relaxed)
+	}
+
+	pub fn samples_per_beat(&self, sample_rate: f64) -> f64 {
+		60.0 / self.tempo() * sample_rate
+	}
+
+	/// Length of a note at the current tempo
+	pub fn note_samples(&self, note: NoteValue, feel: Feel, sample_rate: f64) -> f64 {
+		note.beats() * feel.factor() * self.samples_per_beat(sample_rate)
+	}
+
+	/// Beats since the driver's sample position 0 at `position`
+	pub fn beat_at(&self, position: i64, sample_rate: f64) -> f64 {
+		position as f64 / self.samples_per_beat(sample_rate)
+	}
+}
+
+impl Default for Transport {
+	fn default() -> Transport {
+		Transport::new(DEFAULT_TEMPO)
+	}
+}
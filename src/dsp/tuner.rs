@@ -0,0 +1,396 @@
+use crate::dsp::audio_block::{AudioBlock, AudioBlockMut};
+use crate::dsp::channel_tap::{ChannelTap, TapControl};
+use crate::dsp::fft::{Complex, RealFft};
+use crate::dsp::parameters::{AtomicF64, ParameterId, ParameterInfo, ParameterRegistry, Parameters, Smoothing, Unit};
+use crate::dsp::processor::{pass_through, Processor};
+use crate::dsp::sample::Sample;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Range of detected frequencies, from below the low B of a five string bass to beyond
+/// the highest fret of a guitar
+const MIN_FREQUENCY: f64 = 27.5;
+const MAX_FREQUENCY: f64 = 2000.0;
+
+/// Time between detections
+const INTERVAL: f64 = 0.02;
+
+/// Dip of the normalized difference that counts as a period, after de Cheveigne and Kawahara
+const THRESHOLD: f64 = 0.15;
+
+/// Above this the signal has no pitch
+const UNVOICED: f64 = 0.5;
+
+/// Signals below this RMS level in dBFS have no pitch
+const SILENCE: f64 = -60.0;
+
+const NOTE_NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
+/// Name of a MIDI note number with its octave, e.g. "E2" for 40
+pub fn note_name(note: i32) -> String {
+	format!("{}{}", NOTE_NAMES[note.rem_euclid(12) as usize], note.div_euclid(12) - 1)
+}
+
+/// Pitch of the analyzed channel
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PitchReading {
+	pub frequency: f64,
+	pub note: i32,				// nearest MIDI note number, 69 is the reference A4
+	pub cents: f64,				// -50 to 50 from the note
+	pub confidence: f64			// 0.0 to 1.0
+}
+
+impl PitchReading {
+	pub fn new(frequency: f64, confidence: f64, reference: f64) -> PitchReading {
+		let semitones = 69.0 + 12.0 * (frequency / reference).log2();
+		let note = semitones.round();
+
+		PitchReading {
+			frequency,
+			note: note as i32,
+			cents: 100.0 * (semitones - note),
+			confidence
+		}
+	}
+
+	pub fn note_name(&self) -> String {
+		note_name(self.note)
+	}
+}
+
+struct Shared {
+	sample_rate: AtomicF64,
+	frequency: AtomicF64,
+	confidence: AtomicF64,
+	detections: AtomicU64
+}
+
+/// Settings and readings of a `Tuner`, for the UI
+#[derive(Clone)]
+pub struct TunerControl {
+	parameters: Parameters,
+	reference: ParameterId,
+	shared: Arc<Shared>,
+	tap: TapControl
+}
+
+impl TunerControl {
+	/// The parameter "Reference", the frequency of A4
+	pub fn parameters(&self) -> &Parameters {
+		&self.parameters
+	}
+
+	/// Channel of the processor's input to detect the pitch of
+	pub fn set_channel(&self, channel: usize) {
+		self.tap.set_channel(channel);
+	}
+
+	pub fn channel(&self) -> usize {
+		self.tap.channel()
+	}
+
+	/// Latest pitch, none for silence or signals without a pitch
+	pub fn reading(&self) -> Option<PitchReading> {
+		let frequency = self.shared.frequency.load();
+		if frequency <= 0.0 {
+			return None;
+		}
+
+		Some(PitchReading::new(frequency, self.shared.confidence.load(), self.parameters.get(self.reference)))
+	}
+
+	/// Number of detections run so far
+	pub fn detections(&self) -> u64 {
+		self.shared.detections.load(Ordering::Relaxed)
+	}
+
+	/// Chunks the callback dropped because the detection fell behind
+	pub fn dropped_chunks(&self) -> u64 {
+		self.tap.dropped_chunks()
+	}
+}
+
+/// State of the detection thread
+struct Detection {
+	shared: Arc<Shared>,
+	sample_rate: f64,
+	window: usize,
+	min_lag: usize,
+	max_lag: usize,
+	interval: usize,
+	history: Vec<f64>,
+	position: usize,
+	filled: usize,
+	since_detection: usize,
+	fft: RealFft,
+	frame: Vec<f64>,
+	spectrum: Vec<Complex>,
+	lagged_spectrum: Vec<Complex>,
+	correlation: Vec<f64>,
+	difference: Vec<f64>,
+	normalized: Vec<f64>
+}
+
+impl Detection {
+	fn new(shared: Arc<Shared>) -> Detection {
+		let mut detection = Detection {
+			shared,
+			sample_rate: 0.0,
+			window: 0,
+			min_lag: 0,
+			max_lag: 0,
+			interval: 0,
+			history: Vec::new(),
+			position: 0,
+			filled: 0,
+			since_detection: 0,
+			fft: RealFft::new(2),
+			frame: Vec::new(),
+			spectrum: Vec::new(),
+			lagged_spectrum: Vec::new(),
+			correlation: Vec::new(),
+			difference: Vec::new(),
+			normalized: Vec::new()
+		};
+		detection.configure();
+		detection
+	}
+
+	/// Sizes the buffers for the sample rate. The difference is summed over a window of at
+	/// least the longest period, at lags up to that period.
+	fn configure(&mut self) {
+		self.sample_rate = self.shared.sample_rate.load();
+		self.max_lag = (self.sample_rate / MIN_FREQUENCY).ceil() as usize;
+		self.min_lag = ((self.sample_rate / MAX_FREQUENCY).floor() as usize).max(2);
+		self.window = self.max_lag.next_power_of_two();
+		self.interval = ((INTERVAL * self.sample_rate).round() as usize).max(1);
+
+		let size = (self.window + self.max_lag + 1).next_power_of_two();
+		self.history = vec![0.0; size];
+		self.position = 0;
+		self.filled = 0;
+		self.since_detection = 0;
+		self.fft = RealFft::new(size);
+		self.frame = vec![0.0; size];
+		self.spectrum = vec![Complex::ZERO; size / 2 + 1];
+		self.lagged_spectrum = vec![Complex::ZERO; size / 2 + 1];
+		self.correlation = vec![0.0; size];
+		self.difference = vec![0.0; self.max_lag + 2];
+		self.normalized = vec![0.0; self.max_lag + 2];
+	}
+
+	fn push(&mut self, samples: &[f32]) {
+		if self.shared.sample_rate.load() != self.sample_rate {
+			self.configure();
+		}
+
+		let size = self.history.len();
+		for sample in samples {
+			self.history[self.position] = *sample as f64;
+			self.position = (self.position + 1) % size;
+			self.filled = (self.filled + 1).min(size);
+			self.since_detection += 1;
+
+			if self.filled == size && self.since_detection >= self.interval {
+				self.since_detection = 0;
+				let (frequency, confidence) = self.detect().unwrap_or((0.0, 0.0));
+				self.shared.frequency.store(frequency);
+				self.shared.confidence.store(confidence);
+				self.shared.detections.fetch_add(1, Ordering::Relaxed);
+			}
+		}
+	}
+
+	/// YIN on the latest samples, the frequency and confidence of the period found
+	fn detect(&mut self) -> Option<(f64, f64)> {
+		let size = self.history.len();
+		let length = self.window + self.max_lag + 1;
+		let sample = |history: &[f64], index: usize| history[(self.position + size - length + index) % size];
+
+		// Correlation of the window with the lagged samples, r(lag) = sum of x[j] x[j + lag]
+		for (index, value) in self.frame.iter_mut().enumerate() {
+			*value = if index < self.window { sample(&self.history, index) } else { 0.0 };
+		}
+		self.fft.forward(&self.frame, &mut self.spectrum);
+		for (index, value) in self.frame.iter_mut().enumerate() {
+			*value = if index < length { sample(&self.history, index) } else { 0.0 };
+		}
+		self.fft.forward(&self.frame, &mut self.lagged_spectrum);
+		for (value, lagged) in self.spectrum.iter_mut().zip(self.lagged_spectrum.iter()) {
+			*value = value.conj() * *lagged;
+		}
+		self.fft.inverse(&self.spectrum, &mut self.correlation);
+
+		let energy: f64 = (0..self.window).map(|index| sample(&self.history, index).powi(2)).sum();
+		if energy / (self.window as f64) < 10.0f64.powf(SILENCE / 10.0) {
+			return None;
+		}
+
+		// Cumulative mean normalized difference, d(lag) = e(0) + e(lag) - 2 r(lag)
+		let mut lagged_energy = energy;
+		let mut sum = 0.0;
+		self.difference[0] = 0.0;
+		self.normalized[0] = 1.0;
+		for lag in 1..=self.max_lag + 1 {
+			let leaving = sample(&self.history, lag - 1);
+			let entering = sample(&self.history, lag + self.window - 1);
+			lagged_energy += entering * entering - leaving * leaving;
+
+			let difference = (energy + lagged_energy - 2.0 * self.correlation[lag]).max(0.0);
+			sum += difference;
+			self.difference[lag] = difference;
+			self.normalized[lag] = if sum > 0.0 { difference * lag as f64 / sum } else { 1.0 };
+		}
+
+		// The first dip below the threshold, followed to its minimum, else the deepest dip
+		let normalized = &self.normalized;
+		let lag = match (self.min_lag..=self.max_lag).find(|lag| normalized[*lag] < THRESHOLD) {
+			Some(mut lag) => {
+				while lag < self.max_lag && normalized[lag + 1] < normalized[lag] {
+					lag += 1;
+				}
+				lag
+			},
+			None => (self.min_lag..=self.max_lag).min_by(|a, b| normalized[*a].total_cmp(&normalized[*b])).unwrap()
+		};
+		if normalized[lag] >= UNVOICED {
+			return None;
+		}
+
+		// Parabola through the minimum of the plain difference and its neighbours, which the
+		// normalization would bias at short lags
+		let (before, at, after) = (self.difference[lag - 1], self.difference[lag], self.difference[lag + 1]);
+		let curvature = before - 2.0 * at + after;
+		let offset = if curvature > 0.0 { (0.5 * (before - after) / curvature).clamp(-0.5, 0.5) } else { 0.0 };
+
+		Some((self.sample_rate / (lag as f64 + offset), (1.0 - normalized[lag]).clamp(0.0, 1.0)))
+	}
+}
+
+/// Tuner for one channel passing through, which it leaves unchanged. The callback only
+/// taps the channel, whose thread detects the pitch with YIN every 20 ms and publishes it.
+pub struct Tuner<S: Sample> {
+	control: TunerControl,
+	tap: ChannelTap,
+	_sample: std::marker::PhantomData<S>
+}
+
+impl<S: Sample> Tuner<S> {
+	/// Starts the detection thread, which ends when the tuner is dropped
+	pub fn new(sample_rate: f64) -> Tuner<S> {
+		let mut registry = ParameterRegistry::new();
+		let reference = registry.add(ParameterInfo::new("Reference", 415.0, 466.0, 440.0, Unit::Hertz).with_smoothing(Smoothing::None, 0.0));
+
+		let shared = Arc::new(Shared {
+			sample_rate: AtomicF64::new(sample_rate),
+			frequency: AtomicF64::new(0.0),
+			confidence: AtomicF64::new(0.0),
+			detections: AtomicU64::new(0)
+		});
+
+		let mut detection = Detection::new(shared.clone());
+		let tap = ChannelTap::new("lobster tuner", move |samples| detection.push(samples));
+
+		Tuner {
+			control: TunerControl {
+				parameters: registry.build(),
+				reference,
+				shared,
+				tap: tap.control()
+			},
+			tap,
+			_sample: std::marker::PhantomData
+		}
+	}
+
+	pub fn control(&self) -> TunerControl {
+		self.control.clone()
+	}
+}
+
+impl<S: Sample> Processor<S> for Tuner<S> {
+	fn process(&mut self, input: &AudioBlock<S>, output: &mut AudioBlockMut<S>) {
+		pass_through(input, output);
+		self.tap.process(input);
+	}
+
+	fn prepare(&mut self, sample_rate: f64, _max_block_size: usize) {
+		self.control.shared.sample_rate.store(sample_rate);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::asio_core::random::Random;
+	use std::f64::consts::PI;
+
+	const SAMPLE_RATE: f64 = 48000.0;
+
+	/// Runs the detection on the samples, the reading of the latest detection
+	fn detect(samples: &[f64]) -> Option<PitchReading> {
+		let shared = Arc::new(Shared {
+			sample_rate: AtomicF64::new(SAMPLE_RATE),
+			frequency: AtomicF64::new(0.0),
+			confidence: AtomicF64::new(0.0),
+			detections: AtomicU64::new(0)
+		});
+		let mut detection = Detection::new(shared.clone());
+		let samples: Vec<f32> = samples.iter().map(|sample| *sample as f32).collect();
+		detection.push(&samples);
+
+		assert!(shared.detections.load(Ordering::Relaxed) > 0);
+		let frequency = shared.frequency.load();
+		if frequency <= 0.0 {
+			return None;
+		}
+		Some(PitchReading::new(frequency, shared.confidence.load(), 440.0))
+	}
+
+	/// A fifth of a second of harmonics with the given amplitudes, the first at `frequency`
+	fn tone(frequency: f64, harmonics: &[f64]) -> Vec<f64> {
+		(0..(0.2 * SAMPLE_RATE) as usize).map(|frame| {
+			harmonics.iter().enumerate().map(|(index, amplitude)| {
+				let harmonic = (index + 1) as f64;
+				amplitude * (2.0 * PI * harmonic * frequency * frame as f64 / SAMPLE_RATE + harmonic).sin()
+			}).sum()
+		}).collect()
+	}
+
+	#[test]
+	fn note_names() {
+		assert_eq!(note_name(23), "B0");
+		assert_eq!(note_name(40), "E2");
+		assert_eq!(note_name(61), "C#4");
+		assert_eq!(note_name(69), "A4");
+		assert_eq!(note_name(88), "E6");
+		assert_eq!(note_name(-1), "B-2");
+	}
+
+	#[test]
+	fn detects_notes_from_b0_to_e6() {
+		let spectra: [&[f64]; 3] = [&[0.5], &[0.5, 0.25, 0.17, 0.12, 0.1, 0.08], &[0.1, 0.4, 0.3, 0.2]];
+
+		for note in 23..=88 {
+			// Detuned by up to 40 cents either way, differently for each note
+			let offset = ((note * 37) % 81 - 40) as f64;
+			let frequency = 440.0 * 2.0f64.powf((note as f64 + offset / 100.0 - 69.0) / 12.0);
+
+			for harmonics in spectra.iter() {
+				let reading = detect(&tone(frequency, harmonics)).unwrap_or_else(|| panic!("no pitch at {} Hz", frequency));
+				assert_eq!(reading.note_name(), note_name(note), "{} Hz with harmonics {:?}", frequency, harmonics);
+				assert!((reading.cents - offset).abs() <= 1.0, "{} cents instead of {} at {} Hz with harmonics {:?}", reading.cents, offset, frequency, harmonics);
+			}
+		}
+	}
+
+	#[test]
+	fn silence_and_noise_have_no_pitch() {
+		assert_eq!(detect(&[0.0; 9600]), None);
+		assert_eq!(detect(&tone(440.0, &[0.0005])), None);
+
+		let mut random = Random::new(3);
+		let noise: Vec<f64> = (0..9600).map(|_| 0.5 * random.next_triangular()).collect();
+		assert_eq!(detect(&noise), None);
+	}
+}
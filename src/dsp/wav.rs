@@ -0,0 +1,281 @@
+use std::fmt;
+use std::path::Path;
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_FLOAT: u16 = 3;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+#[derive(Debug)]
+pub enum WavError {
+	Io(std::io::Error),
+	NotWave,						// no RIFF WAVE header
+	MissingChunk(&'static str),
+	Truncated,						// a chunk extends beyond the end of the file
+	Unsupported(u16, u16)			// format tag and bits per sample
+}
+
+impl fmt::Display for WavError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			WavError::Io(error) => write!(f, "{}", error),
+			WavError::NotWave => write!(f, "Not a WAVE file"),
+			WavError::MissingChunk(chunk) => write!(f, "WAVE file has no '{}' chunk", chunk),
+			WavError::Truncated => write!(f, "WAVE file is truncated"),
+			WavError::Unsupported(format, bits) => write!(f, "Unsupported WAVE format {} with {} bits", format, bits)
+		}
+	}
+}
+
+impl From<std::io::Error> for WavError {
+	fn from(error: std::io::Error) -> WavError {
+		WavError::Io(error)
+	}
+}
+
+/// Audio of a WAVE file as planar samples between -1.0 and 1.0. Reads 8, 16, 24 and 32 bit
+/// integer and 32 and 64 bit float files, also with the extensible header.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Wav {
+	pub sample_rate: f64,
+	pub channels: Vec<Vec<f64>>
+}
+
+impl Wav {
+	pub fn new(sample_rate: f64, channels: Vec<Vec<f64>>) -> Wav {
+		Wav {
+			sample_rate,
+			channels
+		}
+	}
+
+	pub fn read(path: impl AsRef<Path>) -> Result<Wav, WavError> {
+		Wav::parse(&std::fs::read(path)?)
+	}
+
+	pub fn parse(bytes: &[u8]) -> Result<Wav, WavError> {
+		if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+			return Err(WavError::NotWave);
+		}
+
+		let mut format = None;
+		let mut data = None;
+		let mut position = 12;
+
+		while position + 8 <= bytes.len() {
+			let id = &bytes[position..position + 4];
+			let size = u32::from_le_bytes(bytes[position + 4..position + 8].try_into().unwrap()) as usize;
+			let start = position + 8;
+			let end = start.checked_add(size).ok_or(WavError::Truncated)?;
+
+			if id == b"data" {
+				// Streaming writers leave the size open, the data then runs to the end
+				data = Some(&bytes[start..end.min(bytes.len())]);
+			} else if end > bytes.len() {
+				return Err(WavError::Truncated);
+			} else if id == b"fmt " {
+				format = Some(&bytes[start..end]);
+			}
+
+			// Chunks are padded to an even size
+			position = end + (size & 1);
+		}
+
+		let format = format.ok_or(WavError::MissingChunk("fmt "))?;
+		let data = data.ok_or(WavError::MissingChunk("data"))?;
+		if format.len() < 16 {
+			return Err(WavError::Truncated);
+		}
+
+		let read_u16 = |offset: usize| u16::from_le_bytes([format[offset], format[offset + 1]]);
+		let mut tag = read_u16(0);
+		let num_channels = read_u16(2) as usize;
+		let sample_rate = u32::from_le_bytes(format[4..8].try_into().unwrap()) as f64;
+		let bits = read_u16(14);
+
+		if tag == FORMAT_EXTENSIBLE {
+			// The sub format GUID starts with the actual format tag
+			if format.len() < 26 {
+				return Err(WavError::Truncated);
+			}
+			tag = read_u16(24);
+		}
+
+		if num_channels == 0 {
+			return Err(WavError::Unsupported(tag, bits));
+		}
+
+		// Samples are stored in containers of whole bytes, e.g. 20 bits in 3 bytes
+		let bytes_per_sample = read_u16(12) as usize / num_channels;
+		let decode: fn(&[u8]) -> f64 = match (tag, bytes_per_sample) {
+			(FORMAT_PCM, 1) => |bytes| (bytes[0] as f64 - 128.0) / 128.0,
+			(FORMAT_PCM, 2) => |bytes| i16::from_le_bytes([bytes[0], bytes[1]]) as f64 / 32768.0,
+			(FORMAT_PCM, 3) => |bytes| (i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8) as f64 / 8388608.0,
+			(FORMAT_PCM, 4) => |bytes| i32::from_le_bytes(bytes.try_into().unwrap()) as f64 / 2147483648.0,
+			(FORMAT_FLOAT, 4) => |bytes| f32::from_le_bytes(bytes.try_into().unwrap()) as f64,
+			(FORMAT_FLOAT, 8) => |bytes| f64::from_le_bytes(bytes.try_into().unwrap()),
+			_ => return Err(WavError::Unsupported(tag, bits))
+		};
+
+		let frame_size = bytes_per_sample * num_channels;
+		let mut channels = vec![Vec::with_capacity(data.len() / frame_size); num_channels];
+		for frame in data.chunks_exact(frame_size) {
+			for (channel, sample) in channels.iter_mut().zip(frame.chunks_exact(bytes_per_sample)) {
+				channel.push(decode(sample));
+			}
+		}
+
+		Ok(Wav {
+			sample_rate,
+			channels
+		})
+	}
+
+	pub fn num_channels(&self) -> usize {
+		self.channels.len()
+	}
+
+	pub fn num_frames(&self) -> usize {
+		self.channels.first().map_or(0, |channel| channel.len())
+	}
+
+	/// Encodes the audio as a 32 bit float file
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let num_channels = self.num_channels() as u16;
+		let data_size = (self.num_frames() * self.num_channels() * 4) as u32;
+		let mut bytes = Vec::with_capacity(data_size as usize + 44);
+
+		bytes.extend_from_slice(b"RIFF");
+		bytes.extend_from_slice(&(36 + data_size).to_le_bytes());
+		bytes.extend_from_slice(b"WAVE");
+		bytes.extend_from_slice(b"fmt ");
+		bytes.extend_from_slice(&16u32.to_le_bytes());
+		bytes.extend_from_slice(&FORMAT_FLOAT.to_le_bytes());
+		bytes.extend_from_slice(&num_channels.to_le_bytes());
+		bytes.extend_from_slice(&(self.sample_rate as u32).to_le_bytes());
+		bytes.extend_from_slice(&(self.sample_rate as u32 * num_channels as u32 * 4).to_le_bytes());
+		bytes.extend_from_slice(&(num_channels * 4).to_le_bytes());
+		bytes.extend_from_slice(&32u16.to_le_bytes());
+		bytes.extend_from_slice(b"data");
+		bytes.extend_from_slice(&data_size.to_le_bytes());
+
+		for frame in 0..self.num_frames() {
+			for channel in self.channels.iter() {
+				bytes.extend_from_slice(&(channel[frame] as f32).to_le_bytes());
+			}
+		}
+		bytes
+	}
+
+	pub fn write(&self, path: impl AsRef<Path>) -> Result<(), WavError> {
+		std::fs::write(path, self.to_bytes())?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// A RIFF WAVE file with the given chunks
+	fn riff(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+		let mut body = b"WAVE".to_vec();
+		for (id, content) in chunks {
+			body.extend_from_slice(*id);
+			body.extend_from_slice(&(content.len() as u32).to_le_bytes());
+			body.extend_from_slice(content);
+			if content.len() % 2 == 1 {
+				body.push(0);
+			}
+		}
+
+		let mut bytes = b"RIFF".to_vec();
+		bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+		bytes.extend_from_slice(&body);
+		bytes
+	}
+
+	/// Format chunk content, `extensible` wraps `tag` into a sub format
+	fn format(tag: u16, num_channels: u16, sample_rate: u32, bits: u16, container: u16, extensible: bool) -> Vec<u8> {
+		let block_align = num_channels * container / 8;
+		let mut bytes = Vec::new();
+
+		bytes.extend_from_slice(&(if extensible { FORMAT_EXTENSIBLE } else { tag }).to_le_bytes());
+		bytes.extend_from_slice(&num_channels.to_le_bytes());
+		bytes.extend_from_slice(&sample_rate.to_le_bytes());
+		bytes.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+		bytes.extend_from_slice(&block_align.to_le_bytes());
+		bytes.extend_from_slice(&container.to_le_bytes());
+		if extensible {
+			bytes.extend_from_slice(&22u16.to_le_bytes());
+			bytes.extend_from_slice(&bits.to_le_bytes());
+			bytes.extend_from_slice(&3u32.to_le_bytes());
+			bytes.extend_from_slice(&tag.to_le_bytes());
+			bytes.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71]);
+		}
+		bytes
+	}
+
+	#[test]
+	fn float_round_trip() {
+		let channels = vec![
+			(0..100).map(|frame| (frame as f64 * 0.1).sin() as f32 as f64).collect(),
+			(0..100).map(|frame| -(frame as f64) / 128.0).collect()
+		];
+		let wav = Wav::new(44100.0, channels);
+		assert_eq!(Wav::parse(&wav.to_bytes()).unwrap(), wav);
+
+		let empty = Wav::new(48000.0, vec![Vec::new()]);
+		assert_eq!(Wav::parse(&empty.to_bytes()).unwrap(), empty);
+	}
+
+	#[test]
+	fn integer_formats() {
+		let cases: [(u16, u16, &[u8], [f64; 2]); 4] = [
+			(8, 8, &[0x80, 0xC0], [0.0, 0.5]),
+			(16, 16, &[0x00, 0x80, 0x00, 0x40], [-1.0, 0.5]),
+			(24, 24, &[0x00, 0x00, 0xC0, 0xFF, 0xFF, 0x7F], [-0.5, 8388607.0 / 8388608.0]),
+			(20, 24, &[0x00, 0x00, 0xC0, 0xFF, 0xFF, 0x7F], [-0.5, 8388607.0 / 8388608.0])
+		];
+
+		for &(bits, container, data, expected) in cases.iter() {
+			for &extensible in [false, true].iter() {
+				let bytes = riff(&[(b"fmt ", &format(FORMAT_PCM, 2, 48000, bits, container, extensible)), (b"data", data)]);
+				let wav = Wav::parse(&bytes).unwrap();
+				assert_eq!(wav.sample_rate, 48000.0);
+				assert_eq!(wav.channels, vec![vec![expected[0]], vec![expected[1]]], "{} bits, extensible {}", bits, extensible);
+			}
+		}
+	}
+
+	#[test]
+	fn extensible_float() {
+		let data: Vec<u8> = [0.25f64, -0.75].iter().flat_map(|value| value.to_le_bytes()).collect();
+		let bytes = riff(&[(b"fmt ", &format(FORMAT_FLOAT, 1, 96000, 64, 64, true)), (b"LIST", b"odd"), (b"data", &data)]);
+		assert_eq!(Wav::parse(&bytes).unwrap(), Wav::new(96000.0, vec![vec![0.25, -0.75]]));
+
+		// An extensible header too short to hold the sub format
+		let short = &format(FORMAT_FLOAT, 1, 96000, 64, 64, true)[..24];
+		let bytes = riff(&[(b"fmt ", short), (b"data", &data)]);
+		assert!(matches!(Wav::parse(&bytes), Err(WavError::Truncated)));
+	}
+
+	#[test]
+	fn truncated_files() {
+		let wav = Wav::new(48000.0, vec![vec![0.5; 10], vec![-0.5; 10]]);
+		let bytes = wav.to_bytes();
+
+		assert!(matches!(Wav::parse(&bytes[..8]), Err(WavError::NotWave)));
+		assert!(matches!(Wav::parse(&bytes[..30]), Err(WavError::Truncated)));
+		assert!(matches!(Wav::parse(&bytes[..36]), Err(WavError::MissingChunk("data"))));
+
+		// Data cut short, as when the writer did not finish, keeps the complete frames
+		let partial = Wav::parse(&bytes[..44 + 8 * 4 + 5]).unwrap();
+		assert_eq!(partial, Wav::new(48000.0, vec![vec![0.5; 4], vec![-0.5; 4]]));
+
+		let data_only = riff(&[(b"data", &[0; 8])]);
+		assert!(matches!(Wav::parse(&data_only), Err(WavError::MissingChunk("fmt "))));
+
+		let unsupported = riff(&[(b"fmt ", &format(FORMAT_FLOAT, 1, 48000, 16, 16, false)), (b"data", &[0; 4])]);
+		assert!(matches!(Wav::parse(&unsupported), Err(WavError::Unsupported(FORMAT_FLOAT, 16))));
+	}
+}
@@ -0,0 +1,179 @@
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle, Thread};
+
+/// Iterations a worker busy-waits for the next job before parking
+const SPIN_ITERATIONS: usize = 20000;
+
+/// Work shared with the pool for one block, `run` is called on every thread with `data`
+#[derive(Copy, Clone)]
+pub struct Job {
+	pub run: unsafe fn(data: *const ()),
+	pub data: *const ()
+}
+
+struct PoolState {
+	run: AtomicUsize,
+	data: AtomicPtr<()>,
+	generation: AtomicU64,
+	open: AtomicBool,
+	active: AtomicUsize,
+	stop: AtomicBool
+}
+
+/// Pre-spawned threads that help the callback thread with a job, without locks or allocation.
+/// Only one job runs at a time, `try_run` reports when the pool is busy.
+pub struct WorkerPool {
+	state: Arc<PoolState>,
+	threads: Vec<Thread>,
+	handles: Vec<JoinHandle<()>>,
+	busy: AtomicBool
+}
+
+impl WorkerPool {
+	/// Spawns `num_threads` workers, fewer if the system does not allow more threads
+	pub fn new(num_threads: usize) -> WorkerPool {
+		let state = Arc::new(PoolState {
+			run: AtomicUsize::new(0),
+			data: AtomicPtr::new(std::ptr::null_mut()),
+			generation: AtomicU64::new(0),
+			open: AtomicBool::new(false),
+			active: AtomicUsize::new(0),
+			stop: AtomicBool::new(false)
+		});
+
+		let mut handles = Vec::with_capacity(num_threads);
+
+		for index in 0..num_threads {
+			let worker_state = state.clone();
+			let spawned = thread::Builder::new()
+				.name(format!("lobster worker {}", index + 1))
+				.spawn(move || WorkerPool::work(worker_state));
+
+			match spawned {
+				Ok(handle) => handles.push(handle),
+				Err(_) => break
+			}
+		}
+
+		WorkerPool {
+			state,
+			threads: handles.iter().map(|handle| handle.thread().clone()).collect(),
+			handles,
+			busy: AtomicBool::new(false)
+		}
+	}
+
+	/// One worker per core besides the callback thread
+	pub fn with_available_parallelism() -> WorkerPool {
+		let cores = thread::available_parallelism().map(|count| count.get()).unwrap_or(1);
+		WorkerPool::new(cores - 1)
+	}
+
+	pub fn num_threads(&self) -> usize {
+		self.threads.len()
+	}
+
+	/// Runs `job` on the calling thread and all workers, and returns once no worker uses it
+	/// anymore. Returns false without running the job if the pool has no workers or is busy.
+	///
+	/// # Safety
+	/// `job.run` must be safe to call concurrently with `job.data`, which must stay valid
+	/// until this function returns.
+	pub unsafe fn try_run(&self, job: Job) -> bool {
+		if self.threads.is_empty() || self.busy.swap(true, Ordering::Acquire) {
+			return false;
+		}
+
+		let state = &self.state;
+		state.run.store(job.run as usize, Ordering::Relaxed);
+		state.data.store(job.data as *mut (), Ordering::Relaxed);
+		state.open.store(true, Ordering::SeqCst);
+		state.generation.fetch_add(1, Ordering::SeqCst);
+
+		for thread in self.threads.iter() {
+			thread.unpark();
+		}
+
+		(job.run)(job.data);
+
+		// Workers entering after this see the job closed, the others are waited for
+		state.open.store(false, Ordering::SeqCst);
+		while state.active.load(Ordering::SeqCst) != 0 {
+			std::hint::spin_loop();
+		}
+
+		self.busy.store(false, Ordering::Release);
+		true
+	}
+
+	fn work(state: Arc<PoolState>) {
+		raise_thread_priority();
+
+		let mut seen = 0;
+
+		loop {
+			let mut spins = 0;
+			let mut generation = state.generation.load(Ordering::SeqCst);
+
+			while generation == seen && !state.stop.load(Ordering::Relaxed) {
+				if spins < SPIN_ITERATIONS {
+					spins += 1;
+					std::hint::spin_loop();
+				} else {
+					thread::park();
+				}
+				generation = state.generation.load(Ordering::SeqCst);
+			}
+
+			if state.stop.load(Ordering::Relaxed) {
+				return;
+			}
+			seen = generation;
+
+			// Announce the worker before checking that the job is still open, the pool
+			// closes the job before checking for active workers
+			state.active.fetch_add(1, Ordering::SeqCst);
+
+			if state.open.load(Ordering::SeqCst) && state.generation.load(Ordering::SeqCst) == seen {
+				let run: unsafe fn(*const ()) = unsafe { std::mem::transmute(state.run.load(Ordering::Relaxed)) };
+				let data = state.data.load(Ordering::Relaxed) as *const ();
+				unsafe { run(data) };
+			}
+
+			state.active.fetch_sub(1, Ordering::SeqCst);
+		}
+	}
+}
+
+impl Drop for WorkerPool {
+	fn drop(&mut self) {
+		self.state.stop.store(true, Ordering::Relaxed);
+
+		for thread in self.threads.iter() {
+			thread.unpark();
+		}
+		for handle in self.handles.drain(..) {
+			let _ = handle.join();
+		}
+	}
+}
+
+#[cfg(windows)]
+fn raise_thread_priority() {
+	const THREAD_PRIORITY_TIME_CRITICAL: i32 = 15;
+
+	#[link(name = "kernel32")]
+	extern "system" {
+		fn GetCurrentThread() -> *mut std::ffi::c_void;
+		fn SetThreadPriority(thread: *mut std::ffi::c_void, priority: i32) -> i32;
+	}
+
+	unsafe {
+		SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_TIME_CRITICAL);
+	}
+}
+
+#[cfg(not(windows))]
+fn raise_thread_priority() {
+}
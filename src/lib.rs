@@ -0,0 +1,2 @@
+pub mod asio_core;
+pub mod dsp;
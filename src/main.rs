@@ -1,4 +1,5 @@
-mod asio_core;
+use lobster::asio_core::device_factory::DeviceFactory;
+use lobster::dsp::routing_matrix::{RoutingMatrix, PAN_LEFT, PAN_RIGHT};
 
 use std::thread;
 use std::time::Duration;
@@ -20,12 +21,26 @@ fn main() {
 			data4: [0x8B, 0xC0, 0x43, 0x7D, 0x94, 0xF3, 0x71, 0x42],
 		};
 
-		let device =
-			asio_core::device_factory::DeviceFactory::create_device(clsid, process_buffers);
+		// Input 1 plays on both outputs
+		let matrix = RoutingMatrix::<f32>::new(2, 2, 48000.0f64);
+		let routing = matrix.control();
+		routing.set_gain(0, 0, 1.0);
+		routing.set_pan(0, 0, PAN_LEFT);
+		routing.set_gain(0, 1, 1.0);
+		routing.set_pan(0, 1, PAN_RIGHT);
+
+		let device = DeviceFactory::create_device(clsid, matrix);
 
 		println!("Created ASIO device '{}'", device.get_driver_name());
 
 		device.set_sample_rate(48000.0f64);
+		device.set_block_size(256);
+
+		let latencies = device.get_latencies();
+		println!(
+			"Latency in frames: {} input, {} processing, {} output",
+			latencies.input, latencies.processing, latencies.output
+		);
 
 		println!("ASIO device starting");
 		device.start();
@@ -37,27 +52,10 @@ fn main() {
 		device.stop();
 		println!("ASIO device stopped");
 
-		asio_core::device_factory::DeviceFactory::drop_device();
+		DeviceFactory::drop_device();
 	}
 
 	unsafe {
 		com::sys::CoUninitialize();
 	}
 }
-
-fn process_buffers(input: Vec<Vec<f64>>, outputs: &mut [Vec<f64>]) {
-	let ins = input.len() as i32;
-	let outs = outputs.len() as i32;
-
-	if ins >= 1 {
-		if outs == 2 {
-			for o in 0..outs {
-				let mut proc = input[0].iter().map(|s| *s * 1.0);
-				let dst = outputs[o as usize].iter_mut();
-				for target in dst {
-					*target = proc.next().expect("Not enough input data");
-				}
-			}
-		}
-	}
-}